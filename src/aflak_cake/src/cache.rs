@@ -1,26 +1,132 @@
+use std::collections::{HashMap, HashSet};
+use std::mem;
 use std::sync::{
     atomic::{AtomicBool, AtomicUsize, Ordering},
-    Arc,
+    Arc, Mutex,
 };
-use std::thread;
-use std::time::Instant;
+use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant};
 
+use crate::disk_cache::DiskCache;
 use crate::dst::{Output, TransformIdx};
+use crate::in_flight::{InFlight, Join, StolenJob};
 use crate::timed::Timed;
 
 use chashmap::CHashMap;
 
+/// Estimate the memory used by a value.
+///
+/// Implement this trait for the values computed by a `DST`, so that the
+/// `Cache` can keep the memory used by cached results under its budget.
+pub trait EstimateSize {
+    /// Estimated size of the value in bytes, including heap allocations.
+    ///
+    /// Default to the size of the value on the stack.
+    fn estimate_size(&self) -> usize {
+        mem::size_of_val(self)
+    }
+}
+
 /// The Cache object used to run cached computations with cake
 ///
 /// The cache is a concurrent hash-map, that will stay alive after
 /// being dropped at least until the last worker using the cache releases it.
+///
+/// The memory used by cached results may be bounded with a budget. When the
+/// budget is exceeded, results are evicted from the cache with the
+/// *GreedyDual-Size* policy: results that were least recently used, that are
+/// the largest and that were the fastest to compute are evicted first.
+/// Results of pinned transforms (e.g. outputs currently displayed) are never
+/// evicted.
+///
+/// A [`DiskCache`] may be attached to persist expensive results across runs.
+///
+/// Concurrent requests for the results of the same transform (e.g. from two
+/// outputs depending on it) are de-duplicated: the results are computed once
+/// and shared.
 #[derive(Debug)]
 pub struct Cache<T, E> {
     cache: CHashMap<TransformIdx, Option<CacheBox<T, E>>>,
+    accounting: Mutex<Accounting>,
+    in_flight: InFlight<Vec<Result<Arc<T>, Arc<E>>>>,
+    disk: Option<Arc<DiskCache<T>>>,
     in_use: Arc<AtomicUsize>,
     scheduled_for_destruction: Arc<AtomicBool>,
 }
 
+/// Statistics on the use of a `Cache`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Number of results found in the cache.
+    pub hits: usize,
+    /// Number of hits on results that were being computed for a concurrent
+    /// request, and were shared with it instead of being computed twice.
+    pub shared: usize,
+    /// Number of results that had to be computed.
+    pub misses: usize,
+    /// Number of missed results that were loaded from the disk cache
+    /// instead of being computed.
+    pub disk_hits: usize,
+    /// Number of results evicted from the cache.
+    pub evictions: usize,
+    /// Number of results currently in the cache.
+    pub entries: usize,
+    /// Estimated memory used by the results in the cache, in bytes.
+    pub bytes: usize,
+    /// Memory budget in bytes. `None` if the cache is unbounded.
+    pub budget: Option<usize>,
+}
+
+/// Profile of the computations of a transform, recorded by a `Cache`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct NodeProfile {
+    /// Number of times the results were found in the cache.
+    pub hits: usize,
+    /// Number of times the results had to be computed.
+    pub misses: usize,
+    /// Number of missed results that were loaded from the disk cache
+    /// instead of being computed.
+    pub disk_hits: usize,
+    /// Time spent in the transform, excluding its dependencies, the last
+    /// time it was computed.
+    pub last_time: Duration,
+    /// Total time spent in the transform, excluding its dependencies.
+    pub total_time: Duration,
+    /// Estimated size of the results in bytes, the last time they were
+    /// computed.
+    pub bytes: usize,
+}
+
+impl NodeProfile {
+    /// Mean time spent in the transform each time it was computed.
+    pub fn mean_time(&self) -> Duration {
+        let computed = self.misses.saturating_sub(self.disk_hits).max(1);
+        self.total_time / computed as u32
+    }
+}
+
+/// Book-keeping of the cached results, used to choose which results to
+/// evict.
+#[derive(Debug, Default)]
+struct Accounting {
+    entries: HashMap<TransformIdx, EntryInfo>,
+    pinned: HashSet<TransformIdx>,
+    profiles: HashMap<TransformIdx, NodeProfile>,
+    /// Priority of the last evicted entry. Increase over time so that
+    /// recently used entries have a higher priority.
+    inflation: f64,
+    stats: CacheStats,
+}
+
+#[derive(Debug)]
+struct EntryInfo {
+    /// Instant of the cached values, to identify them
+    time: Instant,
+    bytes: usize,
+    cost: Duration,
+    priority: f64,
+}
+
 impl<T, E> Default for Cache<T, E> {
     fn default() -> Self {
         Self::new()
@@ -43,7 +149,7 @@ impl<T, E> Clone for CacheRef<T, E> {
     }
 }
 
-impl<T, E> CacheRef<T, E> {
+impl<T: EstimateSize, E> CacheRef<T, E> {
     /// Compute and insert in cache *or* get from cache.
     /// Return None if the cache is scheduled for destruction.
     ///
@@ -56,7 +162,7 @@ impl<T, E> CacheRef<T, E> {
         f: F,
     ) -> Option<Timed<Vec<Result<Arc<T>, Arc<E>>>>>
     where
-        F: FnOnce() -> Vec<Result<Arc<T>, Arc<E>>>,
+        F: FnOnce() -> Computed<Vec<Result<Arc<T>, Arc<E>>>>,
     {
         if self.scheduled_for_destruction.load(Ordering::Acquire) {
             None
@@ -70,27 +176,274 @@ impl<T, E> CacheRef<T, E> {
             Some(ret)
         }
     }
+
+    /// Get the disk cache attached to the cache, if any.
+    ///
+    /// Must only be called from the closure run by `compute`, while the
+    /// cache is known to be alive.
+    pub(crate) fn disk_cache(&self) -> Option<Arc<DiskCache<T>>> {
+        unsafe { (*self.inner).disk.clone() }
+    }
+
+    /// Count a result loaded from the disk cache.
+    ///
+    /// Must only be called from the closure run by `compute`, while the
+    /// cache is known to be alive.
+    pub(crate) fn count_disk_hit(&self, t_idx: TransformIdx) {
+        let mut accounting = unsafe { (*self.inner).accounting.lock().unwrap() };
+        accounting.stats.disk_hits += 1;
+        accounting.profile_mut(t_idx).disk_hits += 1;
+    }
+
+    /// Record that the current worker runs a job spawned by `spawner` in a
+    /// `rayon::scope`, until the returned guard is dropped.
+    ///
+    /// Must only be called from the closure run by `compute`, while the
+    /// cache is known to be alive.
+    pub(crate) fn stolen_job(
+        &self,
+        spawner: ThreadId,
+    ) -> StolenJob<'_, Vec<Result<Arc<T>, Arc<E>>>> {
+        unsafe { (*self.inner).in_flight.stolen_job(spawner) }
+    }
+
+    /// Record the time spent in the transform `t_idx`.
+    ///
+    /// Must only be called from the closure run by `compute`, while the
+    /// cache is known to be alive.
+    pub(crate) fn record_time(&self, t_idx: TransformIdx, time: Duration) {
+        let mut accounting = unsafe { (*self.inner).accounting.lock().unwrap() };
+        let profile = accounting.profile_mut(t_idx);
+        profile.last_time = time;
+        profile.total_time += time;
+    }
 }
 
 unsafe impl<T, E> Sync for CacheRef<T, E> {}
 unsafe impl<T, E> Send for CacheRef<T, E> {}
 
+/// Values computed by the closure passed to `Cache::compute`.
+pub(crate) enum Computed<V> {
+    /// The values are complete and can be cached.
+    Done(V),
+    /// The computation was interrupted. The values are returned but not
+    /// cached.
+    Interrupted(V),
+}
+
 #[derive(Debug)]
 struct CacheBox<T, E> {
     time: Instant,
     values: Vec<Result<Arc<T>, Arc<E>>>,
 }
 
+impl EntryInfo {
+    /// Priority of an entry in the GreedyDual-Size policy. Entries with the
+    /// lowest priority are evicted first.
+    fn priority(inflation: f64, cost: Duration, bytes: usize) -> f64 {
+        inflation + cost.as_secs_f64() / bytes.max(1) as f64
+    }
+}
+
+impl Accounting {
+    fn bytes(&self) -> usize {
+        self.stats.bytes
+    }
+
+    fn profile_mut(&mut self, t_idx: TransformIdx) -> &mut NodeProfile {
+        self.profiles.entry(t_idx).or_default()
+    }
+
+    fn count_hit(&mut self, t_idx: &TransformIdx) {
+        self.stats.hits += 1;
+        self.profile_mut(*t_idx).hits += 1;
+        self.touch(t_idx);
+    }
+
+    fn remove(&mut self, t_idx: &TransformIdx) -> Option<EntryInfo> {
+        let info = self.entries.remove(t_idx);
+        if let Some(info) = &info {
+            self.stats.bytes -= info.bytes;
+            self.stats.entries -= 1;
+        }
+        info
+    }
+
+    fn insert(&mut self, t_idx: TransformIdx, time: Instant, bytes: usize, cost: Duration) {
+        self.remove(&t_idx);
+        let priority = EntryInfo::priority(self.inflation, cost, bytes);
+        self.entries.insert(
+            t_idx,
+            EntryInfo {
+                time,
+                bytes,
+                cost,
+                priority,
+            },
+        );
+        self.stats.bytes += bytes;
+        self.stats.entries += 1;
+    }
+
+    fn touch(&mut self, t_idx: &TransformIdx) {
+        let inflation = self.inflation;
+        if let Some(info) = self.entries.get_mut(t_idx) {
+            info.priority = EntryInfo::priority(inflation, info.cost, info.bytes);
+        }
+    }
+
+    /// Choose entries to evict until the memory used fits in the budget.
+    /// Entry `keep` is only evicted as a last resort.
+    fn evict(&mut self, keep: Option<TransformIdx>) -> Vec<(TransformIdx, Instant)> {
+        let mut evicted = vec![];
+        let budget = match self.stats.budget {
+            Some(budget) => budget,
+            None => return evicted,
+        };
+        while self.bytes() > budget {
+            let victim = self
+                .entries
+                .iter()
+                .filter(|(t_idx, _)| !self.pinned.contains(t_idx))
+                .min_by(|(idx1, info1), (idx2, info2)| {
+                    let kept1 = Some(**idx1) == keep;
+                    let kept2 = Some(**idx2) == keep;
+                    kept1
+                        .cmp(&kept2)
+                        .then(
+                            info1
+                                .priority
+                                .partial_cmp(&info2.priority)
+                                .unwrap_or(std::cmp::Ordering::Equal),
+                        )
+                        .then(idx1.cmp(idx2))
+                })
+                .map(|(t_idx, _)| *t_idx);
+            if let Some(t_idx) = victim {
+                let info = self.remove(&t_idx).unwrap();
+                self.inflation = self.inflation.max(info.priority);
+                self.stats.evictions += 1;
+                evicted.push((t_idx, info.time));
+            } else {
+                break;
+            }
+        }
+        evicted
+    }
+}
+
 impl<T, E> Cache<T, E> {
-    /// Initialize Cache
+    /// Initialize an unbounded Cache
     pub fn new() -> Self {
         Self {
             cache: CHashMap::new(),
+            accounting: Mutex::new(Accounting::default()),
+            in_flight: InFlight::new(),
+            disk: None,
             in_use: Arc::new(AtomicUsize::new(0)),
             scheduled_for_destruction: Arc::new(AtomicBool::new(false)),
         }
     }
 
+    /// Initialize a Cache whose results use at most `budget` bytes.
+    pub fn with_budget(budget: usize) -> Self {
+        let cache = Self::new();
+        cache.set_budget(Some(budget));
+        cache
+    }
+
+    /// Get memory budget in bytes. `None` if the cache is unbounded.
+    pub fn budget(&self) -> Option<usize> {
+        self.accounting.lock().unwrap().stats.budget
+    }
+
+    /// Set memory budget in bytes (`None` for an unbounded cache).
+    /// Results are evicted immediately if the new budget is exceeded.
+    pub fn set_budget(&self, budget: Option<usize>) {
+        let evicted = {
+            let mut accounting = self.accounting.lock().unwrap();
+            accounting.stats.budget = budget;
+            accounting.evict(None)
+        };
+        self.clear_evicted(evicted);
+    }
+
+    /// Pin the results of the transform `t_idx`, so that they are never
+    /// evicted.
+    pub fn pin(&self, t_idx: TransformIdx) {
+        self.accounting.lock().unwrap().pinned.insert(t_idx);
+    }
+
+    /// Unpin the results of the transform `t_idx`.
+    pub fn unpin(&self, t_idx: &TransformIdx) {
+        self.accounting.lock().unwrap().pinned.remove(t_idx);
+    }
+
+    /// Replace the set of pinned transforms with `t_indices`.
+    pub fn set_pinned<I: IntoIterator<Item = TransformIdx>>(&self, t_indices: I) {
+        self.accounting.lock().unwrap().pinned = t_indices.into_iter().collect();
+    }
+
+    /// Check whether the results of the transform `t_idx` are pinned.
+    pub fn is_pinned(&self, t_idx: &TransformIdx) -> bool {
+        self.accounting.lock().unwrap().pinned.contains(t_idx)
+    }
+
+    /// Get statistics on the use of the cache.
+    pub fn stats(&self) -> CacheStats {
+        self.accounting.lock().unwrap().stats
+    }
+
+    /// Reset hit, miss and eviction counters.
+    pub fn reset_stats(&self) {
+        let mut accounting = self.accounting.lock().unwrap();
+        let stats = &mut accounting.stats;
+        stats.hits = 0;
+        stats.shared = 0;
+        stats.misses = 0;
+        stats.disk_hits = 0;
+        stats.evictions = 0;
+    }
+
+    /// Get the profile of the computations of the transform `t_idx`, if it
+    /// was ever requested.
+    pub fn profile(&self, t_idx: &TransformIdx) -> Option<NodeProfile> {
+        self.accounting.lock().unwrap().profiles.get(t_idx).copied()
+    }
+
+    /// Get the profiles of all the transforms requested so far.
+    pub fn profiles(&self) -> HashMap<TransformIdx, NodeProfile> {
+        self.accounting.lock().unwrap().profiles.clone()
+    }
+
+    /// Forget the profiles of all the transforms.
+    pub fn reset_profiles(&self) {
+        self.accounting.lock().unwrap().profiles.clear();
+    }
+
+    /// Get the disk cache in which expensive results are persisted.
+    pub fn disk_cache(&self) -> Option<&Arc<DiskCache<T>>> {
+        self.disk.as_ref()
+    }
+
+    /// Attach a disk cache in which expensive results are persisted
+    /// (or detach it with `None`).
+    pub fn set_disk_cache(&mut self, disk: Option<Arc<DiskCache<T>>>) {
+        self.disk = disk;
+    }
+
+    /// Remove evicted values from the cache, unless they were replaced in
+    /// the meantime.
+    fn clear_evicted(&self, evicted: Vec<(TransformIdx, Instant)>) {
+        for (t_idx, time) in evicted {
+            if let Some(mut some_cache_box) = self.cache.get_mut(&t_idx) {
+                if some_cache_box.as_ref().map(|cache_box| cache_box.time) == Some(time) {
+                    *some_cache_box = None;
+                }
+            }
+        }
+    }
+
     /// Get currently cached value for given Input.
     /// The value may or may not have expired.
     pub fn get(&self, output: &Output) -> Option<Result<Arc<T>, Arc<E>>> {
@@ -117,6 +470,27 @@ impl<T, E> Cache<T, E> {
             }
         }
     }
+}
+
+impl<T: EstimateSize, E> Cache<T, E> {
+    /// Get the results of `t_idx` if they were cached at `t_instant` or
+    /// later.
+    fn get_cached(
+        &self,
+        t_idx: TransformIdx,
+        t_instant: Instant,
+    ) -> Option<Timed<Vec<Result<Arc<T>, Arc<E>>>>> {
+        let cached = self.cache.get(&t_idx).and_then(|some_cache_box| {
+            some_cache_box
+                .as_ref()
+                .filter(|cache_box| cache_box.time >= t_instant)
+                .map(|cache_box| Timed::from_instant(cache_box.values.clone(), cache_box.time))
+        });
+        if cached.is_some() {
+            self.accounting.lock().unwrap().count_hit(&t_idx);
+        }
+        cached
+    }
 
     pub(crate) fn compute<F>(
         &self,
@@ -125,24 +499,65 @@ impl<T, E> Cache<T, E> {
         f: F,
     ) -> Timed<Vec<Result<Arc<T>, Arc<E>>>>
     where
-        F: FnOnce() -> Vec<Result<Arc<T>, Arc<E>>>,
+        F: FnOnce() -> Computed<Vec<Result<Arc<T>, Arc<E>>>>,
     {
-        if let Some(some_cache_box) = self.cache.get(&t_idx) {
-            if let Some(ref cache_box) = *some_cache_box {
-                if cache_box.time >= t_instant {
-                    return Timed::from_instant(cache_box.values.clone(), cache_box.time);
+        if let Some(cached) = self.get_cached(t_idx, t_instant) {
+            return cached;
+        }
+
+        let owner = match self.in_flight.join(t_idx, t_instant) {
+            Join::Owner(owner) => {
+                // The results may have been cached by a computation that
+                // finished since they were looked up
+                if let Some(cached) = self.get_cached(t_idx, t_instant) {
+                    return cached;
                 }
+                Some(owner)
             }
-        }
+            Join::Shared(values, time) => {
+                let mut accounting = self.accounting.lock().unwrap();
+                accounting.stats.shared += 1;
+                accounting.count_hit(&t_idx);
+                return Timed::from_instant(values, time);
+            }
+            Join::Alone => None,
+        };
 
-        let result = f();
+        let start = Instant::now();
+        let result = match f() {
+            Computed::Done(result) => result,
+            Computed::Interrupted(result) => return Timed::from_instant(result, t_instant),
+        };
+        let cost = start.elapsed();
 
+        let bytes = result
+            .iter()
+            .map(|value| match value {
+                Ok(t) => t.estimate_size(),
+                Err(_) => mem::size_of::<E>(),
+            })
+            .sum();
         let ret = result.clone();
-        let mut some_cache_box = self.cache.get_mut(&t_idx).unwrap();
-        *some_cache_box = Some(CacheBox {
-            time: t_instant,
-            values: result,
-        });
+        {
+            let mut some_cache_box = self.cache.get_mut(&t_idx).unwrap();
+            *some_cache_box = Some(CacheBox {
+                time: t_instant,
+                values: result,
+            });
+        }
+        let evicted = {
+            let mut accounting = self.accounting.lock().unwrap();
+            accounting.stats.misses += 1;
+            let profile = accounting.profile_mut(t_idx);
+            profile.misses += 1;
+            profile.bytes = bytes;
+            accounting.insert(t_idx, t_instant, bytes, cost);
+            accounting.evict(Some(t_idx))
+        };
+        self.clear_evicted(evicted);
+        if let Some(owner) = owner {
+            owner.finish(ret.clone());
+        }
         Timed::from_instant(ret, t_instant)
     }
 }
@@ -0,0 +1,364 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Mutex,
+};
+use std::time::{Duration, SystemTime};
+
+/// Encode and decode values to be stored in a `DiskCache`.
+///
+/// The codec is provided by the user of the crate, as only they know how
+/// their values should be serialized.
+pub trait Codec<T>: Send + Sync {
+    /// Write `value` to `w`.
+    fn encode(&self, value: &T, w: &mut dyn Write) -> io::Result<()>;
+    /// Read a value encoded with `encode` from `r`.
+    fn decode(&self, r: &mut dyn Read) -> io::Result<T>;
+    /// Bytes identifying `value` when it is used as an input of a node.
+    ///
+    /// Two values with the same fingerprint must give the same results.
+    /// Return `None` if the value cannot be identified, in which case the
+    /// results depending on it are not persisted.
+    ///
+    /// Default to the encoded value. Override this for values that refer to
+    /// external resources (e.g. file paths), so that the fingerprint changes
+    /// when the resource changes.
+    fn fingerprint(&self, value: &T) -> Option<Vec<u8>> {
+        let mut buf = vec![];
+        self.encode(value, &mut buf).ok()?;
+        Some(buf)
+    }
+}
+
+/// Version of the format of the files written by `DiskCache`.
+/// Bump it to invalidate all the existing cache files.
+const FORMAT_VERSION: u32 = 1;
+const MAGIC: &[u8; 4] = b"CAKE";
+const EXTENSION: &str = "cake";
+
+/// Persistent cache storing computed results in a directory.
+///
+/// Results are identified by a content hash of the node that computed them,
+/// see [`DST::content_hash`](struct.DST.html#method.content_hash). Only the
+/// results of nodes that took more than `min_cost` to compute are stored.
+///
+/// The size of the directory may be bounded, in which case the least recently
+/// used results are deleted first.
+pub struct DiskCache<T> {
+    dir: PathBuf,
+    codec: Box<dyn Codec<T>>,
+    max_bytes: Option<u64>,
+    min_cost: Duration,
+    index: Mutex<HashMap<u64, FileInfo>>,
+    tmp_counter: AtomicUsize,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct FileInfo {
+    bytes: u64,
+    last_used: SystemTime,
+}
+
+impl<T> fmt::Debug for DiskCache<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DiskCache")
+            .field("dir", &self.dir)
+            .field("max_bytes", &self.max_bytes)
+            .field("min_cost", &self.min_cost)
+            .finish()
+    }
+}
+
+impl<T> DiskCache<T> {
+    /// Open the cache stored in directory `dir`, creating it if necessary.
+    ///
+    /// By default the cache is unbounded and only stores results that took
+    /// more than 100ms to compute.
+    pub fn open<P: AsRef<Path>>(dir: P, codec: Box<dyn Codec<T>>) -> io::Result<Self> {
+        let dir = dir.as_ref().to_owned();
+        fs::create_dir_all(&dir)?;
+        let mut index = HashMap::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if let Some(key) = key_from_path(&path) {
+                let metadata = fs::metadata(&path)?;
+                index.insert(
+                    key,
+                    FileInfo {
+                        bytes: metadata.len(),
+                        last_used: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                    },
+                );
+            } else if path.extension().map_or(false, |ext| ext == "tmp") {
+                // Leftover of an interrupted write
+                let _ = fs::remove_file(&path);
+            }
+        }
+        Ok(Self {
+            dir,
+            codec,
+            max_bytes: None,
+            min_cost: Duration::from_millis(100),
+            index: Mutex::new(index),
+            tmp_counter: AtomicUsize::new(0),
+        })
+    }
+
+    /// Directory where results are stored.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Codec used to encode the results.
+    pub fn codec(&self) -> &dyn Codec<T> {
+        &*self.codec
+    }
+
+    /// Maximum size of the cache directory in bytes. `None` if unbounded.
+    pub fn max_bytes(&self) -> Option<u64> {
+        self.max_bytes
+    }
+
+    /// Set maximum size of the cache directory in bytes.
+    /// Files are deleted immediately if the cache is too large.
+    pub fn set_max_bytes(&mut self, max_bytes: Option<u64>) {
+        self.max_bytes = max_bytes;
+        self.enforce_limit();
+    }
+
+    /// Minimum time a node must have taken to compute for its results to
+    /// be stored.
+    pub fn min_cost(&self) -> Duration {
+        self.min_cost
+    }
+
+    /// Set minimum time a node must take to compute for its results to be
+    /// stored.
+    pub fn set_min_cost(&mut self, min_cost: Duration) {
+        self.min_cost = min_cost;
+    }
+
+    /// Number of results stored in the cache.
+    pub fn len(&self) -> usize {
+        self.index.lock().unwrap().len()
+    }
+
+    /// Check if the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Total size of the stored results in bytes.
+    pub fn size(&self) -> u64 {
+        self.index
+            .lock()
+            .unwrap()
+            .values()
+            .map(|info| info.bytes)
+            .sum()
+    }
+
+    /// Delete all the stored results.
+    pub fn clear(&self) -> io::Result<()> {
+        let mut index = self.index.lock().unwrap();
+        for key in index.keys() {
+            remove_file_if_exists(&self.path(*key))?;
+        }
+        index.clear();
+        Ok(())
+    }
+
+    fn path(&self, key: u64) -> PathBuf {
+        self.dir.join(format!("{:016x}.{}", key, EXTENSION))
+    }
+
+    /// Load the `count` values stored with `key`.
+    /// Return `None` if they cannot be found or decoded.
+    pub(crate) fn load(&self, key: u64, count: usize) -> Option<Vec<T>> {
+        if !self.index.lock().unwrap().contains_key(&key) {
+            return None;
+        }
+        match self.read(key, count) {
+            Ok(values) => {
+                if let Some(info) = self.index.lock().unwrap().get_mut(&key) {
+                    info.last_used = SystemTime::now();
+                }
+                Some(values)
+            }
+            Err(_) => {
+                // Corrupted or outdated file, do not try again
+                self.index.lock().unwrap().remove(&key);
+                let _ = fs::remove_file(self.path(key));
+                None
+            }
+        }
+    }
+
+    fn read(&self, key: u64, count: usize) -> io::Result<Vec<T>> {
+        let mut f = io::BufReader::new(fs::File::open(self.path(key))?);
+        let mut magic = [0; 4];
+        f.read_exact(&mut magic)?;
+        if &magic != MAGIC
+            || read_u32(&mut f)? != FORMAT_VERSION
+            || read_u32(&mut f)? as usize != count
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Unexpected cache file header",
+            ));
+        }
+        let mut values = Vec::with_capacity(count);
+        for _ in 0..count {
+            let len = read_u64(&mut f)?;
+            let mut chunk = (&mut f).take(len);
+            values.push(self.codec.decode(&mut chunk)?);
+            if chunk.limit() != 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Value not fully decoded",
+                ));
+            }
+        }
+        Ok(values)
+    }
+
+    /// Store `values` with `key`.
+    pub(crate) fn store<'a, I>(&self, key: u64, values: I) -> io::Result<()>
+    where
+        I: IntoIterator<Item = &'a T>,
+        T: 'a,
+    {
+        let values: Vec<_> = values.into_iter().collect();
+        let tmp_path = self.dir.join(format!(
+            "{:016x}.{}.tmp",
+            key,
+            self.tmp_counter.fetch_add(1, Ordering::Relaxed)
+        ));
+        let written = self.write(&tmp_path, &values);
+        let bytes = match written.and_then(|_| fs::metadata(&tmp_path)) {
+            Ok(metadata) => metadata.len(),
+            Err(e) => {
+                let _ = fs::remove_file(&tmp_path);
+                return Err(e);
+            }
+        };
+        fs::rename(&tmp_path, self.path(key))?;
+        self.index.lock().unwrap().insert(
+            key,
+            FileInfo {
+                bytes,
+                last_used: SystemTime::now(),
+            },
+        );
+        self.enforce_limit();
+        Ok(())
+    }
+
+    fn write(&self, path: &Path, values: &[&T]) -> io::Result<()> {
+        let mut f = io::BufWriter::new(fs::File::create(path)?);
+        f.write_all(MAGIC)?;
+        f.write_all(&FORMAT_VERSION.to_le_bytes())?;
+        f.write_all(&(values.len() as u32).to_le_bytes())?;
+        let mut buf = vec![];
+        for value in values {
+            buf.clear();
+            self.codec.encode(value, &mut buf)?;
+            f.write_all(&(buf.len() as u64).to_le_bytes())?;
+            f.write_all(&buf)?;
+        }
+        f.flush()
+    }
+
+    /// Delete the least recently used files until the cache fits in its
+    /// maximum size.
+    fn enforce_limit(&self) {
+        let max_bytes = if let Some(max_bytes) = self.max_bytes {
+            max_bytes
+        } else {
+            return;
+        };
+        let mut index = self.index.lock().unwrap();
+        let mut total: u64 = index.values().map(|info| info.bytes).sum();
+        while total > max_bytes {
+            let oldest = index
+                .iter()
+                .min_by_key(|(_, info)| info.last_used)
+                .map(|(key, info)| (*key, info.bytes));
+            if let Some((key, bytes)) = oldest {
+                index.remove(&key);
+                let _ = fs::remove_file(self.path(key));
+                total -= bytes;
+            } else {
+                break;
+            }
+        }
+    }
+}
+
+fn key_from_path(path: &Path) -> Option<u64> {
+    if path.extension()? != EXTENSION {
+        return None;
+    }
+    let stem = path.file_stem()?.to_str()?;
+    u64::from_str_radix(stem, 16).ok()
+}
+
+fn remove_file_if_exists(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        res => res,
+    }
+}
+
+fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    r.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// 64-bit FNV-1a hasher.
+///
+/// Contrary to the hashers of the standard library, the hash is guaranteed
+/// to be the same across runs, platforms and versions of the compiler, as
+/// long as the hashed data are written explicitly with the methods below.
+pub(crate) struct StableHasher(u64);
+
+impl StableHasher {
+    pub fn new() -> Self {
+        let mut hasher = StableHasher(0xcbf2_9ce4_8422_2325);
+        hasher.write_u64(u64::from(FORMAT_VERSION));
+        hasher
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.0 ^= u64::from(*byte);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    pub fn write_u64(&mut self, n: u64) {
+        self.write(&n.to_le_bytes());
+    }
+
+    /// Write a byte string prefixed with its length, so that consecutive
+    /// strings cannot be confused.
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_u64(bytes.len() as u64);
+        self.write(bytes);
+    }
+
+    pub fn finish(&self) -> u64 {
+        self.0
+    }
+}
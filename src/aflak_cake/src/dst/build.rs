@@ -239,6 +239,88 @@ where
         self.add_transform_impl(Bow::Owned(t), macro_id)
     }
 
+    /// Make a new [`DST`] made of the given nodes, with the links between
+    /// them. Nodes keep their identifiers and default inputs.
+    ///
+    /// Nodes that do not exist are ignored. Output nodes attached to a
+    /// transform that is not part of the sub-graph are detached.
+    pub fn sub_graph(&self, nodes: &[NodeId]) -> Self {
+        let mut sub = Self::new();
+        for node_id in nodes {
+            if let NodeId::Transform(t_idx) = node_id {
+                if let Some(meta) = self.transforms.get(t_idx) {
+                    sub.transforms.insert(*t_idx, meta.clone());
+                }
+            }
+        }
+        for (output, input_list) in &self.edges {
+            if !sub.transforms.contains_key(&output.t_idx) {
+                continue;
+            }
+            let inputs: Vec<_> = input_list
+                .inputs
+                .iter()
+                .filter(|input| sub.transforms.contains_key(&input.t_idx))
+                .cloned()
+                .collect();
+            if !inputs.is_empty() {
+                sub.edges.insert(*output, InputList::new(inputs));
+            }
+        }
+        for node_id in nodes {
+            if let NodeId::Output(output_id) = node_id {
+                if let Some((some_output, name)) = self.outputs.get(output_id) {
+                    let some_output =
+                        some_output.filter(|output| sub.transforms.contains_key(&output.t_idx));
+                    sub.outputs.insert(*output_id, (some_output, name.clone()));
+                }
+            }
+        }
+        sub
+    }
+
+    /// Add all the nodes of `other` and the links between them, with new
+    /// identifiers. Set `macro_id` if `self` is the [`DST`] of a macro.
+    ///
+    /// Return the new identifier of each node of `other`.
+    pub fn merge(&mut self, other: Self, macro_id: Option<Uuid>) -> BTreeMap<NodeId, NodeId> {
+        let mut new_ids = BTreeMap::new();
+        let mut new_t_indices = BTreeMap::new();
+        for (t_idx, mut meta) in other.transforms {
+            let mut new_t_idx = self.new_transform_idx(macro_id);
+            if let Some(macro_id) = macro_id {
+                new_t_idx = new_t_idx.set_macro(macro_id);
+            }
+            meta.updated_now();
+            self.transforms.insert(new_t_idx, meta);
+            new_t_indices.insert(t_idx, new_t_idx);
+            new_ids.insert(NodeId::Transform(t_idx), NodeId::Transform(new_t_idx));
+        }
+        let new_output = |output: Output| Output {
+            t_idx: new_t_indices[&output.t_idx],
+            ..output
+        };
+        for (output, input_list) in other.edges {
+            let inputs = self
+                .edges
+                .entry(new_output(output))
+                .or_insert_with(|| InputList::new(vec![]));
+            for input in input_list.inputs {
+                inputs.push(Input {
+                    t_idx: new_t_indices[&input.t_idx],
+                    ..input
+                });
+            }
+        }
+        for (output_id, (some_output, name)) in other.outputs {
+            let new_output_id = self.new_output_id();
+            self.outputs
+                .insert(new_output_id, (some_output.map(new_output), name));
+            new_ids.insert(NodeId::Output(output_id), NodeId::Output(new_output_id));
+        }
+        new_ids
+    }
+
     fn add_transform_impl(
         &mut self,
         t: Bow<'t, Transform<'t, T, E>>,
@@ -4,16 +4,19 @@ use std::collections;
 use std::error;
 use std::fmt;
 use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
 
 use boow::Bow;
 use rayon;
 
 use super::super::ConvertibleVariants;
-use crate::cache::{Cache, CacheRef};
-use crate::dst::{Input, Output, OutputId, TransformIdx, DST};
+use crate::cache::{Cache, CacheRef, Computed, EstimateSize, NodeProfile};
+use crate::dst::{Input, MetaTransform, Output, OutputId, TransformIdx, DST};
 use crate::future::Task;
+use crate::progress::{self, ComputeContext};
 use crate::timed::Timed;
-use crate::transform::{ArgumentError, CallError};
+use crate::transform::{Algorithm, ArgumentError, CallError};
 use variant_name::VariantName;
 
 /// The successful result of a computation.
@@ -46,6 +49,8 @@ pub enum ComputeError<E> {
     },
     UnusableCache(Output),
     NothingDoneYet,
+    /// The computation was cancelled before it could finish.
+    Cancelled,
     /// Represent an error during computing, caused by user-defined
     /// transformations. This is usually caused by an unexpected input causing
     /// the calculation to abort.
@@ -75,6 +80,18 @@ pub enum ComputeError<E> {
     },
 }
 
+impl<E> ComputeError<E> {
+    /// Check whether the error was caused by the cancellation of the
+    /// computation.
+    pub fn is_cancelled(&self) -> bool {
+        match self {
+            ComputeError::Cancelled => true,
+            ComputeError::ErrorStack { cause, .. } => cause.is_cancelled(),
+            _ => false,
+        }
+    }
+}
+
 impl<E: fmt::Display + fmt::Debug> error::Error for ComputeError<E> {
     fn description(&self) -> &'static str {
         "aflak_cake::ComputeError"
@@ -113,6 +130,7 @@ impl<E: fmt::Display> fmt::Display for ComputeError<E> {
                 t_name,
             } => write!(f, "{}\n    in node #{} {}", cause, t_idx.1, t_name),
             NothingDoneYet => write!(f, "Nothing done yet!"),
+            Cancelled => write!(f, "Computation cancelled"),
             ErrorStack {
                 cause,
                 t_idx,
@@ -144,7 +162,7 @@ impl<E: fmt::Display> fmt::Display for ComputeError<E> {
 
 impl<T, E> DST<'static, T, E>
 where
-    T: Clone + VariantName + ConvertibleVariants + Send + Sync,
+    T: Clone + VariantName + ConvertibleVariants + EstimateSize + Send + Sync,
     E: Send + Sync,
 {
     /// Return the value out of the output given as argument.
@@ -154,6 +172,20 @@ where
         &self,
         output_id: OutputId,
         cache: &mut Cache<T, ComputeError<E>>,
+    ) -> Task<SuccessOut<T>, ErrorOut<E>> {
+        self.compute_with(output_id, cache, ComputeContext::default())
+    }
+
+    /// Same as [`DST::compute`], but the computation runs in the given
+    /// context. It is aborted as soon as possible once the context is
+    /// cancelled, and reports the progress of each node.
+    ///
+    /// Results of an aborted computation are not cached.
+    pub fn compute_with(
+        &self,
+        output_id: OutputId,
+        cache: &mut Cache<T, ComputeError<E>>,
+        ctx: ComputeContext,
     ) -> Task<SuccessOut<T>, ErrorOut<E>> {
         let t_indices = self.transforms.keys().cloned();
         cache.init(t_indices);
@@ -163,7 +195,7 @@ where
                 let output = *output;
                 let cache_ref = cache.get_ref();
                 let dst = self.clone();
-                Task::new(move || dst._compute(output, cache_ref))
+                Task::new(move || dst._compute(output, cache_ref, &ctx))
             } else {
                 Task::errored(Timed::from(Arc::new(ComputeError::UnattachedOutputID(
                     output_id,
@@ -176,7 +208,15 @@ where
         }
     }
 
-    fn _compute(&self, output: Output, cache: CacheRef<T, ComputeError<E>>) -> NodeResult<T, E> {
+    fn _compute(
+        &self,
+        output: Output,
+        cache: CacheRef<T, ComputeError<E>>,
+        ctx: &ComputeContext,
+    ) -> NodeResult<T, E> {
+        if ctx.is_cancelled() {
+            return Err(Timed::from(Arc::new(ComputeError::Cancelled)));
+        }
         let meta = if let Some(meta) = self.transforms.get(&output.t_idx) {
             meta
         } else {
@@ -194,76 +234,42 @@ where
         }
 
         if let Some(result) = cache.compute(t_idx, updated_on, || {
-            let deps = self
-                .outputs_attached_to_transform(t_idx)
-                .expect("Tranform not found!");
-
-            let mut results = Vec::with_capacity(deps.len());
-            for _ in 0..(deps.len()) {
-                results.push(Err(Arc::new(ComputeError::NothingDoneYet)));
-            }
-            let defaults = meta.defaults().to_vec();
-            rayon::scope(|s| {
-                for (i, ((result, parent_output), default)) in
-                    results.iter_mut().zip(deps).zip(defaults).enumerate()
-                {
-                    let cache_clone = cache.clone();
-                    s.spawn(move |_| {
-                        *result = if let Some(output) = parent_output {
-                            Timed::take_from_result(self._compute(output, cache_clone))
-                        } else if let Some(default) = default {
-                            Ok(Arc::new(default))
-                        } else {
-                            Err(Arc::new(ComputeError::MissingDependency {
-                                input: Input::new(t_idx, i),
-                                t_name: t.name(),
-                            }))
-                        }
-                    })
+            let _node_guard = ctx.start_node(t_idx);
+            let output_count = t.outputs().len();
+            // Only results of functions are worth persisting
+            let persisted = match t.algorithm() {
+                Algorithm::Function { .. } => cache.disk_cache().and_then(|disk| {
+                    self.content_hash(t_idx, disk.codec())
+                        .map(|key| (disk, key))
+                }),
+                _ => None,
+            };
+            if let Some((disk, key)) = &persisted {
+                if let Some(values) = disk.load(*key, output_count) {
+                    cache.count_disk_hit(t_idx);
+                    return Computed::Done(
+                        values
+                            .into_iter()
+                            .map(|value| Ok(Arc::new(value)))
+                            .collect(),
+                    );
                 }
-            });
+            }
 
-            let output_count = t.outputs().len();
-            let mut op = t.start();
-            for result in &results {
-                match result {
-                    Ok(ok) => {
-                        if let Err(e) = op.feed(&**ok) {
-                            let runtime_error = ComputeError::ArgumentError {
-                                cause: e,
-                                t_idx,
-                                t_name: t.name(),
-                            };
-                            return vec![Err(Arc::new(runtime_error)); output_count];
-                        }
-                    }
-                    Err(e) => {
-                        let error_stack = ComputeError::ErrorStack {
-                            cause: e.clone(),
-                            t_idx,
-                            t_name: t.name(),
-                        };
-                        return vec![Err(Arc::new(error_stack)); output_count];
+            let start = Instant::now();
+            let out = self.compute_transform(t_idx, meta, &cache, ctx);
+            if let (Some((disk, key)), Computed::Done(out)) = (persisted, &out) {
+                if start.elapsed() >= disk.min_cost() {
+                    let values: Option<Vec<&T>> = out
+                        .iter()
+                        .map(|value| value.as_ref().ok().map(|value| &**value))
+                        .collect();
+                    if let Some(values) = values {
+                        // Failing to persist a result is not an error
+                        let _ = disk.store(key, values);
                     }
                 }
             }
-            let mut out = Vec::with_capacity(output_count);
-            for output in op.call() {
-                out.push(output.map(Arc::new).map_err(|e| {
-                    Arc::new(match e {
-                        CallError::FunctionError(e) => ComputeError::RuntimeError {
-                            cause: e,
-                            t_idx,
-                            t_name: t.name(),
-                        },
-                        CallError::MacroEvalError(e) => ComputeError::ErrorStack {
-                            cause: e,
-                            t_idx,
-                            t_name: t.name(),
-                        },
-                    })
-                }));
-            }
             out
         }) {
             let timed = Timed::map(result, |mut result| result.remove(index));
@@ -273,6 +279,126 @@ where
         }
     }
 
+    /// Compute the results of the transform `t_idx`, computing its
+    /// dependencies first.
+    fn compute_transform(
+        &self,
+        t_idx: TransformIdx,
+        meta: &MetaTransform<'static, T, E>,
+        cache: &CacheRef<T, ComputeError<E>>,
+        ctx: &ComputeContext,
+    ) -> Computed<Vec<Result<Arc<T>, Arc<ComputeError<E>>>>> {
+        let t = meta.transform();
+        let deps = self
+            .outputs_attached_to_transform(t_idx)
+            .expect("Tranform not found!");
+
+        // Inputs that are not attached to any output are resolved right away,
+        // the others are computed concurrently on the rayon pool.
+        let mut results = Vec::with_capacity(deps.len());
+        let mut parents = vec![];
+        for (i, (parent_output, default)) in deps.into_iter().zip(meta.defaults()).enumerate() {
+            results.push(if let Some(output) = parent_output {
+                parents.push(output);
+                None
+            } else if let Some(default) = default {
+                Some(Ok(Arc::new(default.clone())))
+            } else {
+                Some(Err(Arc::new(ComputeError::MissingDependency {
+                    input: Input::new(t_idx, i),
+                    t_name: t.name(),
+                })))
+            });
+        }
+        let mut parent_results: Vec<_> = parents.iter().map(|_| None).collect();
+        let spawner = thread::current().id();
+        rayon::scope(|s| {
+            let mut jobs = parents.iter().zip(parent_results.iter_mut());
+            // The last dependency is computed on the current thread, so that
+            // a chain of transforms does not hop from thread to thread.
+            let last = jobs.next_back();
+            for (output, result) in jobs {
+                let cache_clone = cache.clone();
+                s.spawn(move |_| {
+                    // The spawning thread waits for this job at the end of
+                    // the scope, which matters if the job was stolen
+                    let _stolen = cache.stolen_job(spawner);
+                    *result = Some(Timed::take_from_result(self._compute(
+                        *output,
+                        cache_clone,
+                        ctx,
+                    )));
+                });
+            }
+            if let Some((output, result)) = last {
+                *result = Some(Timed::take_from_result(self._compute(
+                    *output,
+                    cache.clone(),
+                    ctx,
+                )));
+            }
+        });
+        let mut parent_results = parent_results.into_iter();
+        let results: Vec<_> = results
+            .into_iter()
+            .map(|result| result.or_else(|| parent_results.next().unwrap()).unwrap())
+            .collect();
+
+        let output_count = t.outputs().len();
+        let cancelled = || vec![Err(Arc::new(ComputeError::Cancelled)); output_count];
+        if ctx.is_cancelled() {
+            return Computed::Interrupted(cancelled());
+        }
+        let mut op = t.start();
+        for result in &results {
+            match result {
+                Ok(ok) => {
+                    if let Err(e) = op.feed(&**ok) {
+                        let runtime_error = ComputeError::ArgumentError {
+                            cause: e,
+                            t_idx,
+                            t_name: t.name(),
+                        };
+                        return Computed::Done(vec![Err(Arc::new(runtime_error)); output_count]);
+                    }
+                }
+                Err(e) => {
+                    let error_stack = ComputeError::ErrorStack {
+                        cause: e.clone(),
+                        t_idx,
+                        t_name: t.name(),
+                    };
+                    return Computed::Done(vec![Err(Arc::new(error_stack)); output_count]);
+                }
+            }
+        }
+        let start = Instant::now();
+        let outputs = progress::run_in_context(ctx, t_idx, || op.call());
+        // The results of a cancelled transform may be incomplete
+        if ctx.is_cancelled() {
+            return Computed::Interrupted(cancelled());
+        }
+        cache.record_time(t_idx, start.elapsed());
+        let mut out = Vec::with_capacity(output_count);
+        for output in outputs {
+            out.push(output.map(Arc::new).map_err(|e| {
+                Arc::new(match e {
+                    CallError::FunctionError(e) => ComputeError::RuntimeError {
+                        cause: e,
+                        t_idx,
+                        t_name: t.name(),
+                    },
+                    CallError::MacroEvalError(e) => ComputeError::ErrorStack {
+                        cause: e,
+                        t_idx,
+                        t_name: t.name(),
+                    },
+                })
+            }));
+        }
+        Computed::Done(out)
+    }
+
     /// Update default input values with the current value in the cache
     pub fn update_defaults_from_cache(&mut self, cache: &Cache<T, ComputeError<E>>) {
         // Iterate over inputs with a default value AND connected to an input
@@ -302,6 +428,19 @@ where
     }
 }
 
+impl<'t, T, E> DST<'t, T, E> {
+    /// Get the profiles of the transforms of this DST recorded by `cache`,
+    /// sorted by transform index.
+    ///
+    /// Transforms that were never requested are omitted.
+    pub fn profile<CE>(&self, cache: &Cache<T, CE>) -> Vec<(TransformIdx, NodeProfile)> {
+        self.transforms
+            .keys()
+            .filter_map(|t_idx| cache.profile(t_idx).map(|profile| (*t_idx, profile)))
+            .collect()
+    }
+}
+
 impl<'t, T, E> DST<'t, T, E>
 where
     T: Clone + VariantName + ConvertibleVariants,
@@ -330,6 +469,10 @@ where
         if let Some(cached_result) = cache.get(&output) {
             return (*cached_result).clone();
         }
+        // Macros are computed synchronously in the context of the macro node
+        if progress::is_cancelled() {
+            return Err(Arc::new(ComputeError::Cancelled));
+        }
 
         let meta = if let Some(meta) = self.transforms.get(&output.t_idx) {
             meta
@@ -0,0 +1,289 @@
+//! Grouping nodes of a [`DST`] into a macro, and expanding macros inline.
+use std::collections::{BTreeMap, BTreeSet};
+
+use uuid::Uuid;
+
+use super::super::ConvertibleVariants;
+use crate::dst::node::NodeId;
+use crate::dst::{DSTError, Input, InputSlot, Output, TransformIdx, DST};
+use crate::macros::MacroManager;
+use crate::transform::{Algorithm, Transform};
+use variant_name::VariantName;
+
+impl<'t, T, E> DST<'t, T, E>
+where
+    T: Clone + VariantName + ConvertibleVariants,
+{
+    /// Move the given transforms into a new macro registered in `macros`,
+    /// and replace them by a single node using this macro. Set `macro_id` if
+    /// `self` is the [`DST`] of a macro.
+    ///
+    /// Links coming from the rest of the graph become inputs of the macro,
+    /// while outputs used by the rest of the graph become outputs of the
+    /// macro. Return the identifier of the new macro node.
+    ///
+    /// Return an error if no transform is given, or if the transforms depend
+    /// on each other through nodes that are not grouped, as grouping them
+    /// would create a cycle. The graph and `macros` are left untouched on
+    /// error.
+    ///
+    /// `self` must not be the [`DST`] of a macro currently locked for
+    /// writing, as all macros are read to name the new one.
+    pub fn group_into_macro(
+        &mut self,
+        nodes: &[TransformIdx],
+        macros: &mut MacroManager<'t, T, E>,
+        macro_id: Option<Uuid>,
+    ) -> Result<TransformIdx, DSTError> {
+        let selection: BTreeSet<_> = nodes
+            .iter()
+            .filter(|t_idx| self.transforms.contains_key(t_idx))
+            .cloned()
+            .collect();
+        if selection.is_empty() {
+            return Err(DSTError::InvalidInput(
+                "There is no node to group into a macro!".to_owned(),
+            ));
+        }
+        if let Some(t_idx) = self.find_path_around(&selection) {
+            return Err(DSTError::Cycle(format!(
+                "Selected nodes depend on each other through #{}, which is not selected!",
+                t_idx.id()
+            )));
+        }
+
+        let mut incoming = vec![];
+        let mut outgoing = vec![];
+        for (output, input) in self.edges_iter() {
+            match (
+                selection.contains(&output.t_idx),
+                selection.contains(&input.t_idx),
+            ) {
+                (false, true) => incoming.push((*output, *input)),
+                (true, false) => outgoing.push((*output, *input)),
+                _ => {}
+            }
+        }
+        let output_nodes: Vec<_> = self
+            .outputs
+            .iter()
+            .filter_map(|(output_id, (some_output, name))| match some_output {
+                Some(output) if selection.contains(&output.t_idx) => {
+                    Some((*output_id, *output, name.clone()))
+                }
+                _ => None,
+            })
+            .collect();
+        // Outputs used outside of the selection, in order
+        let used_outputs: BTreeSet<_> = outgoing
+            .iter()
+            .map(|(output, _)| *output)
+            .chain(output_nodes.iter().map(|(_, output, _)| *output))
+            .collect();
+
+        let node_ids: Vec<_> = selection.iter().cloned().map(NodeId::Transform).collect();
+        let sub_graph = self.sub_graph(&node_ids);
+
+        // The macro is only registered once the graph is rewired
+        let handle = macros.new_macro();
+        let (new_ids, macro_output_ids) = {
+            let mut lock = handle.write();
+            let dst = lock.dst_mut();
+            let new_ids = dst.merge(sub_graph, Some(handle.id()));
+            let mut macro_output_ids = BTreeMap::new();
+            for output in &used_outputs {
+                let new_output = Output {
+                    t_idx: new_t_idx(&new_ids, output.t_idx),
+                    ..*output
+                };
+                let output_id = dst.attach_output(new_output)?;
+                macro_output_ids.insert(*output, output_id);
+            }
+            (new_ids, macro_output_ids)
+        };
+        let (input_slots, macro_outputs) = {
+            let lock = handle.read();
+            let macro_outputs: Vec<_> = lock.dst().outputs.keys().cloned().collect();
+            (lock.input_slots(), macro_outputs)
+        };
+        let macro_input_index = |input: &Input| {
+            let new_input = InputSlot::Transform(Input {
+                t_idx: new_t_idx(&new_ids, input.t_idx),
+                ..*input
+            });
+            input_slots.iter().position(|slot| *slot == new_input)
+        };
+        let macro_output = |t_idx: TransformIdx, output: &Output| {
+            let output_id = macro_output_ids[output];
+            let index = macro_outputs.iter().position(|id| *id == output_id);
+            Output::new(t_idx, index.unwrap())
+        };
+
+        // Rewire a copy of the graph, so that it is left untouched on error
+        let mut dst = self.clone();
+        for t_idx in &selection {
+            dst.remove_transform(*t_idx);
+        }
+        let t_idx = dst.add_owned_transform(Transform::from_macro(handle.clone()), macro_id);
+        for (output, input) in &incoming {
+            if let Some(index) = macro_input_index(input) {
+                dst.connect(*output, Input::new(t_idx, index))?;
+            }
+        }
+        for (output, input) in &outgoing {
+            dst.connect(macro_output(t_idx, output), *input)?;
+        }
+        for (output_id, output, name) in output_nodes {
+            dst.update_output(output_id, macro_output(t_idx, &output), name);
+        }
+        *self = dst;
+        macros.register_macro(handle);
+        Ok(t_idx)
+    }
+
+    /// Replace the macro node `t_idx` by the content of its macro. Set
+    /// `macro_id` if `self` is the [`DST`] of a macro.
+    ///
+    /// Links to the inputs and outputs of the macro node are moved to the
+    /// nodes inside the macro, and default values set on the macro node are
+    /// copied. Return the identifiers of the inserted transforms.
+    ///
+    /// Return an error if `t_idx` is not a macro node. The graph is left
+    /// untouched on error.
+    pub fn expand_macro(
+        &mut self,
+        t_idx: TransformIdx,
+        macro_id: Option<Uuid>,
+    ) -> Result<Vec<TransformIdx>, DSTError> {
+        let handle = match self.get_transform(t_idx).map(Transform::algorithm) {
+            Some(Algorithm::Macro { handle }) => handle.clone(),
+            _ => {
+                return Err(DSTError::InvalidInput(format!(
+                    "Node #{} is not a macro node!",
+                    t_idx.id()
+                )))
+            }
+        };
+        let (inner, input_slots) = {
+            let lock = handle.read();
+            (lock.dst().clone(), lock.input_slots())
+        };
+        let incoming = self.outputs_attached_to_transform(t_idx).unwrap();
+        let defaults = self.get_default_inputs(t_idx).unwrap().into_owned();
+        let macro_outputs: Vec<_> = inner
+            .outputs
+            .iter()
+            .map(|(output_id, (some_output, _))| (*output_id, *some_output))
+            .collect();
+        let mut consumers = vec![];
+        for (index, _) in macro_outputs.iter().enumerate() {
+            let output = Output::new(t_idx, index);
+            let inputs: Vec<_> = self
+                .inputs_attached_to(&output)
+                .map(|inputs| inputs.cloned().collect())
+                .unwrap_or_default();
+            let output_nodes: Vec<_> = self
+                .outputs
+                .iter()
+                .filter(|(_, (some_output, _))| *some_output == Some(output))
+                .map(|(output_id, (_, name))| (*output_id, name.clone()))
+                .collect();
+            consumers.push((inputs, output_nodes));
+        }
+
+        // Rewire a copy of the graph, so that it is left untouched on error
+        let mut dst = self.clone();
+        let new_ids = dst.merge(inner, macro_id);
+        let new_output = |output: Output| Output {
+            t_idx: new_t_idx(&new_ids, output.t_idx),
+            ..output
+        };
+        // Output nodes of the macro are not needed anymore
+        for (output_id, _) in &macro_outputs {
+            dst.remove_node(&new_ids[&NodeId::Output(*output_id)]);
+        }
+        dst.remove_transform(t_idx);
+
+        for (index, slot) in input_slots.iter().enumerate() {
+            if let InputSlot::Transform(input) = slot {
+                let input = Input {
+                    t_idx: new_t_idx(&new_ids, input.t_idx),
+                    ..*input
+                };
+                if let Some(Some(output)) = incoming.get(index) {
+                    dst.connect(*output, input)?;
+                } else if let Some(Some(default)) = defaults.get(index) {
+                    if let Some(mut input_defaults) = dst.get_default_inputs_mut(input.t_idx) {
+                        input_defaults.write(input.index(), default.clone());
+                    }
+                }
+            }
+        }
+        for ((output_id, some_output), (inputs, output_nodes)) in
+            macro_outputs.into_iter().zip(consumers)
+        {
+            let source = match some_output {
+                Some(output) => Some(new_output(output)),
+                // The output node is directly an input of the macro
+                None => input_slots
+                    .iter()
+                    .position(|slot| *slot == InputSlot::Output(output_id))
+                    .and_then(|index| incoming.get(index).cloned().flatten()),
+            };
+            if let Some(source) = source {
+                for input in inputs {
+                    dst.connect(source, input)?;
+                }
+                for (output_id, name) in output_nodes {
+                    dst.update_output(output_id, source, name);
+                }
+            }
+        }
+
+        *self = dst;
+        Ok(new_ids
+            .values()
+            .filter_map(|node_id| match node_id {
+                NodeId::Transform(t_idx) => Some(*t_idx),
+                NodeId::Output(_) => None,
+            })
+            .collect())
+    }
+
+    /// Find a transform outside of `selection` that depends on a transform of
+    /// `selection` and that a transform of `selection` depends on.
+    fn find_path_around(&self, selection: &BTreeSet<TransformIdx>) -> Option<TransformIdx> {
+        let mut children: BTreeMap<TransformIdx, Vec<TransformIdx>> = BTreeMap::new();
+        for (output, input) in self.edges_iter() {
+            children.entry(output.t_idx).or_default().push(input.t_idx);
+        }
+        // Walk the transforms outside of the selection that depend on it,
+        // remembering from which of them the walk started
+        let mut visited = BTreeSet::new();
+        let mut stack: Vec<_> = selection
+            .iter()
+            .flat_map(|t_idx| children.get(t_idx).into_iter().flatten())
+            .filter(|t_idx| !selection.contains(t_idx))
+            .map(|t_idx| (*t_idx, *t_idx))
+            .collect();
+        while let Some((t_idx, start)) = stack.pop() {
+            if !visited.insert(t_idx) {
+                continue;
+            }
+            for child in children.get(&t_idx).into_iter().flatten() {
+                if selection.contains(child) {
+                    return Some(start);
+                }
+                stack.push((*child, start));
+            }
+        }
+        None
+    }
+}
+
+fn new_t_idx(new_ids: &BTreeMap<NodeId, NodeId>, t_idx: TransformIdx) -> TransformIdx {
+    match new_ids[&NodeId::Transform(t_idx)] {
+        NodeId::Transform(t_idx) => t_idx,
+        NodeId::Output(_) => unreachable!("Transforms are mapped to transforms"),
+    }
+}
@@ -0,0 +1,79 @@
+use std::collections::HashMap;
+
+use crate::disk_cache::{Codec, StableHasher};
+use crate::dst::{TransformIdx, DST};
+use crate::transform::Algorithm;
+use variant_name::VariantName;
+
+impl<'t, T, E> DST<'t, T, E>
+where
+    T: Clone + VariantName,
+{
+    /// Compute a hash identifying the results of the transform `t_idx`.
+    ///
+    /// The hash is stable across runs. It depends on the id and version of
+    /// the transform, on its default input values and on the whole sub-graph
+    /// upstream of it. Values are identified with [`Codec::fingerprint`].
+    ///
+    /// Return `None` if the results cannot be identified, i.e. if the
+    /// transform or one of its dependencies is a macro or holds a value
+    /// without fingerprint.
+    pub fn content_hash(&self, t_idx: TransformIdx, codec: &dyn Codec<T>) -> Option<u64> {
+        self._content_hash(t_idx, codec, &mut HashMap::new())
+    }
+
+    fn _content_hash(
+        &self,
+        t_idx: TransformIdx,
+        codec: &dyn Codec<T>,
+        memo: &mut HashMap<TransformIdx, Option<u64>>,
+    ) -> Option<u64> {
+        if let Some(hash) = memo.get(&t_idx) {
+            return *hash;
+        }
+        let hash = self.compute_content_hash(t_idx, codec, memo);
+        memo.insert(t_idx, hash);
+        hash
+    }
+
+    fn compute_content_hash(
+        &self,
+        t_idx: TransformIdx,
+        codec: &dyn Codec<T>,
+        memo: &mut HashMap<TransformIdx, Option<u64>>,
+    ) -> Option<u64> {
+        let meta = self.transforms.get(&t_idx)?;
+        let mut hasher = StableHasher::new();
+        match meta.transform().algorithm() {
+            Algorithm::Function { id, version, .. } => {
+                hasher.write_bytes(b"function");
+                hasher.write_bytes(id.name().as_bytes());
+                hasher.write(&[version.major, version.minor, version.patch]);
+            }
+            Algorithm::Constant(t) => {
+                hasher.write_bytes(b"constant");
+                hasher.write_bytes(t.variant_name().as_bytes());
+                hasher.write_bytes(&codec.fingerprint(t)?);
+            }
+            // The content of a macro cannot be identified across runs
+            Algorithm::Macro { .. } => return None,
+        }
+
+        let deps = self.outputs_attached_to_transform(t_idx)?;
+        let defaults = meta.defaults();
+        for (i, parent_output) in deps.into_iter().enumerate() {
+            if let Some(output) = parent_output {
+                hasher.write_bytes(b"output");
+                hasher.write_u64(self._content_hash(output.t_idx, codec, memo)?);
+                hasher.write_u64(output.index() as u64);
+            } else if let Some(Some(default)) = defaults.get(i) {
+                hasher.write_bytes(b"default");
+                hasher.write_bytes(default.variant_name().as_bytes());
+                hasher.write_bytes(&codec.fingerprint(default)?);
+            } else {
+                hasher.write_bytes(b"missing");
+            }
+        }
+        Some(hasher.finish())
+    }
+}
@@ -235,14 +235,14 @@ where
         output_ids.chain(inputs)
     }
 
-    fn unattached_output_ids(&self) -> impl Iterator<Item = OutputId> + '_ {
+    pub(crate) fn unattached_output_ids(&self) -> impl Iterator<Item = OutputId> + '_ {
         self.outputs
             .iter()
             .filter(|(_, (some_output, _))| some_output.is_none())
             .map(|(output_id, _)| *output_id)
     }
 
-    fn unattached_inputs(&self) -> Vec<Input> {
+    pub(crate) fn unattached_inputs(&self) -> Vec<Input> {
         self.transform_inputs()
             .filter(|input| {
                 for input_list in self.edges.values() {
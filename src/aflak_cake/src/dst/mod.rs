@@ -11,10 +11,14 @@ use variant_name::VariantName;
 
 mod build;
 pub mod compute;
+mod group;
+mod hash;
 mod iterators;
 mod node;
+mod validate;
 pub use self::iterators::{Dependency, LinkIter, NodeIter};
 pub use self::node::{Node, NodeId};
+pub use self::validate::{Diagnostic, Severity};
 use uuid::Uuid;
 
 /// Dynamic Syntax Tree
@@ -275,6 +279,15 @@ impl fmt::Display for Input {
     }
 }
 
+impl fmt::Display for InputSlot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InputSlot::Transform(input) => write!(f, "{}", input),
+            InputSlot::Output(output_id) => write!(f, "output node #{}", output_id.id()),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 struct InputList {
     /// List of all inputs to which the data is fed
@@ -348,7 +361,7 @@ impl From<InputIdx> for usize {
 }
 
 impl TransformIdx {
-    fn incr(self) -> Self {
+    pub(crate) fn incr(self) -> Self {
         TransformIdx(self.0, self.1 + 1)
     }
     pub fn macro_id(self) -> Option<Uuid> {
@@ -363,7 +376,7 @@ impl TransformIdx {
 }
 
 impl OutputId {
-    fn incr(self) -> Self {
+    pub(crate) fn incr(self) -> Self {
         OutputId(self.0 + 1)
     }
     pub fn id(self) -> usize {
@@ -0,0 +1,301 @@
+//! Static validation of a [`DST`].
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use uuid::Uuid;
+
+use super::super::ConvertibleVariants;
+use crate::dst::node::NodeId;
+use crate::dst::{Input, InputSlot, Output, OutputId, TransformIdx, DST};
+use crate::macros::MacroManager;
+use crate::transform::{Algorithm, TypeId};
+use variant_name::VariantName;
+
+/// Severity of a [`Diagnostic`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Severity {
+    /// The graph can be computed, but is probably not what the user wants.
+    Warning,
+    /// Computing the graph fails.
+    Error,
+}
+
+/// A problem found in a [`DST`] by [`DST::validate`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Diagnostic {
+    /// `input` is not connected and has no default value.
+    MissingInput {
+        input: Input,
+        t_name: Cow<'static, str>,
+    },
+    /// The type of `output` cannot be converted into the type of `input`
+    /// it is connected to.
+    IncompatibleTypes {
+        output: Output,
+        input: Input,
+        output_type: TypeId,
+        input_type: TypeId,
+    },
+    /// Output node that is not attached to any transform.
+    DanglingOutput(OutputId),
+    /// Node using a macro that is not registered in the macro manager.
+    UnknownMacro {
+        t_idx: TransformIdx,
+        macro_id: Uuid,
+        name: String,
+    },
+    /// Node using a macro that contains itself.
+    RecursiveMacro { t_idx: TransformIdx, name: String },
+    /// Transforms that depend on each other, in the order of their
+    /// connections.
+    Cycle(Vec<TransformIdx>),
+    /// Transform whose results are not used by any output node.
+    UnusedNode(TransformIdx),
+}
+
+impl Diagnostic {
+    pub fn severity(&self) -> Severity {
+        match self {
+            Diagnostic::DanglingOutput(_) | Diagnostic::UnusedNode(_) => Severity::Warning,
+            _ => Severity::Error,
+        }
+    }
+
+    /// Nodes concerned by the diagnostic.
+    pub fn nodes(&self) -> Vec<NodeId> {
+        match self {
+            Diagnostic::MissingInput { input, .. } => vec![NodeId::Transform(input.t_idx)],
+            Diagnostic::IncompatibleTypes { output, input, .. } => vec![
+                NodeId::Transform(output.t_idx),
+                NodeId::Transform(input.t_idx),
+            ],
+            Diagnostic::DanglingOutput(output_id) => vec![NodeId::Output(*output_id)],
+            Diagnostic::UnknownMacro { t_idx, .. }
+            | Diagnostic::RecursiveMacro { t_idx, .. }
+            | Diagnostic::UnusedNode(t_idx) => vec![NodeId::Transform(*t_idx)],
+            Diagnostic::Cycle(t_indices) => t_indices
+                .iter()
+                .map(|t_idx| NodeId::Transform(*t_idx))
+                .collect(),
+        }
+    }
+
+    /// Input slot concerned by the diagnostic, if any.
+    pub fn input_slot(&self) -> Option<InputSlot> {
+        match self {
+            Diagnostic::MissingInput { input, .. }
+            | Diagnostic::IncompatibleTypes { input, .. } => Some(InputSlot::Transform(*input)),
+            Diagnostic::DanglingOutput(output_id) => Some(InputSlot::Output(*output_id)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::Diagnostic::*;
+
+        match self {
+            MissingInput { input, t_name } => write!(
+                f,
+                "No output attached to {} in '{}', and no default value",
+                input, t_name
+            ),
+            IncompatibleTypes {
+                output,
+                input,
+                output_type,
+                input_type,
+            } => write!(
+                f,
+                "{} of type '{}' cannot be converted to type '{}' of {}",
+                output,
+                output_type.name(),
+                input_type.name(),
+                input
+            ),
+            DanglingOutput(output_id) => {
+                write!(f, "Output #{} is not attached to a program", output_id.id())
+            }
+            UnknownMacro { t_idx, name, .. } => {
+                write!(f, "Node #{} uses unknown macro '{}'", t_idx.id(), name)
+            }
+            RecursiveMacro { t_idx, name } => write!(
+                f,
+                "Node #{} uses macro '{}', which contains itself",
+                t_idx.id(),
+                name
+            ),
+            Cycle(t_indices) => {
+                write!(f, "Cyclic dependency between nodes")?;
+                for t_idx in t_indices {
+                    write!(f, " #{}", t_idx.id())?;
+                }
+                Ok(())
+            }
+            UnusedNode(t_idx) => write!(
+                f,
+                "Results of node #{} are not used by any output",
+                t_idx.id()
+            ),
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Visit {
+    InProgress,
+    Done,
+}
+
+impl<'t, T, E> DST<'t, T, E>
+where
+    T: Clone + VariantName + ConvertibleVariants,
+{
+    /// Check the graph for problems that would make its computation fail or
+    /// that are likely mistakes, without computing anything.
+    ///
+    /// Macros are not checked against a macro manager. Use
+    /// [`DST::validate_with_macros`] to report unknown macros.
+    pub fn validate(&self) -> Vec<Diagnostic> {
+        self._validate(None)
+    }
+
+    /// Same as [`DST::validate`], also reporting nodes using macros that are
+    /// not registered in `macros`.
+    pub fn validate_with_macros(&self, macros: &MacroManager<'t, T, E>) -> Vec<Diagnostic> {
+        self._validate(Some(macros))
+    }
+
+    fn _validate(&self, macros: Option<&MacroManager<'t, T, E>>) -> Vec<Diagnostic> {
+        let mut diagnostics = self.find_cycles();
+
+        for (t_idx, meta) in &self.transforms {
+            if let Algorithm::Macro { handle } = meta.transform().algorithm() {
+                let macro_id = handle.id();
+                if let Some(macros) = macros {
+                    if macros.get_macro(macro_id).is_none() {
+                        diagnostics.push(Diagnostic::UnknownMacro {
+                            t_idx: *t_idx,
+                            macro_id,
+                            name: handle.name(),
+                        });
+                    }
+                }
+                if handle.is_recursive() {
+                    diagnostics.push(Diagnostic::RecursiveMacro {
+                        t_idx: *t_idx,
+                        name: handle.name(),
+                    });
+                }
+            }
+        }
+
+        for input in self.unattached_inputs() {
+            let meta = &self.transforms[&input.t_idx];
+            if meta
+                .defaults()
+                .get(input.index())
+                .map_or(true, Option::is_none)
+            {
+                diagnostics.push(Diagnostic::MissingInput {
+                    input,
+                    t_name: meta.transform().name(),
+                });
+            }
+        }
+
+        for (output, input) in self.edges_iter() {
+            if let (Some(output_t), Some(input_t)) = (
+                self.get_transform(output.t_idx),
+                self.get_transform(input.t_idx),
+            ) {
+                let output_type = output_t.nth_output_type(output.index());
+                let input_type = input_t.nth_input_type(input.index());
+                if !T::convertible(output_type.name(), input_type.name()) {
+                    diagnostics.push(Diagnostic::IncompatibleTypes {
+                        output: *output,
+                        input: *input,
+                        output_type,
+                        input_type,
+                    });
+                }
+            }
+        }
+
+        diagnostics.extend(self.unattached_output_ids().map(Diagnostic::DanglingOutput));
+
+        let used = self.used_transforms();
+        diagnostics.extend(
+            self.transforms
+                .keys()
+                .filter(|t_idx| !used.contains(t_idx))
+                .map(|t_idx| Diagnostic::UnusedNode(*t_idx)),
+        );
+
+        diagnostics
+    }
+
+    /// Transforms whose results are needed by at least an output node.
+    fn used_transforms(&self) -> BTreeSet<TransformIdx> {
+        let mut used = BTreeSet::new();
+        let mut stack: Vec<_> = self
+            .outputs
+            .values()
+            .filter_map(|(some_output, _)| some_output.map(|output| output.t_idx))
+            .collect();
+        while let Some(t_idx) = stack.pop() {
+            if !used.insert(t_idx) {
+                continue;
+            }
+            if let Some(parents) = self.outputs_attached_to_transform(t_idx) {
+                stack.extend(parents.into_iter().flatten().map(|output| output.t_idx));
+            }
+        }
+        used
+    }
+
+    /// Find cyclic dependencies between transforms.
+    ///
+    /// [`DST::connect`] refuses to create cycles, but they may still be
+    /// found in graphs built by other means, e.g. imported from a file.
+    fn find_cycles(&self) -> Vec<Diagnostic> {
+        let mut children: BTreeMap<TransformIdx, Vec<TransformIdx>> = BTreeMap::new();
+        for (output, input) in self.edges_iter() {
+            children.entry(output.t_idx).or_default().push(input.t_idx);
+        }
+
+        fn visit(
+            t_idx: TransformIdx,
+            children: &BTreeMap<TransformIdx, Vec<TransformIdx>>,
+            visits: &mut BTreeMap<TransformIdx, Visit>,
+            path: &mut Vec<TransformIdx>,
+            cycles: &mut Vec<Diagnostic>,
+        ) {
+            visits.insert(t_idx, Visit::InProgress);
+            path.push(t_idx);
+            for child in children.get(&t_idx).into_iter().flatten() {
+                match visits.get(child) {
+                    Some(Visit::InProgress) => {
+                        let start = path.iter().position(|idx| idx == child).unwrap();
+                        cycles.push(Diagnostic::Cycle(path[start..].to_vec()));
+                    }
+                    Some(Visit::Done) => {}
+                    None => visit(*child, children, visits, path, cycles),
+                }
+            }
+            path.pop();
+            visits.insert(t_idx, Visit::Done);
+        }
+
+        let mut visits = BTreeMap::new();
+        let mut cycles = vec![];
+        for t_idx in self.transforms.keys() {
+            if !visits.contains_key(t_idx) {
+                visit(*t_idx, &children, &mut visits, &mut vec![], &mut cycles);
+            }
+        }
+        cycles
+    }
+}
@@ -1,5 +1,6 @@
 //! Structures for serialization and deserialization of node graph.
 use std::borrow::Cow;
+use std::collections::HashMap;
 use std::error;
 use std::fmt;
 
@@ -14,10 +15,24 @@ use crate::dst::{DSTError, Input, Output, OutputId, TransformIdx, DST};
 use crate::macros::MacroManager;
 use crate::transform::{Algorithm, Transform, Version};
 
+mod diff;
+mod migration;
+
+pub(crate) use self::diff::merge3;
+pub use self::diff::{Change, Conflict, DSTDiff, DSTMerge, NodeDiff, OutputDiff};
+use self::migration::{MigratedInput, MigrationPlan};
+pub use self::migration::{Migration, MigrationRegistry};
+
 /// Trait that defines a function to get a [`Transform`] by its name.
 pub trait NamedAlgorithms<E>: Sized {
     /// Get a transform by name.
     fn get_transform(s: &str) -> Option<&'static Transform<'static, Self, E>>;
+
+    /// Get the migrations used to import nodes saved with older major
+    /// versions of the transforms. Default to no migration.
+    fn migrations() -> Option<&'static MigrationRegistry<Self>> {
+        None
+    }
 }
 
 /// Error type used to represent a failed deserialization into DST.
@@ -40,6 +55,13 @@ pub enum ImportError {
     DuplicateMacroId(Uuid),
     /// Type found does not exist
     UnexpectedType(String),
+    /// A transform was saved with a major version that cannot be migrated to
+    /// the current version.
+    UnsupportedVersion {
+        transform_name: String,
+        saved: Version,
+        current: Version,
+    },
 }
 
 impl fmt::Display for ImportError {
@@ -66,6 +88,25 @@ impl fmt::Display for ImportError {
             ImportError::UnexpectedType(ref type_id) => {
                 write!(f, "Type '{}' does not exist", type_id)
             }
+            ImportError::UnsupportedVersion {
+                ref transform_name,
+                saved,
+                current,
+            } => {
+                if saved.major > current.major {
+                    write!(
+                        f,
+                        "Transform '{}' was saved with version {}, which is newer than the current version {}",
+                        transform_name, saved, current,
+                    )
+                } else {
+                    write!(
+                        f,
+                        "Transform '{}' was saved with version {}, which cannot be migrated to the current version {}",
+                        transform_name, saved, current,
+                    )
+                }
+            }
         }
     }
 }
@@ -85,7 +126,7 @@ pub enum SerialTransform<'t, T: 't> {
 }
 
 #[doc(hidden)]
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum DeserTransform<T> {
     Function(String, u8, u8, u8),
     Constant(T),
@@ -114,6 +155,12 @@ where
     }
 }
 
+/// Transform and the migrations applied to the node that uses it.
+type MigratedTransform<T, E> = (
+    Bow<'static, Transform<'static, T, E>>,
+    MigrationPlan<'static, T>,
+);
+
 impl<T> DeserTransform<T> {
     pub fn from_transform<'t, E>(t: &Transform<'t, T, E>) -> Self
     where
@@ -140,32 +187,78 @@ impl<T> DeserTransform<T> {
         macro_manager: &MacroManager<'static, T, E>,
     ) -> Result<Bow<'static, Transform<'static, T, E>>, ImportError>
     where
-        T: NamedAlgorithms<E>,
+        T: NamedAlgorithms<E> + Clone,
+    {
+        self.into_migrated_transform(macro_manager).map(|(t, _)| t)
+    }
+
+    /// Same as `into_transform`, also returning the migrations applied to
+    /// upgrade a function saved with an older major version.
+    fn into_migrated_transform<E>(
+        self,
+        macro_manager: &MacroManager<'static, T, E>,
+    ) -> Result<MigratedTransform<T, E>, ImportError>
+    where
+        T: NamedAlgorithms<E> + Clone,
     {
         match self {
-            DeserTransform::Function(name, major, _, _) => {
-                if let Some(t) = NamedAlgorithms::get_transform(&name) {
-                    if let Algorithm::Function { version: v, .. } = t.algorithm() {
-                        if v.major != major {
-                            eprintln!(
-                                "Current built-in transform '{}' is of major version {}, but the program was written with the major version {}",
-                                name, v.major, major
-                            );
+            DeserTransform::Function(name, major, minor, patch) => {
+                let saved = Version::new(major, minor, patch);
+                let mut plan = MigrationPlan::new();
+                let mut name = Cow::Owned(name);
+                let mut major = major;
+                loop {
+                    let t = NamedAlgorithms::get_transform(&name);
+                    let current = t.and_then(|t| match t.algorithm() {
+                        Algorithm::Function { version, .. } => Some(*version),
+                        _ => None,
+                    });
+                    if let (Some(t), Some(current)) = (t, current) {
+                        if current.major == major {
+                            return Ok((Bow::Borrowed(t), plan));
+                        } else if current.major < major {
+                            return Err(ImportError::UnsupportedVersion {
+                                transform_name: name.into_owned(),
+                                saved,
+                                current,
+                            });
+                        }
+                    }
+                    let migration = T::migrations().and_then(|m| m.get(&name, major));
+                    match (migration, current) {
+                        (Some(migration), _) => {
+                            plan.push(migration);
+                            name = Cow::Borrowed(migration.to_name());
+                            major = migration.to_major();
+                        }
+                        (None, Some(current)) => {
+                            return Err(ImportError::UnsupportedVersion {
+                                transform_name: name.into_owned(),
+                                saved,
+                                current,
+                            })
+                        }
+                        (None, None) => {
+                            return Err(ImportError::TransformNotFound(format!(
+                                "Transform '{}' not found!",
+                                name
+                            )))
                         }
                     }
-                    Ok(Bow::Borrowed(t))
-                } else {
-                    Err(ImportError::TransformNotFound(format!(
-                        "Transform '{}' not found!",
-                        name
-                    )))
                 }
             }
-            DeserTransform::Constant(c) => Ok(Bow::Owned(Transform::new_constant(c))),
+            DeserTransform::Constant(c) => {
+                Ok((Bow::Owned(Transform::new_constant(c)), MigrationPlan::new()))
+            }
             DeserTransform::Macro(id) => macro_manager
                 .get_macro(id)
-                .map(|handle| Bow::Owned(Transform::from_macro(handle.clone())))
-                .ok_or_else(|| ImportError::MacroNotFound(id)),
+                .map(|handle| {
+                    (
+                        Bow::Owned(Transform::from_macro(handle.clone())),
+                        MigrationPlan::new(),
+                    )
+                })
+                .ok_or(ImportError::MacroNotFound(id)),
         }
     }
 }
@@ -215,7 +308,7 @@ where
 }
 
 /// A representation of a DST for deserialization.
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(bound(deserialize = "T: Deserialize<'de>"))]
 pub struct DeserDST<T> {
     transforms: Vec<(TransformIdx, DeserMetaTransform<T>)>,
@@ -223,7 +316,7 @@ pub struct DeserDST<T> {
     outputs: Vec<(OutputId, Option<Output>, String)>,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(bound(deserialize = "T: Deserialize<'de>"))]
 struct DeserMetaTransform<T> {
     t: DeserTransform<T>,
@@ -258,6 +351,14 @@ impl<T> DeserDST<T> {
                 .collect(),
         }
     }
+
+    /// IDs of the macros used by the nodes of this DST.
+    pub(crate) fn macro_ids(&self) -> impl Iterator<Item = Uuid> + '_ {
+        self.transforms.iter().filter_map(|(_, meta)| match meta.t {
+            DeserTransform::Macro(id) => Some(id),
+            _ => None,
+        })
+    }
 }
 
 impl<T> DeserDST<T>
@@ -273,8 +374,21 @@ where
         T: NamedAlgorithms<E>,
     {
         let mut dst = DST::new();
-        for (t_idx, meta) in self.transforms {
-            let t = meta.t.into_transform(macro_manager)?;
+        // Inputs of the migrated nodes, to re-map their connections
+        let mut migrated_inputs = HashMap::new();
+        for (t_idx, mut meta) in self.transforms {
+            let (t, plan) = meta.t.into_migrated_transform(macro_manager)?;
+            if !plan.is_empty() {
+                let inputs = plan.inputs(meta.input_defaults.len());
+                meta.input_defaults = inputs
+                    .iter()
+                    .map(|input| match input {
+                        MigratedInput::Saved(i) => meta.input_defaults[*i].clone(),
+                        MigratedInput::Added(default) => default.clone(),
+                    })
+                    .collect();
+                migrated_inputs.insert(t_idx, inputs);
+            }
             let orig_defaults = t.defaults();
             let mut input_defaults = Vec::with_capacity(orig_defaults.len());
             let mut orig_defaults_iter = orig_defaults.into_iter();
@@ -309,7 +423,19 @@ where
 
             dst.add_transform_with_idx(t_idx, t, input_defaults);
         }
-        for (output, input) in self.edges {
+        for (output, mut input) in self.edges {
+            if let Some(inputs) = migrated_inputs.get(&input.t_idx) {
+                let new_index = inputs.iter().position(|migrated| match migrated {
+                    MigratedInput::Saved(i) => *i == input.index(),
+                    MigratedInput::Added(_) => false,
+                });
+                if let Some(new_index) = new_index {
+                    input = Input::new(input.t_idx, new_index);
+                } else {
+                    // The input was removed by a migration
+                    continue;
+                }
+            }
             dst.connect(output, input).map_err(|err| {
                 ImportError::ConstructionError(
                     "Data is inconsistent. DST cannot be constructed.",
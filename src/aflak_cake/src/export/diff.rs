@@ -0,0 +1,709 @@
+//! Differences between two exported DSTs, and three-way merge.
+//!
+//! Nodes are matched by their [`TransformIdx`] and output nodes by their
+//! [`OutputId`]. As each input slot is connected to at most one output, links
+//! are compared input slot by input slot.
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+use uuid::Uuid;
+
+use super::{DeserDST, DeserMetaTransform, DeserTransform};
+use crate::dst::{Input, InputSlot, Output, OutputId, TransformIdx};
+
+/// Value before and after a change.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Change<V> {
+    pub before: V,
+    pub after: V,
+}
+
+/// Change of a transform node.
+#[derive(Clone, Debug, PartialEq)]
+pub enum NodeDiff<T> {
+    Added(DeserTransform<T>),
+    Removed(DeserTransform<T>),
+    Changed {
+        /// `None` if the node still uses the same transform
+        transform: Option<Change<DeserTransform<T>>>,
+        /// Changed default values, by input index
+        defaults: BTreeMap<usize, Change<Option<T>>>,
+    },
+}
+
+/// Change of an output node.
+#[derive(Clone, Debug, PartialEq)]
+pub enum OutputDiff {
+    Added(String),
+    Removed(String),
+    Renamed(Change<String>),
+}
+
+/// Differences between two [`DeserDST`]s. Unchanged items are left out.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DSTDiff<T> {
+    pub nodes: BTreeMap<TransformIdx, NodeDiff<T>>,
+    /// Output connected to each input slot whose connection changed
+    pub links: BTreeMap<InputSlot, Change<Option<Output>>>,
+    pub outputs: BTreeMap<OutputId, OutputDiff>,
+}
+
+impl<T> DSTDiff<T> {
+    /// Check if there is no difference.
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty() && self.links.is_empty() && self.outputs.is_empty()
+    }
+}
+
+/// Conflicting changes found by [`DeserDST::merge`]. Our side is kept.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Conflict<T> {
+    /// Both sides changed the transform of the node, or one side removed the
+    /// node and the other changed it. `None` if removed.
+    Transform {
+        t_idx: TransformIdx,
+        ours: Option<DeserTransform<T>>,
+        theirs: Option<DeserTransform<T>>,
+    },
+    /// Both sides changed the default value of an input of the node.
+    Default {
+        t_idx: TransformIdx,
+        index: usize,
+        ours: Option<T>,
+        theirs: Option<T>,
+    },
+    /// Both sides connected the input slot differently, or one side connected
+    /// it to or from a node removed by the other.
+    Link {
+        slot: InputSlot,
+        ours: Option<Output>,
+        theirs: Option<Output>,
+    },
+    /// Both sides renamed the output node differently, or one side removed
+    /// the output node and the other renamed it. `None` if removed.
+    Output {
+        output_id: OutputId,
+        ours: Option<String>,
+        theirs: Option<String>,
+    },
+    /// Both sides renamed the macro differently, or one side removed the
+    /// macro and the other changed it. `None` if removed.
+    Macro {
+        id: Uuid,
+        ours: Option<String>,
+        theirs: Option<String>,
+    },
+}
+
+/// Result of a three-way merge.
+#[derive(Clone, Debug)]
+pub struct DSTMerge<T> {
+    pub dst: DeserDST<T>,
+    pub conflicts: Vec<Conflict<T>>,
+    /// New indices of the nodes added on their side, whose index was used by
+    /// another node added on our side
+    pub renumbered_nodes: BTreeMap<TransformIdx, TransformIdx>,
+    /// New IDs of the output nodes added on their side, whose ID was used by
+    /// another output node added on our side
+    pub renumbered_outputs: BTreeMap<OutputId, OutputId>,
+}
+
+/// Three-way merge of a single value. Return our value as an error if both
+/// sides changed it differently.
+pub(crate) fn merge3<'a, V: PartialEq>(
+    base: &V,
+    ours: &'a V,
+    theirs: &'a V,
+) -> Result<&'a V, &'a V> {
+    if ours == theirs || base == theirs {
+        Ok(ours)
+    } else if base == ours {
+        Ok(theirs)
+    } else {
+        Err(ours)
+    }
+}
+
+impl<T> DeserMetaTransform<T>
+where
+    T: Clone + PartialEq,
+{
+    fn default(&self, index: usize) -> Option<T> {
+        self.input_defaults.get(index).cloned().flatten()
+    }
+
+    fn diff(&self, other: &Self) -> Option<NodeDiff<T>> {
+        let transform = if self.t == other.t {
+            None
+        } else {
+            Some(Change {
+                before: self.t.clone(),
+                after: other.t.clone(),
+            })
+        };
+        let count = self.input_defaults.len().max(other.input_defaults.len());
+        let defaults: BTreeMap<_, _> = (0..count)
+            .filter_map(|index| {
+                let before = self.default(index);
+                let after = other.default(index);
+                if before == after {
+                    None
+                } else {
+                    Some((index, Change { before, after }))
+                }
+            })
+            .collect();
+        if transform.is_none() && defaults.is_empty() {
+            None
+        } else {
+            Some(NodeDiff::Changed {
+                transform,
+                defaults,
+            })
+        }
+    }
+
+    fn merge(
+        t_idx: TransformIdx,
+        base: &Self,
+        ours: &Self,
+        theirs: &Self,
+        conflicts: &mut Vec<Conflict<T>>,
+    ) -> Self {
+        let t = merge3(&base.t, &ours.t, &theirs.t).unwrap_or_else(|t| {
+            conflicts.push(Conflict::Transform {
+                t_idx,
+                ours: Some(ours.t.clone()),
+                theirs: Some(theirs.t.clone()),
+            });
+            t
+        });
+        let count = base
+            .input_defaults
+            .len()
+            .max(ours.input_defaults.len())
+            .max(theirs.input_defaults.len());
+        let mut input_defaults = Vec::with_capacity(count);
+        for index in 0..count {
+            let our_default = ours.default(index);
+            let their_default = theirs.default(index);
+            let default = merge3(&base.default(index), &our_default, &their_default)
+                .unwrap_or_else(|default| {
+                    conflicts.push(Conflict::Default {
+                        t_idx,
+                        index,
+                        ours: our_default.clone(),
+                        theirs: their_default.clone(),
+                    });
+                    default
+                });
+            input_defaults.push(default.clone());
+        }
+        Self {
+            t: t.clone(),
+            input_defaults,
+        }
+    }
+}
+
+impl<T> DeserDST<T> {
+    fn transform_map(&self) -> BTreeMap<TransformIdx, &DeserMetaTransform<T>> {
+        self.transforms
+            .iter()
+            .map(|(t_idx, meta)| (*t_idx, meta))
+            .collect()
+    }
+
+    /// Output connected to each connected input slot.
+    fn link_map(&self) -> BTreeMap<InputSlot, Output> {
+        let edges = self
+            .edges
+            .iter()
+            .map(|(output, input)| (InputSlot::Transform(*input), *output));
+        let outputs = self.outputs.iter().filter_map(|(output_id, output, _)| {
+            output.map(|output| (InputSlot::Output(*output_id), output))
+        });
+        edges.chain(outputs).collect()
+    }
+
+    fn output_names(&self) -> BTreeMap<OutputId, &String> {
+        self.outputs
+            .iter()
+            .map(|(output_id, _, name)| (*output_id, name))
+            .collect()
+    }
+
+    /// Replace the indices of nodes and output nodes as given by the maps.
+    fn renumbered(
+        &self,
+        nodes: &BTreeMap<TransformIdx, TransformIdx>,
+        outputs: &BTreeMap<OutputId, OutputId>,
+    ) -> Self
+    where
+        T: Clone,
+    {
+        let node = |t_idx: TransformIdx| *nodes.get(&t_idx).unwrap_or(&t_idx);
+        let output = |o: Output| Output::new(node(o.t_idx), o.index());
+        Self {
+            transforms: self
+                .transforms
+                .iter()
+                .map(|(t_idx, meta)| (node(*t_idx), meta.clone()))
+                .collect(),
+            edges: self
+                .edges
+                .iter()
+                .map(|(o, input)| (output(*o), Input::new(node(input.t_idx), input.index())))
+                .collect(),
+            outputs: self
+                .outputs
+                .iter()
+                .map(|(output_id, some_output, name)| {
+                    (
+                        *outputs.get(output_id).unwrap_or(output_id),
+                        some_output.map(output),
+                        name.clone(),
+                    )
+                })
+                .collect(),
+        }
+    }
+}
+
+impl<T> DeserDST<T>
+where
+    T: Clone + PartialEq,
+{
+    /// Compute the differences from `self` to `other`.
+    pub fn diff(&self, other: &Self) -> DSTDiff<T> {
+        let before = self.transform_map();
+        let after = other.transform_map();
+        let mut nodes = BTreeMap::new();
+        for t_idx in keys(&before, &after) {
+            let diff = match (before.get(&t_idx), after.get(&t_idx)) {
+                (Some(before), Some(after)) => before.diff(after),
+                (Some(before), None) => Some(NodeDiff::Removed(before.t.clone())),
+                (None, Some(after)) => Some(NodeDiff::Added(after.t.clone())),
+                (None, None) => None,
+            };
+            if let Some(diff) = diff {
+                nodes.insert(t_idx, diff);
+            }
+        }
+
+        let before = self.link_map();
+        let after = other.link_map();
+        let mut links = BTreeMap::new();
+        for slot in keys(&before, &after) {
+            let before = before.get(&slot).cloned();
+            let after = after.get(&slot).cloned();
+            if before != after {
+                links.insert(slot, Change { before, after });
+            }
+        }
+
+        let before = self.output_names();
+        let after = other.output_names();
+        let mut outputs = BTreeMap::new();
+        for output_id in keys(&before, &after) {
+            let diff = match (before.get(&output_id), after.get(&output_id)) {
+                (Some(before), Some(after)) if before != after => {
+                    Some(OutputDiff::Renamed(Change {
+                        before: (*before).clone(),
+                        after: (*after).clone(),
+                    }))
+                }
+                (Some(before), None) => Some(OutputDiff::Removed((*before).clone())),
+                (None, Some(after)) => Some(OutputDiff::Added((*after).clone())),
+                _ => None,
+            };
+            if let Some(diff) = diff {
+                outputs.insert(output_id, diff);
+            }
+        }
+
+        DSTDiff {
+            nodes,
+            links,
+            outputs,
+        }
+    }
+
+    /// Three-way merge of the changes made from `base` on our side and on
+    /// their side.
+    ///
+    /// Changes made on one side only are applied. Conflicting changes are
+    /// reported, keeping our side. Nodes added on both sides with the same
+    /// index are the same node if they have the same transform, default
+    /// values and incoming links. Otherwise the node added on their side is
+    /// renumbered. Output nodes are renumbered likewise if their name or
+    /// connection differ.
+    ///
+    /// The merged DST may still contain cycles or connections between
+    /// incompatible types, which are reported when it is imported.
+    pub fn merge(base: &Self, ours: &Self, theirs: &Self) -> DSTMerge<T> {
+        let (theirs, renumbered_nodes, renumbered_outputs) = theirs.renumber_added(base, ours);
+        let mut conflicts = vec![];
+
+        let base_nodes = base.transform_map();
+        let our_nodes = ours.transform_map();
+        let their_nodes = theirs.transform_map();
+        let mut transforms = vec![];
+        for t_idx in keys(&our_nodes, &their_nodes)
+            .into_iter()
+            .chain(base_nodes.keys().cloned())
+            .collect::<BTreeSet<_>>()
+        {
+            let base = base_nodes.get(&t_idx).cloned();
+            let ours = our_nodes.get(&t_idx).cloned();
+            let theirs = their_nodes.get(&t_idx).cloned();
+            match merge3(&base, &ours, &theirs) {
+                Ok(merged) => {
+                    if let Some(meta) = merged {
+                        transforms.push((t_idx, (*meta).clone()));
+                    }
+                }
+                Err(_) => match (base, ours, theirs) {
+                    (Some(base), Some(ours), Some(theirs)) => {
+                        let meta =
+                            DeserMetaTransform::merge(t_idx, base, ours, theirs, &mut conflicts);
+                        transforms.push((t_idx, meta));
+                    }
+                    (_, ours, theirs) => {
+                        conflicts.push(Conflict::Transform {
+                            t_idx,
+                            ours: ours.map(|meta| meta.t.clone()),
+                            theirs: theirs.map(|meta| meta.t.clone()),
+                        });
+                        if let Some(ours) = ours {
+                            transforms.push((t_idx, ours.clone()));
+                        }
+                    }
+                },
+            }
+        }
+        let merged_nodes: BTreeSet<_> = transforms.iter().map(|(t_idx, _)| *t_idx).collect();
+
+        let base_names = base.output_names();
+        let our_names = ours.output_names();
+        let their_names = theirs.output_names();
+        let mut names = BTreeMap::new();
+        for output_id in keys(&our_names, &their_names)
+            .into_iter()
+            .chain(base_names.keys().cloned())
+            .collect::<BTreeSet<_>>()
+        {
+            let base = base_names.get(&output_id).cloned();
+            let ours = our_names.get(&output_id).cloned();
+            let theirs = their_names.get(&output_id).cloned();
+            let name = merge3(&base, &ours, &theirs).unwrap_or_else(|name| {
+                conflicts.push(Conflict::Output {
+                    output_id,
+                    ours: ours.cloned(),
+                    theirs: theirs.cloned(),
+                });
+                name
+            });
+            if let Some(name) = name {
+                names.insert(output_id, (*name).clone());
+            }
+        }
+
+        let base_links = base.link_map();
+        let our_links = ours.link_map();
+        let their_links = theirs.link_map();
+        let mut links = BTreeMap::new();
+        for slot in keys(&our_links, &their_links)
+            .into_iter()
+            .chain(base_links.keys().cloned())
+            .collect::<BTreeSet<_>>()
+        {
+            let base = base_links.get(&slot).cloned();
+            let ours = our_links.get(&slot).cloned();
+            let theirs = their_links.get(&slot).cloned();
+            let conflict = Conflict::Link { slot, ours, theirs };
+            match merge3(&base, &ours, &theirs) {
+                Ok(Some(output)) => {
+                    let slot_exists = match slot {
+                        InputSlot::Transform(input) => merged_nodes.contains(&input.t_idx),
+                        InputSlot::Output(output_id) => names.contains_key(&output_id),
+                    };
+                    if slot_exists && merged_nodes.contains(&output.t_idx) {
+                        links.insert(slot, *output);
+                    } else {
+                        conflicts.push(conflict);
+                    }
+                }
+                Ok(None) => (),
+                Err(ours) => {
+                    conflicts.push(conflict);
+                    if let Some(output) = ours {
+                        links.insert(slot, *output);
+                    }
+                }
+            }
+        }
+
+        let edges = links
+            .iter()
+            .filter_map(|(slot, output)| match slot {
+                InputSlot::Transform(input) => Some((*output, *input)),
+                InputSlot::Output(_) => None,
+            })
+            .collect();
+        let outputs = names
+            .into_iter()
+            .map(|(output_id, name)| {
+                let output = links.get(&InputSlot::Output(output_id)).cloned();
+                (output_id, output, name)
+            })
+            .collect();
+        DSTMerge {
+            dst: DeserDST {
+                transforms,
+                edges,
+                outputs,
+            },
+            conflicts,
+            renumbered_nodes,
+            renumbered_outputs,
+        }
+    }
+
+    /// Renumber nodes and output nodes added on their side (`self`) whose
+    /// index is also used by different nodes added on our side.
+    ///
+    /// Nodes are the same if they have the same transform, default values
+    /// and incoming links.
+    fn renumber_added(
+        &self,
+        base: &Self,
+        ours: &Self,
+    ) -> (
+        Self,
+        BTreeMap<TransformIdx, TransformIdx>,
+        BTreeMap<OutputId, OutputId>,
+    ) {
+        let base_nodes = base.transform_map();
+        let our_nodes = ours.transform_map();
+        let mut next_t_idx = base
+            .transforms
+            .iter()
+            .chain(&ours.transforms)
+            .chain(&self.transforms)
+            .map(|(t_idx, _)| *t_idx)
+            .max()
+            .map(TransformIdx::incr);
+        let our_links = ours.link_map();
+        let their_links = self.link_map();
+        let incoming = |links: &BTreeMap<InputSlot, Output>, t_idx: TransformIdx| {
+            links
+                .iter()
+                .filter(|(slot, _)| match slot {
+                    InputSlot::Transform(input) => input.t_idx == t_idx,
+                    InputSlot::Output(_) => false,
+                })
+                .map(|(slot, output)| (*slot, *output))
+                .collect::<Vec<_>>()
+        };
+        let mut nodes = BTreeMap::new();
+        for (t_idx, meta) in &self.transforms {
+            if base_nodes.contains_key(t_idx) {
+                continue;
+            }
+            match (our_nodes.get(t_idx), next_t_idx) {
+                (Some(our_meta), Some(new_t_idx))
+                    if *our_meta != meta
+                        || incoming(&our_links, *t_idx) != incoming(&their_links, *t_idx) =>
+                {
+                    nodes.insert(*t_idx, new_t_idx);
+                    next_t_idx = Some(new_t_idx.incr());
+                }
+                _ => (),
+            }
+        }
+        let renumbered = self.renumbered(&nodes, &BTreeMap::new());
+
+        let base_outputs: BTreeSet<_> = base.outputs.iter().map(|(id, ..)| *id).collect();
+        let our_outputs: BTreeMap<_, _> = ours
+            .outputs
+            .iter()
+            .map(|(id, output, name)| (*id, (*output, name)))
+            .collect();
+        let mut next_output_id = base
+            .outputs
+            .iter()
+            .chain(&ours.outputs)
+            .chain(&self.outputs)
+            .map(|(output_id, ..)| *output_id)
+            .max()
+            .map(OutputId::incr);
+        let mut outputs = BTreeMap::new();
+        for (output_id, output, name) in &renumbered.outputs {
+            if base_outputs.contains(output_id) {
+                continue;
+            }
+            match (our_outputs.get(output_id), next_output_id) {
+                (Some(ours), Some(new_output_id)) if *ours != (*output, name) => {
+                    outputs.insert(*output_id, new_output_id);
+                    next_output_id = Some(new_output_id.incr());
+                }
+                _ => (),
+            }
+        }
+        let renumbered = renumbered.renumbered(&BTreeMap::new(), &outputs);
+        (renumbered, nodes, outputs)
+    }
+}
+
+/// Sorted keys of both maps.
+fn keys<K: Copy + Ord, V1, V2>(a: &BTreeMap<K, V1>, b: &BTreeMap<K, V2>) -> BTreeSet<K> {
+    a.keys().chain(b.keys()).cloned().collect()
+}
+
+impl<T: fmt::Debug> fmt::Display for DeserTransform<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DeserTransform::Function(name, major, minor, patch) => {
+                write!(f, "{} v{}.{}.{}", name, major, minor, patch)
+            }
+            DeserTransform::Constant(c) => write!(f, "constant {:?}", c),
+            DeserTransform::Macro(id) => write!(f, "macro {}", id),
+        }
+    }
+}
+
+/// Display an optional value, or the given text if there is none.
+struct DisplayOption<'a, V>(&'a Option<V>, &'static str);
+
+impl<'a, V: fmt::Display> fmt::Display for DisplayOption<'a, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(v) => write!(f, "{}", v),
+            None => write!(f, "{}", self.1),
+        }
+    }
+}
+
+/// Same as [`DisplayOption`], using the [`fmt::Debug`] implementation of the
+/// value.
+struct DebugOption<'a, V>(&'a Option<V>, &'static str);
+
+impl<'a, V: fmt::Debug> fmt::Display for DebugOption<'a, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Some(v) => write!(f, "{:?}", v),
+            None => write!(f, "{}", self.1),
+        }
+    }
+}
+
+/// One line per difference, starting with `+` for additions, `-` for
+/// removals and `~` for changes.
+impl<T: fmt::Debug> fmt::Display for DSTDiff<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (t_idx, diff) in &self.nodes {
+            let id = t_idx.id();
+            match diff {
+                NodeDiff::Added(t) => writeln!(f, "+ node #{}: {}", id, t)?,
+                NodeDiff::Removed(t) => writeln!(f, "- node #{}: {}", id, t)?,
+                NodeDiff::Changed {
+                    transform,
+                    defaults,
+                } => {
+                    if let Some(change) = transform {
+                        writeln!(f, "~ node #{}: {} -> {}", id, change.before, change.after)?;
+                    }
+                    for (index, change) in defaults {
+                        writeln!(
+                            f,
+                            "~ node #{}: default value of input #{}: {} -> {}",
+                            id,
+                            index + 1,
+                            DebugOption(&change.before, "none"),
+                            DebugOption(&change.after, "none")
+                        )?;
+                    }
+                }
+            }
+        }
+        for (slot, change) in &self.links {
+            writeln!(
+                f,
+                "~ {}: connected to {} -> {}",
+                slot,
+                DisplayOption(&change.before, "nothing"),
+                DisplayOption(&change.after, "nothing")
+            )?;
+        }
+        for (output_id, diff) in &self.outputs {
+            let id = output_id.id();
+            match diff {
+                OutputDiff::Added(name) => writeln!(f, "+ output node #{} '{}'", id, name)?,
+                OutputDiff::Removed(name) => writeln!(f, "- output node #{} '{}'", id, name)?,
+                OutputDiff::Renamed(change) => writeln!(
+                    f,
+                    "~ output node #{}: renamed '{}' -> '{}'",
+                    id, change.before, change.after
+                )?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<T: fmt::Debug> fmt::Display for Conflict<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Conflict::Transform {
+                t_idx,
+                ours,
+                theirs,
+            } => write!(
+                f,
+                "node #{}: ours {}, theirs {}",
+                t_idx.id(),
+                DisplayOption(ours, "removed"),
+                DisplayOption(theirs, "removed")
+            ),
+            Conflict::Default {
+                t_idx,
+                index,
+                ours,
+                theirs,
+            } => write!(
+                f,
+                "default value of input #{} of node #{}: ours {}, theirs {}",
+                index + 1,
+                t_idx.id(),
+                DebugOption(ours, "none"),
+                DebugOption(theirs, "none")
+            ),
+            Conflict::Link { slot, ours, theirs } => write!(
+                f,
+                "{}: ours connected to {}, theirs connected to {}",
+                slot,
+                DisplayOption(ours, "nothing"),
+                DisplayOption(theirs, "nothing")
+            ),
+            Conflict::Output {
+                output_id,
+                ours,
+                theirs,
+            } => write!(
+                f,
+                "output node #{}: ours {}, theirs {}",
+                output_id.id(),
+                DebugOption(ours, "removed"),
+                DebugOption(theirs, "removed")
+            ),
+            Conflict::Macro { id, ours, theirs } => write!(
+                f,
+                "macro {}: ours {}, theirs {}",
+                id,
+                DebugOption(ours, "removed"),
+                DebugOption(theirs, "removed")
+            ),
+        }
+    }
+}
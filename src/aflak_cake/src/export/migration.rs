@@ -0,0 +1,221 @@
+//! Migration of nodes saved with older major versions of a transform.
+//!
+//! When the signature of a transform changes in a backward-incompatible way,
+//! its major version is bumped and a [`Migration`] is registered to upgrade
+//! the nodes saved with the previous major version. Migrations are chained,
+//! so that a node saved with version 1 of a transform is upgraded to version
+//! 3 with the migrations from 1 to 2 and from 2 to 3.
+use std::collections::HashMap;
+
+/// Upgrade of the nodes of a transform from a major version to the next.
+///
+/// Inputs are identified by their index. Steps are applied in the order
+/// they are declared, each using the indices resulting from the previous
+/// steps.
+///
+/// ```
+/// use aflak_cake::export::Migration;
+///
+/// // Version 2 of "sum" is called "add", and takes a third input
+/// let migration: Migration<f64> = Migration::new("sum", 1, 2)
+///     .rename("add")
+///     .reorder_inputs(vec![1, 0])
+///     .add_input(2, Some(0.0));
+/// ```
+#[derive(Clone, Debug)]
+pub struct Migration<T> {
+    name: &'static str,
+    from_major: u8,
+    to_name: &'static str,
+    to_major: u8,
+    steps: Vec<InputStep<T>>,
+}
+
+#[derive(Clone, Debug)]
+enum InputStep<T> {
+    /// The new input `i` is the input `order[i]` of the previous step
+    Reorder(Vec<usize>),
+    /// Insert an input at the given index with the given default value
+    Add(usize, Option<T>),
+    /// Remove the input at the given index, dropping its connection
+    Remove(usize),
+}
+
+/// Origin of an input of a migrated node.
+#[derive(Clone, Debug)]
+pub(crate) enum MigratedInput<T> {
+    /// Input at the given index in the saved node
+    Saved(usize),
+    /// Input added by a migration, with its default value
+    Added(Option<T>),
+}
+
+impl<T> Migration<T> {
+    /// Make a migration of the transform `name` from `from_major` to
+    /// `to_major`, that does not change anything by default.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `to_major` is not greater than `from_major`.
+    pub fn new(name: &'static str, from_major: u8, to_major: u8) -> Self {
+        assert!(
+            from_major < to_major,
+            "Migration must upgrade to a greater major version"
+        );
+        Self {
+            name,
+            from_major,
+            to_name: name,
+            to_major,
+            steps: vec![],
+        }
+    }
+
+    /// The transform was renamed to `name`.
+    pub fn rename(mut self, name: &'static str) -> Self {
+        self.to_name = name;
+        self
+    }
+
+    /// Reorder inputs, so that the new input `i` is the previous input
+    /// `order[i]`. Previous inputs missing from `order` are removed.
+    pub fn reorder_inputs(mut self, order: Vec<usize>) -> Self {
+        self.steps.push(InputStep::Reorder(order));
+        self
+    }
+
+    /// Insert a new input at `index`, with the given default value.
+    /// If `default` is `None`, the default of the transform is used.
+    pub fn add_input(mut self, index: usize, default: Option<T>) -> Self {
+        self.steps.push(InputStep::Add(index, default));
+        self
+    }
+
+    /// Remove the input at `index`. Its connection, if any, is dropped.
+    pub fn remove_input(mut self, index: usize) -> Self {
+        self.steps.push(InputStep::Remove(index));
+        self
+    }
+
+    /// Name of the migrated transform.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Major version from which nodes are migrated.
+    pub fn from_major(&self) -> u8 {
+        self.from_major
+    }
+
+    /// Name of the transform after migration.
+    pub fn to_name(&self) -> &'static str {
+        self.to_name
+    }
+
+    /// Major version to which nodes are migrated.
+    pub fn to_major(&self) -> u8 {
+        self.to_major
+    }
+
+    fn apply(&self, inputs: &mut Vec<MigratedInput<T>>)
+    where
+        T: Clone,
+    {
+        for step in &self.steps {
+            match step {
+                InputStep::Reorder(order) => {
+                    *inputs = order
+                        .iter()
+                        .filter_map(|i| inputs.get(*i).cloned())
+                        .collect();
+                }
+                InputStep::Add(index, default) => {
+                    let index = (*index).min(inputs.len());
+                    inputs.insert(index, MigratedInput::Added(default.clone()));
+                }
+                InputStep::Remove(index) => {
+                    if *index < inputs.len() {
+                        inputs.remove(*index);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Registry of the migrations of transforms, queried when importing a DST.
+///
+/// See [`NamedAlgorithms::migrations`](trait.NamedAlgorithms.html#method.migrations).
+#[derive(Clone, Debug)]
+pub struct MigrationRegistry<T> {
+    /// Migrations indexed by transform name and major version
+    migrations: HashMap<&'static str, HashMap<u8, Migration<T>>>,
+}
+
+impl<T> Default for MigrationRegistry<T> {
+    fn default() -> Self {
+        Self {
+            migrations: HashMap::new(),
+        }
+    }
+}
+
+impl<T> MigrationRegistry<T> {
+    /// Make an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a migration, replacing any migration of the same transform
+    /// from the same major version.
+    pub fn register(&mut self, migration: Migration<T>) -> &mut Self {
+        self.migrations
+            .entry(migration.name)
+            .or_default()
+            .insert(migration.from_major, migration);
+        self
+    }
+
+    /// Same as `register`, consuming and returning the registry.
+    pub fn with(mut self, migration: Migration<T>) -> Self {
+        self.register(migration);
+        self
+    }
+
+    /// Get the migration of the transform `name` from major version `major`.
+    pub fn get(&self, name: &str, major: u8) -> Option<&Migration<T>> {
+        self.migrations
+            .get(name)
+            .and_then(|migrations| migrations.get(&major))
+    }
+}
+
+/// Migrations applied to a saved node.
+#[derive(Debug)]
+pub(crate) struct MigrationPlan<'a, T> {
+    migrations: Vec<&'a Migration<T>>,
+}
+
+impl<'a, T: Clone> MigrationPlan<'a, T> {
+    pub fn new() -> Self {
+        Self { migrations: vec![] }
+    }
+
+    pub fn push(&mut self, migration: &'a Migration<T>) {
+        self.migrations.push(migration);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.migrations.is_empty()
+    }
+
+    /// Compute the origin of each input of the migrated node, given that
+    /// the saved node has `saved_count` inputs.
+    pub fn inputs(&self, saved_count: usize) -> Vec<MigratedInput<T>> {
+        let mut inputs = (0..saved_count).map(MigratedInput::Saved).collect();
+        for migration in &self.migrations {
+            migration.apply(&mut inputs);
+        }
+        inputs
+    }
+}
@@ -0,0 +1,226 @@
+//! De-duplication of concurrent computations of the same transform.
+//!
+//! The first worker requesting the results of a transform becomes the
+//! *owner* of its computation. Workers requesting the same results in the
+//! meantime wait for the owner to finish and share its results.
+//!
+//! As rayon workers may steal unrelated jobs while waiting for their own
+//! jobs, the stack of a blocked worker may contain computations that other
+//! workers are waiting for. Two kinds of waits are tracked:
+//!
+//! - a worker waiting for a computation owned by another worker;
+//! - a worker waiting at the end of a `rayon::scope` for a job that was
+//!   stolen by another worker (see [`InFlight::stolen_job`]).
+//!
+//! A worker never waits for a computation if that would close a cycle of
+//! waiting workers, and computes the results itself instead.
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, ThreadId};
+use std::time::Instant;
+
+use crate::dst::TransformIdx;
+
+/// Computations currently running, and the workers waiting for them.
+#[derive(Debug)]
+pub(crate) struct InFlight<V> {
+    table: Mutex<Table<V>>,
+}
+
+#[derive(Debug)]
+struct Table<V> {
+    running: HashMap<TransformIdx, Arc<Running<V>>>,
+    waiting: HashMap<ThreadId, TransformIdx>,
+    /// Workers running jobs spawned by another worker, indexed by the
+    /// worker that spawned them. A worker may run several of them.
+    stolen: HashMap<ThreadId, Vec<ThreadId>>,
+}
+
+#[derive(Debug)]
+struct Running<V> {
+    time: Instant,
+    owner: ThreadId,
+    /// `None` while running, then `Some(None)` if the computation was
+    /// interrupted.
+    result: Mutex<Option<Option<V>>>,
+    done: Condvar,
+}
+
+/// Outcome of a request for the results of a transform.
+pub(crate) enum Join<'a, V> {
+    /// The caller must compute the results and hand them to the guard.
+    Owner(OwnerGuard<'a, V>),
+    /// The results were computed by a concurrent computation.
+    Shared(V, Instant),
+    /// The caller must compute the results without sharing them.
+    Alone,
+}
+
+impl<V: Clone> InFlight<V> {
+    pub fn new() -> Self {
+        Self {
+            table: Mutex::new(Table {
+                running: HashMap::new(),
+                waiting: HashMap::new(),
+                stolen: HashMap::new(),
+            }),
+        }
+    }
+
+    /// Request the results of `t_idx` computed at `time` or later.
+    ///
+    /// Block while another worker computes them.
+    pub fn join(&self, t_idx: TransformIdx, time: Instant) -> Join<'_, V> {
+        let me = thread::current().id();
+        loop {
+            let running = {
+                let mut table = self.table.lock().unwrap();
+                let running = match table.running.get(&t_idx) {
+                    Some(running) if running.time >= time => running.clone(),
+                    _ => {
+                        let running = Arc::new(Running {
+                            time,
+                            owner: me,
+                            result: Mutex::new(None),
+                            done: Condvar::new(),
+                        });
+                        table.running.insert(t_idx, running.clone());
+                        return Join::Owner(OwnerGuard {
+                            in_flight: self,
+                            t_idx,
+                            running,
+                            result: None,
+                        });
+                    }
+                };
+                if table.would_deadlock(running.owner, me) {
+                    return Join::Alone;
+                }
+                table.waiting.insert(me, t_idx);
+                running
+            };
+
+            let result = {
+                let mut result = running.result.lock().unwrap();
+                while result.is_none() {
+                    result = running.done.wait(result).unwrap();
+                }
+                result.clone().unwrap()
+            };
+            self.table.lock().unwrap().waiting.remove(&me);
+            if let Some(values) = result {
+                return Join::Shared(values, running.time);
+            }
+            // The owner was interrupted, try to compute the results again
+        }
+    }
+
+    /// Record that the current worker runs a job spawned by `spawner`,
+    /// until the returned guard is dropped.
+    ///
+    /// Must be called at the start of each job spawned in a `rayon::scope`
+    /// whose jobs may wait for computations, as the spawner waits for the
+    /// job at the end of the scope.
+    pub fn stolen_job(&self, spawner: ThreadId) -> StolenJob<'_, V> {
+        let me = thread::current().id();
+        let stolen = spawner != me;
+        if stolen {
+            let mut table = self.table.lock().unwrap();
+            table.stolen.entry(spawner).or_default().push(me);
+        }
+        StolenJob {
+            in_flight: self,
+            spawner,
+            stolen,
+        }
+    }
+}
+
+impl<V> Table<V> {
+    /// Check whether waiting for a computation owned by `owner` would make
+    /// `me` wait for itself.
+    fn would_deadlock(&self, owner: ThreadId, me: ThreadId) -> bool {
+        let mut visited = HashSet::new();
+        let mut to_visit = vec![owner];
+        while let Some(worker) = to_visit.pop() {
+            if worker == me {
+                return true;
+            }
+            if !visited.insert(worker) {
+                continue;
+            }
+            let owner = self
+                .waiting
+                .get(&worker)
+                .and_then(|t_idx| self.running.get(t_idx))
+                .map(|running| running.owner);
+            to_visit.extend(owner);
+            if let Some(thieves) = self.stolen.get(&worker) {
+                to_visit.extend(thieves.iter().cloned());
+            }
+        }
+        false
+    }
+}
+
+/// Guard held by the owner of a computation.
+///
+/// Waiting workers are woken up when the guard is dropped. They share the
+/// results set with `finish`, or compute the results again if the guard
+/// is dropped without results (e.g. the computation was interrupted or
+/// panicked).
+pub(crate) struct OwnerGuard<'a, V> {
+    in_flight: &'a InFlight<V>,
+    t_idx: TransformIdx,
+    running: Arc<Running<V>>,
+    result: Option<V>,
+}
+
+impl<'a, V> OwnerGuard<'a, V> {
+    /// Share `values` with the waiting workers.
+    pub fn finish(mut self, values: V) {
+        self.result = Some(values);
+    }
+}
+
+impl<'a, V> Drop for OwnerGuard<'a, V> {
+    fn drop(&mut self) {
+        {
+            let mut table = self.in_flight.table.lock().unwrap();
+            let is_current = table
+                .running
+                .get(&self.t_idx)
+                .map_or(false, |running| Arc::ptr_eq(running, &self.running));
+            if is_current {
+                table.running.remove(&self.t_idx);
+            }
+        }
+        *self.running.result.lock().unwrap() = Some(self.result.take());
+        self.running.done.notify_all();
+    }
+}
+
+/// Guard held while running a job spawned by another worker.
+pub(crate) struct StolenJob<'a, V> {
+    in_flight: &'a InFlight<V>,
+    spawner: ThreadId,
+    stolen: bool,
+}
+
+impl<'a, V> Drop for StolenJob<'a, V> {
+    fn drop(&mut self) {
+        if !self.stolen {
+            return;
+        }
+        let me = thread::current().id();
+        let mut table = self.in_flight.table.lock().unwrap();
+        if let Some(thieves) = table.stolen.get_mut(&self.spawner) {
+            if let Some(i) = thieves.iter().position(|thief| *thief == me) {
+                thieves.swap_remove(i);
+            }
+            if thieves.is_empty() {
+                table.stolen.remove(&self.spawner);
+            }
+        }
+    }
+}
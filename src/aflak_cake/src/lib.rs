@@ -17,20 +17,27 @@ pub extern crate uuid;
 extern crate variant_name;
 
 mod cache;
+mod disk_cache;
 mod dst;
 pub mod export;
 mod future;
+mod in_flight;
 pub mod macros;
+pub mod progress;
 mod timed;
 mod transform;
 
-pub use crate::cache::Cache;
+pub use crate::cache::{Cache, CacheStats, EstimateSize, NodeProfile};
+pub use crate::disk_cache::{Codec, DiskCache};
 pub use crate::dst::{
-    compute, DSTError, Input, InputDefaultsMut, InputSlot, LinkIter, MetaTransform, Node, NodeId,
-    NodeIter, Output, OutputId, TransformAndDefaults, TransformIdx, DST,
+    compute, DSTError, Diagnostic, Input, InputDefaultsMut, InputSlot, LinkIter, MetaTransform,
+    Node, NodeId, NodeIter, Output, OutputId, Severity, TransformAndDefaults, TransformIdx, DST,
+};
+pub use crate::export::{
+    DeserDST, ImportError, Migration, MigrationRegistry, NamedAlgorithms, SerialDST,
 };
-pub use crate::export::{DeserDST, ImportError, NamedAlgorithms, SerialDST};
 pub use crate::future::Task;
+pub use crate::progress::{CancellationToken, ComputeContext, Progress, ProgressEvent};
 pub use crate::timed::Timed;
 pub use crate::transform::*;
 pub use boow::Bow;
@@ -1,5 +1,5 @@
-use crate::dst::TransformIdx;
-use std::collections::{BTreeMap, HashMap};
+use crate::dst::{OutputId, TransformIdx};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::fmt;
 use std::ops;
 use std::ptr;
@@ -14,7 +14,7 @@ use super::{
     VariantName, DST,
 };
 use crate::compute::ComputeError;
-use crate::export::{DeserDST, ImportError, NamedAlgorithms};
+use crate::export::{merge3, Change, Conflict, DSTDiff, DeserDST, ImportError, NamedAlgorithms};
 
 pub struct MacroHandle<'t, T: 't, E: 't> {
     inner: Arc<RwLock<Macro<'t, T, E>>>,
@@ -148,6 +148,28 @@ impl<'t, T, E> MacroHandle<'t, T, E> {
         self.read().updated_on
     }
 
+    /// Check whether the macro contains itself, directly or through other
+    /// macros.
+    pub(crate) fn is_recursive(&self) -> bool {
+        fn inner<'t, T, E>(
+            macr: &MacroHandle<'t, T, E>,
+            target: &MacroHandle<'t, T, E>,
+            visited: &mut Vec<MacroHandle<'t, T, E>>,
+        ) -> bool {
+            if visited.contains(macr) {
+                return false;
+            }
+            visited.push(macr.clone());
+            let lock = macr.read();
+            let found = lock
+                .children_shallow()
+                .any(|child| child == target || inner(child, target, visited));
+            found
+        }
+
+        inner(self, self, &mut vec![])
+    }
+
     /// Get all children, ordered from deepest to shallowest
     fn children_deep(&self) -> impl Iterator<Item = MacroHandle<'t, T, E>> {
         fn inner<'t, T, E>(
@@ -231,6 +253,11 @@ impl<'t, T, E> Macro<'t, T, E> {
             .collect()
     }
 
+    /// Slot of the macro's [`DST`] that receives each input of the macro.
+    pub(crate) fn input_slots(&self) -> Vec<InputSlot> {
+        self.inputs.iter().map(|input| input.slot).collect()
+    }
+
     fn defaults(&self) -> Vec<Option<T>>
     where
         T: Clone,
@@ -390,6 +417,18 @@ impl<'t, T, E> MacroManager<'t, T, E> {
     }
 
     pub fn create_macro(&mut self) -> &MacroHandle<'t, T, E>
+    where
+        T: Clone + VariantName,
+    {
+        let handle = self.new_macro();
+        let id = handle.id();
+        self.macros.insert(id, handle);
+        self.macros.get(&id).unwrap()
+    }
+
+    /// Make a new empty macro with a unique name, without registering it.
+    /// Register it with [`MacroManager::register_macro`].
+    pub(crate) fn new_macro(&self) -> MacroHandle<'t, T, E>
     where
         T: Clone + VariantName,
     {
@@ -401,17 +440,18 @@ impl<'t, T, E> MacroManager<'t, T, E> {
             .map(|cnt| format!("New macro #{}", cnt))
             .find(|name| self.macros.values().all(|macr| &macr.name() != name))
             .unwrap();
-        self.macros.insert(
+        MacroHandle::from(Macro {
             id,
-            MacroHandle::from(Macro {
-                id,
-                name,
-                inputs: Macro::find_default_inputs(&dst),
-                dst,
-                updated_on: Instant::now(),
-            }),
-        );
-        self.macros.get(&id).unwrap()
+            name,
+            inputs: Macro::find_default_inputs(&dst),
+            dst,
+            updated_on: Instant::now(),
+        })
+    }
+
+    /// Register a macro made with [`MacroManager::new_macro`].
+    pub(crate) fn register_macro(&mut self, handle: MacroHandle<'t, T, E>) {
+        self.macros.insert(handle.id(), handle);
     }
 
     pub fn to_serializable(&self) -> SerdeMacroManager<T>
@@ -467,7 +507,7 @@ where
     }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 struct SerdeMacro<T> {
     id: Uuid,
     name: String,
@@ -590,6 +630,24 @@ impl<T> SerdeDSTStandAlone<T> {
             .into_dst(&macro_manager)
             .map(|dst| (dst, macro_manager))
     }
+
+    /// Same as `into_dst`, but macros already in `macro_manager` are re-used
+    /// instead of being copied. Missing macros are added to `macro_manager`.
+    pub fn into_dst_in<E>(
+        self,
+        macro_manager: &mut MacroManager<'static, T, E>,
+    ) -> Result<DST<'static, T, E>, ImportError>
+    where
+        T: Clone + VariantName + ConvertibleVariants + NamedAlgorithms<E>,
+    {
+        for macr in self.subs {
+            if macro_manager.get_macro(macr.id).is_none() {
+                let sub = macr.into_macro(macro_manager)?;
+                macro_manager.add_macro(sub)?;
+            }
+        }
+        self.main.into_dst(macro_manager)
+    }
 }
 
 impl<'t, 'd, T, E> From<&'d DST<'t, T, E>> for SerdeDSTStandAlone<T>
@@ -613,3 +671,270 @@ where
         }
     }
 }
+
+/// Change of a macro used by a [`SerdeDSTStandAlone`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum MacroDiff<T> {
+    /// Name of the added macro
+    Added(String),
+    /// Name of the removed macro
+    Removed(String),
+    Changed {
+        name: Change<String>,
+        dst: DSTDiff<T>,
+    },
+}
+
+/// Differences between two [`SerdeDSTStandAlone`]s.
+#[derive(Clone, Debug, PartialEq)]
+pub struct StandAloneDiff<T> {
+    pub main: DSTDiff<T>,
+    pub macros: BTreeMap<Uuid, MacroDiff<T>>,
+    /// Macros that were added or changed, directly or through one of the
+    /// macros they use
+    pub changed_macros: BTreeSet<Uuid>,
+}
+
+impl<T> StandAloneDiff<T> {
+    /// Check if there is no difference.
+    pub fn is_empty(&self) -> bool {
+        self.main.is_empty() && self.macros.is_empty()
+    }
+}
+
+/// Result of a three-way merge of [`SerdeDSTStandAlone`]s.
+#[derive(Clone, Debug)]
+pub struct StandAloneMerge<T> {
+    pub dst: SerdeDSTStandAlone<T>,
+    /// Conflicts in the main DST and between macros
+    pub conflicts: Vec<Conflict<T>>,
+    /// Conflicts inside each macro
+    pub macro_conflicts: BTreeMap<Uuid, Vec<Conflict<T>>>,
+    /// Renumbered nodes of the main DST, as in
+    /// [`DSTMerge`](crate::export::DSTMerge)
+    pub renumbered_nodes: BTreeMap<TransformIdx, TransformIdx>,
+    /// Renumbered output nodes of the main DST
+    pub renumbered_outputs: BTreeMap<OutputId, OutputId>,
+    /// Renumbered nodes inside each macro
+    pub macro_renumbered_nodes: BTreeMap<Uuid, BTreeMap<TransformIdx, TransformIdx>>,
+    /// Renumbered output nodes inside each macro
+    pub macro_renumbered_outputs: BTreeMap<Uuid, BTreeMap<OutputId, OutputId>>,
+}
+
+fn macro_map<T>(macros: &[SerdeMacro<T>]) -> BTreeMap<Uuid, &SerdeMacro<T>> {
+    macros.iter().map(|macr| (macr.id, macr)).collect()
+}
+
+/// Push the macro with the given ID to `subs`, after the macros it uses.
+/// Macros missing from `macros` are taken from `fallback`.
+fn push_macro_deep<T: Clone>(
+    id: Uuid,
+    macros: &BTreeMap<Uuid, SerdeMacro<T>>,
+    fallback: &BTreeMap<Uuid, &SerdeMacro<T>>,
+    visited: &mut BTreeSet<Uuid>,
+    subs: &mut Vec<SerdeMacro<T>>,
+) {
+    if !visited.insert(id) {
+        return;
+    }
+    if let Some(macr) = macros.get(&id).or_else(|| fallback.get(&id).cloned()) {
+        for sub_id in macr.dst.macro_ids() {
+            push_macro_deep(sub_id, macros, fallback, visited, subs);
+        }
+        subs.push(macr.clone());
+    }
+}
+
+impl<T> SerdeDSTStandAlone<T>
+where
+    T: Clone + PartialEq,
+{
+    /// Compute the differences from `self` to `other`. Macros are matched by
+    /// their ID.
+    pub fn diff(&self, other: &Self) -> StandAloneDiff<T> {
+        let before = macro_map(&self.subs);
+        let after = macro_map(&other.subs);
+        let mut macros = BTreeMap::new();
+        for id in before
+            .keys()
+            .chain(after.keys())
+            .cloned()
+            .collect::<BTreeSet<_>>()
+        {
+            let diff = match (before.get(&id), after.get(&id)) {
+                (Some(before), Some(after)) => {
+                    let dst = before.dst.diff(&after.dst);
+                    if before.name == after.name && dst.is_empty() {
+                        None
+                    } else {
+                        Some(MacroDiff::Changed {
+                            name: Change {
+                                before: before.name.clone(),
+                                after: after.name.clone(),
+                            },
+                            dst,
+                        })
+                    }
+                }
+                (Some(before), None) => Some(MacroDiff::Removed(before.name.clone())),
+                (None, Some(after)) => Some(MacroDiff::Added(after.name.clone())),
+                (None, None) => None,
+            };
+            if let Some(diff) = diff {
+                macros.insert(id, diff);
+            }
+        }
+
+        let mut changed_macros: BTreeSet<_> = macros
+            .iter()
+            .filter(|(_, diff)| !matches!(diff, MacroDiff::Removed(_)))
+            .map(|(id, _)| *id)
+            .collect();
+        loop {
+            let using: Vec<_> = other
+                .subs
+                .iter()
+                .filter(|macr| {
+                    !changed_macros.contains(&macr.id)
+                        && macr.dst.macro_ids().any(|id| changed_macros.contains(&id))
+                })
+                .map(|macr| macr.id)
+                .collect();
+            if using.is_empty() {
+                break;
+            }
+            changed_macros.extend(using);
+        }
+
+        StandAloneDiff {
+            main: self.main.diff(&other.main),
+            macros,
+            changed_macros,
+        }
+    }
+
+    /// Three-way merge of the changes made from `base` on our side and on
+    /// their side, as done by [`DeserDST::merge`]. Macros are merged by ID.
+    ///
+    /// Only the macros used by the merged DST are kept. A macro removed on
+    /// our side is kept if the merged DST still uses it.
+    pub fn merge(base: &Self, ours: &Self, theirs: &Self) -> StandAloneMerge<T> {
+        let main = DeserDST::merge(&base.main, &ours.main, &theirs.main);
+        let mut conflicts = main.conflicts;
+        let mut macro_conflicts = BTreeMap::new();
+        let mut macro_renumbered_nodes = BTreeMap::new();
+        let mut macro_renumbered_outputs = BTreeMap::new();
+
+        let base_macros = macro_map(&base.subs);
+        let our_macros = macro_map(&ours.subs);
+        let their_macros = macro_map(&theirs.subs);
+        let mut macros = BTreeMap::new();
+        for id in base_macros
+            .keys()
+            .chain(our_macros.keys())
+            .chain(their_macros.keys())
+            .cloned()
+            .collect::<BTreeSet<_>>()
+        {
+            let base = base_macros.get(&id).cloned();
+            let ours = our_macros.get(&id).cloned();
+            let theirs = their_macros.get(&id).cloned();
+            match merge3(&base, &ours, &theirs) {
+                Ok(merged) => {
+                    if let Some(macr) = merged {
+                        macros.insert(id, (*macr).clone());
+                    }
+                }
+                Err(_) => match (base, ours, theirs) {
+                    (Some(base), Some(ours), Some(theirs)) => {
+                        let name =
+                            merge3(&base.name, &ours.name, &theirs.name).unwrap_or_else(|name| {
+                                conflicts.push(Conflict::Macro {
+                                    id,
+                                    ours: Some(ours.name.clone()),
+                                    theirs: Some(theirs.name.clone()),
+                                });
+                                name
+                            });
+                        let dst = DeserDST::merge(&base.dst, &ours.dst, &theirs.dst);
+                        if !dst.conflicts.is_empty() {
+                            macro_conflicts.insert(id, dst.conflicts);
+                        }
+                        if !dst.renumbered_nodes.is_empty() {
+                            macro_renumbered_nodes.insert(id, dst.renumbered_nodes);
+                        }
+                        if !dst.renumbered_outputs.is_empty() {
+                            macro_renumbered_outputs.insert(id, dst.renumbered_outputs);
+                        }
+                        macros.insert(
+                            id,
+                            SerdeMacro {
+                                id,
+                                name: name.clone(),
+                                dst: dst.dst,
+                            },
+                        );
+                    }
+                    (_, ours, theirs) => {
+                        conflicts.push(Conflict::Macro {
+                            id,
+                            ours: ours.map(|macr| macr.name.clone()),
+                            theirs: theirs.map(|macr| macr.name.clone()),
+                        });
+                        if let Some(ours) = ours {
+                            macros.insert(id, ours.clone());
+                        }
+                    }
+                },
+            }
+        }
+
+        let mut fallback = their_macros;
+        fallback.extend(our_macros);
+        let mut visited = BTreeSet::new();
+        let mut subs = vec![];
+        for id in main.dst.macro_ids() {
+            push_macro_deep(id, &macros, &fallback, &mut visited, &mut subs);
+        }
+        StandAloneMerge {
+            dst: SerdeDSTStandAlone {
+                main: main.dst,
+                subs,
+            },
+            conflicts,
+            macro_conflicts,
+            renumbered_nodes: main.renumbered_nodes,
+            renumbered_outputs: main.renumbered_outputs,
+            macro_renumbered_nodes,
+            macro_renumbered_outputs,
+        }
+    }
+}
+
+/// Differences of the main DST, followed by the changes of each macro.
+impl<T: fmt::Debug> fmt::Display for StandAloneDiff<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.main)?;
+        for (id, diff) in &self.macros {
+            match diff {
+                MacroDiff::Added(name) => writeln!(f, "+ macro '{}' ({})", name, id)?,
+                MacroDiff::Removed(name) => writeln!(f, "- macro '{}' ({})", name, id)?,
+                MacroDiff::Changed { name, dst } => {
+                    if name.before == name.after {
+                        writeln!(f, "~ macro '{}' ({})", name.after, id)?;
+                    } else {
+                        writeln!(
+                            f,
+                            "~ macro '{}' ({}): renamed '{}' -> '{}'",
+                            name.after, id, name.before, name.after
+                        )?;
+                    }
+                    for line in dst.to_string().lines() {
+                        writeln!(f, "    {}", line)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
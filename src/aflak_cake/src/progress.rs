@@ -0,0 +1,283 @@
+//! Cooperative cancellation and progress reporting of computations.
+//!
+//! Each computation started with [`DST::compute_with`] runs with a
+//! [`ComputeContext`], made of a [`CancellationToken`] and an optional
+//! progress channel. The context is checked between nodes, so that a
+//! cancelled computation stops as soon as possible.
+//!
+//! Long-running transforms may also check for cancellation and report their
+//! progress with [`is_cancelled`] and [`report`], or with a
+//! [`ProgressCounter`]. These functions are no-op when called outside of a
+//! computation.
+//!
+//! [`DST::compute_with`]: ../struct.DST.html#method.compute_with
+use std::cell::RefCell;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    mpsc, Arc, Mutex,
+};
+
+use crate::dst::TransformIdx;
+
+/// Token used to cancel a computation.
+///
+/// Cloned tokens share the same state, so that cancelling one of them
+/// cancels all of them.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Request the cancellation of the computations using this token.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+
+    /// Check whether cancellation was requested.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Acquire)
+    }
+}
+
+/// Progress of the computation of a node.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Progress {
+    /// Fraction of the work done, between 0 and 1.
+    pub fraction: f32,
+    /// Description of the current step.
+    pub message: String,
+}
+
+/// Event sent on a progress channel.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ProgressEvent {
+    /// The computation of a node started.
+    Started(TransformIdx),
+    /// A node reported its progress.
+    Progress(TransformIdx, Progress),
+    /// The computation of a node finished, was cancelled or failed.
+    Finished(TransformIdx),
+}
+
+/// Sending half of a progress channel.
+#[derive(Clone, Debug)]
+pub struct ProgressSender(Arc<Mutex<mpsc::Sender<ProgressEvent>>>);
+
+/// Receiving half of a progress channel.
+#[derive(Debug)]
+pub struct ProgressReceiver(mpsc::Receiver<ProgressEvent>);
+
+/// Create a new progress channel.
+pub fn channel() -> (ProgressSender, ProgressReceiver) {
+    let (tx, rx) = mpsc::channel();
+    (
+        ProgressSender(Arc::new(Mutex::new(tx))),
+        ProgressReceiver(rx),
+    )
+}
+
+impl ProgressSender {
+    /// Send an event. Events are dropped if the receiver is gone.
+    pub fn send(&self, event: ProgressEvent) {
+        let _ = self.0.lock().unwrap().send(event);
+    }
+}
+
+impl ProgressReceiver {
+    /// Iterate over the events received so far, without blocking.
+    pub fn try_iter(&self) -> mpsc::TryIter<'_, ProgressEvent> {
+        self.0.try_iter()
+    }
+}
+
+/// Context in which a computation runs.
+#[derive(Clone, Debug, Default)]
+pub struct ComputeContext {
+    token: CancellationToken,
+    progress: Option<ProgressSender>,
+}
+
+impl ComputeContext {
+    /// Make a new context that can be cancelled with `token`.
+    pub fn new(token: CancellationToken) -> Self {
+        Self {
+            token,
+            progress: None,
+        }
+    }
+
+    /// Report progress of the computed nodes to `progress`.
+    pub fn with_progress(mut self, progress: ProgressSender) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// Get the cancellation token of the context.
+    pub fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+
+    /// Check whether cancellation was requested.
+    pub fn is_cancelled(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    pub(crate) fn send(&self, event: ProgressEvent) {
+        if let Some(progress) = &self.progress {
+            progress.send(event);
+        }
+    }
+
+    /// Notify the start of the computation of node `t_idx`. The end of the
+    /// computation is notified when the returned guard is dropped.
+    pub(crate) fn start_node(&self, t_idx: TransformIdx) -> NodeGuard<'_> {
+        self.send(ProgressEvent::Started(t_idx));
+        NodeGuard { ctx: self, t_idx }
+    }
+}
+
+pub(crate) struct NodeGuard<'a> {
+    ctx: &'a ComputeContext,
+    t_idx: TransformIdx,
+}
+
+impl<'a> Drop for NodeGuard<'a> {
+    fn drop(&mut self) {
+        self.ctx.send(ProgressEvent::Finished(self.t_idx));
+    }
+}
+
+/// Context of the node currently computed on this thread.
+///
+/// Clone it to check for cancellation or report progress from other threads.
+#[derive(Clone, Debug)]
+pub struct NodeContext {
+    ctx: ComputeContext,
+    t_idx: TransformIdx,
+}
+
+impl NodeContext {
+    /// Node being computed.
+    pub fn t_idx(&self) -> TransformIdx {
+        self.t_idx
+    }
+
+    /// Check whether cancellation was requested.
+    pub fn is_cancelled(&self) -> bool {
+        self.ctx.is_cancelled()
+    }
+
+    /// Report the progress of the node.
+    pub fn report<S: Into<String>>(&self, fraction: f32, message: S) {
+        self.ctx.send(ProgressEvent::Progress(
+            self.t_idx,
+            Progress {
+                fraction: fraction.max(0.0).min(1.0),
+                message: message.into(),
+            },
+        ));
+    }
+}
+
+thread_local! {
+    static CURRENT: RefCell<Option<NodeContext>> = RefCell::new(None);
+}
+
+/// Get the context of the node computed on this thread, if any.
+pub fn current() -> Option<NodeContext> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+/// Check whether the computation running on this thread was cancelled.
+pub fn is_cancelled() -> bool {
+    CURRENT.with(|current| {
+        current
+            .borrow()
+            .as_ref()
+            .map_or(false, NodeContext::is_cancelled)
+    })
+}
+
+/// Report the progress of the node computed on this thread.
+pub fn report<S: Into<String>>(fraction: f32, message: S) {
+    if let Some(ctx) = current() {
+        ctx.report(fraction, message);
+    }
+}
+
+/// Run `f` with `ctx` as the context of the current thread.
+pub(crate) fn run_in_context<F, R>(ctx: &ComputeContext, t_idx: TransformIdx, f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    struct Restore(Option<NodeContext>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let previous = self.0.take();
+            CURRENT.with(|current| *current.borrow_mut() = previous);
+        }
+    }
+
+    let node_ctx = NodeContext {
+        ctx: ctx.clone(),
+        t_idx,
+    };
+    let previous = CURRENT.with(|current| current.borrow_mut().replace(node_ctx));
+    let _restore = Restore(previous);
+    f()
+}
+
+/// Count the steps of a long-running computation to report its progress
+/// and check for cancellation.
+///
+/// Progress is only reported, and cancellation checked, when progress
+/// changed by more than 1%, so that counting each step is cheap.
+pub struct ProgressCounter {
+    ctx: Option<NodeContext>,
+    message: String,
+    total: usize,
+    done: usize,
+    next_report: usize,
+}
+
+impl ProgressCounter {
+    /// Count `total` steps of the node computed on this thread.
+    pub fn new<S: Into<String>>(total: usize, message: S) -> Self {
+        let ctx = current();
+        let message = message.into();
+        if let Some(ctx) = &ctx {
+            ctx.report(0.0, message.clone());
+        }
+        Self {
+            ctx,
+            message,
+            total,
+            done: 0,
+            next_report: 0,
+        }
+    }
+
+    /// Count a step. Return `false` if the computation was cancelled, in
+    /// which case the caller should stop as soon as possible.
+    pub fn tick(&mut self) -> bool {
+        let ctx = if let Some(ctx) = &self.ctx {
+            ctx
+        } else {
+            return true;
+        };
+        self.done += 1;
+        if self.done >= self.next_report {
+            self.next_report = self.done + (self.total / 100).max(1);
+            ctx.report(
+                self.done as f32 / self.total.max(1) as f32,
+                self.message.clone(),
+            );
+            !ctx.is_cancelled()
+        } else {
+            true
+        }
+    }
+}
@@ -55,13 +55,29 @@ pub enum Algorithm<'t, T: 't, E: 't> {
 }
 
 /// Semantic version
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct Version {
     pub major: u8,
     pub minor: u8,
     pub patch: u8,
 }
 
+impl Version {
+    pub fn new(major: u8, minor: u8, patch: u8) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct TransformInputSlot<T> {
     pub type_id: TypeId,
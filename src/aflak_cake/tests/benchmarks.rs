@@ -0,0 +1,260 @@
+//! Tests and benchmarks of the concurrent evaluation of a DST.
+//!
+//! The benchmarks are ignored by default. Run them with
+//! `cargo test --release --test benchmarks -- --ignored --nocapture`.
+#[macro_use]
+extern crate variant_name_derive;
+extern crate variant_name;
+#[macro_use]
+extern crate lazy_static;
+extern crate aflak_cake;
+extern crate futures;
+extern crate rayon;
+#[macro_use]
+extern crate serde;
+
+mod support;
+use crate::support::*;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex, Once};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use aflak_cake::Cache;
+use futures::{future::Future, Async};
+
+/// Time taken by the slow transforms.
+const SLOW: Duration = Duration::from_millis(200);
+
+static SLOW_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+/// Give up waiting for the other branch after this time.
+const MEET_TIMEOUT: Duration = Duration::from_secs(10);
+
+lazy_static! {
+    /// Number of branches that arrived at the meeting point.
+    static ref MEETING: (Mutex<usize>, Condvar) = (Mutex::new(0), Condvar::new());
+    /// Number of branches that met the other branch.
+    static ref MET: AtomicUsize = AtomicUsize::new(0);
+}
+
+/// Wait until two calls are running at the same time, or until
+/// `MEET_TIMEOUT` elapsed.
+fn meet() {
+    let (count, arrived) = &*MEETING;
+    let mut count = count.lock().unwrap();
+    *count += 1;
+    arrived.notify_all();
+    let (count, _) = arrived
+        .wait_timeout_while(count, MEET_TIMEOUT, |count| *count < 2)
+        .unwrap();
+    if *count >= 2 {
+        MET.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+/// Make sure that the rayon pool has enough workers to run the tests
+/// concurrently, whatever the number of CPUs of the machine.
+fn init_pool() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(8)
+            .build_global()
+            .unwrap();
+    });
+}
+
+fn wait<F: Future>(mut task: F) -> Result<F::Item, F::Error> {
+    loop {
+        match task.poll() {
+            Ok(Async::Ready(r)) => break Ok(r),
+            Ok(Async::NotReady) => thread::yield_now(),
+            Err(e) => break Err(e),
+        }
+    }
+}
+
+fn get_counted_slow_plus1_transform() -> Transform<'static, AlgoIO, E> {
+    cake_transform!("Slowly add 1 and count", "Test", 1, 0, 0, counted_slow_plus1<AlgoIO, E>(i: Integer = 0) -> Integer {
+        SLOW_CALLS.fetch_add(1, Ordering::SeqCst);
+        thread::sleep(SLOW);
+        vec![Ok(AlgoIO::Integer(i + 1))]
+    })
+}
+
+fn get_meet_plus1_transform() -> Transform<'static, AlgoIO, E> {
+    cake_transform!("Meet and add 1", "Test", 1, 0, 0, meet_plus1<AlgoIO, E>(i: Integer = 0) -> Integer {
+        meet();
+        vec![Ok(AlgoIO::Integer(i + 1))]
+    })
+}
+
+fn get_add_transform() -> Transform<'static, AlgoIO, E> {
+    cake_transform!("Add", "Test", 1, 0, 0, add<AlgoIO, E>(a: Integer = 0, b: Integer = 0) -> Integer {
+        vec![Ok(AlgoIO::Integer(a + b))]
+    })
+}
+
+#[test]
+fn test_independent_branches_run_concurrently() {
+    init_pool();
+    let meet_plus1 = Box::leak(Box::new(get_meet_plus1_transform()));
+    let get1 = Box::leak(Box::new(get_get1_transform()));
+    let add = Box::leak(Box::new(get_add_transform()));
+
+    // get1 -> meet_plus1 \
+    //                     add -> OUT
+    // get1 -> meet_plus1 /
+    let mut dst = DST::new();
+    let a = dst.add_transform(get1, None);
+    let b = dst.add_transform(meet_plus1, None);
+    let c = dst.add_transform(get1, None);
+    let d = dst.add_transform(meet_plus1, None);
+    let e = dst.add_transform(add, None);
+    dst.connect(Output::new(a, 0), Input::new(b, 0)).unwrap();
+    dst.connect(Output::new(c, 0), Input::new(d, 0)).unwrap();
+    dst.connect(Output::new(b, 0), Input::new(e, 0)).unwrap();
+    dst.connect(Output::new(d, 0), Input::new(e, 1)).unwrap();
+    let out = dst.attach_output(Output::new(e, 0)).unwrap();
+
+    let mut cache = Cache::new();
+    let result = wait(dst.compute(out, &mut cache)).unwrap();
+    assert_eq!(**result, AlgoIO::Integer(4));
+    // Each branch waits for the other one, so they only meet if they run
+    // at the same time
+    assert_eq!(MET.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn test_concurrent_requests_are_deduplicated() {
+    init_pool();
+    let counted_slow_plus1 = Box::leak(Box::new(get_counted_slow_plus1_transform()));
+    let plus1 = Box::leak(Box::new(get_plus1_transform()));
+    let get1 = Box::leak(Box::new(get_get1_transform()));
+
+    //                         / plus1 -> OUT1
+    // get1 -> counted_slow_plus1
+    //                         \ plus1 -> OUT2
+    let mut dst = DST::new();
+    let a = dst.add_transform(get1, None);
+    let b = dst.add_transform(counted_slow_plus1, None);
+    let c = dst.add_transform(plus1, None);
+    let d = dst.add_transform(plus1, None);
+    dst.connect(Output::new(a, 0), Input::new(b, 0)).unwrap();
+    dst.connect(Output::new(b, 0), Input::new(c, 0)).unwrap();
+    dst.connect(Output::new(b, 0), Input::new(d, 0)).unwrap();
+    let out1 = dst.attach_output(Output::new(c, 0)).unwrap();
+    let out2 = dst.attach_output(Output::new(d, 0)).unwrap();
+
+    let mut cache = Cache::new();
+    let task1 = dst.compute(out1, &mut cache);
+    let task2 = dst.compute(out2, &mut cache);
+    assert_eq!(**wait(task1).unwrap(), AlgoIO::Integer(3));
+    assert_eq!(**wait(task2).unwrap(), AlgoIO::Integer(3));
+
+    // The slow node is computed once, though both outputs requested it
+    // at the same time
+    assert_eq!(SLOW_CALLS.load(Ordering::SeqCst), 1);
+    let stats = cache.stats();
+    assert_eq!(stats.misses, 4);
+    assert_eq!(stats.hits, 1);
+}
+
+/// Make a DST made of `width` independent chains of `depth` transforms,
+/// summed together into a single output.
+fn make_wide_dst(width: usize, depth: usize) -> (DST<'static, AlgoIO, E>, OutputId) {
+    let plus1 = Box::leak(Box::new(get_plus1_transform()));
+    let get1 = Box::leak(Box::new(get_get1_transform()));
+    let add = Box::leak(Box::new(get_add_transform()));
+
+    let mut dst = DST::new();
+    let mut heads = vec![];
+    for _ in 0..width {
+        let mut head = dst.add_transform(get1, None);
+        for _ in 0..depth {
+            let next = dst.add_transform(plus1, None);
+            dst.connect(Output::new(head, 0), Input::new(next, 0))
+                .unwrap();
+            head = next;
+        }
+        heads.push(head);
+    }
+    while heads.len() > 1 {
+        let mut sums = vec![];
+        for pair in heads.chunks(2) {
+            if let [left, right] = pair {
+                let sum = dst.add_transform(add, None);
+                dst.connect(Output::new(*left, 0), Input::new(sum, 0))
+                    .unwrap();
+                dst.connect(Output::new(*right, 0), Input::new(sum, 1))
+                    .unwrap();
+                sums.push(sum);
+            } else {
+                sums.push(pair[0]);
+            }
+        }
+        heads = sums;
+    }
+    let out = dst.attach_output(Output::new(heads[0], 0)).unwrap();
+    (dst, out)
+}
+
+fn bench<F: FnMut()>(name: &str, iterations: u32, mut f: F) {
+    // Warm up
+    f();
+    let start = Instant::now();
+    for _ in 0..iterations {
+        f();
+    }
+    println!("{}: {:?}/iter", name, start.elapsed() / iterations);
+}
+
+#[test]
+#[ignore]
+fn bench_wide_graph() {
+    init_pool();
+    const WIDTH: usize = 64;
+    const DEPTH: usize = 32;
+    let (dst, out) = make_wide_dst(WIDTH, DEPTH);
+    let expected = AlgoIO::Integer(((1 + DEPTH) * WIDTH) as u64);
+
+    bench("wide graph, cold cache", 20, || {
+        let mut cache = Cache::new();
+        assert_eq!(**wait(dst.compute(out, &mut cache)).unwrap(), expected);
+    });
+
+    let mut cache = Cache::new();
+    bench("wide graph, warm cache", 20, || {
+        assert_eq!(**wait(dst.compute(out, &mut cache)).unwrap(), expected);
+    });
+}
+
+#[test]
+#[ignore]
+fn bench_concurrent_outputs() {
+    init_pool();
+    const OUTPUTS: usize = 16;
+    let (mut dst, out) = make_wide_dst(32, 32);
+    let root = dst
+        .outputs_iter()
+        .find(|(id, _)| **id == out)
+        .and_then(|(_, (output, _))| *output)
+        .unwrap();
+    let outs: Vec<_> = (1..OUTPUTS)
+        .map(|_| dst.attach_output(root).unwrap())
+        .chain(Some(out))
+        .collect();
+
+    bench("concurrent outputs sharing a graph, cold cache", 20, || {
+        let mut cache = Cache::new();
+        let tasks: Vec<_> = outs
+            .iter()
+            .map(|out| dst.compute(*out, &mut cache))
+            .collect();
+        for task in tasks {
+            wait(task).unwrap();
+        }
+    });
+}
@@ -0,0 +1,210 @@
+#[macro_use]
+extern crate variant_name_derive;
+extern crate variant_name;
+#[macro_use]
+extern crate lazy_static;
+extern crate aflak_cake;
+extern crate futures;
+extern crate ron;
+#[macro_use]
+extern crate serde;
+
+mod support;
+use crate::support::*;
+
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+use std::time::Duration;
+
+use aflak_cake::{Cache, Codec, DiskCache};
+use futures::{future::Future, Async};
+
+macro_rules! assert_output_eq {
+    ($dst: expr, $output: expr, $expected_value: expr, $cache: expr) => {{
+        let mut promise = $dst.compute($output, $cache);
+        let out = loop {
+            match promise.poll() {
+                Ok(Async::Ready(r)) => break r,
+                Ok(Async::NotReady) => ::std::thread::yield_now(),
+                Err(e) => panic!("Fails: {}", e),
+            }
+        };
+        assert_eq!(**out, $expected_value);
+    }};
+}
+
+/// Make the DST `get1 -> plus1 -> plus1 -> OUT` and return it along with
+/// the transform indices of its nodes and its output.
+fn make_chain() -> (DST<'static, AlgoIO, E>, [TransformIdx; 3], OutputId) {
+    let plus1 = Box::leak(Box::new(get_plus1_transform()));
+    let get1 = Box::leak(Box::new(get_get1_transform()));
+    let mut dst = DST::new();
+    let a = dst.add_transform(get1, None);
+    let b = dst.add_transform(plus1, None);
+    let c = dst.add_transform(plus1, None);
+    dst.connect(Output::new(a, 0), Input::new(b, 0)).unwrap();
+    dst.connect(Output::new(b, 0), Input::new(c, 0)).unwrap();
+    let out = dst.attach_output(Output::new(c, 0)).unwrap();
+    (dst, [a, b, c], out)
+}
+
+#[test]
+fn test_cache_statistics() {
+    let (dst, [a, _, c], out) = make_chain();
+    let mut cache = Cache::new();
+
+    assert_output_eq!(dst, out, AlgoIO::Integer(3), &mut cache);
+    let stats = cache.stats();
+    assert_eq!(stats.misses, 3);
+    assert_eq!(stats.hits, 0);
+    assert_eq!(stats.entries, 3);
+    assert_eq!(stats.bytes, 3 * 8);
+    assert_eq!(stats.budget, None);
+
+    assert_output_eq!(dst, out, AlgoIO::Integer(3), &mut cache);
+    let stats = cache.stats();
+    assert_eq!(stats.misses, 3);
+    assert_eq!(stats.hits, 1);
+    assert!(cache.get(&Output::new(a, 0)).is_some());
+    assert!(cache.get(&Output::new(c, 0)).is_some());
+
+    cache.reset_stats();
+    assert_eq!(cache.stats().hits, 0);
+    assert_eq!(cache.stats().entries, 3);
+}
+
+#[test]
+fn test_node_profiles() {
+    let (dst, [a, b, c], out) = make_chain();
+    let mut cache = Cache::new();
+
+    assert_output_eq!(dst, out, AlgoIO::Integer(3), &mut cache);
+    assert_output_eq!(dst, out, AlgoIO::Integer(3), &mut cache);
+    let profiles = dst.profile(&cache);
+    assert_eq!(
+        profiles.iter().map(|(t_idx, _)| *t_idx).collect::<Vec<_>>(),
+        vec![a, b, c]
+    );
+    for (t_idx, profile) in profiles {
+        assert_eq!(profile.misses, 1);
+        assert_eq!(profile.disk_hits, 0);
+        assert_eq!(profile.bytes, 8);
+        assert_eq!(profile.total_time, profile.last_time);
+        assert_eq!(profile.mean_time(), profile.last_time);
+        // Only the output node is requested once its results are cached
+        assert_eq!(profile.hits, if t_idx == c { 1 } else { 0 });
+    }
+
+    cache.reset_profiles();
+    assert!(dst.profile(&cache).is_empty());
+    assert_eq!(cache.profile(&c), None);
+}
+
+#[test]
+fn test_cache_eviction() {
+    let (dst, [a, b, c], out) = make_chain();
+    let mut cache = Cache::with_budget(2 * 8);
+
+    assert_output_eq!(dst, out, AlgoIO::Integer(3), &mut cache);
+    let stats = cache.stats();
+    assert_eq!(stats.evictions, 1);
+    assert_eq!(stats.bytes, 2 * 8);
+    // The deepest intermediate result is the least recently used
+    assert!(cache.get(&Output::new(a, 0)).is_none());
+    assert!(cache.get(&Output::new(b, 0)).is_some());
+    assert!(cache.get(&Output::new(c, 0)).is_some());
+
+    // Evicted results are computed again when needed
+    assert_output_eq!(dst, out, AlgoIO::Integer(3), &mut cache);
+    assert_eq!(cache.stats().hits, 1);
+}
+
+#[test]
+fn test_cache_pinning() {
+    let (dst, [a, b, c], out) = make_chain();
+    let mut cache = Cache::new();
+    cache.pin(c);
+    assert!(cache.is_pinned(&c));
+
+    assert_output_eq!(dst, out, AlgoIO::Integer(3), &mut cache);
+    cache.set_budget(Some(0));
+    let stats = cache.stats();
+    assert_eq!(stats.evictions, 2);
+    assert_eq!(stats.bytes, 8);
+    assert!(cache.get(&Output::new(a, 0)).is_none());
+    assert!(cache.get(&Output::new(b, 0)).is_none());
+    assert!(cache.get(&Output::new(c, 0)).is_some());
+
+    cache.unpin(&c);
+    cache.set_budget(Some(0));
+    assert_eq!(cache.stats().entries, 0);
+    assert!(cache.get(&Output::new(c, 0)).is_none());
+}
+
+struct RonCodec;
+
+impl Codec<AlgoIO> for RonCodec {
+    fn encode(&self, value: &AlgoIO, w: &mut dyn Write) -> io::Result<()> {
+        let s = ron::ser::to_string(value)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e.to_string()))?;
+        w.write_all(s.as_bytes())
+    }
+
+    fn decode(&self, r: &mut dyn Read) -> io::Result<AlgoIO> {
+        let mut s = String::new();
+        r.read_to_string(&mut s)?;
+        ron::de::from_str(&s).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))
+    }
+}
+
+#[test]
+fn test_content_hash() {
+    let (dst1, [_, _, c1], _) = make_chain();
+    let (mut dst2, [a2, b2, c2], _) = make_chain();
+    let hash = dst1.content_hash(c1, &RonCodec);
+    assert!(hash.is_some());
+    // Same sub-graph, same hash
+    assert_eq!(hash, dst2.content_hash(c2, &RonCodec));
+
+    // Default values of connected inputs are not used
+    let mut defaults = dst2.get_default_inputs_mut(b2).unwrap();
+    defaults.write(0, AlgoIO::Integer(5));
+    assert_eq!(hash, dst2.content_hash(c2, &RonCodec));
+
+    dst2.disconnect(&Output::new(a2, 0), &Input::new(b2, 0));
+    let disconnected_hash = dst2.content_hash(c2, &RonCodec);
+    assert_ne!(hash, disconnected_hash);
+
+    let mut defaults = dst2.get_default_inputs_mut(b2).unwrap();
+    defaults.write(0, AlgoIO::Integer(0));
+    assert_ne!(disconnected_hash, dst2.content_hash(c2, &RonCodec));
+}
+
+#[test]
+fn test_disk_cache() {
+    let dir = std::env::temp_dir().join(format!("aflak_cake_disk_cache_{}", std::process::id()));
+    let mut disk = DiskCache::open(&dir, Box::new(RonCodec)).unwrap();
+    disk.set_min_cost(Duration::from_secs(0));
+    let disk = Arc::new(disk);
+
+    let (dst, _, out) = make_chain();
+    let mut cache = Cache::new();
+    cache.set_disk_cache(Some(disk.clone()));
+    assert_output_eq!(dst, out, AlgoIO::Integer(3), &mut cache);
+    assert_eq!(cache.stats().disk_hits, 0);
+    // Constants are not persisted
+    assert_eq!(disk.len(), 2);
+
+    // A new session only loads the final result from the disk
+    let (dst, _, out) = make_chain();
+    let mut cache = Cache::new();
+    cache.set_disk_cache(Some(disk.clone()));
+    assert_output_eq!(dst, out, AlgoIO::Integer(3), &mut cache);
+    let stats = cache.stats();
+    assert_eq!(stats.misses, 1);
+    assert_eq!(stats.disk_hits, 1);
+
+    disk.clear().unwrap();
+    assert!(disk.is_empty());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
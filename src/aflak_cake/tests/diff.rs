@@ -0,0 +1,228 @@
+extern crate aflak_cake;
+#[macro_use]
+extern crate variant_name_derive;
+extern crate variant_name;
+#[macro_use]
+extern crate lazy_static;
+
+#[macro_use]
+extern crate serde;
+
+mod support;
+use crate::support::*;
+
+use aflak_cake::export::{Change, Conflict, DeserTransform, NodeDiff, OutputDiff};
+use aflak_cake::macros::{MacroManager, SerdeDSTStandAlone};
+
+/// Build the DST `a, get1 -> b, plus1 -> OUT`.
+fn make_base() -> (DST<'static, AlgoIO, E>, [TransformIdx; 2], OutputId) {
+    let plus1 = Box::leak(Box::new(get_plus1_transform()));
+    let get1 = Box::leak(Box::new(get_get1_transform()));
+    let mut dst = DST::new();
+    let a = dst.add_transform(get1, None);
+    let b = dst.add_transform(plus1, None);
+    dst.connect(Output::new(a, 0), Input::new(b, 0)).unwrap();
+    let out = dst.attach_output(Output::new(b, 0)).unwrap();
+    (dst, [a, b], out)
+}
+
+fn minus1() -> &'static Transform<'static, AlgoIO, E> {
+    Box::leak(Box::new(get_minus1_transform()))
+}
+
+#[test]
+fn test_diff() {
+    let (base, [a, b], out) = make_base();
+    let mut other = base.clone();
+    let c = other.add_transform(minus1(), None);
+    other.disconnect(&Output::new(a, 0), &Input::new(b, 0));
+    other.connect(Output::new(a, 0), Input::new(c, 0)).unwrap();
+    other
+        .get_default_inputs_mut(b)
+        .unwrap()
+        .write(0, AlgoIO::Integer(5));
+    other.change_output_name(out, "Result".to_owned());
+
+    let base = DeserDST::from_dst(&base);
+    let other = DeserDST::from_dst(&other);
+    assert!(base.diff(&base).is_empty());
+
+    let diff = base.diff(&other);
+    assert_eq!(diff.nodes.len(), 2);
+    assert_eq!(
+        diff.nodes[&c],
+        NodeDiff::Added(DeserTransform::Function("minus1".to_owned(), 1, 0, 0))
+    );
+    match &diff.nodes[&b] {
+        NodeDiff::Changed {
+            transform: None,
+            defaults,
+        } => assert_eq!(
+            defaults[&0],
+            Change {
+                before: Some(AlgoIO::Integer(0)),
+                after: Some(AlgoIO::Integer(5)),
+            }
+        ),
+        _ => panic!("Expected changed default value"),
+    }
+    assert_eq!(diff.links.len(), 2);
+    assert_eq!(
+        diff.links[&InputSlot::Transform(Input::new(b, 0))],
+        Change {
+            before: Some(Output::new(a, 0)),
+            after: None,
+        }
+    );
+    assert_eq!(
+        diff.links[&InputSlot::Transform(Input::new(c, 0))],
+        Change {
+            before: None,
+            after: Some(Output::new(a, 0)),
+        }
+    );
+    assert_eq!(
+        diff.outputs[&out],
+        OutputDiff::Renamed(Change {
+            before: "Output #1".to_owned(),
+            after: "Result".to_owned(),
+        })
+    );
+
+    let text = diff.to_string();
+    assert!(text.contains("+ node #3: minus1 v1.0.0"));
+    assert!(text.contains("~ node #2: default value of input #1: Integer(0) -> Integer(5)"));
+    assert!(text.contains("~ output node #1: renamed 'Output #1' -> 'Result'"));
+}
+
+#[test]
+fn test_merge_independent_changes() {
+    let (base, [a, b], out) = make_base();
+    // Both sides add a node with the same index
+    let mut ours = base.clone();
+    let c = ours.add_transform(minus1(), None);
+    ours.connect(Output::new(b, 0), Input::new(c, 0)).unwrap();
+    let mut theirs = base.clone();
+    let d = theirs.add_transform(minus1(), None);
+    theirs.connect(Output::new(a, 0), Input::new(d, 0)).unwrap();
+    theirs
+        .get_default_inputs_mut(d)
+        .unwrap()
+        .write(0, AlgoIO::Integer(3));
+    theirs.change_output_name(out, "Result".to_owned());
+    assert_eq!(c, d);
+
+    let merge = DeserDST::merge(
+        &DeserDST::from_dst(&base),
+        &DeserDST::from_dst(&ours),
+        &DeserDST::from_dst(&theirs),
+    );
+    assert_eq!(merge.conflicts, vec![]);
+    assert_eq!(merge.renumbered_nodes.len(), 1);
+    let new_d = merge.renumbered_nodes[&d];
+
+    let merged = merge.dst.into_dst(&MacroManager::new()).unwrap();
+    assert_eq!(merged.node_ids().len(), 5);
+    assert_eq!(
+        merged.outputs_attached_to_transform(c).unwrap(),
+        vec![Some(Output::new(b, 0))]
+    );
+    assert_eq!(
+        merged.outputs_attached_to_transform(new_d).unwrap(),
+        vec![Some(Output::new(a, 0))]
+    );
+    assert_eq!(
+        merged.get_default_inputs(new_d).unwrap().to_vec(),
+        vec![Some(AlgoIO::Integer(3))]
+    );
+    match merged.get_node(&NodeId::Output(out)) {
+        Some(Node::Output((Some(output), name))) => {
+            assert_eq!(*output, Output::new(b, 0));
+            assert_eq!(name, "Result");
+        }
+        _ => panic!("Expected output node"),
+    }
+}
+
+#[test]
+fn test_merge_conflicts() {
+    let (base, [a, b], out) = make_base();
+    let mut ours = base.clone();
+    ours.get_default_inputs_mut(b)
+        .unwrap()
+        .write(0, AlgoIO::Integer(5));
+    ours.change_output_name(out, "Ours".to_owned());
+    ours.remove_node(&NodeId::Transform(a));
+    let mut theirs = base.clone();
+    theirs
+        .get_default_inputs_mut(b)
+        .unwrap()
+        .write(0, AlgoIO::Integer(7));
+    theirs.change_output_name(out, "Theirs".to_owned());
+    let c = theirs.add_transform(minus1(), None);
+    theirs.connect(Output::new(a, 0), Input::new(c, 0)).unwrap();
+
+    let merge = DeserDST::merge(
+        &DeserDST::from_dst(&base),
+        &DeserDST::from_dst(&ours),
+        &DeserDST::from_dst(&theirs),
+    );
+    assert_eq!(
+        merge.conflicts,
+        vec![
+            Conflict::Default {
+                t_idx: b,
+                index: 0,
+                ours: Some(AlgoIO::Integer(5)),
+                theirs: Some(AlgoIO::Integer(7)),
+            },
+            Conflict::Output {
+                output_id: out,
+                ours: Some("Ours".to_owned()),
+                theirs: Some("Theirs".to_owned()),
+            },
+            // Node a was removed on our side
+            Conflict::Link {
+                slot: InputSlot::Transform(Input::new(c, 0)),
+                ours: None,
+                theirs: Some(Output::new(a, 0)),
+            },
+        ]
+    );
+
+    // Our side is kept
+    let merged = merge.dst.into_dst(&MacroManager::new()).unwrap();
+    assert!(merged.get_transform(a).is_none());
+    assert_eq!(merged.outputs_attached_to_transform(c).unwrap(), vec![None]);
+    assert_eq!(
+        merged.get_default_inputs(b).unwrap().to_vec(),
+        vec![Some(AlgoIO::Integer(5))]
+    );
+}
+
+#[test]
+fn test_merge_standalone_renumbers_macro_nodes() {
+    let (mut dst, _, _) = make_base();
+    let macr = MacroManager::new().create_macro().clone();
+    let macro_id = macr.id();
+    dst.add_owned_transform(Transform::from_macro(macr.clone()), None);
+    let base = SerdeDSTStandAlone::from(&dst);
+    let macro_base = macr.read().dst().clone();
+
+    // Both sides add a node with the same index inside the macro
+    macr.write()
+        .dst_mut()
+        .add_transform(minus1(), Some(macro_id));
+    let ours = SerdeDSTStandAlone::from(&dst);
+    let plus1 = Box::leak(Box::new(get_plus1_transform()));
+    *macr.write().dst_mut() = macro_base;
+    macr.write().dst_mut().add_transform(plus1, Some(macro_id));
+    let theirs = SerdeDSTStandAlone::from(&dst);
+
+    let merge = SerdeDSTStandAlone::merge(&base, &ours, &theirs);
+    assert_eq!(merge.conflicts, vec![]);
+    assert!(merge.macro_conflicts.is_empty());
+    assert!(merge.renumbered_nodes.is_empty());
+    assert_eq!(merge.macro_renumbered_nodes.len(), 1);
+    assert_eq!(merge.macro_renumbered_nodes[&macro_id].len(), 1);
+}
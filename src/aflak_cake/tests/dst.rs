@@ -189,3 +189,46 @@ fn test_remove_node() {
         vec
     });
 }
+
+#[test]
+fn test_sub_graph_and_merge() {
+    let [plus1, minus1, get1, _image, _] = get_all_transforms();
+
+    // a, get1 -> b, plus1 -> c, minus1 -> OUT1
+    let mut dst = DST::new();
+    let a = dst.add_transform(&get1, None);
+    let b = dst.add_transform(&plus1, None);
+    let c = dst.add_transform(&minus1, None);
+    let out1 = dst.attach_output(Output::new(c, 0)).unwrap();
+    dst.connect(Output::new(a, 0), Input::new(b, 0)).unwrap();
+    dst.connect(Output::new(b, 0), Input::new(c, 0)).unwrap();
+
+    // Copy b -> c -> OUT1, without a
+    let nodes = [
+        NodeId::Transform(b),
+        NodeId::Transform(c),
+        NodeId::Output(out1),
+    ];
+    let sub = dst.sub_graph(&nodes);
+    assert_eq!(sub.node_ids(), nodes.to_vec());
+    assert_eq!(sub.links_iter().count(), 2);
+
+    // Paste the copy, and feed it with a
+    let new_ids = dst.merge(sub, None);
+    assert_eq!(new_ids.len(), 3);
+    let new_b = match new_ids[&NodeId::Transform(b)] {
+        NodeId::Transform(t_idx) => t_idx,
+        NodeId::Output(_) => panic!("Expected a transform"),
+    };
+    let new_out1 = match new_ids[&NodeId::Output(out1)] {
+        NodeId::Output(output_id) => output_id,
+        NodeId::Transform(_) => panic!("Expected an output"),
+    };
+    assert!(new_b != b && new_out1 != out1);
+    dst.connect(Output::new(a, 0), Input::new(new_b, 0))
+        .unwrap();
+
+    let mut cache = Cache::new();
+    assert_output_eq!(dst, out1, AlgoIO::Integer(1), &mut cache);
+    assert_output_eq!(dst, new_out1, AlgoIO::Integer(1), &mut cache);
+}
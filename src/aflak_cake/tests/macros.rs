@@ -149,3 +149,154 @@ fn test_nested_macro_standalone_serde() {
 
     assert_eq!(out, out2);
 }
+
+fn compute(dst: &DST<'static, AlgoIO, E>, output_id: OutputId) -> AlgoIO {
+    dst.compute_sync(output_id, &mut std::collections::HashMap::new())
+        .unwrap()
+}
+
+#[test]
+fn test_group_into_macro_and_expand() {
+    if let &[plus1, minus1, get1, _, _] = *TRANSFORMATIONS_REF {
+        // get1 -> a, plus1 -> b, minus1 -> c, plus1 -> OUT1
+        //                       \-> OUT2
+        let mut dst = DST::new();
+        let one = dst.add_transform(&get1, None);
+        let a = dst.add_transform(&plus1, None);
+        let b = dst.add_transform(&minus1, None);
+        let c = dst.add_transform(&plus1, None);
+        dst.connect(Output::new(one, 0), Input::new(a, 0)).unwrap();
+        dst.connect(Output::new(a, 0), Input::new(b, 0)).unwrap();
+        dst.connect(Output::new(b, 0), Input::new(c, 0)).unwrap();
+        let out1 = dst.attach_output(Output::new(c, 0)).unwrap();
+        let out2 = dst.attach_output(Output::new(a, 0)).unwrap();
+
+        let mut manager = aflak_cake::macros::MacroManager::new();
+        let m = dst.group_into_macro(&[a, b], &mut manager, None).unwrap();
+        assert_eq!(manager.macros().count(), 1);
+        assert!(dst.get_transform(a).is_none());
+        assert!(dst.get_transform(b).is_none());
+        assert_eq!(
+            dst.outputs_attached_to_transform(m).unwrap(),
+            vec![Some(Output::new(one, 0))]
+        );
+        assert_eq!(
+            dst.outputs_attached_to_transform(c).unwrap()[0]
+                .unwrap()
+                .t_idx,
+            m
+        );
+        assert_eq!(compute(&dst, out1), AlgoIO::Integer(2));
+        assert_eq!(compute(&dst, out2), AlgoIO::Integer(2));
+
+        let new_nodes = dst.expand_macro(m, None).unwrap();
+        assert_eq!(new_nodes.len(), 2);
+        assert!(dst.get_transform(m).is_none());
+        assert_eq!(dst.outputs_iter().count(), 2);
+        assert_eq!(compute(&dst, out1), AlgoIO::Integer(2));
+        assert_eq!(compute(&dst, out2), AlgoIO::Integer(2));
+    } else {
+        unreachable!()
+    }
+}
+
+#[test]
+fn test_group_into_macro_refuses_cycles() {
+    if let &[plus1, _, get1, _, _] = *TRANSFORMATIONS_REF {
+        // get1 -> a, plus1 -> b, plus1 -> c, plus1
+        let mut dst = DST::new();
+        let one = dst.add_transform(&get1, None);
+        let a = dst.add_transform(&plus1, None);
+        let b = dst.add_transform(&plus1, None);
+        let c = dst.add_transform(&plus1, None);
+        dst.connect(Output::new(one, 0), Input::new(a, 0)).unwrap();
+        dst.connect(Output::new(a, 0), Input::new(b, 0)).unwrap();
+        dst.connect(Output::new(b, 0), Input::new(c, 0)).unwrap();
+
+        let mut manager = aflak_cake::macros::MacroManager::new();
+        match dst.group_into_macro(&[a, c], &mut manager, None) {
+            Err(DSTError::Cycle(_)) => {}
+            res => panic!("Expected a cycle error, got {:?}", res),
+        }
+        assert_eq!(manager.macros().count(), 0);
+        assert!(dst.get_transform(a).is_some());
+    } else {
+        unreachable!()
+    }
+}
+
+/// Make a macro computing `t` on its single input.
+fn single_node_macro(
+    manager: &mut aflak_cake::macros::MacroManager<'static, AlgoIO, E>,
+    t: &'static Transform<'static, AlgoIO, E>,
+) -> aflak_cake::macros::MacroHandle<'static, AlgoIO, E> {
+    let mut dst = DST::new();
+    let a = dst.add_transform(t, None);
+    dst.attach_output(Output::new(a, 0)).unwrap();
+    let macr = manager.create_macro();
+    *macr.write().dst_mut() = dst;
+    macr.clone()
+}
+
+fn macro_names(manager: &aflak_cake::macros::MacroManager<'static, AlgoIO, E>) -> Vec<String> {
+    manager.macros().map(|macr| macr.name()).collect()
+}
+
+#[test]
+fn test_group_into_macro_leaves_graph_untouched_on_error() {
+    if let &[plus1, minus1, get1, _, divide_by_10] = *TRANSFORMATIONS_REF {
+        // get1 -> m (macro of plus1) -> minus1 -> OUT
+        let mut manager = aflak_cake::macros::MacroManager::new();
+        let handle = single_node_macro(&mut manager, plus1);
+        let mut dst = DST::new();
+        let one = dst.add_transform(&get1, None);
+        let m = dst.add_owned_transform(Transform::from_macro(handle.clone()), None);
+        let b = dst.add_transform(&minus1, None);
+        dst.connect(Output::new(one, 0), Input::new(m, 0)).unwrap();
+        dst.connect(Output::new(m, 0), Input::new(b, 0)).unwrap();
+        dst.attach_output(Output::new(b, 0)).unwrap();
+        // The macro now outputs a float, so its link to minus1 cannot be
+        // made again
+        *handle.write().dst_mut() = single_node_macro(&mut manager, divide_by_10)
+            .read()
+            .dst()
+            .clone();
+
+        let before = format!("{:?}", dst);
+        let macros_before = macro_names(&manager);
+        match dst.group_into_macro(&[m], &mut manager, None) {
+            Err(DSTError::IncompatibleTypes(_)) => {}
+            res => panic!("Expected incompatible types, got {:?}", res),
+        }
+        assert_eq!(format!("{:?}", dst), before);
+        assert_eq!(macro_names(&manager), macros_before);
+    } else {
+        unreachable!()
+    }
+}
+
+#[test]
+fn test_expand_macro_leaves_graph_untouched_on_error() {
+    if let &[plus1, _, _, _, divide_by_10] = *TRANSFORMATIONS_REF {
+        // divide_by_10 -> m (macro of divide_by_10) -> OUT
+        let mut manager = aflak_cake::macros::MacroManager::new();
+        let handle = single_node_macro(&mut manager, divide_by_10);
+        let mut dst = DST::new();
+        let a = dst.add_transform(&divide_by_10, None);
+        let m = dst.add_owned_transform(Transform::from_macro(handle.clone()), None);
+        dst.connect(Output::new(a, 0), Input::new(m, 0)).unwrap();
+        dst.attach_output(Output::new(m, 0)).unwrap();
+        // The macro now expects an integer, so the float linked to it cannot
+        // be linked to its content
+        *handle.write().dst_mut() = single_node_macro(&mut manager, plus1).read().dst().clone();
+
+        let before = format!("{:?}", dst);
+        match dst.expand_macro(m, None) {
+            Err(DSTError::IncompatibleTypes(_)) => {}
+            res => panic!("Expected incompatible types, got {:?}", res),
+        }
+        assert_eq!(format!("{:?}", dst), before);
+    } else {
+        unreachable!()
+    }
+}
@@ -0,0 +1,121 @@
+#[macro_use]
+extern crate variant_name_derive;
+extern crate variant_name;
+#[macro_use]
+extern crate lazy_static;
+extern crate aflak_cake;
+extern crate futures;
+#[macro_use]
+extern crate serde;
+
+mod support;
+use crate::support::*;
+
+use std::thread;
+use std::time::Duration;
+
+use aflak_cake::{progress, Cache};
+use futures::{future::Future, Async};
+
+fn wait<F: Future>(mut task: F) -> Result<F::Item, F::Error> {
+    loop {
+        match task.poll() {
+            Ok(Async::Ready(r)) => break Ok(r),
+            Ok(Async::NotReady) => thread::yield_now(),
+            Err(e) => break Err(e),
+        }
+    }
+}
+
+fn get_wait_for_cancel_transform() -> Transform<'static, AlgoIO, E> {
+    cake_transform!("Wait until cancelled", "Test", 1, 0, 0, wait_for_cancel<AlgoIO, E>(i: Integer = 0) -> Integer {
+        let mut counter = progress::ProgressCounter::new(1000, "Waiting");
+        for _ in 0..1000 {
+            if !counter.tick() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        vec![Ok(AlgoIO::Integer(*i))]
+    })
+}
+
+#[test]
+fn test_progress_events() {
+    let plus1 = Box::leak(Box::new(get_plus1_transform()));
+    let get1 = Box::leak(Box::new(get_get1_transform()));
+    let mut dst = DST::new();
+    let a = dst.add_transform(get1, None);
+    let b = dst.add_transform(plus1, None);
+    dst.connect(Output::new(a, 0), Input::new(b, 0)).unwrap();
+    let out = dst.attach_output(Output::new(b, 0)).unwrap();
+
+    let (tx, rx) = progress::channel();
+    let ctx = ComputeContext::default().with_progress(tx);
+    let mut cache = Cache::new();
+    let result = wait(dst.compute_with(out, &mut cache, ctx)).unwrap();
+    assert_eq!(**result, AlgoIO::Integer(2));
+
+    let events: Vec<_> = rx.try_iter().collect();
+    for t_idx in &[a, b] {
+        assert!(events.contains(&ProgressEvent::Started(*t_idx)));
+        assert!(events.contains(&ProgressEvent::Finished(*t_idx)));
+    }
+    // b starts before a and finishes after it
+    assert_eq!(events.first(), Some(&ProgressEvent::Started(b)));
+    assert_eq!(events.last(), Some(&ProgressEvent::Finished(b)));
+}
+
+#[test]
+fn test_cancel_computation() {
+    let wait_for_cancel = Box::leak(Box::new(get_wait_for_cancel_transform()));
+    let get1 = Box::leak(Box::new(get_get1_transform()));
+    let mut dst = DST::new();
+    let a = dst.add_transform(get1, None);
+    let b = dst.add_transform(wait_for_cancel, None);
+    dst.connect(Output::new(a, 0), Input::new(b, 0)).unwrap();
+    let out = dst.attach_output(Output::new(b, 0)).unwrap();
+
+    let (tx, rx) = progress::channel();
+    let token = CancellationToken::new();
+    let ctx = ComputeContext::new(token.clone()).with_progress(tx);
+    let mut cache = Cache::new();
+    let task = dst.compute_with(out, &mut cache, ctx);
+
+    // Wait for the transform to report its progress before cancelling it
+    loop {
+        let reported = rx.try_iter().any(|event| match event {
+            ProgressEvent::Progress(t_idx, p) => t_idx == b && p.message == "Waiting",
+            _ => false,
+        });
+        if reported {
+            break;
+        }
+        thread::yield_now();
+    }
+    token.cancel();
+    let error = wait(task).unwrap_err();
+    assert!(error.is_cancelled());
+
+    // Results of cancelled nodes are not cached
+    assert!(cache.get(&Output::new(a, 0)).is_some());
+    assert!(cache.get(&Output::new(b, 0)).is_none());
+}
+
+#[test]
+fn test_cancel_before_compute() {
+    let plus1 = Box::leak(Box::new(get_plus1_transform()));
+    let get1 = Box::leak(Box::new(get_get1_transform()));
+    let mut dst = DST::new();
+    let a = dst.add_transform(get1, None);
+    let b = dst.add_transform(plus1, None);
+    dst.connect(Output::new(a, 0), Input::new(b, 0)).unwrap();
+    let out = dst.attach_output(Output::new(b, 0)).unwrap();
+
+    let token = CancellationToken::new();
+    token.cancel();
+    let mut cache = Cache::new();
+    let error = wait(dst.compute_with(out, &mut cache, ComputeContext::new(token))).unwrap_err();
+    assert!(error.is_cancelled());
+    assert_eq!(cache.stats().entries, 0);
+}
@@ -14,6 +14,7 @@ use crate::support::*;
 
 use aflak_cake::export::{DeserTransform, SerialTransform};
 use aflak_cake::macros::MacroManager;
+use aflak_cake::{DeserDST, ImportError};
 use ron::de::from_str;
 use ron::ser::to_string;
 
@@ -46,3 +47,88 @@ fn test_plus1() {
     let mut ret = caller.call();
     assert_eq!(ret.next().unwrap().unwrap(), AlgoIO::Integer(1));
 }
+
+/// Export the DST `get1 -> plus1 -> OUT`, replacing the saved transform
+/// `plus1` with `saved_transform`.
+fn export_with_saved_transform(saved_transform: &str) -> (String, [TransformIdx; 2]) {
+    let plus1 = Box::leak(Box::new(get_plus1_transform()));
+    let get1 = Box::leak(Box::new(get_get1_transform()));
+    let mut dst = DST::new();
+    let a = dst.add_transform(get1, None);
+    let b = dst.add_transform(plus1, None);
+    dst.connect(Output::new(a, 0), Input::new(b, 0)).unwrap();
+    dst.attach_output(Output::new(b, 0)).unwrap();
+
+    let s = to_string(&dst).unwrap();
+    let current = "Function(\"plus1\",1,0,0,)";
+    assert!(s.contains(current));
+    (s.replace(current, saved_transform), [a, b])
+}
+
+fn import(s: &str) -> Result<DST<'static, AlgoIO, E>, ImportError> {
+    let deser: DeserDST<AlgoIO> = from_str(s).unwrap();
+    deser.into_dst(&MacroManager::new())
+}
+
+#[test]
+fn test_migrate_renamed_transform() {
+    let (s, [a, b]) = export_with_saved_transform("Function(\"add_one\",0,3,0,)");
+    let dst = import(&s).unwrap();
+    assert_eq!(dst.get_transform(b).unwrap().name(), "plus1");
+    assert_eq!(
+        dst.outputs_attached_to_transform(b).unwrap(),
+        vec![Some(Output::new(a, 0))]
+    );
+}
+
+#[test]
+fn test_migrate_inputs() {
+    let (s, [_, b]) = export_with_saved_transform("Function(\"plus1\",0,1,0,)");
+    let dst = import(&s).unwrap();
+    assert_eq!(dst.get_transform(b).unwrap().name(), "plus1");
+    // The saved input was removed with its connection, the added input
+    // has the default value set by the migration
+    assert_eq!(dst.outputs_attached_to_transform(b).unwrap(), vec![None]);
+    assert_eq!(
+        dst.get_default_inputs(b).unwrap().to_vec(),
+        vec![Some(AlgoIO::Integer(5))]
+    );
+}
+
+#[test]
+fn test_minor_version_needs_no_migration() {
+    let (s, [a, b]) = export_with_saved_transform("Function(\"plus1\",1,7,2,)");
+    let dst = import(&s).unwrap();
+    assert_eq!(
+        dst.outputs_attached_to_transform(b).unwrap(),
+        vec![Some(Output::new(a, 0))]
+    );
+}
+
+#[test]
+fn test_unsupported_version() {
+    // Saved with a newer version
+    let (s, _) = export_with_saved_transform("Function(\"plus1\",2,0,0,)");
+    match import(&s) {
+        Err(ImportError::UnsupportedVersion {
+            transform_name,
+            saved,
+            current,
+        }) => {
+            assert_eq!(transform_name, "plus1");
+            assert_eq!(saved, Version::new(2, 0, 0));
+            assert_eq!(current, Version::new(1, 0, 0));
+        }
+        _ => panic!("Expected unsupported version"),
+    }
+
+    // Saved with an older version, without migration
+    let (s, _) = export_with_saved_transform("Function(\"minus1\",0,1,0,)");
+    match import(&s) {
+        Err(ImportError::UnsupportedVersion { saved, current, .. }) => {
+            assert_eq!(saved, Version::new(0, 1, 0));
+            assert_eq!(current, Version::new(1, 0, 0));
+        }
+        _ => panic!("Expected unsupported version"),
+    }
+}
@@ -62,6 +62,18 @@ lazy_static! {
     };
 }
 
+lazy_static! {
+    static ref MIGRATIONS: MigrationRegistry<AlgoIO> = MigrationRegistry::new()
+        // "add_one" was renamed "plus1" in version 1
+        .with(Migration::new("add_one", 0, 1).rename("plus1"))
+        // Version 0 of "plus1" had no input and added 1 to 5
+        .with(
+            Migration::new("plus1", 0, 1)
+                .add_input(0, Some(AlgoIO::Integer(5)))
+                .remove_input(1),
+        );
+}
+
 impl NamedAlgorithms<E> for AlgoIO {
     fn get_transform(s: &str) -> Option<&'static Transform<'static, AlgoIO, E>> {
         for t in TRANSFORMATIONS_REF.iter() {
@@ -71,6 +83,19 @@ impl NamedAlgorithms<E> for AlgoIO {
         }
         None
     }
+
+    fn migrations() -> Option<&'static MigrationRegistry<AlgoIO>> {
+        Some(&MIGRATIONS)
+    }
+}
+
+impl EstimateSize for AlgoIO {
+    fn estimate_size(&self) -> usize {
+        match self {
+            AlgoIO::Image2d(image) => image.iter().map(|row| row.len() * 8).sum(),
+            _ => 8,
+        }
+    }
 }
 
 impl ConvertibleVariants for AlgoIO {
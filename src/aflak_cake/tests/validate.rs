@@ -0,0 +1,116 @@
+#[macro_use]
+extern crate variant_name_derive;
+extern crate variant_name;
+#[macro_use]
+extern crate lazy_static;
+extern crate aflak_cake;
+#[macro_use]
+extern crate serde;
+
+mod support;
+use crate::support::*;
+
+use aflak_cake::macros::MacroManager;
+
+#[test]
+fn test_valid_dst() {
+    let plus1 = Box::leak(Box::new(get_plus1_transform()));
+    let minus1 = Box::leak(Box::new(get_minus1_transform()));
+    let get1 = Box::leak(Box::new(get_get1_transform()));
+
+    // get1 -> minus1 -> plus1 -> OUT
+    let mut dst = DST::new();
+    let a = dst.add_transform(get1, None);
+    let b = dst.add_transform(minus1, None);
+    let c = dst.add_transform(plus1, None);
+    dst.connect(Output::new(a, 0), Input::new(b, 0)).unwrap();
+    dst.connect(Output::new(b, 0), Input::new(c, 0)).unwrap();
+    dst.attach_output(Output::new(c, 0)).unwrap();
+
+    assert_eq!(dst.validate(), vec![]);
+}
+
+#[test]
+fn test_all_problems_are_reported() {
+    let plus1 = Box::leak(Box::new(get_plus1_transform()));
+    let minus1 = Box::leak(Box::new(get_minus1_transform()));
+
+    // minus1 -> OUT1    plus1    OUT2
+    let mut dst = DST::new();
+    let a = dst.add_transform(minus1, None);
+    let b = dst.add_transform(plus1, None);
+    dst.attach_output(Output::new(a, 0)).unwrap();
+    let out2 = dst.create_output();
+
+    let diagnostics = dst.validate();
+    assert_eq!(
+        diagnostics,
+        vec![
+            Diagnostic::MissingInput {
+                input: Input::new(a, 0),
+                t_name: "minus1".into(),
+            },
+            Diagnostic::DanglingOutput(out2),
+            Diagnostic::UnusedNode(b),
+        ]
+    );
+    let severities: Vec<_> = diagnostics.iter().map(Diagnostic::severity).collect();
+    assert_eq!(
+        severities,
+        vec![Severity::Error, Severity::Warning, Severity::Warning]
+    );
+    assert_eq!(diagnostics[0].nodes(), vec![NodeId::Transform(a)]);
+    assert_eq!(
+        diagnostics[0].input_slot(),
+        Some(InputSlot::Transform(Input::new(a, 0)))
+    );
+}
+
+#[test]
+fn test_unknown_macro() {
+    let mut manager = MacroManager::new();
+    let macr = manager.create_macro().clone();
+
+    let mut dst: DST<AlgoIO, E> = DST::new();
+    let a = dst.add_owned_transform(Transform::from_macro(macr.clone()), None);
+
+    let is_unknown_macro = |diagnostic: &Diagnostic| match diagnostic {
+        Diagnostic::UnknownMacro {
+            t_idx, macro_id, ..
+        } => *t_idx == a && *macro_id == macr.id(),
+        _ => false,
+    };
+    assert!(!dst
+        .validate_with_macros(&manager)
+        .iter()
+        .any(is_unknown_macro));
+    assert!(dst
+        .validate_with_macros(&MacroManager::new())
+        .iter()
+        .any(is_unknown_macro));
+}
+
+#[test]
+fn test_recursive_macro() {
+    let mut manager = MacroManager::new();
+    let macr_a = manager.create_macro().clone();
+    let macr_b = manager.create_macro().clone();
+    // a contains b, which contains a
+    macr_a
+        .write()
+        .dst_mut()
+        .add_owned_transform(Transform::from_macro(macr_b.clone()), Some(macr_a.id()));
+    macr_b
+        .write()
+        .dst_mut()
+        .add_owned_transform(Transform::from_macro(macr_a.clone()), Some(macr_b.id()));
+
+    let mut dst: DST<AlgoIO, E> = DST::new();
+    let a = dst.add_owned_transform(Transform::from_macro(macr_a), None);
+
+    let diagnostics = dst.validate_with_macros(&manager);
+    assert!(diagnostics.iter().any(|diagnostic| match diagnostic {
+        Diagnostic::RecursiveMacro { t_idx, .. } => *t_idx == a,
+        _ => false,
+    }));
+}
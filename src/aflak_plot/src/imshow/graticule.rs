@@ -0,0 +1,278 @@
+//! Draw a grid of celestial coordinates over an image.
+use imgui::DrawListMut;
+
+const LINE_COLOR: u32 = 0xA0FF_FF00;
+const LABEL_COLOR: u32 = 0xFFFF_FF00;
+/// Maximum number of lines drawn for each coordinate
+const MAX_LINES: f32 = 6.0;
+/// Number of segments used to draw each line
+const LINE_SEGMENTS: usize = 64;
+/// Number of points sampled along each image side to find bounds
+const BOUND_SAMPLES: usize = 16;
+
+/// Celestial coordinates of an image, used to draw a graticule on it.
+///
+/// Lines of constant longitude and latitude are computed with the world
+/// coordinates of the image, so they are curved if the projection requires
+/// it.
+pub struct Graticule {
+    pix2world: Box<dyn Fn(f32, f32) -> (f32, f32)>,
+    world2pix: Box<dyn Fn(f32, f32) -> (f32, f32)>,
+    hours: bool,
+}
+
+struct Bounds {
+    lng: (f32, f32),
+    lat: (f32, f32),
+}
+
+impl Graticule {
+    /// Make a new graticule.
+    ///
+    /// `pix2world` converts pixel coordinates (x, y) of the image to
+    /// celestial coordinates (longitude, latitude) in degrees. `world2pix`
+    /// is its inverse. Both may return NaN values out of the bounds of the
+    /// projection. If `hours` is true, longitudes are right ascensions
+    /// labelled in hours.
+    pub fn new(
+        pix2world: Box<dyn Fn(f32, f32) -> (f32, f32)>,
+        world2pix: Box<dyn Fn(f32, f32) -> (f32, f32)>,
+        hours: bool,
+    ) -> Self {
+        Self {
+            pix2world,
+            world2pix,
+            hours,
+        }
+    }
+
+    /// Draw graticule over an image of `tex_size` pixels, drawn at screen
+    /// position `p` with size `size`.
+    pub(crate) fn draw(
+        &self,
+        draw_list: &DrawListMut,
+        tex_size: (f32, f32),
+        p: [f32; 2],
+        size: [f32; 2],
+    ) {
+        let bounds = match self.bounds(tex_size) {
+            Some(bounds) => bounds,
+            None => return,
+        };
+        let lng_step = nice_step(bounds.lng.1 - bounds.lng.0, self.hours);
+        let lat_step = nice_step(bounds.lat.1 - bounds.lat.0, false);
+
+        let to_screen = |(x, y): (f32, f32)| {
+            [
+                p[0] + (x + 0.5) / tex_size.0 * size[0],
+                p[1] + size[1] - (y + 0.5) / tex_size.1 * size[1],
+            ]
+        };
+        // Longitude wraps around at 360 degrees. Any larger jump between
+        // consecutive points on screen should not be drawn.
+        let max_jump = tex_size.0.max(tex_size.1) / 4.0;
+
+        let bottom_right_corner = [p[0] + size[0], p[1] + size[1]];
+        draw_list.with_clip_rect_intersect(p, bottom_right_corner, || {
+            for lng in multiples(bounds.lng, lng_step) {
+                let points = sample(bounds.lat, |lat| (self.world2pix)(lng, lat));
+                draw_polyline(draw_list, &points, max_jump, &to_screen);
+                // Label longitude lines at the bottom of the image
+                let label_point = points
+                    .iter()
+                    .cloned()
+                    .filter(|&point| is_inside(point, tex_size))
+                    .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+                if let Some(point) = label_point {
+                    let [x, y] = to_screen(point);
+                    let label = format_sexagesimal(lng, self.hours, lng_step);
+                    draw_list.add_text([x + 2.0, y - 16.0], LABEL_COLOR, label);
+                }
+            }
+            for lat in multiples(bounds.lat, lat_step) {
+                let points = sample(bounds.lng, |lng| (self.world2pix)(lng, lat));
+                draw_polyline(draw_list, &points, max_jump, &to_screen);
+                // Label latitude lines at the left of the image
+                let label_point = points
+                    .iter()
+                    .cloned()
+                    .filter(|&point| is_inside(point, tex_size))
+                    .min_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+                if let Some(point) = label_point {
+                    let [x, y] = to_screen(point);
+                    let label = format_sexagesimal(lat, false, lat_step);
+                    draw_list.add_text([x + 2.0, y + 1.0], LABEL_COLOR, label);
+                }
+            }
+        });
+    }
+
+    /// Find the ranges of longitudes and latitudes covered by the image.
+    ///
+    /// The range of longitudes may go beyond [0, 360) if the image covers
+    /// the origin of longitudes.
+    fn bounds(&self, tex_size: (f32, f32)) -> Option<Bounds> {
+        let (center_lng, _) = (self.pix2world)((tex_size.0 - 1.0) / 2.0, (tex_size.1 - 1.0) / 2.0);
+        if !center_lng.is_finite() {
+            return None;
+        }
+
+        let mut lng = (std::f32::INFINITY, std::f32::NEG_INFINITY);
+        let mut lat = (std::f32::INFINITY, std::f32::NEG_INFINITY);
+        for i in 0..=BOUND_SAMPLES {
+            for j in 0..=BOUND_SAMPLES {
+                let x = -0.5 + i as f32 / BOUND_SAMPLES as f32 * tex_size.0;
+                let y = -0.5 + j as f32 / BOUND_SAMPLES as f32 * tex_size.1;
+                let (point_lng, point_lat) = (self.pix2world)(x, y);
+                if !point_lng.is_finite() || !point_lat.is_finite() {
+                    continue;
+                }
+                // Longitude relative to the center, in [-180, 180)
+                let d_lng = (point_lng - center_lng + 540.0) % 360.0 - 180.0;
+                lng = (lng.0.min(d_lng), lng.1.max(d_lng));
+                lat = (lat.0.min(point_lat), lat.1.max(point_lat));
+            }
+        }
+        if !lng.0.is_finite() || !lat.0.is_finite() {
+            return None;
+        }
+
+        // If a pole is in the image, all longitudes are visible around it
+        for &pole in &[90.0, -90.0] {
+            if is_inside((self.world2pix)(center_lng, pole), tex_size) {
+                // Leave out +180, the same line as -180
+                lng = (-180.0, 179.999);
+                lat = (lat.0.min(pole), lat.1.max(pole));
+            }
+        }
+
+        Some(Bounds {
+            lng: (center_lng + lng.0, center_lng + lng.1),
+            lat,
+        })
+    }
+}
+
+/// Whether pixel `(x, y)` is within an image of `tex_size` pixels.
+fn is_inside((x, y): (f32, f32), tex_size: (f32, f32)) -> bool {
+    x >= -0.5 && x <= tex_size.0 - 0.5 && y >= -0.5 && y <= tex_size.1 - 0.5
+}
+
+/// Iterate over the multiples of `step` within `range`.
+fn multiples((min, max): (f32, f32), step: f32) -> impl Iterator<Item = f32> {
+    let first = (min / step).ceil() as i64;
+    let last = (max / step).floor() as i64;
+    (first..=last).map(move |k| k as f32 * step)
+}
+
+/// Sample `f` along `range` to draw a line.
+fn sample<F>((min, max): (f32, f32), f: F) -> Vec<(f32, f32)>
+where
+    F: Fn(f32) -> (f32, f32),
+{
+    (0..=LINE_SEGMENTS)
+        .map(|i| f(min + i as f32 / LINE_SEGMENTS as f32 * (max - min)))
+        .collect()
+}
+
+fn draw_polyline<F>(draw_list: &DrawListMut, points: &[(f32, f32)], max_jump: f32, to_screen: &F)
+where
+    F: Fn((f32, f32)) -> [f32; 2],
+{
+    for segment in points.windows(2) {
+        let ((x0, y0), (x1, y1)) = (segment[0], segment[1]);
+        let is_finite = x0.is_finite() && y0.is_finite() && x1.is_finite() && y1.is_finite();
+        if is_finite && (x1 - x0).hypot(y1 - y0) < max_jump {
+            draw_list
+                .add_line(to_screen(segment[0]), to_screen(segment[1]), LINE_COLOR)
+                .build();
+        }
+    }
+}
+
+/// Choose a round spacing between lines to cover `range` degrees with at most
+/// `MAX_LINES` lines.
+///
+/// If `hours` is true, spacings are round numbers of hours, minutes and
+/// seconds of right ascension.
+fn nice_step(range: f32, hours: bool) -> f32 {
+    const SUBDIVISIONS: [f32; 6] = [1.0, 2.0, 5.0, 10.0, 15.0, 30.0];
+    let unit = if hours { 15.0 } else { 1.0 };
+    let largest: &[f32] = if hours {
+        &[1.0, 2.0, 3.0, 4.0, 6.0, 12.0]
+    } else {
+        &[1.0, 2.0, 5.0, 10.0, 15.0, 30.0, 45.0, 90.0]
+    };
+    let seconds = SUBDIVISIONS.iter().map(|s| s / 3600.0);
+    let minutes = SUBDIVISIONS.iter().map(|s| s / 60.0);
+    let steps = seconds.chain(minutes).chain(largest.iter().cloned());
+
+    let mut step = unit;
+    for s in steps {
+        step = s * unit;
+        if range / step <= MAX_LINES {
+            break;
+        }
+    }
+    step
+}
+
+/// Format `degrees` in sexagesimal notation, with the precision required by
+/// the spacing `step` in degrees.
+///
+/// If `hours` is true, `degrees` is formatted as a right ascension in hours,
+/// minutes and seconds. Otherwise, it is formatted in degrees, arcminutes and
+/// arcseconds.
+fn format_sexagesimal(degrees: f32, hours: bool, step: f32) -> String {
+    let (value, step, sign, units) = if hours {
+        let value = (degrees % 360.0 + 360.0) % 360.0 / 15.0;
+        (value, step / 15.0, "", ["h", "m", "s"])
+    } else {
+        let sign = if degrees < 0.0 { "-" } else { "+" };
+        (degrees.abs(), step, sign, ["°", "'", "\""])
+    };
+
+    let total_seconds = (value as f64 * 3600.0).round() as i64;
+    let mut first = total_seconds / 3600;
+    if hours {
+        first %= 24;
+    }
+    let minutes = total_seconds / 60 % 60;
+    let seconds = total_seconds % 60;
+    if step >= 1.0 - 1e-4 {
+        format!("{}{}{}", sign, first, units[0])
+    } else if step >= 1.0 / 60.0 - 1e-6 {
+        format!("{}{}{}{:02}{}", sign, first, units[0], minutes, units[1])
+    } else {
+        format!(
+            "{}{}{}{:02}{}{:02}{}",
+            sign, first, units[0], minutes, units[1], seconds, units[2]
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{format_sexagesimal, nice_step};
+    #[test]
+    fn test_format_sexagesimal() {
+        assert_eq!(format_sexagesimal(45.0, false, 5.0), "+45°");
+        assert_eq!(format_sexagesimal(-30.5, false, 0.5), "-30°30'");
+        assert_eq!(
+            format_sexagesimal(10.0 + 1.0 / 240.0, false, 1.0 / 3600.0),
+            "+10°00'15\""
+        );
+        assert_eq!(format_sexagesimal(195.0, true, 15.0), "13h");
+        assert_eq!(format_sexagesimal(-15.0, true, 15.0 / 60.0), "23h00m");
+        assert_eq!(format_sexagesimal(15.125, true, 15.0 / 3600.0), "1h00m30s");
+    }
+
+    #[test]
+    fn test_nice_step() {
+        let assert_close = |a: f32, b: f32| assert!((a - b).abs() < 1e-6, "{} != {}", a, b);
+        assert_close(nice_step(20.0, false), 5.0);
+        assert_close(nice_step(0.9, false), 10.0 / 60.0);
+        assert_close(nice_step(80.0, true), 15.0);
+        assert_close(nice_step(0.9, true), 15.0 / 60.0);
+    }
+}
@@ -1,4 +1,4 @@
-use ndarray::{ArrayBase, Axis, Data, Ix2, Ix3};
+use ndarray::{ArrayBase, ArrayView2, Axis, Data, Ix2, Ix3};
 
 #[derive(Copy, Clone, Debug)]
 pub struct Bin {
@@ -7,7 +7,14 @@ pub struct Bin {
     pub count: usize,
 }
 
-pub fn histogram<S>(data: &ArrayBase<S, Ix2>, min: f32, max: f32) -> Vec<Bin>
+/// Make histogram of the values of `data`, leaving out values where `mask`
+/// is true.
+pub fn histogram<S>(
+    data: &ArrayBase<S, Ix2>,
+    mask: Option<&ArrayView2<bool>>,
+    min: f32,
+    max: f32,
+) -> Vec<Bin>
 where
     S: Data<Elem = f32>,
 {
@@ -22,7 +29,10 @@ where
         });
     }
 
-    for val in data.iter() {
+    for (idx, val) in data.indexed_iter() {
+        if mask.and_then(|mask| mask.get(idx)) == Some(&true) {
+            continue;
+        }
         let i = (*val - min) / (max - min) * HISTOGRAM_BIN_COUNT as f32;
         let mut i = i as usize;
         if i >= HISTOGRAM_BIN_COUNT {
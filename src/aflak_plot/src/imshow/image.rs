@@ -17,8 +17,12 @@ use super::lut::ColorLUT;
 use super::{Error, Textures};
 use crate::lims;
 
+/// Color of invalid pixels
+const MASKED_COLOR: [u8; 3] = [0x40, 0x40, 0x40];
+
 fn make_raw_image<S>(
     image: &ArrayBase<S, Ix2>,
+    mask: Option<&ArrayView2<bool>>,
     vmin: f32,
     vmax: f32,
     lut: &ColorLUT,
@@ -30,9 +34,13 @@ where
     let mut data = Vec::with_capacity(3 * n * m);
 
     if !vmin.is_nan() && !vmax.is_nan() {
-        for val in image.iter() {
+        for (idx, val) in image.indexed_iter() {
             // Make data
-            let [r, g, b] = lut.color_at_bounds(*val, vmin, vmax);
+            let [r, g, b] = if mask.and_then(|mask| mask.get(idx)) == Some(&true) {
+                MASKED_COLOR
+            } else {
+                lut.color_at_bounds(*val, vmin, vmax)
+            };
             data.push(r);
             data.push(g);
             data.push(b);
@@ -95,6 +103,8 @@ pub struct Image<I> {
     tex_size: (f32, f32),
     created_on: Option<Instant>,
     data: Option<I>,
+    /// Invalid pixels of `data`, if any
+    mask: Option<ArrayD<bool>>,
     hist: Vec<hist::Bin>,
     hist_color: Vec<[hist::Bin; 3]>,
 }
@@ -114,6 +124,7 @@ impl<I> Default for Image<I> {
             tex_size: (0.0, 0.0),
             created_on: None,
             data: None,
+            mask: None,
             hist: vec![],
             hist_color: vec![],
         }
@@ -140,8 +151,11 @@ impl<I> Image<I>
 where
     I: Borrow<ArrayD<f32>>,
 {
+    /// Make a new image. Pixels where `mask` is true are invalid. They are
+    /// drawn in gray and left out of the histogram and of color limits.
     pub fn new<F>(
         image: I,
+        mask: Option<ArrayD<bool>>,
         created_on: Instant,
         ctx: &F,
         texture_id: TextureId,
@@ -153,11 +167,13 @@ where
     {
         let (vmin, vmax, vmed, vmad, tex_size, hist) = {
             let image = coerce_to_array_view2(&image);
-            let vmin = lims::get_vmin(&image)?;
-            let vmax = lims::get_vmax(&image)?;
-            let vmed = lims::get_vmed_normalized(&image)?;
-            let vmad = lims::get_vmad_normalized(&image, vmed)?;
-            let raw = make_raw_image(&image, vmin, vmax, lut)?;
+            let mask = mask.as_ref().map(coerce_to_array_view2);
+            let mask = mask.as_ref();
+            let vmin = lims::get_vmin_masked(&image, mask)?;
+            let vmax = lims::get_vmax_masked(&image, mask)?;
+            let vmed = lims::get_vmed_normalized_masked(&image, mask)?;
+            let vmad = lims::get_vmad_normalized_masked(&image, mask, vmed)?;
+            let raw = make_raw_image(&image, mask, vmin, vmax, lut)?;
             let gl_texture = Texture2d::new(ctx, raw)?;
             let tex_size = gl_texture.dimensions();
             let tex_size = (tex_size.0 as f32, tex_size.1 as f32);
@@ -172,7 +188,7 @@ where
                 },
             );
 
-            let hist = hist::histogram(&image, vmin, vmax);
+            let hist = hist::histogram(&image, mask, vmin, vmax);
             (vmin, vmax, vmed, vmad, tex_size, hist)
         };
 
@@ -188,6 +204,7 @@ where
             tex_size,
             created_on: Some(created_on),
             data: Some(image),
+            mask,
             hist,
             hist_color: vec![],
         })
@@ -253,6 +270,7 @@ where
             tex_size,
             created_on: Some(created_on),
             data: Some(image),
+            mask: None,
             hist: vec![],
             hist_color,
         })
@@ -270,7 +288,8 @@ where
     {
         if let Some(data) = &self.data {
             let image = coerce_to_array_view2(data);
-            let raw = make_raw_image(&image, self.vmin, self.vmax, lut)?;
+            let mask = self.mask.as_ref().map(coerce_to_array_view2);
+            let raw = make_raw_image(&image, mask.as_ref(), self.vmin, self.vmax, lut)?;
             let gl_texture = Texture2d::new(ctx, raw)?;
             textures.replace(
                 texture_id,
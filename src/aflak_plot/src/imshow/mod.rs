@@ -1,4 +1,5 @@
 //! Draw 2D images.
+mod graticule;
 mod hist;
 mod image;
 mod lut;
@@ -9,6 +10,7 @@ pub extern crate aflak_cake as cake;
 pub extern crate aflak_primitives as primitives;
 pub extern crate node_editor;
 
+pub use self::graticule::Graticule;
 pub use self::interactions::InteractionId;
 pub use self::state::State;
 
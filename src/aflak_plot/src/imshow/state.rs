@@ -11,6 +11,7 @@ use std::borrow::Borrow;
 use std::collections::HashMap;
 use std::time::Instant;
 
+use super::graticule::Graticule;
 use super::image;
 use super::interactions::{
     Circle, ColorLims, FinedGrainedROI, HorizontalLine, Interaction, InteractionId,
@@ -52,6 +53,10 @@ pub struct State<I> {
     pub zoomkind: [bool; 4],
     scrolling: [f32; 2],
     parent_offset: [f32; 2],
+    pix2world: Option<Box<dyn Fn(f32, f32) -> (f32, f32)>>,
+    graticule: Option<Graticule>,
+    pub show_graticule: bool,
+    mask: Option<ArrayD<bool>>,
 }
 
 #[derive(Default)]
@@ -126,6 +131,10 @@ impl<I> Default for State<I> {
             zoomkind: [true, false, false, false],
             scrolling: [0.0, 0.0],
             parent_offset: [0.0, 0.0],
+            pix2world: None,
+            graticule: None,
+            show_graticule: false,
+            mask: None,
         }
     }
 }
@@ -160,7 +169,15 @@ where
     where
         F: Facade,
     {
-        self.image = image::Image::new(image, created_on, ctx, texture_id, textures, &self.lut)?;
+        self.image = image::Image::new(
+            image,
+            self.mask.clone(),
+            created_on,
+            ctx,
+            texture_id,
+            textures,
+            &self.lut,
+        )?;
         Ok(())
     }
 
@@ -186,6 +203,30 @@ where
         Ok(())
     }
 
+    /// Set invalid pixels of the images passed to `set_image` afterwards.
+    ///
+    /// `mask` is true for invalid pixels and must have the same shape as the
+    /// image.
+    pub fn set_mask(&mut self, mask: Option<ArrayD<bool>>) {
+        self.mask = mask;
+    }
+
+    /// Set the transformation from pixel to world coordinates of both axes.
+    ///
+    /// Used instead of the transformation of each axis when world coordinates
+    /// of the point under the mouse are shown. This is required when world
+    /// coordinates along an axis depend on both pixel coordinates, e.g. for
+    /// celestial coordinates.
+    pub fn set_pix2world(&mut self, pix2world: Option<Box<dyn Fn(f32, f32) -> (f32, f32)>>) {
+        self.pix2world = pix2world;
+    }
+
+    /// Set the celestial coordinates used to draw a graticule on the image,
+    /// if `show_graticule` is set.
+    pub fn set_graticule(&mut self, graticule: Option<Graticule>) {
+        self.graticule = graticule;
+    }
+
     pub fn image_created_on(&self) -> Option<Instant> {
         self.image.created_on()
     }
@@ -1035,6 +1076,11 @@ where
                 s = p;
                 Image::new(texture_id, size).build(ui);
                 ticks.draw(&draw_list, p, size);
+                if self.show_graticule {
+                    if let Some(graticule) = &self.graticule {
+                        graticule.draw(&draw_list, tex_size, p, size);
+                    }
+                }
                 const MIN_WIDTH: f32 = 100.0;
                 const MIN_HEIGHT: f32 = 100.0;
                 let available_size = (
@@ -1069,14 +1115,8 @@ where
                             if y < self.image.dim().0 {
                                 let index = [self.image.dim().0 - 1 - y, x];
                                 if let Some(val) = self.image.get(index) {
-                                    let x_measurement = xaxis.map(|axis| Measurement {
-                                        v: axis.pix2world(x as f32),
-                                        unit: axis.unit(),
-                                    });
-                                    let y_measurement = yaxis.map(|axis| Measurement {
-                                        v: axis.pix2world(y as f32),
-                                        unit: axis.unit(),
-                                    });
+                                    let (x_measurement, y_measurement) =
+                                        self.measurements((x, y), xaxis, yaxis);
                                     let text = self.make_tooltip(
                                         (x, y),
                                         x_measurement,
@@ -1115,14 +1155,8 @@ where
                                 let rindex = [0, self.image.dim().1 - 1 - y, x];
                                 let gindex = [1, self.image.dim().1 - 1 - y, x];
                                 let bindex = [2, self.image.dim().1 - 1 - y, x];
-                                let x_measurement = xaxis.map(|axis| Measurement {
-                                    v: axis.pix2world(x as f32),
-                                    unit: axis.unit(),
-                                });
-                                let y_measurement = yaxis.map(|axis| Measurement {
-                                    v: axis.pix2world(y as f32),
-                                    unit: axis.unit(),
-                                });
+                                let (x_measurement, y_measurement) =
+                                    self.measurements((x, y), xaxis, yaxis);
                                 if let (Some(rval), Some(gval), Some(bval)) = (
                                     self.image.get_color(rindex),
                                     self.image.get_color(gindex),
@@ -2154,6 +2188,36 @@ where
         }
     }
 
+    /// Get world coordinates of pixel (`x`, `y`).
+    fn measurements<'a, FX, FY>(
+        &self,
+        (x, y): (usize, usize),
+        xaxis: Option<&'a AxisTransform<FX>>,
+        yaxis: Option<&'a AxisTransform<FY>>,
+    ) -> (Option<Measurement<'a>>, Option<Measurement<'a>>)
+    where
+        FX: Fn(f32) -> f32,
+        FY: Fn(f32) -> f32,
+    {
+        let (x_world, y_world) = match &self.pix2world {
+            Some(pix2world) => pix2world(x as f32, y as f32),
+            None => (
+                xaxis.map_or(0.0, |axis| axis.pix2world(x as f32)),
+                yaxis.map_or(0.0, |axis| axis.pix2world(y as f32)),
+            ),
+        };
+        (
+            xaxis.map(|axis| Measurement {
+                v: x_world,
+                unit: axis.unit(),
+            }),
+            yaxis.map(|axis| Measurement {
+                v: y_world,
+                unit: axis.unit(),
+            }),
+        )
+    }
+
     fn make_tooltip(
         &self,
         (x_p, y_p): (usize, usize),
@@ -9,11 +9,26 @@ where
     S: ndarray::Data<Elem = f32>,
     D: ndarray::Dimension,
 {
-    let min = image
-        .iter()
-        .min_by(|&&f1, &&f2| float_compare_nan_max(f1, f2));
+    vmin(image.iter().cloned())
+}
+
+/// Same as [`get_vmin`], leaving out values of `image` where `mask` is true.
+pub fn get_vmin_masked<S, M, D>(
+    image: &ArrayBase<S, D>,
+    mask: Option<&ArrayBase<M, D>>,
+) -> Result<f32, Error>
+where
+    S: ndarray::Data<Elem = f32>,
+    M: ndarray::Data<Elem = bool>,
+    D: ndarray::Dimension,
+{
+    vmin(valid_values(image, mask)?)
+}
+
+fn vmin<I: Iterator<Item = f32>>(values: I) -> Result<f32, Error> {
+    let min = values.min_by(|&f1, &f2| float_compare_nan_max(f1, f2));
     if let Some(min) = min {
-        Ok(*min)
+        Ok(min)
     } else {
         Err(Error::Msg("Could not get vmin"))
     }
@@ -24,11 +39,26 @@ where
     S: ndarray::Data<Elem = f32>,
     D: ndarray::Dimension,
 {
-    let max = image
-        .iter()
-        .max_by(|&&f1, &&f2| float_compare_nan_min(f1, f2));
+    vmax(image.iter().cloned())
+}
+
+/// Same as [`get_vmax`], leaving out values of `image` where `mask` is true.
+pub fn get_vmax_masked<S, M, D>(
+    image: &ArrayBase<S, D>,
+    mask: Option<&ArrayBase<M, D>>,
+) -> Result<f32, Error>
+where
+    S: ndarray::Data<Elem = f32>,
+    M: ndarray::Data<Elem = bool>,
+    D: ndarray::Dimension,
+{
+    vmax(valid_values(image, mask)?)
+}
+
+fn vmax<I: Iterator<Item = f32>>(values: I) -> Result<f32, Error> {
+    let max = values.max_by(|&f1, &f2| float_compare_nan_min(f1, f2));
     if let Some(max) = max {
-        Ok(*max)
+        Ok(max)
     } else {
         Err(Error::Msg("Could not get vmax"))
     }
@@ -38,12 +68,26 @@ pub fn get_vmed_normalized<S, D>(image: &ArrayBase<S, D>) -> Result<f32, Error>
 where
     S: ndarray::Data<Elem = f32>,
     D: ndarray::Dimension,
+{
+    get_vmed_normalized_masked(image, None::<&ArrayBase<ndarray::OwnedRepr<bool>, D>>)
+}
+
+/// Same as [`get_vmed_normalized`], leaving out values of `image` where
+/// `mask` is true.
+pub fn get_vmed_normalized_masked<S, M, D>(
+    image: &ArrayBase<S, D>,
+    mask: Option<&ArrayBase<M, D>>,
+) -> Result<f32, Error>
+where
+    S: ndarray::Data<Elem = f32>,
+    M: ndarray::Data<Elem = bool>,
+    D: ndarray::Dimension,
 {
     let mut data = Vec::new();
-    let vmax = get_vmax(image);
-    let vmin = get_vmin(image);
+    let vmax = get_vmax_masked(image, mask);
+    let vmin = get_vmin_masked(image, mask);
     if let (Ok(vmax), Ok(vmin)) = (vmax, vmin) {
-        for i in image.iter() {
+        for i in valid_values(image, mask)? {
             let d = (i - vmin) / (vmax - vmin);
             if !d.is_nan() && !d.is_infinite() {
                 data.push(d);
@@ -71,17 +115,36 @@ pub fn get_vmad_normalized<S, D>(image: &ArrayBase<S, D>, median: f32) -> Result
 where
     S: ndarray::Data<Elem = f32>,
     D: ndarray::Dimension,
+{
+    get_vmad_normalized_masked(
+        image,
+        None::<&ArrayBase<ndarray::OwnedRepr<bool>, D>>,
+        median,
+    )
+}
+
+/// Same as [`get_vmad_normalized`], leaving out values of `image` where
+/// `mask` is true.
+pub fn get_vmad_normalized_masked<S, M, D>(
+    image: &ArrayBase<S, D>,
+    mask: Option<&ArrayBase<M, D>>,
+    median: f32,
+) -> Result<f32, Error>
+where
+    S: ndarray::Data<Elem = f32>,
+    M: ndarray::Data<Elem = bool>,
+    D: ndarray::Dimension,
 {
     let mut data = Vec::new();
-    let vmax = get_vmax(image);
-    let vmin = get_vmin(image);
+    let vmax = get_vmax_masked(image, mask);
+    let vmin = get_vmin_masked(image, mask);
     let vmed = if median.is_nan() {
-        get_vmed_normalized(image)
+        get_vmed_normalized_masked(image, mask)
     } else {
         Ok(median)
     };
     if let (Ok(vmax), Ok(vmin), Ok(vmed)) = (vmax, vmin, vmed) {
-        for i in image.iter() {
+        for i in valid_values(image, mask)? {
             let d = ((i - vmin) / (vmax - vmin) - vmed).abs();
             if !d.is_nan() && !d.is_infinite() {
                 data.push(d);
@@ -105,6 +168,33 @@ where
     }
 }
 
+/// Iterate over the values of `image` where `mask` is false, or over all
+/// values if there is no mask.
+pub(crate) fn valid_values<'a, S, M, D>(
+    image: &'a ArrayBase<S, D>,
+    mask: Option<&'a ArrayBase<M, D>>,
+) -> Result<Box<dyn Iterator<Item = f32> + 'a>, Error>
+where
+    S: ndarray::Data<Elem = f32>,
+    M: ndarray::Data<Elem = bool>,
+    D: ndarray::Dimension + 'a,
+{
+    match mask {
+        Some(mask) => {
+            if mask.shape() != image.shape() {
+                return Err(Error::Msg("Mask and image have different shapes"));
+            }
+            let values = image
+                .iter()
+                .zip(mask.iter())
+                .filter(|(_, masked)| !**masked)
+                .map(|(&v, _)| v);
+            Ok(Box::new(values))
+        }
+        None => Ok(Box::new(image.iter().cloned())),
+    }
+}
+
 fn float_compare_nan_min(f1: f32, f2: f32) -> Ordering {
     PartialOrd::partial_cmp(&f1, &f2).unwrap_or_else(|| match (f32::is_nan(f1), f32::is_nan(f2)) {
         (true, true) => Ordering::Equal,
@@ -122,3 +212,19 @@ fn float_compare_nan_max(f1: f32, f2: f32) -> Ordering {
         _ => unreachable!(),
     })
 }
+
+#[cfg(test)]
+mod test {
+    use super::{get_vmax_masked, get_vmin, get_vmin_masked};
+    use ndarray::arr1;
+
+    #[test]
+    fn test_masked_lims() {
+        let image = arr1(&[-100.0, 1.0, 2.0, 300.0]);
+        let mask = arr1(&[true, false, false, true]);
+        assert_eq!(get_vmin(&image).unwrap(), -100.0);
+        assert_eq!(get_vmin_masked(&image, Some(&mask)).unwrap(), 1.0);
+        assert_eq!(get_vmax_masked(&image, Some(&mask)).unwrap(), 2.0);
+        assert!(get_vmin_masked(&image, Some(&arr1(&[true; 4]))).is_err());
+    }
+}
@@ -1,5 +1,5 @@
 use imgui::{MenuItem, MouseButton, MouseCursor, Ui};
-use ndarray::{ArrayBase, Data, Ix1};
+use ndarray::{Array1, ArrayBase, Data, Ix1};
 use std::collections::HashMap;
 
 use super::interactions::{
@@ -25,6 +25,8 @@ pub struct State {
     zoom: [f32; 2],
     mouse_pos: [f32; 2],
     interactions: Interactions,
+    uncertainty: Option<Vec<f32>>,
+    mask: Option<Array1<bool>>,
     pub show_axis_option: bool,
 }
 
@@ -36,6 +38,8 @@ impl Default for State {
             zoom: [1.0, 1.0],
             mouse_pos: [f32::NAN, f32::NAN],
             interactions: Interactions::new(),
+            uncertainty: None,
+            mask: None,
             show_axis_option: false,
         }
     }
@@ -50,6 +54,18 @@ impl State {
         self.interactions.iter_mut()
     }
 
+    /// Set uncertainty (standard deviation) of each value of the plotted
+    /// image. Error bars are drawn if it is set.
+    pub fn set_uncertainty(&mut self, uncertainty: Option<Vec<f32>>) {
+        self.uncertainty = uncertainty;
+    }
+
+    /// Set invalid values of the plotted image, for which `mask` is true.
+    /// Invalid values are not drawn and are left out of the plot limits.
+    pub fn set_mask(&mut self, mask: Option<Array1<bool>>) {
+        self.mask = mask;
+    }
+
     pub(crate) fn plot<D, F>(
         &mut self,
         ui: &Ui,
@@ -69,8 +85,10 @@ impl State {
         D: Data<Elem = f32>,
         F: Fn(f32) -> f32,
     {
-        let min = lims::get_vmin(image)?;
-        let max = lims::get_vmax(image)?;
+        let mask = self.mask.as_ref().filter(|mask| mask.len() == image.len());
+        let is_masked = |i: usize| mask.map_or(false, |mask| mask[i]);
+        let min = lims::get_vmin_masked(image, mask)?;
+        let max = lims::get_vmax_masked(image, mask)?;
 
         let xvlims = (0.0, (image.len() - 1) as f32);
         let yvlims = (min, max);
@@ -116,6 +134,7 @@ impl State {
 
         const BG_COLOR: u32 = 0xA033_3333;
         const LINE_COLOR: u32 = 0xFFFF_FFFF;
+        const ERROR_BAR_COLOR: u32 = 0x80FF_FFFF;
 
         let bottom_right_corner = [p[0] + size[0], p[1] + size[1]];
 
@@ -128,6 +147,9 @@ impl State {
             let first = image.iter().enumerate();
             let second = image.iter().enumerate().skip(1);
             for ((x1, y1), (x2, y2)) in first.zip(second) {
+                if is_masked(x1) || is_masked(x2) {
+                    continue;
+                }
                 let x1 = x1 as f32;
                 let x2 = x2 as f32;
                 let p0 = [
@@ -140,6 +162,24 @@ impl State {
                 ];
                 draw_list.add_line(p0, p1, LINE_COLOR).build();
             }
+
+            if let Some(uncertainty) = &self.uncertainty {
+                for ((x, y), sigma) in image.iter().enumerate().zip(uncertainty) {
+                    if !sigma.is_finite() || is_masked(x) {
+                        continue;
+                    }
+                    let x = p[0] + (x as f32 - xlims.0) / (xlims.1 - xlims.0) * size[0];
+                    let to_screen_y =
+                        |y: f32| p[1] + size[1] - (y - ylims.0) / (ylims.1 - ylims.0) * size[1];
+                    draw_list
+                        .add_line(
+                            [x, to_screen_y(y - sigma)],
+                            [x, to_screen_y(y + sigma)],
+                            ERROR_BAR_COLOR,
+                        )
+                        .build();
+                }
+            }
         });
 
         let mouse_x = ui.io().mouse_pos[0];
@@ -152,7 +192,12 @@ impl State {
                     unit: axis.unit(),
                 });
                 let val = Measurement { v: *y, unit: vunit };
-                let text = self.make_tooltip(point, x, val);
+                let sigma = self
+                    .uncertainty
+                    .as_ref()
+                    .and_then(|uncertainty| uncertainty.get(point))
+                    .cloned();
+                let text = self.make_tooltip(point, x, val, sigma);
                 ui.tooltip_text(text);
             }
 
@@ -329,7 +374,13 @@ impl State {
         Ok(())
     }
 
-    fn make_tooltip(&self, point: usize, x: Option<Measurement>, y: Measurement) -> String {
+    fn make_tooltip(
+        &self,
+        point: usize,
+        x: Option<Measurement>,
+        y: Measurement,
+        sigma: Option<f32>,
+    ) -> String {
         let x_str = if let Some(x) = x {
             if x.unit.is_empty() {
                 format!("X:   {:.2}", x.v)
@@ -340,10 +391,11 @@ impl State {
             format!("X:    {}", point)
         };
 
-        let val = if y.unit.is_empty() {
-            format!("VAL: {:.2}", y.v)
-        } else {
-            format!("VAL: {:.2} {}", y.v, y.unit)
+        let val = match (sigma, y.unit.is_empty()) {
+            (Some(sigma), true) => format!("VAL: {:.2} ± {:.2}", y.v, sigma),
+            (Some(sigma), false) => format!("VAL: {:.2} ± {:.2} {}", y.v, sigma, y.unit),
+            (None, true) => format!("VAL: {:.2}", y.v),
+            (None, false) => format!("VAL: {:.2} {}", y.v, y.unit),
         };
 
         if x.is_some() {
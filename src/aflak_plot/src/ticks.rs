@@ -58,6 +58,18 @@ impl XYTicks {
     }
 }
 
+/// Whether axis named `label` (`CTYPEi` FITS keyword) holds right ascensions,
+/// whatever the projection.
+fn is_right_ascension(label: &str) -> bool {
+    label.starts_with("RA--")
+}
+
+/// Whether axis named `label` (`CTYPEi` FITS keyword) holds declinations,
+/// whatever the projection.
+fn is_declination(label: &str) -> bool {
+    label.starts_with("DEC-")
+}
+
 fn transform_to_format_imstrings<F>(
     ui: &Ui,
     lims: (f32, f32),
@@ -81,7 +93,7 @@ where
                     let r_transformed = axis.pix2world(r_point) - axis.pix2world(center);
                     let label = if use_ms {
                         match axis.label() {
-                            "RA---TAN" => {
+                            label if is_right_ascension(label) => {
                                 let l_hours = (l_transformed / 15.0).trunc();
                                 let l_minutes =
                                     ((l_transformed / 15.0).fract() * 60.0).trunc().abs();
@@ -135,7 +147,7 @@ where
                                 }
                                 [ImString::new(l_s), ImString::new(r_s)]
                             }
-                            "DEC--TAN" => {
+                            label if is_declination(label) => {
                                 let l_degree = l_transformed.trunc();
                                 let l_minutes = (l_transformed.fract() * 60.0).trunc().abs();
                                 let l_seconds =
@@ -218,8 +230,8 @@ where
         let center_label = if let Some(axis) = axis {
             if use_ms {
                 match axis.label() {
-                    "RA---TAN" => ImString::new(format!("0.00s")),
-                    "DEC--TAN" => ImString::new(format!("0.00''")),
+                    label if is_right_ascension(label) => ImString::new(format!("0.00s")),
+                    label if is_declination(label) => ImString::new(format!("0.00''")),
                     _ => ImString::new(format!("0.00")),
                 }
             } else {
@@ -247,14 +259,14 @@ where
                     let transformed = axis.pix2world(point);
                     let label = if use_ms {
                         match axis.label() {
-                            "RA---TAN" => {
+                            label if is_right_ascension(label) => {
                                 let hours = (transformed / 15.0).trunc();
                                 let minutes = ((transformed / 15.0).fract() * 60.0).trunc().abs();
                                 let seconds =
                                     (((transformed / 15.0).fract() * 60.0).fract() * 60.0).abs();
                                 ImString::new(format!("{:2}h{:2}m{:4.2}s", hours, minutes, seconds))
                             }
-                            "DEC--TAN" => {
+                            label if is_declination(label) => {
                                 let degree = transformed.trunc();
                                 let minutes = (transformed.fract() * 60.0).trunc().abs();
                                 let seconds = ((transformed.fract() * 60.0).fract() * 60.0).abs();
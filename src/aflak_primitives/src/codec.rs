@@ -0,0 +1,50 @@
+use std::io::{self, Read, Write};
+use std::time::UNIX_EPOCH;
+
+use crate::{IOValue, PATHS};
+
+/// Codec to persist [`IOValue`]s in a `cake::DiskCache`.
+///
+/// Values are encoded with bincode. Opened FITS files cannot be encoded, so
+/// the results of `open_fits` are never persisted (though the images
+/// extracted from them are).
+#[derive(Copy, Clone, Debug, Default)]
+pub struct IOValueCodec;
+
+impl cake::Codec<IOValue> for IOValueCodec {
+    fn encode(&self, value: &IOValue, w: &mut dyn Write) -> io::Result<()> {
+        bincode::serialize_into(w, value).map_err(into_io_error)
+    }
+
+    fn decode(&self, r: &mut dyn Read) -> io::Result<IOValue> {
+        bincode::deserialize_from(r).map_err(into_io_error)
+    }
+
+    /// Paths are identified with the size and modification time of the
+    /// files they point to, so that results are computed again when the
+    /// files are modified.
+    fn fingerprint(&self, value: &IOValue) -> Option<Vec<u8>> {
+        let mut buf = vec![];
+        self.encode(value, &mut buf).ok()?;
+        if let IOValue::Paths(PATHS::FileList(paths)) = value {
+            for path in paths {
+                if let Ok(metadata) = path.metadata() {
+                    buf.extend_from_slice(&metadata.len().to_le_bytes());
+                    if let Ok(modified) = metadata.modified() {
+                        let since_epoch = modified.duration_since(UNIX_EPOCH).ok()?;
+                        buf.extend_from_slice(&since_epoch.as_secs().to_le_bytes());
+                        buf.extend_from_slice(&since_epoch.subsec_nanos().to_le_bytes());
+                    }
+                }
+            }
+        }
+        Some(buf)
+    }
+}
+
+fn into_io_error(e: bincode::Error) -> io::Error {
+    match *e {
+        bincode::ErrorKind::Io(e) => e,
+        e => io::Error::new(io::ErrorKind::InvalidData, e.to_string()),
+    }
+}
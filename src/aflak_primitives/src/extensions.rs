@@ -0,0 +1,62 @@
+use std::io;
+use std::path::Path;
+use std::slice;
+
+use fitrs::{Fits, Hdu, HeaderValue};
+
+use crate::unit::WcsArray;
+
+/// Images to be saved as named extensions of a single FITS file.
+///
+/// This is typically used to write multi-extension FITS (MEF) files, with
+/// science data, uncertainties and data quality saved in different
+/// extensions of the same file.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct FitsExtensions {
+    extensions: Vec<(String, WcsArray)>,
+}
+
+impl FitsExtensions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Append `image` as a new extension named `name`.
+    pub fn push(&mut self, name: String, image: WcsArray) {
+        self.extensions.push((name, image));
+    }
+
+    /// Check whether an extension named `name` exists. Comparison is
+    /// case-insensitive, as EXTNAME values are.
+    pub fn contains(&self, name: &str) -> bool {
+        self.extensions
+            .iter()
+            .any(|(extname, _)| extname.eq_ignore_ascii_case(name))
+    }
+
+    pub fn iter(&self) -> slice::Iter<'_, (String, WcsArray)> {
+        self.extensions.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.extensions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.extensions.is_empty()
+    }
+
+    /// Write all extensions to a new FITS file at `path`.
+    ///
+    /// The primary HDU is left empty. Each image is written in its own
+    /// extension, in order, with its name saved in the EXTNAME keyword.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut fits = Fits::create(path, Hdu::empty())?;
+        for (name, image) in &self.extensions {
+            let mut hdu = image.to_hdu();
+            hdu.insert("EXTNAME", HeaderValue::CharacterString(name.to_owned()));
+            fits.push(hdu)?;
+        }
+        Ok(())
+    }
+}
@@ -20,6 +20,7 @@ extern crate variant_name;
 extern crate variant_name_derive;
 #[macro_use]
 extern crate aflak_cake as cake;
+extern crate bincode;
 pub extern crate fitrs;
 extern crate imgui_tone_curve;
 extern crate libm;
@@ -34,24 +35,38 @@ extern crate serde_derive;
 #[macro_use]
 extern crate rawloader;
 
+mod codec;
+mod extensions;
 mod fits;
 #[macro_use]
 mod precond;
+mod physical;
 mod roi;
+mod table;
 mod unit;
+mod wcs;
 
+pub use crate::codec::IOValueCodec;
+pub use crate::extensions::FitsExtensions;
+pub use crate::physical::{Conversion, PhysicalUnit, UnitError};
 pub use crate::roi::ROI;
-pub use crate::unit::{DerivedUnit, Dimensioned, Unit, WcsArray};
+pub use crate::table::{Column, ColumnData, Table, TableReadError};
+pub use crate::unit::{Dimensioned, Unit, WcsArray};
+pub use crate::wcs::{Celestial, Projection, WCS};
 
 use std::error::Error;
 use std::fmt;
 use std::io;
+use std::mem;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
+use crate::cake::progress::ProgressCounter;
 use imgui_tone_curve::ToneCurveState;
 use nalgebra::{DMatrix, DVector, Matrix3, Vector3};
-use ndarray::{Array, Array1, Array2, ArrayD, ArrayViewD, Axis, Dimension, ShapeBuilder, Slice};
+use ndarray::{
+    Array, Array1, Array2, ArrayD, ArrayViewD, Axis, Dimension, IxDyn, ShapeBuilder, Slice, Zip,
+};
 use ndarray_parallel::prelude::*;
 use variant_name::VariantName;
 
@@ -80,6 +95,8 @@ pub enum IOValue {
     Roi(roi::ROI),
     Paths(PATHS),
     ToneCurve(ToneCurveState),
+    Table(Table),
+    FitsExtensions(FitsExtensions),
 }
 
 impl PartialEq for IOValue {
@@ -98,11 +115,32 @@ impl PartialEq for IOValue {
             (Map2dTo3dCoords(m1), Map2dTo3dCoords(m2)) => m1 == m2,
             (Roi(r1), Roi(r2)) => r1 == r2,
             (Paths(p1), Paths(p2)) => p1 == p2,
+            (Table(t1), Table(t2)) => t1 == t2,
+            (FitsExtensions(e1), FitsExtensions(e2)) => e1 == e2,
             _ => false,
         }
     }
 }
 
+impl cake::EstimateSize for IOValue {
+    fn estimate_size(&self) -> usize {
+        use crate::IOValue::*;
+        let heap_size = match self {
+            Str(s) => s.len(),
+            Image(image) => image.heap_size(),
+            Map2dTo3dCoords(map) => map.len() * mem::size_of::<[f32; 3]>(),
+            Paths(PATHS::FileList(paths)) => paths.iter().map(|p| p.as_os_str().len()).sum(),
+            Table(table) => table.heap_size(),
+            FitsExtensions(extensions) => extensions
+                .iter()
+                .map(|(name, image)| name.len() + image.heap_size())
+                .sum(),
+            _ => 0,
+        };
+        mem::size_of::<Self>() + heap_size
+    }
+}
+
 /// Error value used for I/O in astronomical transforms.
 ///
 /// If new use cases arise, please add a new variant to this enumeration.
@@ -168,6 +206,32 @@ lazy_static! {
                     vec![run_fits_to_image(fits, *hdu_idx, extension)]
                 }
             ),
+            cake_transform!(
+                "Extract binary or ASCII table from FITS file.",
+                "04. Extract part of data",
+                1, 0, 0,
+                fits_to_table<IOValue, IOErr>(fits: Fits, hdu_idx: Integer = 1, extension: Str = "".to_owned()) -> Table {
+                    vec![run_fits_to_table(fits, *hdu_idx, extension)]
+                }
+            ),
+            cake_transform!(
+                "Extract column from table as a 1D image. Parameter: table, name.
+The column is found by its name (case-insensitive).",
+                "04. Extract part of data",
+                1, 0, 0,
+                extract_column<IOValue, IOErr>(table: Table, name: Str = "".to_owned()) -> Image {
+                    vec![run_extract_column(table, name)]
+                }
+            ),
+            cake_transform!(
+                "Extract column from table as a 1D image. Parameter: table, index.
+The column is found by its index (starting from 0).",
+                "04. Extract part of data",
+                1, 0, 0,
+                extract_column_at<IOValue, IOErr>(table: Table, index: Integer = 0) -> Image {
+                    vec![run_extract_column_at(table, *index)]
+                }
+            ),
             cake_transform!(
                 "Range specification. Parameter: image, start, end.
 Extract data where the 0th axis is from start to end",
@@ -177,6 +241,24 @@ Extract data where the 0th axis is from start to end",
                     vec![run_range_specification(image, *start, *end)]
                 }
             ),
+            cake_transform!(
+                "Extract data quality flags of each value of an image as a new image.
+The image must have a mask, e.g. read from a DQ extension.",
+                "04. Extract part of data",
+                1, 0, 0,
+                extract_mask<IOValue, IOErr>(image: Image) -> Image {
+                    vec![run_extract_mask(image)]
+                }
+            ),
+            cake_transform!(
+                "Extract uncertainty (standard deviation) of each value of an image.
+The image must have an uncertainty plane, e.g. read from an ERR or VAR extension.",
+                "04. Extract part of data",
+                1, 0, 0,
+                extract_uncertainty<IOValue, IOErr>(image: Image) -> Image {
+                    vec![run_extract_uncertainty(image)]
+                }
+            ),
             cake_transform!(
                 "Image's min and max value. Parameter: image.
 Compute v_min(first), v_max(second)",
@@ -254,6 +336,25 @@ The data sizes for xaxis and yaxis must be the same.",
                     vec![run_create_scatter(xaxis, yaxis)]
                 }
             ),
+            cake_transform!(
+                "Gather two images as named extensions of a FITS file.
+Parameters: image1, name1, image2, name2.
+Save the output to write a multi-extension FITS file. Use append_fits_extension to add more images.",
+                "02. Make new data",
+                1, 0, 0,
+                make_fits_extensions<IOValue, IOErr>(image1: Image, name1: Str = "SCI".to_owned(), image2: Image, name2: Str = "ERR".to_owned()) -> FitsExtensions {
+                    vec![run_make_fits_extensions(&[(image1, name1), (image2, name2)])]
+                }
+            ),
+            cake_transform!(
+                "Append an image as a named extension of a FITS file.
+Parameters: extensions, image, name.",
+                "02. Make new data",
+                1, 0, 0,
+                append_fits_extension<IOValue, IOErr>(extensions: FitsExtensions, image: Image, name: Str = "DQ".to_owned()) -> FitsExtensions {
+                    vec![run_append_fits_extension(extensions, image, name)]
+                }
+            ),
             cake_transform!("Replace all values above or below a threshold in a image with NaN.
 Takes two parameters: a threshold and a bool.
 If bool value is checked, then replaces the values above the threshold with NaN, else replace the values below the threshold with NaN.",
@@ -269,12 +370,15 @@ If bool value is checked, then replaces the values above the threshold with NaN,
                 normalize_image<IOValue, IOErr>(image: Image, min: Float = 0.0, max: Float = 1.0) -> Image {
                     let mut out = image.clone();
                     out.scalar_mut().par_iter_mut().for_each(|v| *v = (*v - min) / (max - min));
+                    let variance = image.variance().map(|variance| variance / ((max - min) * (max - min)));
+                    out.set_variance(variance);
                     vec![Ok(IOValue::Image(out))]
                 }
             ),
-            cake_transform!("Replace all NaN values in image with the provided value.",
+            cake_transform!("Replace all NaN values in image with the provided value.
+Replaced values are flagged as invalid in the mask of the image.",
                 "05. Convert data",
-                1, 0, 0,
+                1, 1, 0,
                 replace_nan_image<IOValue, IOErr>(image: Image, placeholder: Float = 0.0) -> Image {
                     vec![run_replace_nan_image(image, *placeholder)]
                 }
@@ -331,11 +435,61 @@ Currently supported tags: \"BPT\", \"color_image\"",
                     vec![run_change_tag(image, tag)]
                 }
             ),
+            cake_transform!(
+                "Flag values of an image out of a range as invalid.
+Parameters: image, min, max, flag.
+The bits of 'flag' are set in the mask of values lower than 'min', greater than 'max' or NaN.
+Invalid values are left out of reductions.",
+                "05. Convert data",
+                1, 0, 0,
+                mask_threshold<IOValue, IOErr>(image: Image, min: Float = 0.0, max: Float = 1.0, flag: Integer = 1) -> Image {
+                    vec![run_mask_threshold(image, *min, *max, *flag)]
+                }
+            ),
+            cake_transform!(
+                "Select the data quality flags of an image considered as invalid.
+Parameters: image, bits.
+Only the flags in the bitmask 'bits' are kept in the mask. Other flags are ignored.",
+                "05. Convert data",
+                1, 0, 0,
+                select_mask_bits<IOValue, IOErr>(image: Image, bits: Integer = 1) -> Image {
+                    vec![run_select_mask_bits(image, *bits)]
+                }
+            ),
+            cake_transform!(
+                "Set uncertainty (standard deviation) of each value of an image.
+Parameters: image, uncertainty. Both images must have the same dimensions.
+The uncertainty is propagated through subsequent computations.",
+                "05. Convert data",
+                1, 0, 0,
+                set_uncertainty<IOValue, IOErr>(image: Image, uncertainty: Image) -> Image {
+                    vec![run_set_uncertainty(image, uncertainty)]
+                }
+            ),
+            cake_transform!(
+                "Convert the values of an image to another unit.
+Parameters: image, unit (e.g. 'Jy', 'erg/s/cm^2/Angstrom', 'ABmag').
+The unit of the image must have the same dimension as the new unit. Spectral flux densities per unit of wavelength can also be converted to flux densities per unit of frequency and back, using the spectral axis of the image.",
+                "05. Convert data",
+                1, 0, 0,
+                convert_unit<IOValue, IOErr>(image: Image, unit: Str = "".to_owned()) -> Image {
+                    vec![run_convert_unit(image, unit)]
+                }
+            ),
+            cake_transform!(
+                "Convert the world coordinates along an axis of an image to another unit.
+Parameters: image, axis, unit (e.g. 'nm').",
+                "05. Convert data",
+                1, 0, 0,
+                convert_axis_unit<IOValue, IOErr>(image: Image, axis: Integer = 0, unit: Str = "".to_owned()) -> Image {
+                    vec![run_convert_axis_unit(image, *axis, unit)]
+                }
+            ),
             cake_transform!(
                 "Compose 2 images. Parameters: u, v, a, b.
 Compute a*u + b*v.",
                 "06. Calculate",
-                1, 0, 0,
+                1, 1, 0,
                 linear_composition<IOValue, IOErr>(u: Image, v: Image, a: Float = 1.0, b: Float = 1.0) -> Image {
                     vec![run_linear_composition(u, v, *a, *b)]
                 }
@@ -501,7 +655,7 @@ Parameters i_off, i_on, onband-width, min, is_emission.
 Compute value = (i1 - i2) * fl / i1 (if is_emission is true, the sign of this value turns over).
 if value > max, value changes to 0.",
                 "10. Create astronomy-specific map",
-                0, 1, 0,
+                0, 2, 0,
                 create_equivalent_width<IOValue, IOErr>(i_off: Image, i_on: Image, fl: Float = 1.0, max: Float = ::std::f32::INFINITY, is_emission: Bool = false) -> Image {
                     vec![run_create_equivalent_width(i_off, i_on, *fl, *max, *is_emission)]
                 }
@@ -513,8 +667,8 @@ Compute Velocity v = c * (w_i - w_0) / w_0   (c = 3e5 [km/s])",
 "10. Create astronomy-specific map",
                 1, 0, 0,
                 create_velocity_field_map<IOValue, IOErr>(image: Image, w_0: Float = 0.0) -> Image {
-                    let c = Unit::Custom("km/s".to_owned()).new(3e5 as f32);
-                    let w_0 = Unit::Custom("Ang".to_owned()).new(w_0.to_owned());
+                    let c = Unit::parse("km/s").new(3e5 as f32);
+                    let w_0 = Unit::parse("Angstrom").new(w_0.to_owned());
                     let result = (image.array() - w_0.clone()) * c / w_0;
                     let original_meta = image.meta();
                     let original_visualization = image.tag();
@@ -530,7 +684,7 @@ Parameters i_off, i_on, onband-width, min, is_emission.
 Compute value = (i1 - i2) * fl (if is_emission is true, the sign of this value turns over).Integral
 if value > max, value changes to 0.",
 "10. Create astronomy-specific map",
-                0, 1, 0,
+                0, 2, 0,
                 create_emission_line_map<IOValue, IOErr>(i_off: Image, i_on: Image, fl: Float = 1.0, max: Float = ::std::f32::INFINITY, is_emission: Bool = false) -> Image {
                     vec![run_create_emission_line_map(i_off, i_on, *fl, *max, *is_emission)]
                 }
@@ -586,6 +740,7 @@ if value > max, value changes to 0.",
                         };
                         *v = c * (max - min) + min;
                     });
+                    out.set_variance(None);
                     vec![Ok(IOValue::Image(out))]
                 }
             ),
@@ -746,6 +901,15 @@ if value > max, value changes to 0.",
     };
 }
 
+lazy_static! {
+    /// Migrations of the transforms saved with older major versions.
+    ///
+    /// When bumping the major version of a transform in `TRANSFORMATIONS`,
+    /// register here how to upgrade the nodes saved with the previous
+    /// version, so that older files can still be opened.
+    pub static ref MIGRATIONS: cake::MigrationRegistry<IOValue> = cake::MigrationRegistry::new();
+}
+
 impl cake::NamedAlgorithms<IOErr> for IOValue {
     fn get_transform(s: &str) -> Option<&'static cake::Transform<'static, IOValue, IOErr>> {
         for t in TRANSFORMATIONS.iter() {
@@ -755,6 +919,10 @@ impl cake::NamedAlgorithms<IOErr> for IOValue {
         }
         None
     }
+
+    fn migrations() -> Option<&'static cake::MigrationRegistry<IOValue>> {
+        Some(&MIGRATIONS)
+    }
 }
 
 impl cake::DefaultFor for IOValue {
@@ -879,15 +1047,11 @@ fn run_open_raw<P: AsRef<Path>>(path: Vec<P>, n: i64) -> Result<IOValue, IOErr>
     }
 }
 
-/// Turn a FITS file into an image
-fn run_fits_to_image(
-    fits: &Arc<fitrs::Fits>,
-    hdu_idx: i64,
-    extension: &str,
-) -> Result<IOValue, IOErr> {
+/// Find HDU named `extension` in `fits`, falling back to HDU at index
+/// `hdu_idx`.
+fn find_hdu(fits: &fitrs::Fits, hdu_idx: i64, extension: &str) -> Result<fitrs::Hdu, IOErr> {
     let hdu_idx = try_into_unsigned!(hdu_idx)?;
-    let primary_hdu = fits
-        .get_by_name(extension)
+    fits.get_by_name(extension)
         .or_else(|| fits.get(hdu_idx))
         .ok_or_else(|| {
             let hdu_name = if hdu_idx == 0 {
@@ -903,25 +1067,147 @@ fn run_fits_to_image(
                     extension, hdu_name
                 ))
             }
-        })?;
-    WcsArray::from_hdu(&primary_hdu)
-        .map(IOValue::Image)
+        })
+}
+
+/// Turn a FITS file into an image
+fn run_fits_to_image(
+    fits: &Arc<fitrs::Fits>,
+    hdu_idx: i64,
+    extension: &str,
+) -> Result<IOValue, IOErr> {
+    let primary_hdu = find_hdu(fits, hdu_idx, extension)?;
+    let mut image =
+        WcsArray::from_hdu(&primary_hdu).map_err(|e| IOErr::FITSErr(format!("{}", e)))?;
+    if !is_uncertainty_hdu(&primary_hdu) && !is_mask_hdu(&primary_hdu) {
+        image.set_variance(read_variance(fits, image.scalar().shape())?);
+        image.set_mask(read_mask(fits, image.scalar().shape())?);
+    }
+    Ok(IOValue::Image(image))
+}
+
+/// Names of extensions containing the variance of the data
+const VARIANCE_EXTNAMES: [&str; 3] = ["VAR", "VARIANCE", "STAT"];
+/// Names of extensions containing the uncertainty (standard deviation) of
+/// the data
+const ERROR_EXTNAMES: [&str; 4] = ["ERR", "ERROR", "SIGMA", "UNCERT"];
+
+/// Names of extensions containing data quality flags of the data
+const MASK_EXTNAMES: [&str; 3] = ["DQ", "MASK", "FLAGS"];
+
+fn is_mask_hdu(hdu: &fitrs::Hdu) -> bool {
+    match hdu.value("EXTNAME") {
+        Some(fitrs::HeaderValue::CharacterString(extname)) => {
+            let extname = extname.trim();
+            MASK_EXTNAMES
+                .iter()
+                .any(|name| name.eq_ignore_ascii_case(extname))
+        }
+        _ => false,
+    }
+}
+
+/// Read data quality flags of data with shape `shape` from the first
+/// extension of `fits` named as a data quality extension.
+///
+/// Return `None` if no such extension exists, or if its shape differs.
+fn read_mask(fits: &fitrs::Fits, shape: &[usize]) -> Result<Option<ArrayD<u32>>, IOErr> {
+    for name in MASK_EXTNAMES.iter() {
+        if let Some(hdu) = fits.get_by_name(name) {
+            let mask = unit::mask_from_hdu(&hdu).map_err(|e| IOErr::FITSErr(format!("{}", e)))?;
+            if mask.shape() != shape {
+                return Ok(None);
+            }
+            return Ok(Some(mask));
+        }
+    }
+    Ok(None)
+}
+
+fn is_uncertainty_hdu(hdu: &fitrs::Hdu) -> bool {
+    match hdu.value("EXTNAME") {
+        Some(fitrs::HeaderValue::CharacterString(extname)) => {
+            let extname = extname.trim();
+            VARIANCE_EXTNAMES
+                .iter()
+                .chain(ERROR_EXTNAMES.iter())
+                .any(|name| name.eq_ignore_ascii_case(extname))
+        }
+        _ => false,
+    }
+}
+
+/// Read variance of data with shape `shape` from the first extension of
+/// `fits` named as a variance or an uncertainty extension.
+///
+/// Return `None` if no such extension exists, or if its shape differs.
+fn read_variance(fits: &fitrs::Fits, shape: &[usize]) -> Result<Option<ArrayD<f32>>, IOErr> {
+    let extensions = VARIANCE_EXTNAMES
+        .iter()
+        .map(|name| (name, false))
+        .chain(ERROR_EXTNAMES.iter().map(|name| (name, true)));
+    for (name, is_standard_deviation) in extensions {
+        if let Some(hdu) = fits.get_by_name(name) {
+            let uncertainty =
+                WcsArray::from_hdu(&hdu).map_err(|e| IOErr::FITSErr(format!("{}", e)))?;
+            if uncertainty.scalar().shape() != shape {
+                return Ok(None);
+            }
+            let variance = if is_standard_deviation {
+                uncertainty.scalar().mapv(|sigma| sigma * sigma)
+            } else {
+                uncertainty.scalar().clone()
+            };
+            return Ok(Some(variance));
+        }
+    }
+    Ok(None)
+}
+
+fn run_fits_to_table(
+    fits: &Arc<fitrs::Fits>,
+    hdu_idx: i64,
+    extension: &str,
+) -> Result<IOValue, IOErr> {
+    let hdu = find_hdu(fits, hdu_idx, extension)?;
+    Table::from_hdu(&hdu)
+        .map(IOValue::Table)
         .map_err(|e| IOErr::FITSErr(format!("{}", e)))
 }
 
+fn column_to_image(column: &Column) -> Result<IOValue, IOErr> {
+    column.to_image().map(IOValue::Image).ok_or_else(|| {
+        IOErr::UnexpectedInput(format!(
+            "Column '{}' contains strings and cannot be converted to an image.",
+            column.name()
+        ))
+    })
+}
+
+fn run_extract_column(table: &Table, name: &str) -> Result<IOValue, IOErr> {
+    let column = table.column(name).ok_or_else(|| {
+        IOErr::UnexpectedInput(format!("Could not find column '{}' in table.", name))
+    })?;
+    column_to_image(column)
+}
+
+fn run_extract_column_at(table: &Table, index: i64) -> Result<IOValue, IOErr> {
+    let index = try_into_unsigned!(index)?;
+    let column = table.columns().get(index).ok_or_else(|| {
+        IOErr::UnexpectedInput(format!(
+            "Column index {} out of range. Table has {} columns.",
+            index,
+            table.columns().len()
+        ))
+    })?;
+    column_to_image(column)
+}
+
 fn run_slice_one_frame(input_img: &WcsArray, frame_idx: i64) -> Result<IOValue, IOErr> {
     let frame_idx = try_into_unsigned!(frame_idx)?;
     is_sliceable!(input_img, frame_idx)?;
 
-    let image_val = input_img.scalar();
-    let out = image_val.index_axis(Axis(0), frame_idx);
-
-    let wrap_with_unit = input_img.make_slice(
-        &(0..out.ndim()).map(|i| (i, 0.0, 1.0)).collect::<Vec<_>>(),
-        input_img.array().with_new_value(out.to_owned()),
-    );
-
-    Ok(IOValue::Image(wrap_with_unit))
+    Ok(IOValue::Image(input_img.index_axis(0, frame_idx)))
 }
 
 /// Slice a 3D image through an arbitrary 2D plane
@@ -1018,74 +1304,18 @@ fn run_slice_3d_to_2d(image: &WcsArray, map: &Array2<[f32; 3]>) -> Result<IOValu
             Self { origin, dir1, dir2 }
         }
 
-        fn sliced_axes(&self) -> Option<[(usize, f32, f32); 2]> {
-            enum HowToSlice {
-                KeepDirection,
-                RemoveDirection,
-            }
-            impl HowToSlice {
-                fn how(dx1: Option<f32>, dx2: Option<f32>) -> Option<Self> {
-                    if let (Some(dx1), Some(dx2)) = (dx1, dx2) {
-                        if dx1 < EPSILON && dx2 > EPSILON || dx1 > EPSILON && dx2 < EPSILON {
-                            Some(HowToSlice::KeepDirection)
-                        } else if dx1 < EPSILON && dx2 < EPSILON {
-                            Some(HowToSlice::RemoveDirection)
-                        } else {
-                            None
-                        }
-                    } else {
-                        None
-                    }
-                }
-            }
-
-            let remove_x = match HowToSlice::how(self.dir1[0], self.dir2[0]) {
-                Some(HowToSlice::KeepDirection) => false,
-                Some(HowToSlice::RemoveDirection) => true,
-                None => false,
-            };
-            let remove_y = match HowToSlice::how(self.dir1[1], self.dir2[1]) {
-                Some(HowToSlice::KeepDirection) => false,
-                Some(HowToSlice::RemoveDirection) => true,
-                None => false,
-            };
-            let remove_z = match HowToSlice::how(self.dir1[2], self.dir2[2]) {
-                Some(HowToSlice::KeepDirection) => false,
-                Some(HowToSlice::RemoveDirection) => true,
-                None => false,
-            };
-
-            if let Some(origin) = self.origin {
-                match (remove_x, remove_y, remove_z) {
-                    (true, false, false) => self.non_zero_factor(2).and_then(|factor2| {
-                        self.non_zero_factor(1)
-                            .map(|factor1| [(0, origin[2], factor2), (1, origin[1], factor1)])
-                    }),
-                    (false, true, false) => self.non_zero_factor(2).and_then(|factor2| {
-                        self.non_zero_factor(0)
-                            .map(|factor0| [(0, origin[2], factor2), (2, origin[0], factor0)])
-                    }),
-                    (false, false, true) => self.non_zero_factor(1).and_then(|factor1| {
-                        self.non_zero_factor(0)
-                            .map(|factor0| [(1, origin[1], factor1), (2, origin[0], factor0)])
-                    }),
+        /// Return origin and both directions of the map, if they are uniform.
+        fn directions(&self) -> Option<(&'a [f32; 3], [f32; 3], [f32; 3])> {
+            fn unwrap_dir(dir: [Option<f32>; 3]) -> Option<[f32; 3]> {
+                match dir {
+                    [Some(dx), Some(dy), Some(dz)] => Some([dx, dy, dz]),
                     _ => None,
                 }
-            } else {
-                None
-            }
-        }
-        fn non_zero_factor(&self, index: usize) -> Option<f32> {
-            match (self.dir1[index], self.dir2[index]) {
-                (Some(x), Some(y)) => {
-                    if x.abs() < EPSILON {
-                        Some(y)
-                    } else {
-                        Some(x)
-                    }
-                }
-                _ => None,
             }
+            let origin = self.origin?;
+            let dir1 = unwrap_dir(self.dir1)?;
+            let dir2 = unwrap_dir(self.dir2)?;
+            Some((origin, dir1, dir2))
         }
     }
 
@@ -1109,11 +1339,16 @@ fn run_slice_3d_to_2d(image: &WcsArray, map: &Array2<[f32; 3]>) -> Result<IOValu
             let array = array.into_dyn();
             let array = image.array().with_new_value(array);
             let params = MapReverseParams::new(map);
-            let array = if let Some(axes) = params.sliced_axes() {
-                image.make_slice(&axes, array)
-            } else {
-                WcsArray::from_array(array)
+            let mut array = match params.directions() {
+                Some((origin, dir1, dir2)) => image.plane(origin, &dir1, &dir2, array),
+                None => WcsArray::from_array(array),
             };
+            // All pixels were checked to be in bounds above
+            let variance = image.variance().map(|variance| {
+                map.map(|&[x, y, z]| variance[[x as usize, y as usize, z as usize]])
+                    .into_dyn()
+            });
+            array.set_variance(variance);
             IOValue::Image(array)
         })
         .map_err(|e| IOErr::ShapeError(e, "slice3d_to_2d: Unexpected error".to_owned()))
@@ -1150,22 +1385,59 @@ fn run_extract_wave(image: &WcsArray, roi: &roi::ROI) -> Result<IOValue, IOErr>
     let image_val = image.scalar();
 
     let wave_size = *image_val.dim().as_array_view().first().unwrap();
-    let mut wave = Vec::with_capacity(wave_size);
-    for i in 0..wave_size {
-        let mut res = 0.0;
-        for (_, val) in roi.filter(image_val.slice(s![i, .., ..])) {
-            res += val;
+    let sum_roi = |array: &ArrayD<f32>| {
+        let mut wave = Vec::with_capacity(wave_size);
+        for i in 0..wave_size {
+            let mut res = 0.0;
+            for (_, val) in roi.filter(array.slice(s![i, .., ..])) {
+                res += val;
+            }
+            wave.push(res);
         }
-        wave.push(res);
+        Array1::from_vec(wave).into_dyn()
+    };
+    let mut out = image.with_axes(&[0], image.array().with_new_value(sum_roi(image_val)));
+    out.set_variance(image.variance().map(sum_roi));
+    Ok(IOValue::Image(out))
+}
+
+fn push_fits_extension(
+    extensions: &mut FitsExtensions,
+    image: &WcsArray,
+    name: &str,
+) -> Result<(), IOErr> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(IOErr::UnexpectedInput(
+            "Extension name must not be empty.".to_owned(),
+        ));
     }
-    Ok(IOValue::Image(
-        image.make_slice(
-            &[(2, 0.0, 1.0)],
-            image
-                .array()
-                .with_new_value(Array1::from_vec(wave).into_dyn()),
-        ),
-    ))
+    if extensions.contains(name) {
+        return Err(IOErr::UnexpectedInput(format!(
+            "Extension '{}' already exists.",
+            name
+        )));
+    }
+    extensions.push(name.to_owned(), image.clone());
+    Ok(())
+}
+
+fn run_make_fits_extensions(images: &[(&WcsArray, &String)]) -> Result<IOValue, IOErr> {
+    let mut extensions = FitsExtensions::new();
+    for (image, name) in images {
+        push_fits_extension(&mut extensions, image, name)?;
+    }
+    Ok(IOValue::FitsExtensions(extensions))
+}
+
+fn run_append_fits_extension(
+    extensions: &FitsExtensions,
+    image: &WcsArray,
+    name: &str,
+) -> Result<IOValue, IOErr> {
+    let mut extensions = extensions.clone();
+    push_fits_extension(&mut extensions, image, name)?;
+    Ok(IOValue::FitsExtensions(extensions))
 }
 
 fn run_create_scatter(xaxis: &WcsArray, yaxis: &WcsArray) -> Result<IOValue, IOErr> {
@@ -1227,7 +1499,11 @@ fn run_create_scatter(xaxis: &WcsArray, yaxis: &WcsArray) -> Result<IOValue, IOE
 }
 
 fn run_range_specification(image: &WcsArray, start: i64, end: i64) -> Result<IOValue, IOErr> {
-    reduce_array_slice(image, start, end, |slices| slices.to_owned())
+    let start = try_into_unsigned!(start)?;
+    let end = try_into_unsigned!(end)?;
+    is_sliceable!(image, start, end)?;
+
+    Ok(IOValue::Image(image.slice_axis(0, start, end)))
 }
 
 fn run_extrude(image: &WcsArray, roi: &roi::ROI) -> Result<IOValue, IOErr> {
@@ -1262,13 +1538,26 @@ fn run_clip(
     floor_threshold: f32,
     floor: bool,
 ) -> Result<IOValue, IOErr> {
+    let is_clipped =
+        |f: f32| (ceiling && f >= ceiling_threshold) || (floor && f <= floor_threshold);
+    let variance = image.variance().map(|variance| {
+        let mut variance = variance.clone();
+        Zip::from(&mut variance).and(image.scalar()).apply(|v, &f| {
+            if is_clipped(f) {
+                *v = ::std::f32::NAN;
+            }
+        });
+        variance
+    });
+
     let mut image = image.clone();
 
     for f in image.scalar_mut().iter_mut() {
-        if (ceiling && *f >= ceiling_threshold) || (floor && *f <= floor_threshold) {
+        if is_clipped(*f) {
             *f = ::std::f32::NAN;
         }
     }
+    image.set_variance(variance);
 
     Ok(IOValue::Image(image))
 }
@@ -1276,15 +1565,65 @@ fn run_clip(
 fn run_replace_nan_image(image: &WcsArray, placeholder: f32) -> Result<IOValue, IOErr> {
     let mut image = image.clone();
 
-    for f in image.scalar_mut().iter_mut() {
-        if f.is_nan() {
-            *f = placeholder;
-        }
+    let mut mask = image
+        .mask()
+        .cloned()
+        .unwrap_or_else(|| ArrayD::zeros(image.scalar().raw_dim()));
+    let mut any_nan = false;
+    Zip::from(image.scalar_mut())
+        .and(&mut mask)
+        .apply(|f, flags| {
+            if f.is_nan() {
+                *f = placeholder;
+                *flags |= NAN_FLAG;
+                any_nan = true;
+            }
+        });
+    if any_nan || image.mask().is_some() {
+        image.set_mask(Some(mask));
     }
 
     Ok(IOValue::Image(image))
 }
 
+/// Data quality flag set on values replaced because they were NaN
+const NAN_FLAG: u32 = 1;
+
+fn run_mask_threshold(image: &WcsArray, min: f32, max: f32, flag: i64) -> Result<IOValue, IOErr> {
+    let flag = try_into_unsigned!(flag)? as u32;
+    let mut out = image.clone();
+    let mut mask = image
+        .mask()
+        .cloned()
+        .unwrap_or_else(|| ArrayD::zeros(image.scalar().raw_dim()));
+    Zip::from(&mut mask).and(image.scalar()).apply(|flags, &v| {
+        if !(v >= min && v <= max) {
+            *flags |= flag;
+        }
+    });
+    out.set_mask(Some(mask));
+    Ok(IOValue::Image(out))
+}
+
+fn run_select_mask_bits(image: &WcsArray, bits: i64) -> Result<IOValue, IOErr> {
+    let bits = try_into_unsigned!(bits)? as u32;
+    let mut out = image.clone();
+    out.set_mask(image.mask().map(|mask| mask.mapv(|flags| flags & bits)));
+    Ok(IOValue::Image(out))
+}
+
+fn run_extract_mask(image: &WcsArray) -> Result<IOValue, IOErr> {
+    let mask = image
+        .mask()
+        .ok_or_else(|| IOErr::UnexpectedInput("Image has no mask.".to_owned()))?;
+    let flags = Unit::None.new(mask.mapv(|flags| flags as f32));
+    Ok(IOValue::Image(WcsArray::new(
+        image.meta().clone(),
+        flags,
+        None,
+    )))
+}
+
 fn run_linear_composition(
     i1: &WcsArray,
     i2: &WcsArray,
@@ -1292,6 +1631,7 @@ fn run_linear_composition(
     coef2: f32,
 ) -> Result<IOValue, IOErr> {
     are_same_dim!(i1, i2)?;
+    are_compatible_units!(i1, i2)?;
     let out = i1 * coef1 + i2 * coef2;
     Ok(IOValue::Image(out))
 }
@@ -1300,9 +1640,54 @@ fn run_make_float3(f1: f32, f2: f32, f3: f32) -> Result<IOValue, IOErr> {
     Ok(IOValue::Float3([f1, f2, f3]))
 }
 
-fn reduce_array_slice<F>(image: &WcsArray, start: i64, end: i64, f: F) -> Result<IOValue, IOErr>
+/// Reduction along the 0th axis of an image.
+///
+/// `array` reduces all the slices at once. `lane` folds the valid values of
+/// a single lane, and is used instead if the image has a mask.
+struct Reduction<F, L> {
+    array: F,
+    lane: L,
+}
+
+type NoReduction =
+    Reduction<fn(&ArrayViewD<f32>) -> ArrayD<f32>, fn(&mut dyn Iterator<Item = f32>) -> f32>;
+
+/// Reduce the slices of `image` from `start` to `end` along the 0th axis
+/// with `reduction`. The variance of the result is unknown.
+fn reduce_array_slice<F, L>(
+    image: &WcsArray,
+    start: i64,
+    end: i64,
+    reduction: Reduction<F, L>,
+) -> Result<IOValue, IOErr>
 where
     F: Fn(&ArrayViewD<f32>) -> ArrayD<f32>,
+    L: Fn(&mut dyn Iterator<Item = f32>) -> f32,
+{
+    reduce_array_slice_with_variance(image, start, end, reduction, None::<NoReduction>)
+}
+
+/// Reduce the slices of `image` from `start` to `end` along the 0th axis
+/// with `reduction`.
+///
+/// If `image` has a variance plane, `variance_reduction` reduces the slices
+/// of the variance to get the variance of the result.
+///
+/// If `image` has a mask, only valid values are reduced. A value of the
+/// result is NaN if all the values it is reduced from are invalid, and it
+/// keeps the flags they all share.
+fn reduce_array_slice_with_variance<F, L, G, M>(
+    image: &WcsArray,
+    start: i64,
+    end: i64,
+    reduction: Reduction<F, L>,
+    variance_reduction: Option<Reduction<G, M>>,
+) -> Result<IOValue, IOErr>
+where
+    F: Fn(&ArrayViewD<f32>) -> ArrayD<f32>,
+    L: Fn(&mut dyn Iterator<Item = f32>) -> f32,
+    G: Fn(&ArrayViewD<f32>) -> ArrayD<f32>,
+    M: Fn(&mut dyn Iterator<Item = f32>) -> f32,
 {
     let start = try_into_unsigned!(start)?;
     let end = try_into_unsigned!(end)?;
@@ -1311,92 +1696,252 @@ where
     let image_val = image.scalar();
 
     let slices = image_val.slice_axis(Axis(0), Slice::from(start..end));
-    let raw = f(&slices);
-    let ndim = raw.ndim();
+    let mask = image
+        .mask()
+        .map(|mask| mask.slice_axis(Axis(0), Slice::from(start..end)));
+    let raw = match &mask {
+        Some(mask) => reduce_valid(&slices, mask, &reduction.lane),
+        None => (reduction.array)(&slices),
+    };
 
-    let wrap_with_unit = image.make_slice(
-        &(0..ndim).map(|i| (i, 0.0, 1.0)).collect::<Vec<_>>(),
-        image.array().with_new_value(raw),
-    );
-    Ok(IOValue::Image(wrap_with_unit))
+    let mut out = image.drop_axis(0, image.array().with_new_value(raw));
+    if let (Some(variance), Some(variance_reduction)) = (image.variance(), variance_reduction) {
+        let slices = variance.slice_axis(Axis(0), Slice::from(start..end));
+        out.set_variance(Some(match &mask {
+            Some(mask) => reduce_valid(&slices, mask, &variance_reduction.lane),
+            None => (variance_reduction.array)(&slices),
+        }));
+    }
+    if let Some(mask) = &mask {
+        out.set_mask(Some(mask.fold_axis(Axis(0), std::u32::MAX, |&a, &b| a & b)));
+    }
+    Ok(IOValue::Image(out))
 }
 
-fn run_integral(image: &WcsArray, start: i64, end: i64) -> Result<IOValue, IOErr> {
-    reduce_array_slice(image, start, end, |slices| slices.sum_axis(Axis(0)))
+/// Reduce `slices` along the 0th axis with `lane`, leaving out values for
+/// which a flag is set in `mask`.
+///
+/// `lane` folds the valid values of each lane. The result is NaN where all
+/// values are invalid.
+fn reduce_valid<L>(slices: &ArrayViewD<f32>, mask: &ArrayViewD<u32>, lane: &L) -> ArrayD<f32>
+where
+    L: Fn(&mut dyn Iterator<Item = f32>) -> f32,
+{
+    let new_size: Vec<_> = slices.shape().iter().skip(1).cloned().collect();
+    ArrayD::from_shape_fn(new_size, |index| {
+        let mut valid = slices
+            .axis_iter(Axis(0))
+            .zip(mask.axis_iter(Axis(0)))
+            .filter(|(_, flags)| flags[&index] == 0)
+            .map(|(slice, _)| slice[&index])
+            .peekable();
+        if valid.peek().is_none() {
+            std::f32::NAN
+        } else {
+            lane(&mut valid)
+        }
+    })
 }
 
-fn run_average(image: &WcsArray, start: i64, end: i64) -> Result<IOValue, IOErr> {
-    reduce_array_slice(image, start, end, |slices| slices.mean_axis(Axis(0)))
+/// Count and sum of `values`.
+fn count_sum(values: &mut dyn Iterator<Item = f32>) -> (usize, f32) {
+    values.fold((0, 0.0), |(n, sum), v| (n + 1, sum + v))
 }
 
-fn run_variance(image: &WcsArray, start: i64, end: i64) -> Result<IOValue, IOErr> {
-    reduce_array_slice(image, start, end, |slices| slices.var_axis(Axis(0), 1.0))
+/// Unbiased variance of `values`, computed in a single pass.
+fn lane_variance(values: &mut dyn Iterator<Item = f32>) -> f32 {
+    let (n, _, m2) = values.fold((0, 0.0, 0.0), |(n, mean, m2), v| {
+        let n = n + 1;
+        let delta = v - mean;
+        let mean = mean + delta / n as f32;
+        (n, mean, m2 + delta * (v - mean))
+    });
+    m2 / (n - 1) as f32
 }
 
-fn run_stddev(image: &WcsArray, start: i64, end: i64) -> Result<IOValue, IOErr> {
-    reduce_array_slice(image, start, end, |slices| slices.std_axis(Axis(0), 1.0))
+/// Median of `vals`, which are sorted in place.
+fn median(vals: &mut [f32]) -> f32 {
+    //consider NaN!
+    vals.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let n = vals.len();
+    if n % 2 == 1 {
+        vals[(n - 1) / 2]
+    } else {
+        (vals[n / 2] + vals[n / 2 - 1]) / 2.0
+    }
 }
 
-fn run_median(image: &WcsArray, start: i64, end: i64) -> Result<IOValue, IOErr> {
-    let start = try_into_unsigned!(start)?;
-    let end = try_into_unsigned!(end)?;
-    is_sliceable!(image, start, end)?;
+/// Check whether the value at `index` of the `k`-th slice of `image` along
+/// the 0th axis is valid, i.e. has no data quality flag set.
+fn is_valid_at(image: &WcsArray, k: usize, index: &IxDyn) -> bool {
+    image
+        .mask()
+        .map_or(true, |mask| mask.index_axis(Axis(0), k)[index] == 0)
+}
 
-    let image_val = image.scalar();
+fn run_integral(image: &WcsArray, start: i64, end: i64) -> Result<IOValue, IOErr> {
+    let sum = || Reduction {
+        array: |slices: &ArrayViewD<f32>| slices.sum_axis(Axis(0)),
+        lane: |values: &mut dyn Iterator<Item = f32>| values.sum::<f32>(),
+    };
+    reduce_array_slice_with_variance(image, start, end, sum(), Some(sum()))
+}
 
-    let slices = image_val.slice_axis(Axis(0), Slice::from(start..end));
-    let dim = slices.dim();
-    let size = dim.as_array_view();
-    let new_size: Vec<_> = size.iter().skip(1).cloned().collect();
+fn run_average(image: &WcsArray, start: i64, end: i64) -> Result<IOValue, IOErr> {
+    reduce_array_slice_with_variance(
+        image,
+        start,
+        end,
+        Reduction {
+            array: |slices: &ArrayViewD<f32>| slices.mean_axis(Axis(0)),
+            lane: |values: &mut dyn Iterator<Item = f32>| {
+                let (n, sum) = count_sum(values);
+                sum / n as f32
+            },
+        },
+        Some(Reduction {
+            array: |variances: &ArrayViewD<f32>| {
+                let n = variances.len_of(Axis(0)) as f32;
+                variances.sum_axis(Axis(0)) / (n * n)
+            },
+            lane: |variances: &mut dyn Iterator<Item = f32>| {
+                let (n, sum) = count_sum(variances);
+                sum / (n * n) as f32
+            },
+        }),
+    )
+}
 
-    let result = ArrayD::from_shape_fn(new_size, |index| {
-        let mut vals = Vec::new();
-        for (_, slice) in slices.axis_iter(Axis(0)).enumerate() {
-            vals.push(slice[&index]);
-        }
-        //consider NaN!
-        vals.sort_by(|a, b| a.partial_cmp(b).unwrap());
-        let n = vals.len();
-        if n % 2 == 1 {
-            vals[(n - 1) / 2]
-        } else {
-            (vals[n / 2] + vals[n / 2 - 1]) / 2.0
-        }
-    });
+fn run_variance(image: &WcsArray, start: i64, end: i64) -> Result<IOValue, IOErr> {
+    reduce_array_slice(
+        image,
+        start,
+        end,
+        Reduction {
+            array: |slices: &ArrayViewD<f32>| slices.var_axis(Axis(0), 1.0),
+            lane: lane_variance,
+        },
+    )
+}
 
-    // FIXME: Unit support
-    // unit of index(Axis 0) should be adobped
-    //
-    // in above program...
-    // 'waveimg' must have [flux * wavelength], 'flux_sum' must have [flux]
-    // 'result' = waveimg / flux_sum   must have [wavelength]
+fn run_stddev(image: &WcsArray, start: i64, end: i64) -> Result<IOValue, IOErr> {
+    reduce_array_slice(
+        image,
+        start,
+        end,
+        Reduction {
+            array: |slices: &ArrayViewD<f32>| slices.std_axis(Axis(0), 1.0),
+            lane: |values: &mut dyn Iterator<Item = f32>| lane_variance(values).sqrt(),
+        },
+    )
+}
 
-    Ok(IOValue::Image(WcsArray::from_array(Dimensioned::new(
-        result,
-        Unit::None,
-    ))))
+fn run_median(image: &WcsArray, start: i64, end: i64) -> Result<IOValue, IOErr> {
+    reduce_array_slice_with_variance(
+        image,
+        start,
+        end,
+        Reduction {
+            array: |slices: &ArrayViewD<f32>| {
+                let dim = slices.dim();
+                let size = dim.as_array_view();
+                let new_size: Vec<_> = size.iter().skip(1).cloned().collect();
+                let mut progress = ProgressCounter::new(new_size.iter().product(), "Median");
+                let mut cancelled = false;
+                let mut vals = Vec::with_capacity(slices.len_of(Axis(0)));
+                ArrayD::from_shape_fn(new_size, |index| {
+                    // Skip remaining pixels once cancelled, the result is discarded
+                    if cancelled || !progress.tick() {
+                        cancelled = true;
+                        return 0.0;
+                    }
+                    vals.clear();
+                    vals.extend(slices.axis_iter(Axis(0)).map(|slice| slice[&index]));
+                    median(&mut vals)
+                })
+            },
+            // All the values are needed to find the median
+            lane: |values: &mut dyn Iterator<Item = f32>| median(&mut values.collect::<Vec<_>>()),
+        },
+        // Variance of the median of normally distributed values is pi/2 times
+        // the variance of their mean
+        Some(Reduction {
+            array: |variances: &ArrayViewD<f32>| {
+                let n = variances.len_of(Axis(0)) as f32;
+                variances.sum_axis(Axis(0)) * (std::f32::consts::FRAC_PI_2 / (n * n))
+            },
+            lane: |variances: &mut dyn Iterator<Item = f32>| {
+                let (n, sum) = count_sum(variances);
+                sum * (std::f32::consts::FRAC_PI_2 / (n * n) as f32)
+            },
+        }),
+    )
 }
 
 fn run_minmax(image: &WcsArray, start: i64, end: i64, is_min: bool) -> Result<IOValue, IOErr> {
     if !is_min {
-        reduce_array_slice(image, start, end, |slices| {
-            slices.fold_axis(
-                Axis(0),
-                -std::f32::INFINITY,
-                |x, y| if x > y { *x } else { *y },
-            )
-        })
+        reduce_array_slice(
+            image,
+            start,
+            end,
+            Reduction {
+                array: |slices: &ArrayViewD<f32>| {
+                    slices.fold_axis(
+                        Axis(0),
+                        -std::f32::INFINITY,
+                        |x, y| if x > y { *x } else { *y },
+                    )
+                },
+                lane: |values: &mut dyn Iterator<Item = f32>| {
+                    values.fold(-std::f32::INFINITY, |x, y| if x > y { x } else { y })
+                },
+            },
+        )
     } else {
-        reduce_array_slice(image, start, end, |slices| {
-            slices.fold_axis(
-                Axis(0),
-                std::f32::INFINITY,
-                |x, y| if x < y { *x } else { *y },
-            )
-        })
+        reduce_array_slice(
+            image,
+            start,
+            end,
+            Reduction {
+                array: |slices: &ArrayViewD<f32>| {
+                    slices.fold_axis(
+                        Axis(0),
+                        std::f32::INFINITY,
+                        |x, y| if x < y { *x } else { *y },
+                    )
+                },
+                lane: |values: &mut dyn Iterator<Item = f32>| {
+                    values.fold(std::f32::INFINITY, |x, y| if x < y { x } else { y })
+                },
+            },
+        )
     }
 }
 
+/// Find the wavelength axis of `image`, i.e. the "WAVE" axis with a unit of
+/// length, whatever its spelling ("Angstrom", "AA", "nm", ...).
+///
+/// Return the index of the axis and the conversion of its world coordinates
+/// to Angstrom.
+fn wavelength_axis(image: &WcsArray) -> Option<(usize, Conversion)> {
+    let angstrom: PhysicalUnit = "Angstrom".parse().ok()?;
+    image
+        .axes()?
+        .iter()
+        .enumerate()
+        .filter(|(_, axis)| axis.name() == "WAVE")
+        .find_map(|(n, axis)| {
+            let unit: PhysicalUnit = axis.unit().parse().ok()?;
+            if unit.is_compatible(&angstrom) {
+                unit.conversion(&angstrom)
+                    .ok()
+                    .map(|conversion| (n, conversion))
+            } else {
+                None
+            }
+        })
+}
+
 fn run_create_argmap(
     image: &WcsArray,
     start: i64,
@@ -1410,16 +1955,10 @@ fn run_create_argmap(
     is_sliceable!(image, start, end)?;
     let original_meta = image.meta();
     let original_visualization = image.tag();
-    let mut trypix = 0;
-    let mut waveunit = Unit::None;
-    for (n, a) in image.meta().clone().unwrap().axes().iter().enumerate() {
-        if a.name() == "WAVE" {
-            if a.unit() == "Angstrom" || a.unit() == "Ang" {
-                trypix = n;
-                waveunit = Unit::Custom("Ang".to_string());
-            }
-        }
-    }
+    let (trypix, to_angstrom, waveunit) = match wavelength_axis(image) {
+        Some((n, conversion)) => (n, Some(conversion), Unit::parse("Angstrom")),
+        None => (0, None, Unit::None),
+    };
 
     let image_val = image.scalar();
 
@@ -1440,7 +1979,7 @@ fn run_create_argmap(
                 value = slice[&index];
                 if is_actual_value {
                     out = match image.pix2world(trypix, ((k + start) as i64 + range) as f32) {
-                        Some(value) => value,
+                        Some(value) => to_angstrom.map_or(value, |c| c.apply(value)),
                         None => ((k + start) as i64 + range) as f32,
                     };
                 } else {
@@ -1469,16 +2008,10 @@ fn run_centroid(image: &WcsArray, start: i64, end: i64) -> Result<IOValue, IOErr
     is_sliceable!(image, start, end)?;
     let original_meta = image.meta();
     let original_visualization = image.tag();
-    let mut trypix = 0;
-    let mut waveunit = Unit::None;
-    for (n, a) in image.meta().clone().unwrap().axes().iter().enumerate() {
-        if a.name() == "WAVE" {
-            if a.unit() == "Angstrom" || a.unit() == "Ang" {
-                trypix = n;
-                waveunit = Unit::Custom("Ang".to_string());
-            }
-        }
-    }
+    let (trypix, to_angstrom, waveunit) = match wavelength_axis(image) {
+        Some((n, conversion)) => (n, Some(conversion), Unit::parse("Angstrom")),
+        None => (0, None, Unit::None),
+    };
 
     let image_val = image.scalar();
 
@@ -1490,7 +2023,10 @@ fn run_centroid(image: &WcsArray, start: i64, end: i64) -> Result<IOValue, IOErr
 
     let flux_sum = ArrayD::from_shape_fn(new_size, |index| {
         let mut out = 0.0;
-        for (_, slice) in slices.axis_iter(Axis(0)).enumerate() {
+        for (k, slice) in slices.axis_iter(Axis(0)).enumerate() {
+            if !is_valid_at(image, k + start, &index) {
+                continue;
+            }
             let flux = slice[&index];
             out += flux;
         }
@@ -1506,9 +2042,12 @@ fn run_centroid(image: &WcsArray, start: i64, end: i64) -> Result<IOValue, IOErr
     let waveimg = ArrayD::from_shape_fn(new_size_2, |index| {
         let mut out = 0.0;
         for (k, slice) in slices.axis_iter(Axis(0)).enumerate() {
+            if !is_valid_at(image, k + start, &index) {
+                continue;
+            }
             let flux = slice[&index];
             let wavelength = match image.pix2world(trypix, (k + start) as f32) {
-                Some(value) => value,
+                Some(value) => to_angstrom.map_or(value, |c| c.apply(value)),
                 None => (k + start) as f32,
             };
             out += flux * wavelength;
@@ -1543,7 +2082,10 @@ fn run_centroid_with_mask(
         let end = end_mask_val[&index] as usize;
         let slices = image_val.slice_axis(Axis(0), Slice::from(start..end));
 
-        for (_, slice) in slices.axis_iter(Axis(0)).enumerate() {
+        for (k, slice) in slices.axis_iter(Axis(0)).enumerate() {
+            if !is_valid_at(image, k + start, &index) {
+                continue;
+            }
             let flux = slice[&index];
             out += flux;
         }
@@ -1556,6 +2098,9 @@ fn run_centroid_with_mask(
         let end = end_mask_val[&index] as usize;
         let slices = image_val.slice_axis(Axis(0), Slice::from(start..end));
         for (k, slice) in slices.axis_iter(Axis(0)).enumerate() {
+            if !is_valid_at(image, k + start, &index) {
+                continue;
+            }
             let flux = slice[&index];
             let wavelength = match image.pix2world(2, (k + start) as f32) {
                 Some(value) => value,
@@ -1588,10 +2133,25 @@ fn run_create_equivalent_width(
     max: f32,
     is_emission: bool,
 ) -> Result<IOValue, IOErr> {
-    let fl = Dimensioned::new(fl, Unit::Custom("Ang".to_string()));
+    are_compatible_units!(i_off, i_on)?;
+    // Variance of fl * (1 - i_on / i_off), computed before the operands are
+    // combined as they are not independent
+    let variance = unit::propagate_variance(i_off, i_on, |off, on, v_off, v_on| {
+        fl * fl * unit::quotient_variance(on, off, v_on, v_off)
+    });
+    let fl = Dimensioned::new(fl, Unit::parse("Angstrom"));
     let mut t = (i_off - i_on) * fl / i_off * (if is_emission { -1.0 } else { 1.0 });
     let result_val = t.scalar_mut().map(|v| if *v > max { 0.0 } else { *v });
-    let result = WcsArray::from_array(Dimensioned::new(result_val, t.array().unit().to_owned()));
+    let mut result =
+        WcsArray::from_array(Dimensioned::new(result_val, t.array().unit().to_owned()));
+    result.set_variance(variance.map(|mut variance| {
+        Zip::from(&mut variance).and(t.scalar()).apply(|v, &w| {
+            if w > max {
+                *v = 0.0;
+            }
+        });
+        variance
+    }));
     Ok(IOValue::Image(result))
 }
 
@@ -1606,6 +2166,18 @@ fn run_convert_to_logscale(
         let d = (*v - v_min) / (v_max - v_min);
         *v = (a * d + 1.0).ln() / a.ln();
     });
+    let variance = image.variance().map(|variance| {
+        let mut variance = variance.clone();
+        Zip::from(&mut variance)
+            .and(image.scalar())
+            .apply(|var, &v| {
+                let d = (v - v_min) / (v_max - v_min);
+                let derivative = a / ((a * d + 1.0) * a.ln() * (v_max - v_min));
+                *var *= derivative * derivative;
+            });
+        variance
+    });
+    out.set_variance(variance);
 
     Ok(IOValue::Image(out))
 }
@@ -1613,6 +2185,17 @@ fn run_convert_to_logscale(
 fn run_log10(image: &WcsArray) -> Result<IOValue, IOErr> {
     let mut out = image.clone();
     out.scalar_mut().par_iter_mut().for_each(|v| *v = v.log10());
+    let variance = image.variance().map(|variance| {
+        let mut variance = variance.clone();
+        Zip::from(&mut variance)
+            .and(image.scalar())
+            .apply(|var, &v| {
+                let derivative = 1.0 / (v * std::f32::consts::LN_10);
+                *var *= derivative * derivative;
+            });
+        variance
+    });
+    out.set_variance(variance);
     Ok(IOValue::Image(out))
 }
 
@@ -1625,6 +2208,12 @@ fn run_image_multiplier(
     are_same_dim!(i1, i2)?;
     let mut i1 = i1.clone();
     let mut i2 = i2.clone();
+    let variance = unit::propagate_variance(&i1, &i2, |u, v, var_u, var_v| {
+        // Relative variance of u^a * v^b, multiplied by its square
+        let (ua, vb) = (u.powf(coef1), v.powf(coef2));
+        let rel = coef1 * coef1 * var_u / (u * u) + coef2 * coef2 * var_v / (v * v);
+        ua * ua * vb * vb * rel
+    });
     //FIXME: Unit data lost
     i1.scalar_mut()
         .par_iter_mut()
@@ -1632,7 +2221,10 @@ fn run_image_multiplier(
     i2.scalar_mut()
         .par_iter_mut()
         .for_each(|v| *v = (*v).powf(coef2));
-    let out = i1 * i2;
+    i1.set_variance(None);
+    i2.set_variance(None);
+    let mut out = i1 * i2;
+    out.set_variance(variance);
 
     Ok(IOValue::Image(out))
 }
@@ -1654,13 +2246,24 @@ fn run_gaussian_mean(image: &WcsArray, start: i64, end: i64) -> Result<IOValue,
     let image_val = image.scalar();
 
     let slices = image_val.slice_axis(Axis(0), Slice::from(start..end));
+    let variances = image
+        .variance()
+        .map(|variance| variance.slice_axis(Axis(0), Slice::from(start..end)));
     let dim = slices.dim();
     let size = dim.as_array_view();
     let new_size: Vec<_> = size.iter().skip(1).cloned().collect();
 
+    let mut progress = ProgressCounter::new(new_size.iter().product(), "Fitting gaussians");
+    let mut cancelled = false;
     let img = ArrayD::from_shape_fn(new_size, |index| {
+        // Skip remaining pixels once cancelled, the result is discarded
+        if cancelled || !progress.tick() {
+            cancelled = true;
+            return (0.0, 0.0);
+        }
         let mut sums = vec![0.0, 0.0, 0.0, 0.0];
         let mut lns = vec![0.0, 0.0, 0.0];
+        let mut ln_covariance = Matrix3::zeros();
         let n = end - start + 1;
         // Caruanas Algorithm
         for (k, slice) in slices.axis_iter(Axis(0)).enumerate() {
@@ -1673,6 +2276,12 @@ fn run_gaussian_mean(image: &WcsArray, start: i64, end: i64) -> Result<IOValue,
             lns[0] += y.ln();
             lns[1] += x * y.ln();
             lns[2] += x * x * y.ln();
+            if let Some(variances) = &variances {
+                // Variance of ln(y) at first order
+                let var_ln = variances.index_axis(Axis(0), k)[&index] / (y * y);
+                let v = Vector3::new(1.0, x, x * x);
+                ln_covariance += v * v.transpose() * var_ln;
+            }
         }
         let a = Matrix3::new(
             n as f32, sums[0], sums[1], sums[0], sums[1], sums[2], sums[1], sums[2], sums[3],
@@ -1693,15 +2302,28 @@ fn run_gaussian_mean(image: &WcsArray, start: i64, end: i64) -> Result<IOValue,
             Some(value) => value,
             None => (mean + start as f32),
         };
-        out
+        let variance = if variances.is_some() {
+            let scale = match (image.pix2world(2, mean + 1.0), image.pix2world(2, mean)) {
+                (Some(next), Some(value)) => next - value,
+                _ => 1.0,
+            };
+            caruana_mean_variance(&a, &ln_covariance, *b, *c) * scale * scale
+        } else {
+            0.0
+        };
+        (out, variance)
     });
     match flag {
         // maybe some IOErr enum (presenting computation failure)is necessary
         true => Err(IOErr::UnexpectedInput("Linear algebra failed.".to_string())),
-        false => Ok(IOValue::Image(WcsArray::from_array(Dimensioned::new(
-            img,
-            Unit::None,
-        )))),
+        false => {
+            let mut out =
+                WcsArray::from_array(Dimensioned::new(img.mapv(|(mean, _)| mean), Unit::None));
+            if image.variance().is_some() {
+                out.set_variance(Some(img.mapv(|(_, variance)| variance)));
+            }
+            Ok(IOValue::Image(out))
+        }
     }
 }
 
@@ -1717,12 +2339,23 @@ fn run_gaussian_mean_with_mask(
     let size = dim.as_array_view();
     let new_size: Vec<_> = size.iter().skip(1).cloned().collect();
     let mut flag = false;
+    let mut progress = ProgressCounter::new(new_size.iter().product(), "Fitting gaussians");
+    let mut cancelled = false;
     let img = ArrayD::from_shape_fn(new_size, |index| {
+        // Skip remaining pixels once cancelled, the result is discarded
+        if cancelled || !progress.tick() {
+            cancelled = true;
+            return (0.0, 0.0);
+        }
         let mut sums = vec![0.0, 0.0, 0.0, 0.0];
         let mut lns = vec![0.0, 0.0, 0.0];
+        let mut ln_covariance = Matrix3::zeros();
         let start = start_mask_val[&index] as usize;
         let end = end_mask_val[&index] as usize;
         let slices = image_val.slice_axis(Axis(0), Slice::from(start..end));
+        let variances = image
+            .variance()
+            .map(|variance| variance.slice_axis(Axis(0), Slice::from(start..end)));
         let n = end - start + 1;
         // Caruanas Algorithm
         for (k, slice) in slices.axis_iter(Axis(0)).enumerate() {
@@ -1735,6 +2368,12 @@ fn run_gaussian_mean_with_mask(
             lns[0] += y.ln();
             lns[1] += x * y.ln();
             lns[2] += x * x * y.ln();
+            if let Some(variances) = &variances {
+                // Variance of ln(y) at first order
+                let var_ln = variances.index_axis(Axis(0), k)[&index] / (y * y);
+                let v = Vector3::new(1.0, x, x * x);
+                ln_covariance += v * v.transpose() * var_ln;
+            }
         }
         let a = Matrix3::new(
             n as f32, sums[0], sums[1], sums[0], sums[1], sums[2], sums[1], sums[2], sums[3],
@@ -1755,15 +2394,45 @@ fn run_gaussian_mean_with_mask(
             Some(value) => value,
             None => (mean + start as f32),
         };
-        out
+        let variance = if variances.is_some() {
+            let scale = match (image.pix2world(2, mean + 1.0), image.pix2world(2, mean)) {
+                (Some(next), Some(value)) => next - value,
+                _ => 1.0,
+            };
+            caruana_mean_variance(&a, &ln_covariance, *b, *c) * scale * scale
+        } else {
+            0.0
+        };
+        (out, variance)
     });
     match flag {
         // maybe some IOErr enum (presenting computation failure)is necessary
         true => Err(IOErr::UnexpectedInput("Linear algebra failed.".to_string())),
-        false => Ok(IOValue::Image(WcsArray::from_array(Dimensioned::new(
-            img,
-            Unit::None,
-        )))),
+        false => {
+            let mut out =
+                WcsArray::from_array(Dimensioned::new(img.mapv(|(mean, _)| mean), Unit::None));
+            if image.variance().is_some() {
+                out.set_variance(Some(img.mapv(|(_, variance)| variance)));
+            }
+            Ok(IOValue::Image(out))
+        }
+    }
+}
+
+/// First-order variance of the mean `-b / (2c)` of a gaussian fitted with
+/// Caruana's algorithm.
+///
+/// `a` is the matrix of the normal equations. `ln_covariance` is the
+/// covariance of their right-hand side, i.e. the sum of
+/// `var(ln y) * [1, x, x^2]^T [1, x, x^2]` over all fitted points.
+fn caruana_mean_variance(a: &Matrix3<f32>, ln_covariance: &Matrix3<f32>, b: f32, c: f32) -> f32 {
+    match a.try_inverse() {
+        Some(inverse) => {
+            let covariance = inverse * ln_covariance * inverse.transpose();
+            let gradient = Vector3::new(0.0, -1.0 / (2.0 * c), b / (2.0 * c * c));
+            (gradient.transpose() * covariance * gradient)[0]
+        }
+        None => std::f32::NAN,
     }
 }
 
@@ -1774,15 +2443,63 @@ fn run_create_emission_line_map(
     max: f32,
     is_emission: bool,
 ) -> Result<IOValue, IOErr> {
+    are_compatible_units!(i_off, i_on)?;
     let i_off_integral = i_off.scalar();
     let i_on_integral = i_on.scalar();
     let out = (i_off_integral - i_on_integral) * fl * (if is_emission { -1.0 } else { 1.0 });
     let result = out.map(|v| if *v > max { 0.0 } else { *v });
+    let variance =
+        unit::propagate_variance(i_off, i_on, |_, _, v_off, v_on| (v_off + v_on) * fl * fl).map(
+            |mut variance| {
+                Zip::from(&mut variance).and(&out).apply(|v, &w| {
+                    if w > max {
+                        *v = 0.0;
+                    }
+                });
+                variance
+            },
+        );
 
-    Ok(IOValue::Image(WcsArray::from_array(Dimensioned::new(
-        result,
-        Unit::None,
-    ))))
+    let mut result = WcsArray::from_array(Dimensioned::new(result, Unit::None));
+    result.set_variance(variance);
+    Ok(IOValue::Image(result))
+}
+
+fn run_convert_unit(image: &WcsArray, unit: &str) -> Result<IOValue, IOErr> {
+    let unit: PhysicalUnit = unit
+        .parse()
+        .map_err(|e: UnitError| IOErr::UnexpectedInput(e.to_string()))?;
+    image
+        .convert_unit(&unit)
+        .map(IOValue::Image)
+        .map_err(|e| IOErr::UnexpectedInput(e.to_string()))
+}
+
+fn run_convert_axis_unit(image: &WcsArray, axis: i64, unit: &str) -> Result<IOValue, IOErr> {
+    let axis = try_into_unsigned!(axis)?;
+    let ndim = image.scalar().ndim();
+    precheck!(axis < ndim)?;
+    let unit: PhysicalUnit = unit
+        .parse()
+        .map_err(|e: UnitError| IOErr::UnexpectedInput(e.to_string()))?;
+    image
+        .convert_axis_unit(axis, &unit)
+        .map(IOValue::Image)
+        .map_err(|e| IOErr::UnexpectedInput(e.to_string()))
+}
+
+fn run_extract_uncertainty(image: &WcsArray) -> Result<IOValue, IOErr> {
+    image
+        .uncertainty()
+        .map(IOValue::Image)
+        .ok_or_else(|| IOErr::UnexpectedInput("Image has no uncertainty.".to_owned()))
+}
+
+fn run_set_uncertainty(image: &WcsArray, uncertainty: &WcsArray) -> Result<IOValue, IOErr> {
+    are_same_dim!(image, uncertainty)?;
+    let mut out = image.clone();
+    out.set_variance(Some(uncertainty.scalar().mapv(|sigma| sigma * sigma)));
+    Ok(IOValue::Image(out))
 }
 
 fn run_change_tag(data_in: &WcsArray, tag: &str) -> Result<IOValue, IOErr> {
@@ -1897,63 +2614,11 @@ fn run_down_sampling(image: &WcsArray, n: i64) -> Result<IOValue, IOErr> {
         "{}",
         format!("n should be greater than 0, {} > 0", n)
     )?;
-    let tag = image.tag();
+    let n = n as usize;
     if image.scalar().ndim() == 2 {
-        let dim = image.scalar().dim();
-        let size = dim.as_array_view();
-        let mut out = image.clone();
-        let mut new_size = ((size[0]) / (n as usize), (size[1]) / (n as usize));
-        if size[0] % (n as usize) != 0 {
-            new_size.0 += 1;
-        }
-        if size[1] % (n as usize) != 0 {
-            new_size.1 += 1;
-        }
-        let mut new_image = Vec::with_capacity(new_size.0 * new_size.1);
-        let mut counter = 0;
-        for v in out.scalar_mut().iter_mut() {
-            let coord = (counter % size[1], counter / size[1]);
-            if coord.0 % (n as usize) == 0 && coord.1 % (n as usize) == 0 {
-                new_image.push(*v);
-            }
-            counter += 1;
-        }
-
-        let out = Array::from_shape_vec(new_size.strides((new_size.1, 1)), new_image).unwrap();
-        Ok(IOValue::Image(WcsArray::from_array_and_tag(
-            Dimensioned::new(out.into_dyn(), Unit::None),
-            tag.to_owned(),
-        )))
+        Ok(IOValue::Image(image.subsample(&[n, n])))
     } else if image.scalar().ndim() == 3 {
-        let dim = image.scalar().dim();
-        let size = dim.as_array_view();
-        let mut new_size = (size[0], (size[1]) / (n as usize), (size[2]) / (n as usize));
-        if size[1] % (n as usize) != 0 {
-            new_size.1 += 1;
-        }
-        if size[2] % (n as usize) != 0 {
-            new_size.2 += 1;
-        }
-        let mut new_image = Vec::with_capacity(new_size.0 * new_size.1 * new_size.2);
-        for slice in image.scalar().axis_iter(Axis(0)) {
-            let mut counter = 0;
-            for v in slice.iter() {
-                let coord = (counter % size[2], counter / size[2]);
-                if coord.0 % (n as usize) == 0 && coord.1 % (n as usize) == 0 {
-                    new_image.push(*v);
-                }
-                counter += 1;
-            }
-        }
-        let out = Array::from_shape_vec(
-            new_size.strides((new_size.1 * new_size.2, new_size.2, 1)),
-            new_image,
-        )
-        .unwrap();
-        Ok(IOValue::Image(WcsArray::from_array_and_tag(
-            Dimensioned::new(out.into_dyn(), Unit::None),
-            tag.to_owned(),
-        )))
+        Ok(IOValue::Image(image.subsample(&[1, n, n])))
     } else {
         Err(IOErr::UnexpectedInput(format!(
             "This is neither 2 nor 3 dimensional image!",
@@ -1963,9 +2628,30 @@ fn run_down_sampling(image: &WcsArray, n: i64) -> Result<IOValue, IOErr> {
 
 #[cfg(test)]
 mod test {
-    use super::{run_fits_to_image, run_make_plane3d, run_open_fits, run_slice_3d_to_2d, IOValue};
-    use crate::PATHS;
+    extern crate ron;
+
+    use super::{
+        run_append_fits_extension, run_argminmax, run_average, run_centroid, run_convert_axis_unit,
+        run_convert_unit, run_extract_column, run_extract_column_at, run_extract_uncertainty,
+        run_fits_to_image, run_fits_to_table, run_integral, run_linear_composition,
+        run_make_fits_extensions, run_make_plane3d, run_mask_threshold, run_median, run_minmax,
+        run_open_fits, run_replace_nan_image, run_select_mask_bits, run_set_uncertainty,
+        run_slice_3d_to_2d, run_variance, IOValue,
+    };
+    use crate::fitrs::{Fits, Hdu, HeaderValue};
+    use crate::ndarray::{Array2, ArrayD, IxDyn};
+    use crate::{
+        Column, ColumnData, Dimensioned, PhysicalUnit, Table, Unit, UnitError, WcsArray, PATHS, WCS,
+    };
     use std::path::PathBuf;
+
+    fn expect_image(value: IOValue) -> WcsArray {
+        match value {
+            IOValue::Image(image) => image,
+            _ => panic!("Expected image"),
+        }
+    }
+
     #[test]
     fn test_open_fits() {
         let path = PATHS::FileList(vec![PathBuf::from("test/test.fits")]);
@@ -1989,4 +2675,411 @@ mod test {
         }
         panic!("Failed somewhere!");
     }
+
+    #[test]
+    fn test_wcs_array_ron_without_wcs() {
+        // Images exported before WCS were serialized have no `wcs` field
+        let serialized = r#"(
+            meta: Some((
+                axes: [
+                    (name: Some("RA---TAN"), unit: Custom("deg")),
+                    (name: None, unit: None),
+                    (name: None, unit: None),
+                    (name: None, unit: None),
+                ],
+            )),
+            array: (
+                value: (v: 1, dim: [2], data: [1.0, 2.0]),
+                unit: Custom("Jy"),
+                homogeneous: true,
+            ),
+            visualization: None,
+        )"#;
+        let image: WcsArray = ron::de::from_str(serialized).unwrap();
+
+        assert_eq!(image.wcs(), Some(&WCS::default()));
+        assert_eq!(image.axes().unwrap()[0].name(), "RA---TAN");
+        assert_eq!(image.scalar().as_slice().unwrap(), &[1.0, 2.0]);
+    }
+
+    #[test]
+    fn test_wcs_array_ron_roundtrip() {
+        let path = vec![PathBuf::from("test/test.fits")];
+        let fits = match run_open_fits(path, 0).unwrap() {
+            IOValue::Fits(fits) => fits,
+            _ => panic!("Expected FITS file"),
+        };
+        let image = expect_image(run_fits_to_image(&fits, 0, "").unwrap());
+        // Keep a small array so that the test does not serialize the whole
+        // data cube, with a non-trivial WCS.
+        let image = image.make_slice(
+            &[(0, 2.0, 1.0), (1, 0.0, 2.0), (2, 5.0, 1.0)],
+            image
+                .array()
+                .with_new_value(ArrayD::zeros(IxDyn(&[2, 3, 4]))),
+        );
+
+        let serialized = ron::ser::to_string(&image).unwrap();
+        let deserialized: WcsArray = ron::de::from_str(&serialized).unwrap();
+
+        assert_eq!(image, deserialized);
+        assert!(deserialized.wcs().is_some());
+        for axis in 0..3 {
+            for &pixel in &[0.0, 0.5, 1.0, 10.0, -3.0] {
+                assert_eq!(
+                    image.pix2world(axis, pixel),
+                    deserialized.pix2world(axis, pixel)
+                );
+            }
+        }
+    }
+    #[test]
+    fn test_io_value_codec() {
+        use crate::cake::Codec;
+        use crate::IOValueCodec;
+
+        let image = WcsArray::from_array(Dimensioned::new(
+            ArrayD::from_shape_vec(IxDyn(&[2, 3]), vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0]).unwrap(),
+            Unit::parse("erg/s/cm^2"),
+        ));
+        let values = [
+            IOValue::Integer(42),
+            IOValue::Str("SCI".to_owned()),
+            IOValue::Image(image),
+        ];
+        for value in &values {
+            let mut buf = vec![];
+            IOValueCodec.encode(value, &mut buf).unwrap();
+            let decoded = IOValueCodec.decode(&mut buf.as_slice()).unwrap();
+            assert_eq!(value, &decoded);
+        }
+
+        // Fingerprints of paths change with the files they point to
+        let paths = IOValue::Paths(PATHS::FileList(vec![PathBuf::from("test/test.fits")]));
+        let mut encoded = vec![];
+        IOValueCodec.encode(&paths, &mut encoded).unwrap();
+        assert_ne!(IOValueCodec.fingerprint(&paths), Some(encoded));
+    }
+
+    #[test]
+    fn test_slicing_keeps_world_coordinates() {
+        let path = vec![PathBuf::from("test/test.fits")];
+        let fits = match run_open_fits(path, 0).unwrap() {
+            IOValue::Fits(fits) => fits,
+            _ => panic!("Expected FITS file"),
+        };
+        let image = expect_image(run_fits_to_image(&fits, 0, "").unwrap());
+        let assert_close = |a: Option<f32>, b: Option<f32>| {
+            let (a, b) = (a.unwrap(), b.unwrap());
+            assert!((a - b).abs() < 1e-4, "{} != {}", a, b);
+        };
+
+        // Velocity is the last WCS axis, i.e. the first axis of the array
+        let range = image.slice_axis(0, 10, 20);
+        assert_eq!(range.scalar().shape(), &[10, 49, 49]);
+        assert_close(range.pix2world(2, 0.0), image.pix2world(2, 10.0));
+        assert_close(range.pix2world(0, 3.0), image.pix2world(0, 3.0));
+
+        let frame = image.index_axis(0, 10);
+        assert_eq!(frame.scalar().shape(), &[49, 49]);
+        assert_close(frame.pix2world(1, 7.0), image.pix2world(1, 7.0));
+
+        let down = image.subsample(&[1, 3, 3]);
+        assert_eq!(down.scalar().shape(), &[200, 17, 17]);
+        assert_close(down.pix2world(0, 2.0), image.pix2world(0, 6.0));
+        assert_close(down.pix2world(2, 2.0), image.pix2world(2, 2.0));
+
+        let map = Array2::from_shape_fn((4, 5), |(j, i)| [5.0, 2.0 + j as f32, 1.0 + i as f32]);
+        let plane = expect_image(run_slice_3d_to_2d(&image, &map).unwrap());
+        assert_close(plane.pix2world(0, 1.0), image.pix2world(0, 2.0));
+        assert_close(plane.pix2world(1, 1.0), image.pix2world(1, 3.0));
+    }
+
+    #[test]
+    fn test_read_fits_tables() {
+        fn floats(table: &Table, name: &str) -> Vec<f64> {
+            match table.column(name).unwrap().data() {
+                ColumnData::Float(data) => data.clone(),
+                data => panic!("Expected floats in {}, got {:?}", name, data),
+            }
+        }
+        fn strings(table: &Table, name: &str) -> Vec<String> {
+            match table.column(name).unwrap().data() {
+                ColumnData::Str(data) => data.clone(),
+                data => panic!("Expected strings in {}, got {:?}", name, data),
+            }
+        }
+
+        let path = vec![PathBuf::from("test/tables.fits")];
+        let fits = match run_open_fits(path, 0).unwrap() {
+            IOValue::Fits(fits) => fits,
+            _ => panic!("Expected FITS file"),
+        };
+        assert!(run_fits_to_table(&fits, 0, "").is_err());
+
+        let binary = match run_fits_to_table(&fits, 0, "BINTAB").unwrap() {
+            IOValue::Table(table) => table,
+            _ => panic!("Expected table"),
+        };
+        assert_eq!(binary.row_count(), 3);
+        // Integer column with TNULL1 = -1
+        let id = floats(&binary, "ID");
+        assert_eq!((id[0], id[2]), (1.0, 3.0));
+        assert!(id[1].is_nan());
+        assert_eq!(floats(&binary, "FLUX"), vec![1.5, 2.5, 3.5]);
+        assert_eq!(binary.column("FLUX").unwrap().unit(), &Unit::parse("Jy"));
+        assert_eq!(strings(&binary, "NAME"), vec!["abc", "de", "fghi"]);
+
+        let ascii = match run_fits_to_table(&fits, 2, "").unwrap() {
+            IOValue::Table(table) => table,
+            _ => panic!("Expected table"),
+        };
+        assert_eq!(ascii.row_count(), 3);
+        // F6.2 column, with an implicit decimal point in the second row
+        assert_eq!(floats(&ascii, "MAG"), vec![12.5, 1.25, -0.75]);
+        // Integer column with TNULL2 = 'NULL'
+        let num = floats(&ascii, "NUM");
+        assert_eq!((num[0], num[2]), (42.0, 7.0));
+        assert!(num[1].is_nan());
+        assert_eq!(strings(&ascii, "NAME"), vec!["abc", "de", "f"]);
+        // E9.2 column, with a D exponent and a blank field
+        let dist = floats(&ascii, "DIST");
+        assert_eq!((dist[0], dist[1]), (15.0, 2.0));
+        assert!(dist[2].is_nan());
+        assert_eq!(ascii.column("DIST").unwrap().unit(), &Unit::parse("pc"));
+    }
+
+    #[test]
+    fn test_extract_column() {
+        let table = Table::new(vec![
+            Column::new(
+                "FLUX".to_owned(),
+                Unit::parse("Jy"),
+                ColumnData::Float(vec![1.5, 2.5, 3.5]),
+            ),
+            Column::new(
+                "NAME".to_owned(),
+                Unit::None,
+                ColumnData::Str(vec!["a".to_owned(), "b".to_owned(), "c".to_owned()]),
+            ),
+        ]);
+        assert_eq!(table.row_count(), 3);
+
+        let flux = expect_image(run_extract_column(&table, "flux").unwrap());
+        assert_eq!(flux.scalar().as_slice().unwrap(), &[1.5, 2.5, 3.5]);
+        assert_eq!(flux.array().unit(), &Unit::parse("Jy"));
+
+        assert!(run_extract_column(&table, "NAME").is_err());
+        assert!(run_extract_column(&table, "MISSING").is_err());
+        assert!(run_extract_column_at(&table, 0).is_ok());
+        assert!(run_extract_column_at(&table, 2).is_err());
+    }
+
+    #[test]
+    fn test_parse_units() {
+        let flux = Unit::parse("10**(-17) erg s-1 cm-2 Angstrom-1");
+        assert_eq!(flux.repr(), "1e-17 erg/s/cm^2/Angstrom");
+        assert_eq!(flux, Unit::parse("1e-17 erg/s/cm\u{b2}/\u{c5}"));
+        assert_eq!(Unit::parse("km.s**-1").repr(), "km/s");
+        assert_eq!(Unit::parse("(km/s)^2").repr(), "km^2/s^2");
+        assert_eq!(Unit::parse("m/s").mul(Unit::parse("s")).repr(), "m");
+        assert_eq!(Unit::parse("1/s").div(Unit::parse("1/s")).repr(), "");
+        assert_eq!(Unit::parse(""), Unit::None);
+        assert_eq!(Unit::parse("spaxel(("), Unit::Custom("spaxel((".to_owned()));
+        assert!(Unit::parse("W m-2 Hz-1").is_compatible(&Unit::parse("mJy")));
+        assert!(!Unit::parse("erg").is_compatible(&Unit::parse("Jy")));
+        assert!("m//s".parse::<PhysicalUnit>().is_err());
+    }
+
+    #[test]
+    fn test_parse_units_exponent_overflow() {
+        for unit in &[
+            "m\u{2079}\u{2079}\u{2079}\u{2079}\u{2079}\u{2079}\u{2079}\u{2079}\u{2079}\u{2079}",
+            "(m^100000)^100000",
+            "m^2000000000 m^2000000000",
+            "Jy^2000000000",
+        ] {
+            match unit.parse::<PhysicalUnit>() {
+                Err(UnitError::Syntax { reason, .. }) => assert_eq!(reason, "exponent too large"),
+                other => panic!("{}: expected a syntax error, got {:?}", unit, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_unit_dimension_check() {
+        let make = |unit| WcsArray::from_array(Unit::parse(unit).new(ArrayD::ones(IxDyn(&[2]))));
+        let (erg, joule, jansky) = (make("erg"), make("J"), make("Jy"));
+        assert!(run_linear_composition(&erg, &jansky, 1.0, 1.0).is_err());
+        let sum = expect_image(run_linear_composition(&erg, &joule, 1.0, 1.0).unwrap());
+        assert_eq!(sum.array().unit().repr(), "erg");
+        assert_eq!(sum.scalar()[0], 1.0 + 1e7);
+    }
+
+    #[test]
+    fn test_convert_units() {
+        let mut hdu = Hdu::new(&[3], vec![1.0f32, 2.0, 4.0]);
+        hdu.insert(
+            "BUNIT",
+            HeaderValue::CharacterString("1e-17 erg/s/cm2/AA".to_owned()),
+        );
+        hdu.insert("CTYPE1", HeaderValue::CharacterString("WAVE".to_owned()));
+        hdu.insert(
+            "CUNIT1",
+            HeaderValue::CharacterString("Angstrom".to_owned()),
+        );
+        hdu.insert("CRPIX1", HeaderValue::RealFloatingNumber(1.0));
+        hdu.insert("CRVAL1", HeaderValue::RealFloatingNumber(5000.0));
+        hdu.insert("CDELT1", HeaderValue::RealFloatingNumber(1.25));
+        let spectrum = WcsArray::from_hdu(&hdu).unwrap();
+
+        let converted = expect_image(run_convert_unit(&spectrum, "W/m^2/nm").unwrap());
+        assert_eq!(converted.array().unit().repr(), "W/m^2/nm");
+        assert!((converted.scalar()[1] - 2e-19).abs() < 1e-25);
+
+        // f_nu [Jy] = 3.34e4 * lambda^2 [Angstrom^2] * f_lambda [erg/s/cm^2/Angstrom]
+        let converted = expect_image(run_convert_unit(&spectrum, "uJy").unwrap());
+        let expected = 3.335_641e4 * 5000.0 * 5000.0 * 1e-17 * 1e6;
+        assert!((converted.scalar()[0] - expected).abs() < 1e-4 * expected);
+        assert!(run_convert_unit(&spectrum, "km/s").is_err());
+        assert!(run_convert_unit(&spectrum, "m//s").is_err());
+
+        let converted = expect_image(run_convert_axis_unit(&spectrum, 0, "nm").unwrap());
+        assert_eq!(converted.axes().unwrap()[0].unit(), "nm");
+        let wcs = converted.wcs().unwrap();
+        assert!((wcs.pix2world([2.0, 0.0, 0.0, 0.0])[0] - 500.25).abs() < 1e-3);
+        assert!(run_convert_axis_unit(&spectrum, 0, "s").is_err());
+        assert!(run_convert_axis_unit(&spectrum, 1, "nm").is_err());
+    }
+
+    #[test]
+    fn test_wavelength_axis_units() {
+        // The same wavelengths 5000, 5010 and 5020 Angstrom in different units
+        for (unit, crval, cdelt) in &[
+            ("Angstrom", 5000.0, 10.0),
+            ("AA", 5000.0, 10.0),
+            ("angstrom", 5000.0, 10.0),
+            ("nm", 500.0, 1.0),
+        ] {
+            let mut hdu = Hdu::new(&[3], vec![1.0f32, 2.0, 1.0]);
+            hdu.insert("CTYPE1", HeaderValue::CharacterString("WAVE".to_owned()));
+            hdu.insert("CUNIT1", HeaderValue::CharacterString((*unit).to_owned()));
+            hdu.insert("CRPIX1", HeaderValue::RealFloatingNumber(1.0));
+            hdu.insert("CRVAL1", HeaderValue::RealFloatingNumber(*crval));
+            hdu.insert("CDELT1", HeaderValue::RealFloatingNumber(*cdelt));
+            let spectrum = WcsArray::from_hdu(&hdu).unwrap();
+
+            for result in vec![
+                run_centroid(&spectrum, 0, 3).unwrap(),
+                run_argminmax(&spectrum, 0, 3, false).unwrap(),
+            ] {
+                let image = expect_image(result);
+                let wavelength = image.scalar().iter().next().cloned().unwrap();
+                assert!(
+                    (wavelength - 5010.0).abs() < 1e-2,
+                    "{}: {}",
+                    unit,
+                    wavelength
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_save_fits_extensions() {
+        let sci = WcsArray::from_array(Unit::None.new(ArrayD::zeros(IxDyn(&[3, 2]))));
+        let err = WcsArray::from_array(Unit::None.new(ArrayD::from_elem(IxDyn(&[3, 2]), 0.5)));
+        let extensions =
+            match run_make_fits_extensions(&[(&sci, &"SCI".to_owned()), (&err, &"ERR".to_owned())])
+                .unwrap()
+            {
+                IOValue::FitsExtensions(extensions) => extensions,
+                _ => panic!("Expected FITS extensions"),
+            };
+        assert!(run_append_fits_extension(&extensions, &sci, "sci").is_err());
+        assert!(run_append_fits_extension(&extensions, &sci, " ").is_err());
+
+        let path = std::env::temp_dir().join("aflak_test_save_fits_extensions.fits");
+        extensions.save(&path).unwrap();
+        let fits = Fits::open(&path).unwrap();
+        let read = WcsArray::from_hdu(&fits.get_by_name("ERR").unwrap()).unwrap();
+        assert_eq!(read.scalar(), err.scalar());
+        assert_eq!(fits.iter().count(), 3);
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_uncertainty_propagation() {
+        let data = ArrayD::from_elem(IxDyn(&[4, 3, 2]), 1.0);
+        let sigma = ArrayD::from_elem(IxDyn(&[4, 3, 2]), 2.0);
+        let image = WcsArray::from_array(Unit::None.new(data));
+        let sigma = WcsArray::from_array(Unit::None.new(sigma));
+        assert!(run_extract_uncertainty(&image).is_err());
+
+        let image = expect_image(run_set_uncertainty(&image, &sigma).unwrap());
+        assert!(image.variance().unwrap().iter().all(|&v| v == 4.0));
+
+        let other = ArrayD::from_elem(IxDyn(&[4, 3, 2]), 3.0);
+        let other_sigma = ArrayD::from_elem(IxDyn(&[4, 3, 2]), 1.0);
+        let other = WcsArray::from_array(Unit::None.new(other));
+        let other_sigma = WcsArray::from_array(Unit::None.new(other_sigma));
+        let other = expect_image(run_set_uncertainty(&other, &other_sigma).unwrap());
+
+        // Var(2u - v) = 4 Var(u) + Var(v)
+        let composed = expect_image(run_linear_composition(&image, &other, 2.0, -1.0).unwrap());
+        assert!(composed.scalar().iter().all(|&v| v == -1.0));
+        assert!(composed.variance().unwrap().iter().all(|&v| v == 17.0));
+
+        let integral = expect_image(run_integral(&image, 0, 4).unwrap());
+        assert_eq!(integral.variance().unwrap().shape(), &[3, 2]);
+        assert!(integral.variance().unwrap().iter().all(|&v| v == 16.0));
+
+        let average = expect_image(run_average(&image, 0, 4).unwrap());
+        let uncertainty = expect_image(run_extract_uncertainty(&average).unwrap());
+        assert!(uncertainty.scalar().iter().all(|&v| v == 1.0));
+
+        let slice = image.slice_axis(0, 1, 3);
+        assert_eq!(slice.variance().unwrap().shape(), &[2, 3, 2]);
+    }
+
+    #[test]
+    fn test_masked_reductions() {
+        let data = ArrayD::from_shape_vec(
+            IxDyn(&[4, 2]),
+            vec![1.0, 1.0, 2.0, std::f32::NAN, 100.0, 3.0, 4.0, 5.0],
+        )
+        .unwrap();
+        let image = WcsArray::from_array(Unit::None.new(data));
+        let image = expect_image(run_replace_nan_image(&image, 0.0).unwrap());
+        let image = expect_image(run_mask_threshold(&image, 0.0, 10.0, 2).unwrap());
+        assert_eq!(
+            image.mask().unwrap().as_slice().unwrap(),
+            &[0, 0, 0, 1, 2, 0, 0, 0]
+        );
+
+        // Flagged values 100.0 and NaN are left out
+        let integral = expect_image(run_integral(&image, 0, 4).unwrap());
+        assert_eq!(integral.scalar().as_slice().unwrap(), &[7.0, 9.0]);
+        assert_eq!(integral.mask().unwrap().as_slice().unwrap(), &[0, 0]);
+        let average = expect_image(run_average(&image, 0, 4).unwrap());
+        assert_eq!(average.scalar().as_slice().unwrap(), &[7.0 / 3.0, 3.0]);
+        let median = expect_image(run_median(&image, 0, 4).unwrap());
+        assert_eq!(median.scalar().as_slice().unwrap(), &[2.0, 3.0]);
+        let variance = expect_image(run_variance(&image, 0, 4).unwrap());
+        assert!((variance.scalar()[0] - 7.0 / 3.0).abs() < 1e-5);
+        assert!((variance.scalar()[1] - 4.0).abs() < 1e-5);
+        let max = expect_image(run_minmax(&image, 0, 4, false).unwrap());
+        assert_eq!(max.scalar().as_slice().unwrap(), &[4.0, 5.0]);
+
+        // Only the threshold flag is considered
+        let image = expect_image(run_select_mask_bits(&image, 2).unwrap());
+        let integral = expect_image(run_integral(&image, 0, 4).unwrap());
+        assert_eq!(integral.scalar().as_slice().unwrap(), &[7.0, 9.0]);
+        // All values are flagged in the first pixel
+        let integral = expect_image(run_integral(&image, 2, 3).unwrap());
+        assert!(integral.scalar()[0].is_nan());
+        assert_eq!(integral.scalar()[1], 3.0);
+        assert_eq!(integral.mask().unwrap().as_slice().unwrap(), &[2, 0]);
+        assert!(run_select_mask_bits(&image, -1).is_err());
+    }
 }
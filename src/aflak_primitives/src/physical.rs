@@ -0,0 +1,891 @@
+//! Physical units with dimensional analysis.
+//!
+//! A [`PhysicalUnit`] is a product of unit symbols raised to integer powers,
+//! times a numeric factor, e.g. `10**-17 erg/s/cm^2/Angstrom`. Each known
+//! symbol is defined by its scale relative to the SI base units and by its
+//! dimension, which allows to check that two units are compatible and to
+//! convert values between them.
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::str::FromStr;
+use std::{error, fmt};
+
+/// Speed of light in vacuum, in m/s.
+const SPEED_OF_LIGHT: f64 = 299_792_458.0;
+/// Planck constant times the speed of light, in J·m.
+const PLANCK_TIMES_C: f64 = 6.626_070_15e-34 * SPEED_OF_LIGHT;
+/// Flux density of the zero point of the AB magnitude system, in Jy.
+const AB_ZERO_POINT_JY: f64 = 3631.0;
+/// Scale of the Jansky in SI units (W/m^2/Hz).
+const JANSKY: f64 = 1e-26;
+/// Symbol of AB magnitudes.
+const AB_MAG: &str = "ABmag";
+
+/// Number of SI base dimensions (length, mass, time, electric current,
+/// temperature, amount of substance, luminous intensity).
+const BASE_DIMENSIONS: usize = 7;
+
+const LENGTH: [i32; BASE_DIMENSIONS] = [1, 0, 0, 0, 0, 0, 0];
+const MASS: [i32; BASE_DIMENSIONS] = [0, 1, 0, 0, 0, 0, 0];
+const TIME: [i32; BASE_DIMENSIONS] = [0, 0, 1, 0, 0, 0, 0];
+const CURRENT: [i32; BASE_DIMENSIONS] = [0, 0, 0, 1, 0, 0, 0];
+const TEMPERATURE: [i32; BASE_DIMENSIONS] = [0, 0, 0, 0, 1, 0, 0];
+const AMOUNT: [i32; BASE_DIMENSIONS] = [0, 0, 0, 0, 0, 1, 0];
+const LUMINOUS_INTENSITY: [i32; BASE_DIMENSIONS] = [0, 0, 0, 0, 0, 0, 1];
+const DIMENSIONLESS: [i32; BASE_DIMENSIONS] = [0; BASE_DIMENSIONS];
+const FREQUENCY: [i32; BASE_DIMENSIONS] = [0, 0, -1, 0, 0, 0, 0];
+const FORCE: [i32; BASE_DIMENSIONS] = [1, 1, -2, 0, 0, 0, 0];
+const ENERGY: [i32; BASE_DIMENSIONS] = [2, 1, -2, 0, 0, 0, 0];
+const POWER: [i32; BASE_DIMENSIONS] = [2, 1, -3, 0, 0, 0, 0];
+const PRESSURE: [i32; BASE_DIMENSIONS] = [-1, 1, -2, 0, 0, 0, 0];
+const CHARGE: [i32; BASE_DIMENSIONS] = [0, 0, 1, 1, 0, 0, 0];
+const VOLTAGE: [i32; BASE_DIMENSIONS] = [2, 1, -3, -1, 0, 0, 0];
+const RESISTANCE: [i32; BASE_DIMENSIONS] = [2, 1, -3, -2, 0, 0, 0];
+const MAGNETIC_FIELD: [i32; BASE_DIMENSIONS] = [0, 1, -2, -1, 0, 0, 0];
+const FLUX_DENSITY: [i32; BASE_DIMENSIONS] = [0, 1, -2, 0, 0, 0, 0];
+
+/// Definition of a unit symbol.
+struct Definition {
+    /// Accepted spellings. The first one is the canonical symbol.
+    names: &'static [&'static str],
+    /// Value of the unit in SI base units.
+    scale: f64,
+    dimension: [i32; BASE_DIMENSIONS],
+    /// Whether SI prefixes (k, m, µ, ...) may be prepended to the symbol.
+    prefixable: bool,
+}
+
+macro_rules! define {
+    ([$($name: expr),+], $scale: expr, $dimension: expr, $prefixable: expr) => {
+        Definition {
+            names: &[$($name),+],
+            scale: $scale,
+            dimension: $dimension,
+            prefixable: $prefixable,
+        }
+    };
+}
+
+const DEFINITIONS: &[Definition] = &[
+    // SI base units
+    define!(["m"], 1.0, LENGTH, true),
+    define!(["g"], 1e-3, MASS, true),
+    define!(["s", "sec"], 1.0, TIME, true),
+    define!(["A"], 1.0, CURRENT, true),
+    define!(["K"], 1.0, TEMPERATURE, true),
+    define!(["mol"], 1.0, AMOUNT, true),
+    define!(["cd"], 1.0, LUMINOUS_INTENSITY, true),
+    // SI derived units
+    define!(["Hz"], 1.0, FREQUENCY, true),
+    define!(["N"], 1.0, FORCE, true),
+    define!(["J"], 1.0, ENERGY, true),
+    define!(["W"], 1.0, POWER, true),
+    define!(["Pa"], 1.0, PRESSURE, true),
+    define!(["C"], 1.0, CHARGE, true),
+    define!(["V"], 1.0, VOLTAGE, true),
+    define!(["Ohm"], 1.0, RESISTANCE, true),
+    define!(["T"], 1.0, MAGNETIC_FIELD, true),
+    define!(["rad"], 1.0, DIMENSIONLESS, true),
+    define!(["sr"], 1.0, DIMENSIONLESS, false),
+    // CGS units
+    define!(["erg"], 1e-7, ENERGY, false),
+    define!(["dyn", "dyne"], 1e-5, FORCE, false),
+    define!(["G", "gauss"], 1e-4, MAGNETIC_FIELD, false),
+    // Astronomy
+    define!(["Jy"], JANSKY, FLUX_DENSITY, true),
+    define!(
+        ["Angstrom", "angstrom", "Ang", "AA", "\u{c5}", "\u{212b}"],
+        1e-10,
+        LENGTH,
+        false
+    ),
+    define!(["pc", "parsec"], 3.085_677_581_491_367e16, LENGTH, true),
+    define!(["AU", "au"], 1.495_978_707e11, LENGTH, false),
+    define!(["ly", "lyr"], 9.460_730_472_580_8e15, LENGTH, false),
+    define!(["Msun", "solMass", "M_sun"], 1.988_47e30, MASS, false),
+    define!(["Lsun", "solLum", "L_sun"], 3.828e26, POWER, false),
+    define!(["Rsun", "solRad", "R_sun"], 6.957e8, LENGTH, false),
+    define!(["eV"], 1.602_176_634e-19, ENERGY, true),
+    define!(["min"], 60.0, TIME, false),
+    define!(["h", "hr", "hour"], 3600.0, TIME, false),
+    define!(["d", "day"], 86400.0, TIME, false),
+    define!(["yr", "year"], 3.155_76e7, TIME, true),
+    define!(
+        ["deg", "degree", "degrees"],
+        std::f64::consts::PI / 180.0,
+        DIMENSIONLESS,
+        false
+    ),
+    define!(
+        ["arcmin", "amin"],
+        std::f64::consts::PI / 10_800.0,
+        DIMENSIONLESS,
+        false
+    ),
+    define!(
+        ["arcsec", "asec"],
+        std::f64::consts::PI / 648_000.0,
+        DIMENSIONLESS,
+        false
+    ),
+    define!(
+        ["mas"],
+        std::f64::consts::PI / 648_000_000.0,
+        DIMENSIONLESS,
+        false
+    ),
+];
+
+/// SI prefixes with their scale. "µ" (micro sign) and "μ" (Greek mu) are
+/// both normalized to "u".
+const PREFIXES: &[(&str, &str, f64)] = &[
+    ("Y", "Y", 1e24),
+    ("Z", "Z", 1e21),
+    ("E", "E", 1e18),
+    ("P", "P", 1e15),
+    ("T", "T", 1e12),
+    ("G", "G", 1e9),
+    ("M", "M", 1e6),
+    ("k", "k", 1e3),
+    ("h", "h", 1e2),
+    ("da", "da", 1e1),
+    ("d", "d", 1e-1),
+    ("c", "c", 1e-2),
+    ("m", "m", 1e-3),
+    ("u", "u", 1e-6),
+    ("\u{b5}", "u", 1e-6),
+    ("\u{3bc}", "u", 1e-6),
+    ("n", "n", 1e-9),
+    ("p", "p", 1e-12),
+    ("f", "f", 1e-15),
+    ("a", "a", 1e-18),
+    ("z", "z", 1e-21),
+    ("y", "y", 1e-24),
+];
+
+/// Dimension of a unit.
+///
+/// Symbols that are not known physical units (e.g. "spaxel" or "count") are
+/// considered as independent dimensions of their own.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Dimension {
+    base: [i32; BASE_DIMENSIONS],
+    other: BTreeMap<String, i32>,
+}
+
+impl Dimension {
+    fn dimensionless() -> Self {
+        Dimension {
+            base: DIMENSIONLESS,
+            other: BTreeMap::new(),
+        }
+    }
+
+    /// Multiply the dimension by `other` raised to the power `exp`. Return
+    /// `None` if an exponent overflows.
+    fn mul_pow(&mut self, other: &Dimension, exp: i32) -> Option<()> {
+        for (d, o) in self.base.iter_mut().zip(other.base.iter()) {
+            *d = d.checked_add(o.checked_mul(exp)?)?;
+        }
+        for (symbol, e) in &other.other {
+            let entry = self.other.entry(symbol.clone()).or_insert(0);
+            *entry = entry.checked_add(e.checked_mul(exp)?)?;
+            if *entry == 0 {
+                self.other.remove(symbol);
+            }
+        }
+        Some(())
+    }
+}
+
+/// Resolve a symbol into its canonical spelling, its scale in SI units and
+/// its dimension.
+fn resolve(symbol: &str) -> (String, f64, Dimension) {
+    for def in DEFINITIONS {
+        if def.names.contains(&symbol) {
+            return (def.names[0].to_owned(), def.scale, def.dimension());
+        }
+    }
+    for &(prefix, canonical_prefix, prefix_scale) in PREFIXES {
+        if let Some(rest) = symbol.strip_prefix(prefix) {
+            for def in DEFINITIONS.iter().filter(|def| def.prefixable) {
+                if def.names.contains(&rest) {
+                    let canonical = format!("{}{}", canonical_prefix, def.names[0]);
+                    return (canonical, prefix_scale * def.scale, def.dimension());
+                }
+            }
+        }
+    }
+    let mut other = BTreeMap::new();
+    other.insert(symbol.to_owned(), 1);
+    (
+        symbol.to_owned(),
+        1.0,
+        Dimension {
+            base: DIMENSIONLESS,
+            other,
+        },
+    )
+}
+
+impl Definition {
+    fn dimension(&self) -> Dimension {
+        Dimension {
+            base: self.dimension,
+            other: BTreeMap::new(),
+        }
+    }
+}
+
+/// A physical unit, made of a numeric factor and of a product of symbols
+/// raised to integer powers.
+///
+/// The unit is kept in a canonical form: identical symbols are merged,
+/// symbols with a zero exponent are dropped and symbols with the same
+/// dimension that cancel each other out are folded into the factor.
+///
+/// # Examples
+///
+/// ```rust
+/// extern crate aflak_primitives as primitives;
+/// use primitives::PhysicalUnit;
+///
+/// let flux: PhysicalUnit = "10**-17 erg s-1 cm-2 Angstrom-1".parse().unwrap();
+/// assert_eq!(flux.to_string(), "1e-17 erg/s/cm^2/Angstrom");
+/// let velocity: PhysicalUnit = "km.s-1".parse().unwrap();
+/// assert_eq!(velocity.to_string(), "km/s");
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct PhysicalUnit {
+    factor: f64,
+    symbols: Vec<(String, i32)>,
+    repr: String,
+}
+
+/// Describe how values are converted from a unit to another.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Conversion {
+    /// Values are multiplied by a constant factor.
+    Scale(f64),
+    /// Spectral flux densities are converted to AB magnitudes. Values are
+    /// first multiplied by the contained factor to get Jy.
+    ToABMagnitude(f64),
+    /// AB magnitudes are converted to spectral flux densities. Values in Jy
+    /// are then multiplied by the contained factor.
+    FromABMagnitude(f64),
+}
+
+/// Error when parsing or converting units.
+#[derive(Clone, Debug, PartialEq)]
+pub enum UnitError {
+    /// The unit could not be parsed.
+    Syntax { unit: String, reason: String },
+    /// The unit is not a physical unit and cannot be converted.
+    NotPhysical(String),
+    /// The units have different dimensions.
+    Incompatible { from: String, to: String },
+    /// The conversion is not supported.
+    Unsupported(&'static str),
+}
+
+impl fmt::Display for UnitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            UnitError::Syntax {
+                ref unit,
+                ref reason,
+            } => write!(f, "Could not parse unit '{}': {}.", unit, reason),
+            UnitError::NotPhysical(ref unit) => {
+                write!(f, "'{}' is not a known physical unit.", unit)
+            }
+            UnitError::Incompatible { ref from, ref to } => write!(
+                f,
+                "Cannot convert from '{}' to '{}', as they have different dimensions.",
+                from, to
+            ),
+            UnitError::Unsupported(reason) => write!(f, "Unsupported conversion: {}.", reason),
+        }
+    }
+}
+
+impl error::Error for UnitError {
+    fn description(&self) -> &'static str {
+        "UnitError"
+    }
+}
+
+impl Conversion {
+    /// Convert a value.
+    pub fn apply(&self, value: f32) -> f32 {
+        match *self {
+            Conversion::Scale(k) => (f64::from(value) * k) as f32,
+            Conversion::ToABMagnitude(k) => {
+                (-2.5 * (f64::from(value) * k / AB_ZERO_POINT_JY).log10()) as f32
+            }
+            Conversion::FromABMagnitude(k) => {
+                (AB_ZERO_POINT_JY * 10f64.powf(-0.4 * f64::from(value)) * k) as f32
+            }
+        }
+    }
+
+    /// Derivative of the conversion at `value`, used to propagate variances.
+    pub fn derivative(&self, value: f32) -> f32 {
+        match *self {
+            Conversion::Scale(k) => k as f32,
+            Conversion::ToABMagnitude(_) => {
+                (-2.5 / (std::f64::consts::LN_10 * f64::from(value))) as f32
+            }
+            Conversion::FromABMagnitude(_) => {
+                (-0.4 * std::f64::consts::LN_10 * f64::from(self.apply(value))) as f32
+            }
+        }
+    }
+}
+
+impl PhysicalUnit {
+    fn from_parts(factor: f64, symbols: Vec<(String, i32)>) -> Self {
+        let (factor, symbols) = simplify(factor, symbols);
+        let repr = format_unit(factor, &symbols);
+        PhysicalUnit {
+            factor,
+            symbols,
+            repr,
+        }
+    }
+
+    /// Get canonical string representation.
+    pub fn repr(&self) -> &str {
+        &self.repr
+    }
+
+    /// Value of the unit in SI base units.
+    pub fn scale(&self) -> f64 {
+        self.symbols
+            .iter()
+            .fold(self.factor, |scale, (symbol, exp)| {
+                scale * resolve(symbol).1.powi(*exp)
+            })
+    }
+
+    /// Dimension of the unit, or `None` if an exponent overflows.
+    fn dimension(&self) -> Option<Dimension> {
+        let mut dimension = Dimension::dimensionless();
+        for (symbol, exp) in &self.symbols {
+            dimension.mul_pow(&resolve(symbol).2, *exp)?;
+        }
+        Some(dimension)
+    }
+
+    /// Check that both units have the same dimension, i.e. that values in
+    /// these units can be added together.
+    pub fn is_compatible(&self, other: &PhysicalUnit) -> bool {
+        match (self.dimension(), other.dimension()) {
+            (Some(dimension), Some(other)) => dimension == other,
+            _ => false,
+        }
+    }
+
+    fn is_ab_magnitude(&self) -> bool {
+        self.factor == 1.0 && self.symbols.len() == 1 && self.symbols[0] == (AB_MAG.to_owned(), 1)
+    }
+
+    fn is_flux_density(&self) -> bool {
+        self.dimension().map_or(false, |dimension| {
+            dimension.base == FLUX_DENSITY && dimension.other.is_empty()
+        })
+    }
+
+    /// Compute the conversion of values from this unit to the unit `to`.
+    pub fn conversion(&self, to: &PhysicalUnit) -> Result<Conversion, UnitError> {
+        if self.is_compatible(to) {
+            Ok(Conversion::Scale(round(self.scale() / to.scale())))
+        } else if to.is_ab_magnitude() && self.is_flux_density() {
+            Ok(Conversion::ToABMagnitude(self.scale() / JANSKY))
+        } else if self.is_ab_magnitude() && to.is_flux_density() {
+            Ok(Conversion::FromABMagnitude(JANSKY / to.scale()))
+        } else {
+            Err(UnitError::Incompatible {
+                from: self.repr.clone(),
+                to: to.repr.clone(),
+            })
+        }
+    }
+
+    /// Compute the conversion of a spectral flux density from this unit to
+    /// the unit `to`, where either unit is per unit of wavelength (f_λ) and
+    /// the other is per unit of frequency (f_ν).
+    ///
+    /// `wavelength` is given in meters. The conversion is given by
+    /// f_ν = f_λ λ² / c. Units that are already compatible are converted
+    /// with a constant factor.
+    pub fn spectral_conversion(
+        &self,
+        to: &PhysicalUnit,
+        wavelength: f64,
+    ) -> Result<Conversion, UnitError> {
+        if let Ok(conversion) = self.conversion(to) {
+            return Ok(conversion);
+        }
+        // λ² / c has the dimension of a length times a time
+        let per_frequency = PhysicalUnit::from_parts(
+            wavelength * wavelength / SPEED_OF_LIGHT,
+            vec![("m".to_owned(), 1), ("s".to_owned(), 1)],
+        );
+        let f_nu = self.clone().mul(&per_frequency);
+        let f_lambda = self.clone().div(&per_frequency);
+        f_nu.conversion(to)
+            .or_else(|_| f_lambda.conversion(to))
+            .map_err(|_| UnitError::Incompatible {
+                from: self.repr.clone(),
+                to: to.repr.clone(),
+            })
+    }
+
+    /// Interpret `value` in this unit as a spectral coordinate (wavelength,
+    /// frequency, wavenumber or energy of a photon) and return the
+    /// corresponding wavelength in meters.
+    ///
+    /// Return `None` if the unit is not a spectral unit.
+    pub fn to_wavelength(&self, value: f64) -> Option<f64> {
+        let dimension = self.dimension()?;
+        if !dimension.other.is_empty() {
+            return None;
+        }
+        let si = value * self.scale();
+        if dimension.base == LENGTH {
+            Some(si)
+        } else if dimension.base == FREQUENCY {
+            Some(SPEED_OF_LIGHT / si)
+        } else if dimension.base == [-1, 0, 0, 0, 0, 0, 0] {
+            Some(1.0 / si)
+        } else if dimension.base == ENERGY {
+            Some(PLANCK_TIMES_C / si)
+        } else {
+            None
+        }
+    }
+
+    /// Multiply two units.
+    pub fn mul(self, rhs: &PhysicalUnit) -> Self {
+        let mut symbols = self.symbols;
+        symbols.extend(rhs.symbols.iter().cloned());
+        PhysicalUnit::from_parts(self.factor * rhs.factor, symbols)
+    }
+
+    /// Divide two units.
+    pub fn div(self, rhs: &PhysicalUnit) -> Self {
+        let mut symbols = self.symbols;
+        symbols.extend(
+            rhs.symbols
+                .iter()
+                .map(|(s, e)| (s.clone(), e.saturating_neg())),
+        );
+        PhysicalUnit::from_parts(self.factor / rhs.factor, symbols)
+    }
+}
+
+/// Bring factor and symbols to canonical form.
+fn simplify(mut factor: f64, symbols: Vec<(String, i32)>) -> (f64, Vec<(String, i32)>) {
+    // Merge identical symbols, keeping the order of first appearance
+    let mut merged: Vec<(String, i32)> = Vec::with_capacity(symbols.len());
+    for (symbol, exp) in symbols {
+        if let Some(entry) = merged.iter_mut().find(|(s, _)| *s == symbol) {
+            entry.1 = entry.1.saturating_add(exp);
+        } else {
+            merged.push((symbol, exp));
+        }
+    }
+    // Cancel out different symbols with the same dimension, e.g. nm/Angstrom
+    for i in 0..merged.len() {
+        for j in 0..merged.len() {
+            let (ei, ej) = (merged[i].1, merged[j].1);
+            if i == j || ei <= 0 || ej >= 0 {
+                continue;
+            }
+            let (_, scale_i, dim_i) = resolve(&merged[i].0);
+            let (_, scale_j, dim_j) = resolve(&merged[j].0);
+            if dim_i == dim_j && dim_i.other.is_empty() && dim_i.base != DIMENSIONLESS {
+                let n = ei.min(-ej);
+                factor *= (scale_i / scale_j).powi(n);
+                merged[i].1 -= n;
+                merged[j].1 += n;
+            }
+        }
+    }
+    merged.retain(|(_, exp)| *exp != 0);
+    // Positive exponents first
+    let (mut symbols, negatives): (Vec<_>, Vec<_>) =
+        merged.into_iter().partition(|(_, exp)| *exp > 0);
+    symbols.extend(negatives);
+    (round(factor), symbols)
+}
+
+/// Remove rounding errors from factors such as 1e-17 or 0.1.
+fn round(factor: f64) -> f64 {
+    if factor != 0.0 && factor.is_finite() {
+        format!("{:.12e}", factor).parse().unwrap_or(factor)
+    } else {
+        factor
+    }
+}
+
+fn format_unit(factor: f64, symbols: &[(String, i32)]) -> String {
+    let format_symbol = |symbol: &str, exp: i32| {
+        if exp == 1 {
+            symbol.to_owned()
+        } else {
+            format!("{}^{}", symbol, exp)
+        }
+    };
+    let positives: Vec<_> = symbols
+        .iter()
+        .filter(|(_, exp)| *exp > 0)
+        .map(|(s, exp)| format_symbol(s, *exp))
+        .collect();
+    let mut body = positives.join("*");
+    for (symbol, exp) in symbols.iter().filter(|(_, exp)| *exp < 0) {
+        body.push('/');
+        body.push_str(&format_symbol(symbol, exp.saturating_neg()));
+    }
+    if positives.is_empty() && !body.is_empty() {
+        body.insert(0, '1');
+    }
+    if factor == 1.0 {
+        return body;
+    }
+    let exponent = factor.log10().round() as i32;
+    let power_of_ten = format!("1e{}", exponent);
+    let factor = if power_of_ten.parse() == Ok(factor) {
+        power_of_ten
+    } else {
+        format!("{}", factor)
+    };
+    if body.is_empty() {
+        factor
+    } else if body.starts_with("1/") {
+        format!("{}{}", factor, &body[1..])
+    } else {
+        format!("{} {}", factor, body)
+    }
+}
+
+impl fmt::Display for PhysicalUnit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.repr)
+    }
+}
+
+impl FromStr for PhysicalUnit {
+    type Err = UnitError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Parser::new(s).parse()
+    }
+}
+
+impl TryFrom<String> for PhysicalUnit {
+    type Error = UnitError;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<PhysicalUnit> for String {
+    fn from(unit: PhysicalUnit) -> Self {
+        unit.repr
+    }
+}
+
+/// Parser for unit strings.
+///
+/// Understand the FITS standard conventions and the usual variations found
+/// in astronomical data: `*`, `.` or a space for products, `/` for
+/// divisions (applied to the following term only), `^`, `**` or an attached
+/// integer for powers (e.g. `cm^2`, `cm**2`, `cm2`, `cm-2`, `cm²`) and
+/// parentheses. The unit may start with a numeric factor such as `1e-17`,
+/// `10**-17` or `10^(-17)`.
+struct Parser<'a> {
+    input: &'a str,
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a str) -> Self {
+        Parser {
+            input,
+            chars: input.trim().chars().collect(),
+            pos: 0,
+        }
+    }
+
+    fn error<T>(&self, reason: String) -> Result<T, UnitError> {
+        Err(UnitError::Syntax {
+            unit: self.input.to_owned(),
+            reason,
+        })
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).cloned()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).cloned()
+    }
+
+    fn skip_spaces(&mut self) -> bool {
+        let start = self.pos;
+        while self.peek().map_or(false, char::is_whitespace) {
+            self.pos += 1;
+        }
+        self.pos > start
+    }
+
+    fn parse(mut self) -> Result<PhysicalUnit, UnitError> {
+        if self.chars.is_empty() {
+            return self.error("unit is empty".to_owned());
+        }
+        let mut factor = 1.0;
+        let mut leading_division = false;
+        if self
+            .peek()
+            .map_or(false, |c| c.is_ascii_digit() || c == '.')
+        {
+            factor = self.parse_factor()?;
+            self.skip_spaces();
+            match self.peek() {
+                None => return Ok(PhysicalUnit::from_parts(factor, vec![])),
+                Some('*') | Some('.') => self.pos += 1,
+                Some('/') => {
+                    self.pos += 1;
+                    leading_division = true;
+                }
+                _ => (),
+            }
+        }
+        let symbols = self.parse_product(leading_division, false)?;
+        if let Some(c) = self.peek() {
+            return self.error(format!("unexpected character '{}'", c));
+        }
+        // Exponents of a same symbol are summed when simplified
+        if !symbols_sum_fits(&symbols) {
+            return self.error("exponent too large".to_owned());
+        }
+        let unit = PhysicalUnit::from_parts(factor, symbols);
+        if unit.dimension().is_none() {
+            return self.error("exponent too large".to_owned());
+        }
+        Ok(unit)
+    }
+
+    /// Parse a leading numeric factor.
+    fn parse_factor(&mut self) -> Result<f64, UnitError> {
+        let start = self.pos;
+        while self
+            .peek()
+            .map_or(false, |c| c.is_ascii_digit() || c == '.')
+        {
+            self.pos += 1;
+        }
+        if let Some('e') | Some('E') = self.peek() {
+            let sign = match self.peek_at(1) {
+                Some('+') | Some('-') => 1,
+                _ => 0,
+            };
+            if self.peek_at(1 + sign).map_or(false, |c| c.is_ascii_digit()) {
+                self.pos += 1 + sign;
+                while self.peek().map_or(false, |c| c.is_ascii_digit()) {
+                    self.pos += 1;
+                }
+            }
+        }
+        let number: String = self.chars[start..self.pos].iter().collect();
+        let mantissa = match number.parse::<f64>() {
+            Ok(mantissa) => mantissa,
+            Err(_) => return self.error(format!("invalid number '{}'", number)),
+        };
+        if let Some(exp) = self.parse_power_operator()? {
+            Ok(mantissa.powi(exp))
+        } else {
+            Ok(mantissa)
+        }
+    }
+
+    /// Parse an explicit power `^n` or `**n`, if any.
+    fn parse_power_operator(&mut self) -> Result<Option<i32>, UnitError> {
+        if self.peek() == Some('^') {
+            self.pos += 1;
+        } else if self.peek() == Some('*') && self.peek_at(1) == Some('*') {
+            self.pos += 2;
+        } else {
+            return Ok(None);
+        }
+        let parenthesized = self.peek() == Some('(');
+        if parenthesized {
+            self.pos += 1;
+        }
+        let exp = match self.parse_integer() {
+            Some(exp) => exp,
+            None => return self.error("invalid exponent".to_owned()),
+        };
+        if parenthesized {
+            if self.peek() != Some(')') {
+                return self.error("missing ')'".to_owned());
+            }
+            self.pos += 1;
+        }
+        Ok(Some(exp))
+    }
+
+    fn parse_integer(&mut self) -> Option<i32> {
+        let start = self.pos;
+        if let Some('+') | Some('-') = self.peek() {
+            self.pos += 1;
+        }
+        while self.peek().map_or(false, |c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        let number: String = self.chars[start..self.pos].iter().collect();
+        let parsed = number.parse().ok();
+        if parsed.is_none() {
+            self.pos = start;
+        }
+        parsed
+    }
+
+    /// Parse the exponent following a symbol or a parenthesized group.
+    fn parse_exponent(&mut self) -> Result<i32, UnitError> {
+        if let Some(exp) = self.parse_power_operator()? {
+            return Ok(exp);
+        }
+        match self.peek() {
+            Some(c) if c.is_ascii_digit() => Ok(self.parse_integer().unwrap_or(1)),
+            Some('+') | Some('-') if self.peek_at(1).map_or(false, |c| c.is_ascii_digit()) => {
+                Ok(self.parse_integer().unwrap_or(1))
+            }
+            Some(c) if superscript_digit(c).is_some() || c == '\u{207b}' => {
+                let negative = c == '\u{207b}';
+                if negative {
+                    self.pos += 1;
+                }
+                let mut exp: i32 = 0;
+                while let Some(digit) = self.peek().and_then(superscript_digit) {
+                    exp = match exp.checked_mul(10).and_then(|exp| exp.checked_add(digit)) {
+                        Some(exp) => exp,
+                        None => return self.error("exponent too large".to_owned()),
+                    };
+                    self.pos += 1;
+                }
+                Ok(if negative { -exp } else { exp })
+            }
+            _ => Ok(1),
+        }
+    }
+
+    /// Parse a product of terms until the end of the input, or until a
+    /// closing parenthesis if `in_group` is true.
+    fn parse_product(
+        &mut self,
+        mut invert: bool,
+        in_group: bool,
+    ) -> Result<Vec<(String, i32)>, UnitError> {
+        let mut symbols = vec![];
+        loop {
+            self.skip_spaces();
+            let sign = if invert { -1 } else { 1 };
+            for (symbol, exp) in self.parse_term()? {
+                match exp.checked_mul(sign) {
+                    Some(exp) => symbols.push((symbol, exp)),
+                    None => return self.error("exponent too large".to_owned()),
+                }
+            }
+            let had_spaces = self.skip_spaces();
+            match self.peek() {
+                None => break,
+                Some(')') if in_group => break,
+                Some('*') | Some('.') => {
+                    self.pos += 1;
+                    invert = false;
+                }
+                Some('/') => {
+                    self.pos += 1;
+                    invert = true;
+                }
+                Some(_) if had_spaces => invert = false,
+                Some(c) => return self.error(format!("unexpected character '{}'", c)),
+            }
+        }
+        Ok(symbols)
+    }
+
+    fn parse_term(&mut self) -> Result<Vec<(String, i32)>, UnitError> {
+        if self.peek() == Some('(') {
+            self.pos += 1;
+            let symbols = self.parse_product(false, true)?;
+            if self.peek() != Some(')') {
+                return self.error("missing ')'".to_owned());
+            }
+            self.pos += 1;
+            let exp = self.parse_exponent()?;
+            return symbols
+                .into_iter()
+                .map(|(s, e)| match e.checked_mul(exp) {
+                    Some(e) => Ok((s, e)),
+                    None => self.error("exponent too large".to_owned()),
+                })
+                .collect();
+        }
+        let start = self.pos;
+        while self.peek().map_or(false, |c| {
+            c.is_alphabetic() && superscript_digit(c).is_none() || c == '_'
+        }) {
+            self.pos += 1;
+        }
+        if self.pos == start {
+            return match self.peek() {
+                Some(c) => self.error(format!("expected a unit symbol, got '{}'", c)),
+                None => self.error("expected a unit symbol".to_owned()),
+            };
+        }
+        let mut symbol: String = self.chars[start..self.pos].iter().collect();
+        // Magnitudes in the AB system, written as "mag(AB)"
+        if symbol == "mag" && self.chars[self.pos..].starts_with(&['(', 'A', 'B', ')']) {
+            self.pos += 4;
+            symbol = AB_MAG.to_owned();
+        } else if symbol == "AB" {
+            symbol = AB_MAG.to_owned();
+        }
+        let exp = self.parse_exponent()?;
+        let (canonical, _, _) = resolve(&symbol);
+        Ok(vec![(canonical, exp)])
+    }
+}
+
+/// Check that the exponents of each symbol can be summed without overflow.
+fn symbols_sum_fits(symbols: &[(String, i32)]) -> bool {
+    let mut sums = BTreeMap::new();
+    symbols.iter().all(|(symbol, exp)| {
+        let sum: &mut i32 = sums.entry(symbol).or_insert(0);
+        match sum.checked_add(*exp) {
+            Some(new_sum) => {
+                *sum = new_sum;
+                true
+            }
+            None => false,
+        }
+    })
+}
+
+fn superscript_digit(c: char) -> Option<i32> {
+    match c {
+        '\u{2070}' => Some(0),
+        '\u{b9}' => Some(1),
+        '\u{b2}' => Some(2),
+        '\u{b3}' => Some(3),
+        '\u{2074}'..='\u{2079}' => Some(c as i32 - 0x2070),
+        _ => None,
+    }
+}
@@ -112,3 +112,20 @@ macro_rules! are_same_dim {
         }
     }};
 }
+
+/// Check that the values of two WcsArray can be added together, i.e. that
+/// their units have the same dimension
+macro_rules! are_compatible_units {
+    ($wcs_array1: ident, $wcs_array2: ident) => {{
+        let i1_unit = $wcs_array1.array().unit();
+        let i2_unit = $wcs_array2.array().unit();
+        if i1_unit.is_compatible(i2_unit) {
+            Ok(())
+        } else {
+            Err(IOErr::UnexpectedInput(format!(
+                "Cannot add or subtract values with units of different dimensions ('{}' is in '{}', while '{}' is in '{}')",
+                stringify!($wcs_array1), i1_unit, stringify!($wcs_array2), i2_unit,
+            )))
+        }
+    }};
+}
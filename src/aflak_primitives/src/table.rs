@@ -0,0 +1,590 @@
+//! Tables read from FITS binary and ASCII table extensions.
+use std::error;
+use std::fmt;
+use std::mem;
+
+use fitrs::{FitsData, Hdu, HeaderValue};
+use ndarray::{ArrayD, IxDyn};
+
+use crate::unit::{read_float, read_string, read_unit, Unit, WcsArray};
+
+/// A table made of named columns sharing the same number of rows.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Table {
+    columns: Vec<Column>,
+}
+
+/// A named column of a `Table`, with a unit.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Column {
+    name: String,
+    unit: Unit,
+    data: ColumnData,
+}
+
+/// Values contained in a column. All values in a column share the same type.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ColumnData {
+    Bool(Vec<bool>),
+    Integer(Vec<i64>),
+    Float(Vec<f64>),
+    Str(Vec<String>),
+}
+
+#[derive(Debug)]
+pub enum TableReadError {
+    NotATable(Option<String>),
+    MissingKeyword(String),
+    UnexpectedData(&'static str),
+    UnsupportedFormat(String),
+    DataTooShort { expected: usize, got: usize },
+    RowTooShort { column: String, row_width: usize },
+    InvalidValue { column: String, value: String },
+}
+
+impl fmt::Display for TableReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TableReadError::NotATable(Some(xtension)) => write!(
+                f,
+                "Expects a table (BINTABLE or TABLE) extension. Got '{}'.",
+                xtension
+            ),
+            TableReadError::NotATable(None) => write!(
+                f,
+                "Expects a table (BINTABLE or TABLE) extension. Got the primary HDU."
+            ),
+            TableReadError::MissingKeyword(key) => {
+                write!(f, "Missing mandatory keyword '{}' in table header.", key)
+            }
+            TableReadError::UnexpectedData(format) => {
+                write!(f, "Unexpected data array format for a table: '{}'.", format)
+            }
+            TableReadError::UnsupportedFormat(format) => {
+                write!(f, "Unsupported column format: '{}'.", format)
+            }
+            TableReadError::DataTooShort { expected, got } => write!(
+                f,
+                "Table should contain {} bytes. But only {} bytes were found.",
+                expected, got
+            ),
+            TableReadError::RowTooShort { column, row_width } => write!(
+                f,
+                "Column '{}' does not fit in a row of {} bytes.",
+                column, row_width
+            ),
+            TableReadError::InvalidValue { column, value } => {
+                write!(f, "Invalid value '{}' in column '{}'.", value, column)
+            }
+        }
+    }
+}
+
+impl error::Error for TableReadError {
+    fn description(&self) -> &'static str {
+        "TableReadError"
+    }
+}
+
+/// Format of a column in a binary table, parsed from TFORMn keyword.
+struct ColumnFormat {
+    repeat: usize,
+    code: char,
+}
+
+impl ColumnFormat {
+    /// Parse TFORMn value, e.g. "1E", "J" or "20A".
+    fn parse(tform: &str) -> Result<Self, TableReadError> {
+        let tform = tform.trim();
+        let digits = tform.chars().take_while(|c| c.is_ascii_digit()).count();
+        let repeat = if digits == 0 {
+            1
+        } else {
+            tform[..digits]
+                .parse()
+                .map_err(|_| TableReadError::UnsupportedFormat(tform.to_owned()))?
+        };
+        let code = tform[digits..]
+            .chars()
+            .next()
+            .ok_or_else(|| TableReadError::UnsupportedFormat(tform.to_owned()))?;
+        Ok(ColumnFormat { repeat, code })
+    }
+
+    /// Width in bytes of one element of the column.
+    fn element_width(&self) -> Option<usize> {
+        match self.code {
+            'L' | 'B' | 'A' => Some(1),
+            'I' => Some(2),
+            'J' | 'E' => Some(4),
+            'K' | 'D' | 'C' | 'P' => Some(8),
+            'M' | 'Q' => Some(16),
+            _ => None,
+        }
+    }
+
+    /// Width in bytes of the column in a row.
+    fn width(&self) -> Option<usize> {
+        if self.code == 'X' {
+            Some((self.repeat + 7) / 8)
+        } else {
+            self.element_width().map(|width| width * self.repeat)
+        }
+    }
+
+    /// Whether the column can be read as a single value per row.
+    ///
+    /// Vector columns (except strings), complex numbers, bits and variable
+    /// length arrays are not supported.
+    fn is_scalar(&self) -> bool {
+        match self.code {
+            'A' => true,
+            'L' | 'B' | 'I' | 'J' | 'K' | 'E' | 'D' => self.repeat == 1,
+            _ => false,
+        }
+    }
+}
+
+/// Format of a column in an ASCII table, parsed from TFORMn keyword.
+struct AsciiColumnFormat {
+    code: char,
+    width: usize,
+    /// Number of digits after the implicit decimal point of floats
+    decimals: usize,
+}
+
+impl AsciiColumnFormat {
+    /// Parse TFORMn value, e.g. "A10", "I6", "F8.3" or "E12.5".
+    fn parse(tform: &str) -> Result<Self, TableReadError> {
+        let tform = tform.trim();
+        let unsupported = || TableReadError::UnsupportedFormat(tform.to_owned());
+        let mut chars = tform.chars();
+        let code = chars.next().ok_or_else(unsupported)?;
+        let rest = chars.as_str();
+        let (width, decimals) = match rest.split_once('.') {
+            Some((width, decimals)) => (width, decimals.parse().map_err(|_| unsupported())?),
+            None => (rest, 0),
+        };
+        let width = width.parse().map_err(|_| unsupported())?;
+        match code {
+            'A' | 'I' | 'F' | 'E' | 'D' if width > 0 => Ok(AsciiColumnFormat {
+                code,
+                width,
+                decimals,
+            }),
+            _ => Err(unsupported()),
+        }
+    }
+}
+
+/// Kind of a table extension, given by the XTENSION keyword.
+#[derive(Copy, Clone, PartialEq)]
+enum TableKind {
+    Binary,
+    Ascii,
+}
+
+impl Table {
+    pub fn new(columns: Vec<Column>) -> Self {
+        Table { columns }
+    }
+
+    /// Make `Table` from binary (BINTABLE) or ASCII (TABLE) table extension
+    /// found in FITS file.
+    ///
+    /// Read the name (TTYPEn), format (TFORMn) and unit (TUNITn) of each
+    /// column, and the position of columns (TBCOLn) of ASCII tables. Integer
+    /// columns are scaled with TSCALn and TZEROn if present. Integer columns
+    /// with a null value (TNULLn) are converted to floats, with null values
+    /// replaced by NaN. Blank fields of numeric columns of ASCII tables are
+    /// null as well. Columns with a format that cannot be represented as a
+    /// single value per row (vectors, complex numbers, variable length
+    /// arrays) are skipped.
+    pub fn from_hdu(hdu: &Hdu) -> Result<Table, TableReadError> {
+        let kind = match read_string(hdu, "XTENSION") {
+            Some(ref xtension) if xtension.trim() == "BINTABLE" => TableKind::Binary,
+            Some(ref xtension) if xtension.trim() == "TABLE" => TableKind::Ascii,
+            xtension => return Err(TableReadError::NotATable(xtension)),
+        };
+        let row_width = read_integer(hdu, "NAXIS1")?;
+        let row_count = read_integer(hdu, "NAXIS2")?;
+        let field_count = read_integer(hdu, "TFIELDS")?;
+
+        let bytes: Vec<u8> = if row_width * row_count == 0 {
+            vec![]
+        } else {
+            match hdu.read_data() {
+                FitsData::Characters(data) => data.data.iter().map(|&c| c as u32 as u8).collect(),
+                FitsData::FloatingPoint32(_) => {
+                    return Err(TableReadError::UnexpectedData("FloatingPoint32"))
+                }
+                FitsData::FloatingPoint64(_) => {
+                    return Err(TableReadError::UnexpectedData("FloatingPoint64"))
+                }
+                FitsData::IntegersI32(_) => {
+                    return Err(TableReadError::UnexpectedData("IntegersI32"))
+                }
+                FitsData::IntegersU32(_) => {
+                    return Err(TableReadError::UnexpectedData("IntegersU32"))
+                }
+            }
+        };
+        if bytes.len() < row_width * row_count {
+            return Err(TableReadError::DataTooShort {
+                expected: row_width * row_count,
+                got: bytes.len(),
+            });
+        }
+
+        let mut columns = Vec::with_capacity(field_count);
+        let mut offset = 0;
+        for n in 1..=field_count {
+            let tform_key = format!("TFORM{}", n);
+            let tform = read_string(hdu, &tform_key)
+                .ok_or_else(|| TableReadError::MissingKeyword(tform_key.clone()))?;
+            let name = read_string(hdu, &format!("TTYPE{}", n))
+                .map(|name| name.trim().to_owned())
+                .unwrap_or_else(|| format!("COL{}", n));
+            let row_too_short = || TableReadError::RowTooShort {
+                column: tform_key.clone(),
+                row_width,
+            };
+            let data = match kind {
+                TableKind::Binary => {
+                    let format = ColumnFormat::parse(&tform)?;
+                    let width = format
+                        .width()
+                        .ok_or_else(|| TableReadError::UnsupportedFormat(tform.clone()))?;
+                    let cells = column_cells(&bytes, row_width, row_count, offset, width)
+                        .ok_or_else(row_too_short)?;
+                    offset += width;
+                    if !format.is_scalar() {
+                        continue;
+                    }
+                    let null = read_float(hdu, &format!("TNULL{}", n));
+                    read_column(&format, cells).with_null(null)
+                }
+                TableKind::Ascii => {
+                    let format = AsciiColumnFormat::parse(&tform)?;
+                    let tbcol_key = format!("TBCOL{}", n);
+                    // TBCOLn is 1-based
+                    let start = match read_integer(hdu, &tbcol_key)? {
+                        0 => return Err(TableReadError::MissingKeyword(tbcol_key)),
+                        tbcol => tbcol - 1,
+                    };
+                    let cells = column_cells(&bytes, row_width, row_count, start, format.width)
+                        .ok_or_else(row_too_short)?;
+                    let null = read_string(hdu, &format!("TNULL{}", n));
+                    read_ascii_column(&format, cells, null.as_deref(), &name)?
+                }
+            };
+            let scale = read_float(hdu, &format!("TSCAL{}", n));
+            let zero = read_float(hdu, &format!("TZERO{}", n));
+            let data = data.scaled(scale, zero);
+            let unit = read_unit(hdu, &format!("TUNIT{}", n));
+            columns.push(Column::new(name, unit, data));
+        }
+        Ok(Table { columns })
+    }
+
+    /// Estimated memory used by the table, in bytes.
+    pub(crate) fn heap_size(&self) -> usize {
+        self.columns
+            .iter()
+            .map(|column| mem::size_of::<Column>() + column.name.len() + column.data.heap_size())
+            .sum()
+    }
+
+    pub fn columns(&self) -> &[Column] {
+        &self.columns
+    }
+
+    /// Get column named `name`. Comparison is case-insensitive, as FITS
+    /// column names are.
+    pub fn column(&self, name: &str) -> Option<&Column> {
+        self.columns
+            .iter()
+            .find(|column| column.name.eq_ignore_ascii_case(name.trim()))
+    }
+
+    /// Number of rows in the table.
+    pub fn row_count(&self) -> usize {
+        self.columns.first().map(Column::len).unwrap_or(0)
+    }
+}
+
+impl Column {
+    pub fn new(name: String, unit: Unit, data: ColumnData) -> Self {
+        Column { name, unit, data }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn unit(&self) -> &Unit {
+        &self.unit
+    }
+
+    pub fn data(&self) -> &ColumnData {
+        &self.data
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Convert column to a 1D image with the unit of the column.
+    ///
+    /// Boolean values are converted to 0 and 1. Return `None` for columns of
+    /// strings.
+    pub fn to_image(&self) -> Option<WcsArray> {
+        let values: Vec<f32> = match &self.data {
+            ColumnData::Bool(data) => data.iter().map(|&b| if b { 1.0 } else { 0.0 }).collect(),
+            ColumnData::Integer(data) => data.iter().map(|&i| i as f32).collect(),
+            ColumnData::Float(data) => data.iter().map(|&f| f as f32).collect(),
+            ColumnData::Str(_) => return None,
+        };
+        let array = ArrayD::from_shape_vec(IxDyn(&[values.len()]), values).unwrap();
+        Some(WcsArray::from_array(self.unit.clone().new(array)))
+    }
+
+    /// Format value at row `row` for display.
+    pub fn format_value(&self, row: usize) -> String {
+        match &self.data {
+            ColumnData::Bool(data) => format!("{}", data[row]),
+            ColumnData::Integer(data) => format!("{}", data[row]),
+            ColumnData::Float(data) => format!("{}", data[row]),
+            ColumnData::Str(data) => data[row].clone(),
+        }
+    }
+}
+
+impl ColumnData {
+    pub fn len(&self) -> usize {
+        match self {
+            ColumnData::Bool(data) => data.len(),
+            ColumnData::Integer(data) => data.len(),
+            ColumnData::Float(data) => data.len(),
+            ColumnData::Str(data) => data.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Estimated memory used by the values, in bytes.
+    pub(crate) fn heap_size(&self) -> usize {
+        match self {
+            ColumnData::Bool(data) => data.len(),
+            ColumnData::Integer(data) => data.len() * mem::size_of::<i64>(),
+            ColumnData::Float(data) => data.len() * mem::size_of::<f64>(),
+            ColumnData::Str(data) => data
+                .iter()
+                .map(|s| mem::size_of::<String>() + s.len())
+                .sum(),
+        }
+    }
+
+    /// Replace null values (TNULLn) of integer data by NaN.
+    ///
+    /// Integer data with a null value are converted to floats, whether
+    /// they contain null values or not, so that the type of a column only
+    /// depends on its header.
+    fn with_null(self, null: Option<f64>) -> Self {
+        match (self, null) {
+            (ColumnData::Integer(data), Some(null)) => {
+                let null = null as i64;
+                ColumnData::Float(
+                    data.into_iter()
+                        .map(|i| if i == null { ::std::f64::NAN } else { i as f64 })
+                        .collect(),
+                )
+            }
+            (data, _) => data,
+        }
+    }
+
+    /// Apply TSCALn and TZEROn scaling to numeric data.
+    ///
+    /// Scaled integers are converted to floats. Offsets used to store
+    /// unsigned integers keep the data as integers.
+    fn scaled(self, scale: Option<f64>, zero: Option<f64>) -> Self {
+        let scale = scale.unwrap_or(1.0);
+        let zero = zero.unwrap_or(0.0);
+        match self {
+            ColumnData::Integer(data) => {
+                if scale == 1.0 && zero.fract() == 0.0 {
+                    let zero = zero as i64;
+                    ColumnData::Integer(data.into_iter().map(|i| i.wrapping_add(zero)).collect())
+                } else {
+                    ColumnData::Float(data.into_iter().map(|i| i as f64 * scale + zero).collect())
+                }
+            }
+            ColumnData::Float(data) if scale != 1.0 || zero != 0.0 => {
+                ColumnData::Float(data.into_iter().map(|f| f * scale + zero).collect())
+            }
+            data => data,
+        }
+    }
+}
+
+/// Iterate over the cells of a column `width` bytes wide, starting at byte
+/// `start` of each row. Return `None` if the column does not fit in a row.
+fn column_cells(
+    bytes: &[u8],
+    row_width: usize,
+    row_count: usize,
+    start: usize,
+    width: usize,
+) -> Option<impl Iterator<Item = &[u8]>> {
+    if start + width > row_width {
+        return None;
+    }
+    Some((0..row_count).map(move |row| {
+        let start = row * row_width + start;
+        &bytes[start..start + width]
+    }))
+}
+
+/// Read cells of a column. `format` must be scalar.
+fn read_column<'a, I>(format: &ColumnFormat, cells: I) -> ColumnData
+where
+    I: Iterator<Item = &'a [u8]>,
+{
+    match format.code {
+        'L' => ColumnData::Bool(cells.map(|cell| cell[0] == b'T').collect()),
+        'B' => ColumnData::Integer(cells.map(|cell| i64::from(cell[0])).collect()),
+        'I' => ColumnData::Integer(
+            cells
+                .map(|cell| i64::from(i16::from_be_bytes([cell[0], cell[1]])))
+                .collect(),
+        ),
+        'J' => ColumnData::Integer(
+            cells
+                .map(|cell| i64::from(i32::from_be_bytes([cell[0], cell[1], cell[2], cell[3]])))
+                .collect(),
+        ),
+        'K' => ColumnData::Integer(
+            cells
+                .map(|cell| {
+                    let mut bytes = [0; 8];
+                    bytes.copy_from_slice(cell);
+                    i64::from_be_bytes(bytes)
+                })
+                .collect(),
+        ),
+        'E' => ColumnData::Float(
+            cells
+                .map(|cell| f64::from(f32::from_be_bytes([cell[0], cell[1], cell[2], cell[3]])))
+                .collect(),
+        ),
+        'D' => ColumnData::Float(
+            cells
+                .map(|cell| {
+                    let mut bytes = [0; 8];
+                    bytes.copy_from_slice(cell);
+                    f64::from_be_bytes(bytes)
+                })
+                .collect(),
+        ),
+        'A' => ColumnData::Str(
+            cells
+                .map(|cell| {
+                    // Strings are terminated by the first NUL character, and
+                    // padded with spaces.
+                    let end = cell.iter().position(|&b| b == 0).unwrap_or(cell.len());
+                    String::from_utf8_lossy(&cell[..end]).trim_end().to_owned()
+                })
+                .collect(),
+        ),
+        _ => unreachable!("Column format is scalar"),
+    }
+}
+
+/// Read cells of a column of an ASCII table.
+///
+/// Fields equal to `null` (TNULLn) and blank numeric fields are null. Integer
+/// columns containing null values are converted to floats, with null values
+/// replaced by NaN.
+fn read_ascii_column<'a, I>(
+    format: &AsciiColumnFormat,
+    cells: I,
+    null: Option<&str>,
+    column: &str,
+) -> Result<ColumnData, TableReadError>
+where
+    I: Iterator<Item = &'a [u8]>,
+{
+    let fields = cells.map(|cell| String::from_utf8_lossy(cell).trim().to_owned());
+    if format.code == 'A' {
+        return Ok(ColumnData::Str(fields.collect()));
+    }
+    let null = null.map(str::trim);
+    let invalid = |value: &str| TableReadError::InvalidValue {
+        column: column.to_owned(),
+        value: value.to_owned(),
+    };
+    let mut values = vec![];
+    for field in fields {
+        values.push(if field.is_empty() || Some(field.as_str()) == null {
+            None
+        } else if format.code == 'I' {
+            Some(AsciiValue::Integer(
+                field.parse().map_err(|_| invalid(&field))?,
+            ))
+        } else {
+            // Exponents of double precision values may be written with a D
+            let mut value: f64 = field
+                .replace(&['D', 'd'][..], "E")
+                .parse()
+                .map_err(|_| invalid(&field))?;
+            if !field.contains('.') {
+                // The decimal point is implicit
+                value /= 10f64.powi(format.decimals as i32);
+            }
+            Some(AsciiValue::Float(value))
+        });
+    }
+
+    let has_null = values.iter().any(Option::is_none);
+    Ok(if format.code == 'I' && !has_null {
+        ColumnData::Integer(
+            values
+                .into_iter()
+                .map(|value| match value {
+                    Some(AsciiValue::Integer(i)) => i,
+                    _ => unreachable!("Integer column without null values"),
+                })
+                .collect(),
+        )
+    } else {
+        ColumnData::Float(
+            values
+                .into_iter()
+                .map(|value| match value {
+                    Some(AsciiValue::Integer(i)) => i as f64,
+                    Some(AsciiValue::Float(f)) => f,
+                    None => ::std::f64::NAN,
+                })
+                .collect(),
+        )
+    })
+}
+
+enum AsciiValue {
+    Integer(i64),
+    Float(f64),
+}
+
+fn read_integer(hdu: &Hdu, key: &str) -> Result<usize, TableReadError> {
+    match hdu.value(key) {
+        Some(HeaderValue::IntegerNumber(value)) if *value >= 0 => Ok(*value as usize),
+        _ => Err(TableReadError::MissingKeyword(key.to_owned())),
+    }
+}
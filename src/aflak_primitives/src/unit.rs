@@ -1,165 +1,25 @@
-use std::collections::HashMap;
-use std::{fmt, ops};
+use std::{fmt, mem, ops};
 
-use fitrs::{FitsData, Hdu, HeaderValue, WCS};
-use ndarray::{ArrayBase, ArrayD, ArrayView1, ArrayView2, IxDyn, IxDynImpl, OwnedRepr};
-extern crate regex;
+use fitrs::{FitsData, Hdu, HeaderValue};
+use ndarray::{ArrayBase, ArrayD, ArrayView1, ArrayView2, IxDyn, IxDynImpl, OwnedRepr, Slice, Zip};
 
 use crate::fits::{FitsArrayReadError, FitsDataToArray};
+use crate::physical::{Conversion, PhysicalUnit, UnitError};
+use crate::wcs::{WCS, WCS_MAX_AXES};
 
 /// A unit of measurement.
 ///
-/// Would like to extend this type to include SI units or frequent units in
-/// astrophysics.
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+/// Units read from FITS files are parsed into physical units when possible,
+/// so that the dimensions of values are checked when combining them.
+/// Strings that cannot be parsed are kept as is.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub enum Unit {
     /// Unit is unknown
     None,
     /// Custom unit represented with a string
     Custom(String),
-}
-
-/// Decompose the unit into elements and have a Hashmap of key (unit name) and exponent values.
-/// # Examples
-///
-/// ```rust
-/// extern crate aflak_primitives as primitives;
-/// use primitives::{Unit, DerivedUnit};
-///
-/// let flux_unit = Unit::Custom("erg/s/cm^2/Ang/spaxel".to_owned());
-/// let flux_unit_decomposed = DerivedUnit::new(&flux_unit.repr().to_owned());
-/// println!("{:?}", flux_unit_decomposed);
-/// //DerivedUnit { exp: Some((1.0, 0)), derived: {"spaxel": -1, "s": -1, "Ang": -1, "erg": 1, "cm": -2} }
-/// ```
-#[derive(Clone, Debug, PartialEq)]
-pub struct DerivedUnit {
-    exp: Option<(f32, isize)>,
-    derived: HashMap<String, isize>,
-}
-impl DerivedUnit {
-    pub fn new(s: &String) -> Self {
-        let mut ret = HashMap::new();
-        let mut retexp = None;
-        let exp;
-        let dunit;
-        if let Some((e, d)) = s.split_once(' ') {
-            exp = Some(e);
-            dunit = d;
-        } else {
-            exp = None;
-            dunit = s;
-        }
-
-        if let Some(exp) = exp {
-            let re = regex::Regex::new(r"e|E").unwrap();
-            let v = re.splitn(exp, 2).collect::<Vec<_>>();
-            if v.len() == 2 {
-                let (pre, p) = (v[0].parse::<f32>().unwrap(), v[1].parse::<isize>().unwrap());
-                retexp = Some((pre, p));
-            }
-        } else {
-            retexp = Some((1.0, 0));
-        }
-
-        for mat in regex::Regex::new(r"[/|*]?[A-Z|a-z]+\^?[0-9]*")
-            .unwrap()
-            .find_iter(dunit)
-        {
-            let key;
-            let mut s = mat.as_str();
-            let mut p = if s.chars().nth(0) == Some('/') {
-                s = &s[1..];
-                -1
-            } else if s.chars().nth(0) == Some('*') {
-                s = &s[1..];
-                1
-            } else {
-                1
-            };
-            if let Some((k, dec)) = s.split_once('^') {
-                key = k;
-                p *= dec.parse::<isize>().unwrap();
-            } else {
-                key = s;
-            }
-            ret.insert(key.to_string(), p);
-        }
-
-        DerivedUnit {
-            exp: retexp,
-            derived: ret,
-        }
-    }
-
-    pub fn mul(&self, d: DerivedUnit) -> Self {
-        let retexp = match (self.exp, d.exp) {
-            (Some(lexp), Some(rexp)) => Some(((lexp.0 * rexp.0), (lexp.1 + rexp.1))),
-            (Some(lexp), None) => Some(lexp),
-            (None, Some(rexp)) => Some(rexp),
-            (None, None) => None,
-        };
-        let mut ret = self.derived.clone();
-        for (key, val) in d.derived {
-            ret.entry(key).and_modify(|e| *e += val).or_insert(val);
-        }
-
-        DerivedUnit {
-            exp: retexp,
-            derived: ret,
-        }
-    }
-
-    pub fn div(&self, d: DerivedUnit) -> Self {
-        let retexp = match (self.exp, d.exp) {
-            (Some(lexp), Some(rexp)) => Some(((lexp.0 / rexp.0), (lexp.1 - rexp.1))),
-            (Some(lexp), None) => Some(lexp),
-            (None, Some(rexp)) => Some(rexp),
-            (None, None) => None,
-        };
-        let mut ret = self.derived.clone();
-        for (key, val) in d.derived {
-            ret.entry(key).and_modify(|e| *e -= val).or_insert(-val);
-        }
-
-        DerivedUnit {
-            exp: retexp,
-            derived: ret,
-        }
-    }
-
-    pub fn to_string(&self) -> String {
-        let mut ret = String::new();
-        if let Some((pre, p)) = self.exp {
-            if !(pre == 1.0 && p == 0) {
-                if pre - pre.floor() == 0.0 {
-                    ret += format!("{}", pre as i32).as_str();
-                } else {
-                    ret += format!("{}", pre).as_str();
-                }
-                ret += format!("E{} ", p).as_str();
-            }
-        }
-        let mut derived_vec: Vec<_> = self.derived.iter().collect();
-        derived_vec.sort_by(|a, b| b.1.cmp(a.1));
-        let mut count = 0;
-        for (key, val) in derived_vec {
-            if *val == 0 {
-                continue;
-            }
-            if *val < 0 {
-                ret += "/";
-            } else if *val > 0 && count > 0 {
-                ret += "*";
-            }
-            ret += key;
-            let val = val.abs();
-            if val > 1 {
-                ret += format!("^{}", val).as_str();
-            }
-            count += 1;
-        }
-        ret
-    }
+    /// Physical unit with known dimension
+    Physical(PhysicalUnit),
 }
 
 impl Default for Unit {
@@ -180,14 +40,14 @@ impl Default for Unit {
 /// extern crate aflak_primitives as primitives;
 /// use primitives::{Dimensioned, Unit};
 ///
-/// let meter = Unit::Custom("m".to_owned());
+/// let meter = Unit::parse("m");
 /// let val1 = Dimensioned::new(1, meter.clone());
 /// let val2 = Dimensioned::new(2, meter.clone());
 /// let sum = val1 + val2;
 /// assert!(*sum.scalar() == 3);
 /// assert!(sum.unit() == &meter);
 /// ```
-#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Dimensioned<V> {
     value: V,
     unit: Unit,
@@ -205,13 +65,19 @@ pub struct WcsArray {
     meta: Option<MetaWcsArray>,
     array: Dimensioned<ArrayD<f32>>,
     visualization: Option<String>,
+    /// Variance of each value of `array`, in the square of its unit
+    #[serde(default)]
+    variance: Option<ArrayD<f32>>,
+    /// Data quality flags of each value of `array`. A value is invalid if
+    /// any of its flags is set.
+    #[serde(default)]
+    mask: Option<ArrayD<u32>>,
 }
 
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct MetaWcsArray {
-    #[serde(skip_serializing)]
-    #[serde(skip_deserializing)]
-    // TODO: Handle serialization for WCS
+    /// Missing from files exported before WCS were serialized
+    #[serde(default)]
     wcs: WCS,
     axes: [Axis; 4],
 }
@@ -241,15 +107,15 @@ impl Axis {
     }
 }
 
-fn read_unit(hdu: &Hdu, key: &str) -> Unit {
+pub(crate) fn read_unit(hdu: &Hdu, key: &str) -> Unit {
     if let Some(unit) = read_string(hdu, key) {
-        Unit::Custom(unit)
+        Unit::parse(&unit)
     } else {
         Unit::None
     }
 }
 
-fn read_string(hdu: &Hdu, key: &str) -> Option<String> {
+pub(crate) fn read_string(hdu: &Hdu, key: &str) -> Option<String> {
     if let Some(HeaderValue::CharacterString(string)) = hdu.value(key) {
         Some(string.to_owned())
     } else {
@@ -257,7 +123,7 @@ fn read_string(hdu: &Hdu, key: &str) -> Option<String> {
     }
 }
 
-fn read_float(hdu: &Hdu, key: &str) -> Option<f64> {
+pub(crate) fn read_float(hdu: &Hdu, key: &str) -> Option<f64> {
     if let Some(HeaderValue::RealFloatingNumber(value)) = hdu.value(key) {
         Some(value.to_owned())
     } else if let Some(HeaderValue::IntegerNumber(value)) = hdu.value(key) {
@@ -267,6 +133,59 @@ fn read_float(hdu: &Hdu, key: &str) -> Option<f64> {
     }
 }
 
+fn write_string(hdu: &mut Hdu, key: &str, value: &str) {
+    hdu.insert(key, HeaderValue::CharacterString(value.to_owned()));
+}
+
+pub(crate) fn write_float(hdu: &mut Hdu, key: &str, value: f64) {
+    hdu.insert(key, HeaderValue::RealFloatingNumber(value));
+}
+
+/// Non-standard FITS keyword used to store the visualization tag of a
+/// `WcsArray`.
+const VISUALIZATION_KEYWORD: &str = "AFLAKVIZ";
+
+/// Name of the extension where variance is written.
+pub const VARIANCE_EXTNAME: &str = "VAR";
+/// Name of the extension where data quality flags are written.
+pub const MASK_EXTNAME: &str = "DQ";
+
+/// Read data quality flags from `hdu`.
+///
+/// Integer values are read as bitmasks. Floating point values are truncated,
+/// NaN and null values being read as 0 (no flag).
+pub(crate) fn mask_from_hdu(hdu: &Hdu) -> Result<ArrayD<u32>, FitsArrayReadError> {
+    let (shape, data): (Vec<_>, Vec<_>) = match hdu.read_data() {
+        FitsData::IntegersI32(data) => (
+            data.shape,
+            data.data
+                .into_iter()
+                .map(|flags| flags.unwrap_or(0) as u32)
+                .collect(),
+        ),
+        FitsData::IntegersU32(data) => (
+            data.shape,
+            data.data
+                .into_iter()
+                .map(|flags| flags.unwrap_or(0))
+                .collect(),
+        ),
+        FitsData::FloatingPoint32(data) => (
+            data.shape,
+            data.data.into_iter().map(|flags| flags as u32).collect(),
+        ),
+        FitsData::FloatingPoint64(data) => (
+            data.shape,
+            data.data.into_iter().map(|flags| flags as u32).collect(),
+        ),
+        FitsData::Characters(_) => {
+            return Err(FitsArrayReadError::UnsupportedData("Characters"));
+        }
+    };
+    let shape: Vec<_> = shape.into_iter().rev().collect();
+    ArrayD::from_shape_vec(shape, data).map_err(FitsArrayReadError::ShapeError)
+}
+
 impl WcsArray {
     pub fn new(
         meta: Option<MetaWcsArray>,
@@ -277,6 +196,8 @@ impl WcsArray {
             meta,
             array,
             visualization,
+            variance: None,
+            mask: None,
         }
     }
     /// Make `WcsArray` from `Hdu` found in FITS file.
@@ -303,7 +224,7 @@ impl WcsArray {
         let ctype4 = read_string(hdu, "CTYPE4");
         let bzeros = read_float(hdu, "BZERO");
         let bscales = read_float(hdu, "BSCALE");
-        let wcs = WCS::new(hdu);
+        let wcs = WCS::from_hdu(hdu);
         let bzero = if let Some(bzero) = bzeros {
             bzero as f32
         } else {
@@ -315,6 +236,8 @@ impl WcsArray {
             1.0
         };
 
+        let visualization = read_string(hdu, VISUALIZATION_KEYWORD);
+
         Ok(Self {
             meta: Some(MetaWcsArray {
                 wcs,
@@ -326,7 +249,86 @@ impl WcsArray {
                 ],
             }),
             array: vunit.new(image * bscale + bzero),
-            visualization: None,
+            visualization,
+            variance: None,
+            mask: None,
+        })
+    }
+
+    /// Make a FITS `Hdu` containing this array.
+    ///
+    /// The unit of the values, the name and unit of each axis, the world
+    /// coordinates and the visualization tag are written in the header, so
+    /// that the returned `Hdu` can be read back with `WcsArray::from_hdu`.
+    pub fn to_hdu(&self) -> Hdu {
+        let arr = self.scalar();
+        // 0-dim ndarrays contain a single scalar value, however they should be
+        // treated as a 1-dimensional data array of length 1 when embedded as
+        // FITS file.
+        // FITS axes are ordered from the fastest varying, which is the
+        // reverse order of ndarray's axes.
+        let shape: Vec<_> = if arr.ndim() == 0 {
+            vec![1]
+        } else {
+            arr.shape().iter().rev().cloned().collect()
+        };
+        let data: Vec<f32> = arr.iter().cloned().collect();
+        let mut hdu = Hdu::new(&shape, data);
+
+        if *self.array.unit() != Unit::None {
+            write_string(&mut hdu, "BUNIT", self.array.unit().repr());
+        }
+        if let Some(meta) = &self.meta {
+            for (i, axis) in meta.axes.iter().enumerate().take(arr.ndim()) {
+                if let Some(name) = &axis.name {
+                    write_string(&mut hdu, &format!("CTYPE{}", i + 1), name);
+                }
+                if axis.unit != Unit::None {
+                    write_string(&mut hdu, &format!("CUNIT{}", i + 1), axis.unit.repr());
+                }
+            }
+            meta.wcs.write_to_hdu(&mut hdu, arr.ndim());
+        }
+        if let Some(visualization) = &self.visualization {
+            write_string(&mut hdu, VISUALIZATION_KEYWORD, visualization);
+        }
+        hdu
+    }
+
+    /// Make a FITS `Hdu` containing the variance of this array, in an
+    /// extension named `VAR`. Return `None` if the variance is unknown.
+    ///
+    /// The world coordinates are the same as in the `Hdu` returned by
+    /// `WcsArray::to_hdu`.
+    pub fn variance_to_hdu(&self) -> Option<Hdu> {
+        self.variance.as_ref().map(|variance| {
+            let unit = self.array.unit().clone();
+            let variance = WcsArray {
+                meta: self.meta.clone(),
+                array: unit.clone().mul(unit).new(variance.clone()),
+                visualization: None,
+                variance: None,
+                mask: None,
+            };
+            let mut hdu = variance.to_hdu();
+            write_string(&mut hdu, "EXTNAME", VARIANCE_EXTNAME);
+            hdu
+        })
+    }
+
+    /// Make a FITS `Hdu` containing the data quality flags of this array, in
+    /// an extension named `DQ`. Return `None` if there is no mask.
+    pub fn mask_to_hdu(&self) -> Option<Hdu> {
+        self.mask.as_ref().map(|mask| {
+            let shape: Vec<_> = if mask.ndim() == 0 {
+                vec![1]
+            } else {
+                mask.shape().iter().rev().cloned().collect()
+            };
+            let data: Vec<i32> = mask.iter().map(|&flags| flags as i32).collect();
+            let mut hdu = Hdu::new(&shape, data);
+            write_string(&mut hdu, "EXTNAME", MASK_EXTNAME);
+            hdu
         })
     }
 
@@ -347,6 +349,8 @@ impl WcsArray {
             meta: None,
             array,
             visualization: None,
+            variance: None,
+            mask: None,
         }
     }
 
@@ -358,6 +362,8 @@ impl WcsArray {
             meta: None,
             array,
             visualization,
+            variance: None,
+            mask: None,
         }
     }
 
@@ -405,6 +411,67 @@ impl WcsArray {
         self.visualization = tag;
     }
 
+    /// Estimated memory used by the data, variance and mask, in bytes.
+    pub(crate) fn heap_size(&self) -> usize {
+        let variance = self.variance.as_ref().map_or(0, |variance| variance.len());
+        let mask = self.mask.as_ref().map_or(0, |mask| mask.len());
+        self.scalar().len() * mem::size_of::<f32>()
+            + variance * mem::size_of::<f32>()
+            + mask * mem::size_of::<u32>()
+    }
+
+    /// Get variance of each value of the contained array, if known.
+    pub fn variance(&self) -> Option<&ArrayD<f32>> {
+        self.variance.as_ref()
+    }
+
+    /// Set variance of each value of the contained array.
+    ///
+    /// Panic if `variance` does not have the same shape as the contained
+    /// array.
+    pub fn set_variance(&mut self, variance: Option<ArrayD<f32>>) {
+        if let Some(variance) = &variance {
+            assert_eq!(
+                variance.shape(),
+                self.scalar().shape(),
+                "Variance must have the same shape as data"
+            );
+        }
+        self.variance = variance;
+    }
+
+    /// Get uncertainty (standard deviation) of each value as a new array,
+    /// with the same unit and metadata as `self`.
+    pub fn uncertainty(&self) -> Option<WcsArray> {
+        self.variance.as_ref().map(|variance| WcsArray {
+            meta: self.meta.clone(),
+            array: self.array.with_new_value(variance.mapv(f32::sqrt)),
+            visualization: None,
+            variance: None,
+            mask: self.mask.clone(),
+        })
+    }
+
+    /// Get data quality flags of each value of the contained array, if any.
+    /// A value is invalid if any of its flags is set.
+    pub fn mask(&self) -> Option<&ArrayD<u32>> {
+        self.mask.as_ref()
+    }
+
+    /// Set data quality flags of each value of the contained array.
+    ///
+    /// Panic if `mask` does not have the same shape as the contained array.
+    pub fn set_mask(&mut self, mask: Option<ArrayD<u32>>) {
+        if let Some(mask) = &mask {
+            assert_eq!(
+                mask.shape(),
+                self.scalar().shape(),
+                "Mask must have the same shape as the data"
+            );
+        }
+        self.mask = mask;
+    }
+
     pub fn wcs(&self) -> Option<&WCS> {
         self.meta.as_ref().map(|meta| &meta.wcs)
     }
@@ -413,33 +480,139 @@ impl WcsArray {
         &self.meta
     }
 
+    /// Convert the values of the array to `unit`, along with their variance.
+    ///
+    /// Besides units with the same dimension, spectral flux densities can be
+    /// converted to AB magnitudes and back, and flux densities per unit of
+    /// wavelength can be converted to flux densities per unit of frequency
+    /// and back. The latter conversion uses the spectral coordinate of each
+    /// value, given by the spectral axis of the array (`WAVE`, `AWAV`,
+    /// `FREQ`, `WAVN` or `ENER`).
+    pub fn convert_unit(&self, unit: &PhysicalUnit) -> Result<WcsArray, UnitError> {
+        let from = match self.array.unit() {
+            Unit::Physical(from) => from,
+            other => return Err(UnitError::NotPhysical(other.repr().to_owned())),
+        };
+        let mut converted = self.clone();
+        match from.conversion(unit) {
+            Ok(conversion) => converted.apply_conversion(|_| conversion),
+            Err(e) => {
+                let (axis, wavelengths) = self.wavelengths().ok_or(e)?;
+                let conversions = wavelengths
+                    .into_iter()
+                    .map(|wavelength| from.spectral_conversion(unit, wavelength))
+                    .collect::<Result<Vec<_>, _>>()?;
+                converted.apply_conversion(|index| conversions[index[axis]]);
+            }
+        }
+        converted.array.unit = Unit::Physical(unit.clone());
+        Ok(converted)
+    }
+
+    fn apply_conversion<F>(&mut self, conversion: F)
+    where
+        F: Fn(&IxDyn) -> Conversion,
+    {
+        if let Some(variance) = &mut self.variance {
+            for (index, v) in variance.indexed_iter_mut() {
+                let derivative = conversion(&index).derivative(self.array.value[&index]);
+                *v *= derivative * derivative;
+            }
+        }
+        for (index, v) in self.array.value.indexed_iter_mut() {
+            *v = conversion(&index).apply(*v);
+        }
+    }
+
+    /// Find the spectral axis of the array. Return its index in the array
+    /// along with the wavelength in meters of each of its pixels.
+    fn wavelengths(&self) -> Option<(usize, Vec<f64>)> {
+        const SPECTRAL_TYPES: [&str; 5] = ["WAVE", "AWAV", "FREQ", "WAVN", "ENER"];
+        let meta = self.meta.as_ref()?;
+        let shape = self.scalar().shape();
+        let ndim = shape.len();
+        for (wcs_axis, axis) in meta.axes.iter().enumerate().take(ndim) {
+            let unit = match &axis.unit {
+                Unit::Physical(unit) => unit,
+                _ => continue,
+            };
+            if !SPECTRAL_TYPES.iter().any(|t| axis.name().starts_with(t)) {
+                continue;
+            }
+            let array_axis = ndim - 1 - wcs_axis;
+            let wavelengths = (0..shape[array_axis])
+                .map(|i| {
+                    let mut pixel = [0.0; WCS_MAX_AXES];
+                    pixel[wcs_axis] = i as f32;
+                    let world = meta.wcs.pix2world(pixel)[wcs_axis];
+                    unit.to_wavelength(f64::from(world))
+                })
+                .collect::<Option<Vec<_>>>()?;
+            return Some((array_axis, wavelengths));
+        }
+        None
+    }
+
+    /// Express the world coordinates along axis `axis` (index of the array
+    /// axis) in `unit`.
+    pub fn convert_axis_unit(
+        &self,
+        axis: usize,
+        unit: &PhysicalUnit,
+    ) -> Result<WcsArray, UnitError> {
+        let ndim = self.scalar().ndim();
+        let meta = match &self.meta {
+            Some(meta) if axis < ndim && ndim - 1 - axis < WCS_MAX_AXES => meta,
+            _ => return Err(UnitError::Unsupported("axis has no world coordinates")),
+        };
+        let wcs_axis = ndim - 1 - axis;
+        let from = &meta.axes[wcs_axis].unit;
+        let factor = match from.conversion(&Unit::Physical(unit.clone()))? {
+            Conversion::Scale(factor) => factor,
+            _ => {
+                return Err(UnitError::Incompatible {
+                    from: from.repr().to_owned(),
+                    to: unit.repr().to_owned(),
+                })
+            }
+        };
+        let wcs = meta
+            .wcs
+            .scale_axis(wcs_axis, factor)
+            .ok_or(UnitError::Unsupported(
+                "celestial axes are always in degrees",
+            ))?;
+        let mut converted = self.clone();
+        if let Some(meta) = &mut converted.meta {
+            meta.wcs = wcs;
+            meta.axes[wcs_axis].unit = Unit::Physical(unit.clone());
+        }
+        Ok(converted)
+    }
+
     /// Make a slice along the specific `indices` in the array.
     ///
     /// Create a new `WcsArray` containing the provided `array`.
     /// The objective is to have correct metadata for the new `array`. The
     /// new metadata is computed from `indices` and the previous metadata.
     ///
-    /// TODO: This method is hard to understand, and is potentional buggy
-    /// write-only code.
+    /// Axis `k` of `array` corresponds to axis `indices[k].0` of `self`. See
+    /// [`WCS::slice`] for the meaning of the two other values.
     pub(crate) fn make_slice(
         &self,
         indices: &[(usize, f32, f32)],
         array: Dimensioned<ArrayD<f32>>,
     ) -> WcsArray {
-        let slice_index: Vec<_> = indices.iter().map(|idx| idx.0).collect();
         let new_meta = self.meta.as_ref().map(|meta| {
-            let mut wcs = meta.wcs.slice(&slice_index);
-            for (i, start, end) in indices {
-                wcs = wcs.transform(*i, *start, *end);
-            }
+            let wcs = meta.wcs.slice(indices);
             let mut axes = [
                 Axis::default(),
                 Axis::default(),
                 Axis::default(),
                 Axis::default(),
             ];
-            for (i, _, _) in indices {
-                axes[*i] = meta.axes[*i].clone();
+            for (k, (i, _, _)) in indices.iter().enumerate().take(WCS_MAX_AXES) {
+                axes[k] = meta.axes[*i].clone();
             }
             MetaWcsArray { wcs, axes }
         });
@@ -447,11 +620,194 @@ impl WcsArray {
             meta: new_meta,
             array,
             visualization: None,
+            variance: None,
+            mask: None,
         }
     }
+
+    /// Get the index of the WCS axis matching axis `axis` of the contained
+    /// array.
+    ///
+    /// Axes of the contained array are in the reverse order of FITS/WCS
+    /// axes: the last axis of the array is the first WCS axis.
+    fn wcs_axis(&self, axis: usize) -> usize {
+        self.scalar().ndim() - 1 - axis
+    }
+
+    /// Wrap `array` with metadata for the axes `axes` of `self`.
+    ///
+    /// Axis `k` of `array` corresponds to axis `axes[k]` of the array
+    /// contained in `self`. Pixels are neither shifted nor scaled.
+    pub fn with_axes(&self, axes: &[usize], array: Dimensioned<ArrayD<f32>>) -> WcsArray {
+        let indices: Vec<_> = axes
+            .iter()
+            .rev()
+            .map(|&axis| (self.wcs_axis(axis), 0.0, 1.0))
+            .collect();
+        self.make_slice(&indices, array)
+    }
+
+    /// Wrap `array` with the metadata of `self`, with axis `axis` removed.
+    ///
+    /// `array` is typically the result of a reduction of `self` along `axis`.
+    pub fn drop_axis(&self, axis: usize, array: Dimensioned<ArrayD<f32>>) -> WcsArray {
+        let ndim = self.scalar().ndim();
+        let axes: Vec<_> = (0..ndim).filter(|&i| i != axis).collect();
+        self.with_axes(&axes, array)
+    }
+
+    /// Take the sub-array at `index` along `axis`, removing `axis`.
+    ///
+    /// Panic if `index` is out of bounds.
+    pub fn index_axis(&self, axis: usize, index: usize) -> WcsArray {
+        fn index_axis<A: Clone>(array: &ArrayD<A>, axis: usize, index: usize) -> ArrayD<A> {
+            array.index_axis(ndarray::Axis(axis), index).to_owned()
+        }
+        let array = index_axis(self.scalar(), axis, index);
+        let mut out = self.drop_axis(axis, self.array.with_new_value(array));
+        out.variance = self
+            .variance
+            .as_ref()
+            .map(|variance| index_axis(variance, axis, index));
+        out.mask = self.mask.as_ref().map(|mask| index_axis(mask, axis, index));
+        out
+    }
+
+    /// Take the pixels from `start` (inclusive) to `end` (exclusive) along
+    /// `axis`.
+    ///
+    /// The reference pixel is shifted so that world coordinates are kept.
+    ///
+    /// Panic if `start..end` is out of bounds.
+    pub fn slice_axis(&self, axis: usize, start: usize, end: usize) -> WcsArray {
+        let mut slices = vec![Slice::from(..); self.scalar().ndim()];
+        slices[axis] = Slice::from(start..end);
+        self.resample(&slices)
+    }
+
+    /// Keep one pixel every `steps[i]` pixels along each axis `i`, starting
+    /// from the first one.
+    ///
+    /// The increment between two pixels is scaled so that world coordinates
+    /// are kept. Panic if any step is 0.
+    pub fn subsample(&self, steps: &[usize]) -> WcsArray {
+        let slices: Vec<_> = steps
+            .iter()
+            .map(|&step| Slice::new(0, None, step as isize))
+            .collect();
+        self.resample(&slices)
+    }
+
+    /// Resample `self` by slicing each axis `i` with `slices[i]`.
+    ///
+    /// Slices must have non-negative start and step. Visualization tag is
+    /// kept, and variance and mask are resampled the same way.
+    fn resample(&self, slices: &[Slice]) -> WcsArray {
+        fn slice_all<A: Clone>(array: &ArrayD<A>, slices: &[Slice]) -> ArrayD<A> {
+            let mut view = array.view();
+            for (axis, &slice) in slices.iter().enumerate() {
+                view.slice_axis_inplace(ndarray::Axis(axis), slice);
+            }
+            view.to_owned()
+        }
+        let ndim = self.scalar().ndim();
+        let indices: Vec<_> = (0..ndim)
+            .rev()
+            .map(|axis| {
+                (
+                    self.wcs_axis(axis),
+                    slices[axis].start as f32,
+                    slices[axis].step as f32,
+                )
+            })
+            .collect();
+        let array = slice_all(self.scalar(), slices);
+        let mut out = self.make_slice(&indices, self.array.with_new_value(array));
+        out.visualization = self.visualization.clone();
+        out.variance = self
+            .variance
+            .as_ref()
+            .map(|variance| slice_all(variance, slices));
+        out.mask = self.mask.as_ref().map(|mask| slice_all(mask, slices));
+        out
+    }
+
+    /// Wrap the 2D `array` cut through `self` along a plane.
+    ///
+    /// Pixel `(j, i)` of `array` is the pixel of `self` at position
+    /// `origin + i * dir1 + j * dir2`, where each coordinate is an index on
+    /// the axis of the same rank in `self`.
+    ///
+    /// World coordinates can only be kept if each of `dir1` and `dir2` is
+    /// aligned with a distinct axis of `self`. Otherwise `array` is returned
+    /// without any metadata.
+    pub fn plane(
+        &self,
+        origin: &[f32],
+        dir1: &[f32],
+        dir2: &[f32],
+        array: Dimensioned<ArrayD<f32>>,
+    ) -> WcsArray {
+        /// Return the only axis along which `dir` moves, and the step.
+        fn aligned_axis(dir: &[f32]) -> Option<(usize, f32)> {
+            let mut non_zero = dir
+                .iter()
+                .enumerate()
+                .filter(|(_, d)| d.abs() > std::f32::EPSILON);
+            match (non_zero.next(), non_zero.next()) {
+                (Some((axis, &step)), None) => Some((axis, step)),
+                _ => None,
+            }
+        }
+
+        let ndim = self.scalar().ndim();
+        if origin.len() == ndim && dir1.len() == ndim && dir2.len() == ndim {
+            if let (Some((axis1, step1)), Some((axis2, step2))) =
+                (aligned_axis(dir1), aligned_axis(dir2))
+            {
+                if axis1 != axis2 {
+                    // First WCS axis is the last axis of `array`, along `dir1`
+                    return self.make_slice(
+                        &[
+                            (self.wcs_axis(axis1), origin[axis1], step1),
+                            (self.wcs_axis(axis2), origin[axis2], step2),
+                        ],
+                        array,
+                    );
+                }
+            }
+        }
+        WcsArray::from_array(array)
+    }
 }
 
 impl Unit {
+    /// Parse a unit string.
+    ///
+    /// Return `Unit::None` for an empty string, and `Unit::Custom` if the
+    /// string cannot be understood as a physical unit.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// extern crate aflak_primitives as primitives;
+    /// use primitives::Unit;
+    ///
+    /// let flux = Unit::parse("10**(-20)*erg/s/cm**2/Angstrom");
+    /// assert_eq!(flux.repr(), "1e-20 erg/s/cm^2/Angstrom");
+    /// assert!(flux.is_compatible(&Unit::parse("W/m2/nm")));
+    /// assert!(!flux.is_compatible(&Unit::parse("Jy")));
+    /// ```
+    pub fn parse(unit: &str) -> Self {
+        if unit.trim().is_empty() {
+            Unit::None
+        } else {
+            unit.parse()
+                .map(Unit::Physical)
+                .unwrap_or_else(|_| Unit::Custom(unit.to_owned()))
+        }
+    }
+
     pub fn new<V>(self, value: V) -> Dimensioned<V> {
         Dimensioned {
             value,
@@ -464,31 +820,65 @@ impl Unit {
         match *self {
             Unit::None => "",
             Unit::Custom(ref unit) => unit,
+            Unit::Physical(ref unit) => unit.repr(),
+        }
+    }
+
+    /// Check whether values in this unit can be added to values in unit
+    /// `other`.
+    ///
+    /// Physical units are compatible if they have the same dimension. As
+    /// nothing is known about other units, they are deemed compatible with
+    /// any unit.
+    pub fn is_compatible(&self, other: &Unit) -> bool {
+        match (self, other) {
+            (Unit::Physical(u1), Unit::Physical(u2)) => u1.is_compatible(u2),
+            _ => true,
+        }
+    }
+
+    /// Check that adding values in units `self` and `other` keeps the unit
+    /// information correct.
+    fn is_homogeneous_with(&self, other: &Unit) -> bool {
+        match (self, other) {
+            (Unit::Physical(u1), Unit::Physical(u2)) => u1.is_compatible(u2),
+            _ => self == other,
+        }
+    }
+
+    /// Compute the conversion of values from this unit to unit `to`.
+    pub fn conversion(&self, to: &Unit) -> Result<Conversion, UnitError> {
+        match (self, to) {
+            (Unit::Physical(u1), Unit::Physical(u2)) => u1.conversion(u2),
+            _ if self == to => Ok(Conversion::Scale(1.0)),
+            (Unit::Physical(_), _) => Err(UnitError::NotPhysical(to.repr().to_owned())),
+            _ => Err(UnitError::NotPhysical(self.repr().to_owned())),
+        }
+    }
+
+    /// Factor by which values in this unit must be multiplied to be
+    /// expressed in unit `to`. Return `None` if no rescaling is needed or
+    /// possible.
+    pub(crate) fn rescale_factor(&self, to: &Unit) -> Option<f32> {
+        match (self, to) {
+            (Unit::Physical(u1), Unit::Physical(u2)) => match u1.conversion(u2) {
+                Ok(Conversion::Scale(k)) if k != 1.0 => Some(k as f32),
+                _ => None,
+            },
+            _ => None,
         }
     }
 
     pub fn mul(self, rhs: Unit) -> Self {
         match (self, rhs) {
-            (Unit::Custom(s1), Unit::Custom(s2)) => {
-                let d1 = DerivedUnit::new(&s1);
-                let d2 = DerivedUnit::new(&s2);
-                let d = d1.mul(d2);
-                let s = d.to_string();
-                Unit::Custom(s)
-            }
+            (Unit::Physical(u1), Unit::Physical(u2)) => Unit::Physical(u1.mul(&u2)),
             _ => Unit::None,
         }
     }
 
     pub fn div(self, rhs: Unit) -> Self {
         match (self, rhs) {
-            (Unit::Custom(s1), Unit::Custom(s2)) => {
-                let d1 = DerivedUnit::new(&s1);
-                let d2 = DerivedUnit::new(&s2);
-                let d = d1.div(d2);
-                let s = d.to_string();
-                Unit::Custom(s)
-            }
+            (Unit::Physical(u1), Unit::Physical(u2)) => Unit::Physical(u1.div(&u2)),
             _ => Unit::None,
         }
     }
@@ -656,16 +1046,33 @@ where
     }
 }
 
+impl<W> Dimensioned<W>
+where
+    W: ops::Mul<f32, Output = W>,
+{
+    /// Get the contained value expressed in `unit`, if both units are
+    /// compatible. Otherwise the value is returned unchanged.
+    fn into_value_in(self, unit: &Unit) -> W {
+        match self.unit.rescale_factor(unit) {
+            Some(k) => self.value * k,
+            None => self.value,
+        }
+    }
+}
+
 impl<V, W> ops::Add<Dimensioned<W>> for Dimensioned<V>
 where
     V: ops::Add<W>,
+    W: ops::Mul<f32, Output = W>,
 {
     type Output = Dimensioned<<V as ops::Add<W>>::Output>;
 
     fn add(self, rhs: Dimensioned<W>) -> Self::Output {
-        let homogeneous = self.unit == rhs.unit && self.homogeneous && rhs.homogeneous;
+        let homogeneous =
+            self.unit.is_homogeneous_with(&rhs.unit) && self.homogeneous && rhs.homogeneous;
+        let rhs = rhs.into_value_in(&self.unit);
         Dimensioned {
-            value: self.value + rhs.value,
+            value: self.value + rhs,
             unit: self.unit,
             homogeneous,
         }
@@ -675,13 +1082,16 @@ where
 impl<V, W> ops::Sub<Dimensioned<W>> for Dimensioned<V>
 where
     V: ops::Sub<W>,
+    W: ops::Mul<f32, Output = W>,
 {
     type Output = Dimensioned<<V as ops::Sub<W>>::Output>;
 
     fn sub(self, rhs: Dimensioned<W>) -> Self::Output {
-        let homogeneous = self.unit == rhs.unit && self.homogeneous && rhs.homogeneous;
+        let homogeneous =
+            self.unit.is_homogeneous_with(&rhs.unit) && self.homogeneous && rhs.homogeneous;
+        let rhs = rhs.into_value_in(&self.unit);
         Dimensioned {
-            value: self.value - rhs.value,
+            value: self.value - rhs,
             unit: self.unit,
             homogeneous,
         }
@@ -691,13 +1101,16 @@ where
 impl<'a, V, W> ops::Sub<Dimensioned<W>> for &'a Dimensioned<V>
 where
     &'a V: ops::Sub<W>,
+    W: ops::Mul<f32, Output = W>,
 {
     type Output = Dimensioned<<&'a V as ops::Sub<W>>::Output>;
 
     fn sub(self, rhs: Dimensioned<W>) -> Self::Output {
-        let homogeneous = self.unit == rhs.unit && self.homogeneous && rhs.homogeneous;
+        let homogeneous =
+            self.unit.is_homogeneous_with(&rhs.unit) && self.homogeneous && rhs.homogeneous;
+        let rhs = rhs.into_value_in(&self.unit);
         Dimensioned {
-            value: &self.value - rhs.value,
+            value: &self.value - rhs,
             unit: self.unit.clone(),
             homogeneous,
         }
@@ -713,9 +1126,14 @@ impl<'a, 'b> ops::Sub<&'b Dimensioned<ArrayBase<OwnedRepr<f32>, ndarray::Dim<IxD
         self,
         rhs: &'b Dimensioned<ArrayBase<OwnedRepr<f32>, ndarray::Dim<IxDynImpl>>>,
     ) -> Self::Output {
-        let homogeneous = self.unit == rhs.unit && self.homogeneous && rhs.homogeneous;
+        let homogeneous =
+            self.unit.is_homogeneous_with(&rhs.unit) && self.homogeneous && rhs.homogeneous;
+        let value = match rhs.unit.rescale_factor(&self.unit) {
+            Some(k) => &self.value - &(&rhs.value * k),
+            None => &self.value - &rhs.value,
+        };
         Dimensioned {
-            value: &self.value - &rhs.value,
+            value,
             unit: self.unit.clone(),
             homogeneous,
         }
@@ -724,14 +1142,15 @@ impl<'a, 'b> ops::Sub<&'b Dimensioned<ArrayBase<OwnedRepr<f32>, ndarray::Dim<IxD
 
 impl<'a, 'b, V> ops::Sub<&'b Dimensioned<f32>> for &'a Dimensioned<V>
 where
-    &'a V: ops::Sub<&'b f32>,
+    &'a V: ops::Sub<f32>,
 {
-    type Output = Dimensioned<<&'a V as ops::Sub<&'b f32>>::Output>;
+    type Output = Dimensioned<<&'a V as ops::Sub<f32>>::Output>;
 
     fn sub(self, rhs: &'b Dimensioned<f32>) -> Self::Output {
-        let homogeneous = self.unit == rhs.unit && self.homogeneous && rhs.homogeneous;
+        let homogeneous =
+            self.unit.is_homogeneous_with(&rhs.unit) && self.homogeneous && rhs.homogeneous;
         Dimensioned {
-            value: &self.value - &rhs.value,
+            value: &self.value - rhs.clone().into_value_in(&self.unit),
             unit: self.unit.clone(),
             homogeneous,
         }
@@ -740,14 +1159,7 @@ where
 
 impl fmt::Display for Unit {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "{}",
-            match *self {
-                Unit::None => "",
-                Unit::Custom(ref s) => s,
-            }
-        )
+        write!(f, "{}", self.repr())
     }
 }
 
@@ -759,6 +1171,8 @@ impl ops::Mul<f32> for WcsArray {
             meta: self.meta,
             array: self.array * rhs,
             visualization: None,
+            variance: self.variance.map(|variance| variance * (rhs * rhs)),
+            mask: self.mask,
         }
     }
 }
@@ -774,6 +1188,10 @@ impl ops::Mul<Dimensioned<f32>> for WcsArray {
             meta: self.meta,
             array: newdim,
             visualization: None,
+            variance: self
+                .variance
+                .map(|variance| variance * (rhs.value * rhs.value)),
+            mask: self.mask,
         }
     }
 }
@@ -786,6 +1204,11 @@ impl<'a> ops::Mul<f32> for &'a WcsArray {
             meta: self.meta.clone(),
             array: self.array() * rhs,
             visualization: None,
+            variance: self
+                .variance
+                .as_ref()
+                .map(|variance| variance * (rhs * rhs)),
+            mask: self.mask.clone(),
         }
     }
 }
@@ -798,6 +1221,8 @@ impl ops::Div<f32> for WcsArray {
             meta: self.meta,
             array: self.array / rhs,
             visualization: None,
+            variance: self.variance.map(|variance| variance / (rhs * rhs)),
+            mask: self.mask,
         }
     }
 }
@@ -810,6 +1235,11 @@ impl<'a> ops::Div<f32> for &'a WcsArray {
             meta: self.meta.clone(),
             array: self.array() / rhs,
             visualization: None,
+            variance: self
+                .variance
+                .as_ref()
+                .map(|variance| variance / (rhs * rhs)),
+            mask: self.mask.clone(),
         }
     }
 }
@@ -818,6 +1248,9 @@ impl ops::Add for WcsArray {
     type Output = WcsArray;
 
     fn add(self, rhs: WcsArray) -> Self::Output {
+        let k2 = rhs_variance_scale(&self, &rhs);
+        let variance = propagate_variance(&self, &rhs, |_, _, va, vb| va + vb * k2);
+        let mask = combine_masks(&self, &rhs);
         let meta = if self.meta == rhs.meta {
             self.meta
         } else {
@@ -827,6 +1260,8 @@ impl ops::Add for WcsArray {
             meta,
             array: self.array + rhs.array,
             visualization: None,
+            variance,
+            mask,
         }
     }
 }
@@ -835,6 +1270,9 @@ impl ops::Sub for WcsArray {
     type Output = WcsArray;
 
     fn sub(self, rhs: WcsArray) -> Self::Output {
+        let k2 = rhs_variance_scale(&self, &rhs);
+        let variance = propagate_variance(&self, &rhs, |_, _, va, vb| va + vb * k2);
+        let mask = combine_masks(&self, &rhs);
         let meta = if self.meta == rhs.meta {
             self.meta
         } else {
@@ -844,6 +1282,8 @@ impl ops::Sub for WcsArray {
             meta,
             array: self.array - rhs.array,
             visualization: None,
+            variance,
+            mask,
         }
     }
 }
@@ -852,10 +1292,13 @@ impl<'a, 'b> ops::Sub<&'b WcsArray> for &'a WcsArray {
     type Output = WcsArray;
 
     fn sub(self, rhs: &'b WcsArray) -> Self::Output {
+        let k2 = rhs_variance_scale(self, rhs);
         WcsArray {
             meta: self.meta.clone(),
             array: &self.array - &rhs.array,
             visualization: None,
+            variance: propagate_variance(self, rhs, |_, _, va, vb| va + vb * k2),
+            mask: combine_masks(self, rhs),
         }
     }
 }
@@ -875,6 +1318,8 @@ impl<'a, 'b> ops::Div<&'b WcsArray> for &'a WcsArray {
             meta: self.meta.clone(),
             array: newdim,
             visualization: None,
+            variance: propagate_variance(self, rhs, quotient_variance),
+            mask: combine_masks(self, rhs),
         }
     }
 }
@@ -883,18 +1328,7 @@ impl<'a> ops::Div<&'a WcsArray> for WcsArray {
     type Output = WcsArray;
 
     fn div(self, rhs: &'a WcsArray) -> Self::Output {
-        let lval = self.array().scalar();
-        let rval = rhs.array().scalar();
-        let val = lval / rval;
-        let newdim = Dimensioned::new(
-            val,
-            self.array().unit().clone().div(rhs.array().unit().clone()),
-        );
-        WcsArray {
-            meta: self.meta.clone(),
-            array: newdim,
-            visualization: None,
-        }
+        &self / rhs
     }
 }
 
@@ -913,6 +1347,74 @@ impl ops::Mul<WcsArray> for WcsArray {
             meta: self.meta.clone(),
             array: newdim,
             visualization: None,
+            variance: propagate_variance(&self, &rhs, |a, b, va, vb| va * b * b + a * a * vb),
+            mask: combine_masks(&self, &rhs),
+        }
+    }
+}
+
+/// Factor by which the variance of `rhs` is multiplied when `rhs` is
+/// converted to the unit of `lhs` before being added to or subtracted from it.
+fn rhs_variance_scale(lhs: &WcsArray, rhs: &WcsArray) -> f32 {
+    rhs.array
+        .unit()
+        .rescale_factor(lhs.array.unit())
+        .map_or(1.0, |k| k * k)
+}
+
+/// Combine the data quality flags of two arrays `lhs` and `rhs` involved in
+/// an element-wise operation.
+///
+/// A value of the result has the flags of both of its operands. Return
+/// `None` if neither array has a mask, or if the shapes of the arrays differ.
+pub(crate) fn combine_masks(lhs: &WcsArray, rhs: &WcsArray) -> Option<ArrayD<u32>> {
+    if lhs.scalar().shape() != rhs.scalar().shape() {
+        return None;
+    }
+    match (&lhs.mask, &rhs.mask) {
+        (Some(a), Some(b)) => {
+            let mut out = a.clone();
+            Zip::from(&mut out).and(b).apply(|a, &b| *a |= b);
+            Some(out)
         }
+        (Some(mask), None) | (None, Some(mask)) => Some(mask.clone()),
+        (None, None) => None,
     }
 }
+
+/// First-order variance of `a / b`, given the variances `va` and `vb` of
+/// independent values `a` and `b`.
+pub(crate) fn quotient_variance(a: f32, b: f32, va: f32, vb: f32) -> f32 {
+    let b2 = b * b;
+    va / b2 + a * a * vb / (b2 * b2)
+}
+
+/// Compute the variance of an element-wise operation on two independent
+/// arrays `lhs` and `rhs`.
+///
+/// `f(a, b, va, vb)` computes the variance of the result of the operation for
+/// values `a` and `b` with variance `va` and `vb`. A missing variance plane
+/// is considered null. Return `None` if neither array has a variance plane,
+/// or if the shapes of the arrays differ.
+pub(crate) fn propagate_variance<F>(lhs: &WcsArray, rhs: &WcsArray, f: F) -> Option<ArrayD<f32>>
+where
+    F: Fn(f32, f32, f32, f32) -> f32,
+{
+    if lhs.variance.is_none() && rhs.variance.is_none() {
+        return None;
+    }
+    let (a, b) = (lhs.scalar(), rhs.scalar());
+    if a.shape() != b.shape() {
+        return None;
+    }
+    let zeros = || ArrayD::zeros(a.raw_dim());
+    let va = lhs.variance.clone().unwrap_or_else(zeros);
+    let vb = rhs.variance.clone().unwrap_or_else(zeros);
+    let mut out = va;
+    Zip::from(&mut out)
+        .and(a)
+        .and(b)
+        .and(&vb)
+        .apply(|v, &a, &b, &vb| *v = f(a, b, *v, vb));
+    Some(out)
+}
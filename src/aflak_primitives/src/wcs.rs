@@ -0,0 +1,685 @@
+//! World coordinate systems, as defined in FITS WCS Paper I (linear axes)
+//! and Paper II (celestial axes).
+use std::f64::consts::PI;
+
+use fitrs::Hdu;
+use nalgebra::Matrix4;
+
+use crate::unit::{read_float, read_string, write_float};
+
+/// Maximum number of axes handled by the world coordinate system.
+pub(crate) const WCS_MAX_AXES: usize = 4;
+
+const D2R: f64 = PI / 180.0;
+const R2D: f64 = 180.0 / PI;
+
+/// World coordinate system of an array of at most `WCS_MAX_AXES` axes.
+///
+/// Pixel coordinates *p* are first transformed to intermediate world
+/// coordinates *x* with *x<sub>i</sub> = CDELT<sub>i</sub> Σ<sub>j</sub>
+/// PC<sub>ij</sub> (p<sub>j</sub> - CRPIX<sub>j</sub>)*.
+/// World coordinates of linear axes are *CRVAL<sub>i</sub> + x<sub>i</sub>*.
+/// If the array has a pair of celestial axes, their intermediate world
+/// coordinates are deprojected then rotated to celestial coordinates as
+/// defined in FITS WCS Paper II.
+///
+/// All values are kept as read from the FITS header, i.e. `crpix` uses
+/// 1-based pixel coordinates. A `CDi_j` matrix is stored as a `PCi_j` matrix
+/// with unit `CDELTi`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WCS {
+    crpix: [f64; WCS_MAX_AXES],
+    crval: [f64; WCS_MAX_AXES],
+    cdelt: [f64; WCS_MAX_AXES],
+    pc: [[f64; WCS_MAX_AXES]; WCS_MAX_AXES],
+    #[serde(default)]
+    celestial: Option<Celestial>,
+}
+
+/// Celestial axes of a world coordinate system.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Celestial {
+    /// Index of the longitude axis (right ascension, galactic longitude...)
+    lng: usize,
+    /// Index of the latitude axis (declination, galactic latitude...)
+    lat: usize,
+    projection: Projection,
+    /// Native longitude of the celestial pole in degrees (LONPOLE)
+    lonpole: f64,
+    /// Hint on the celestial latitude of the native pole in degrees
+    /// (LATPOLE)
+    latpole: f64,
+    /// Projection parameter `PVi_1` of the latitude axis. Only used by CEA.
+    pv1: f64,
+}
+
+/// Supported spherical projections, named after their FITS code.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Projection {
+    /// Gnomonic (zenithal)
+    Tan,
+    /// Slant orthographic (zenithal)
+    Sin,
+    /// Zenithal equidistant
+    Arc,
+    /// Stereographic (zenithal)
+    Stg,
+    /// Zenithal equal-area
+    Zea,
+    /// Plate carrée (cylindrical)
+    Car,
+    /// Mercator (cylindrical)
+    Mer,
+    /// Cylindrical equal area
+    Cea,
+    /// Sanson-Flamsteed (pseudo-cylindrical), also known as GLS
+    Sfl,
+}
+
+impl Default for WCS {
+    /// Identity transform: world coordinates are pixel coordinates.
+    fn default() -> Self {
+        let mut pc = [[0.0; WCS_MAX_AXES]; WCS_MAX_AXES];
+        for (i, row) in pc.iter_mut().enumerate() {
+            row[i] = 1.0;
+        }
+        WCS {
+            crpix: [1.0; WCS_MAX_AXES],
+            crval: [0.0; WCS_MAX_AXES],
+            cdelt: [1.0; WCS_MAX_AXES],
+            pc,
+            celestial: None,
+        }
+    }
+}
+
+impl WCS {
+    /// Make a new linear WCS. `crpix` uses 1-based pixel coordinates, as in
+    /// FITS headers.
+    pub fn new(
+        crpix: [f64; WCS_MAX_AXES],
+        crval: [f64; WCS_MAX_AXES],
+        cdelt: [f64; WCS_MAX_AXES],
+        pc: [[f64; WCS_MAX_AXES]; WCS_MAX_AXES],
+    ) -> Self {
+        WCS {
+            crpix,
+            crval,
+            cdelt,
+            pc,
+            celestial: None,
+        }
+    }
+
+    /// Read WCS from the header of a FITS `Hdu`.
+    ///
+    /// Missing keywords take their default values defined in the FITS
+    /// standard. If any `CDi_j` keyword is defined, the `CDi_j` matrix takes
+    /// precedence over `PCi_j` and `CDELTi`. The legacy `CROTAi` keyword is
+    /// used for celestial axes if neither matrix is defined.
+    ///
+    /// Celestial axes are recognized from `CTYPEi`. If their projection is
+    /// not supported, they are handled as linear axes.
+    pub fn from_hdu(hdu: &Hdu) -> Self {
+        let mut wcs = WCS::default();
+        let has_cd = (1..=WCS_MAX_AXES).any(|i| {
+            (1..=WCS_MAX_AXES).any(|j| read_float(hdu, &format!("CD{}_{}", i, j)).is_some())
+        });
+        let mut has_pc = false;
+        for i in 0..WCS_MAX_AXES {
+            if let Some(crpix) = read_float(hdu, &format!("CRPIX{}", i + 1)) {
+                wcs.crpix[i] = crpix;
+            }
+            if let Some(crval) = read_float(hdu, &format!("CRVAL{}", i + 1)) {
+                wcs.crval[i] = crval;
+            }
+            if has_cd {
+                for j in 0..WCS_MAX_AXES {
+                    wcs.pc[i][j] =
+                        read_float(hdu, &format!("CD{}_{}", i + 1, j + 1)).unwrap_or(0.0);
+                }
+            } else {
+                if let Some(cdelt) = read_float(hdu, &format!("CDELT{}", i + 1)) {
+                    wcs.cdelt[i] = cdelt;
+                }
+                for j in 0..WCS_MAX_AXES {
+                    if let Some(pc) = read_float(hdu, &format!("PC{}_{}", i + 1, j + 1)) {
+                        wcs.pc[i][j] = pc;
+                        has_pc = true;
+                    }
+                }
+            }
+        }
+
+        wcs.celestial = Celestial::from_hdu(hdu, &wcs.crval);
+        if let Some(cel) = &wcs.celestial {
+            let crota = read_float(hdu, &format!("CROTA{}", cel.lat + 1));
+            if let (false, false, Some(crota)) = (has_cd, has_pc, crota) {
+                let (lng, lat) = (cel.lng, cel.lat);
+                let (sin, cos) = (crota * D2R).sin_cos();
+                let lambda = wcs.cdelt[lat] / wcs.cdelt[lng];
+                wcs.pc[lng][lng] = cos;
+                wcs.pc[lng][lat] = -lambda * sin;
+                wcs.pc[lat][lng] = sin / lambda;
+                wcs.pc[lat][lat] = cos;
+            }
+        }
+        wcs
+    }
+
+    /// Write WCS keywords for the first `naxis` axes in the header of `hdu`.
+    ///
+    /// `CTYPEi` keywords are not written, as they are stored with the other
+    /// properties of each axis.
+    pub fn write_to_hdu(&self, hdu: &mut Hdu, naxis: usize) {
+        let naxis = naxis.min(WCS_MAX_AXES);
+        for i in 0..naxis {
+            write_float(hdu, &format!("CRPIX{}", i + 1), self.crpix[i]);
+            write_float(hdu, &format!("CRVAL{}", i + 1), self.crval[i]);
+            write_float(hdu, &format!("CDELT{}", i + 1), self.cdelt[i]);
+        }
+        for i in 0..naxis {
+            for j in 0..naxis {
+                let identity = if i == j { 1.0 } else { 0.0 };
+                if self.pc[i][j] != identity {
+                    write_float(hdu, &format!("PC{}_{}", i + 1, j + 1), self.pc[i][j]);
+                }
+            }
+        }
+        if let Some(cel) = &self.celestial {
+            write_float(hdu, "LONPOLE", cel.lonpole);
+            write_float(hdu, "LATPOLE", cel.latpole);
+            if cel.projection == Projection::Cea {
+                write_float(hdu, &format!("PV{}_1", cel.lat + 1), cel.pv1);
+            }
+        }
+    }
+
+    /// Get celestial axes, if any.
+    pub fn celestial(&self) -> Option<&Celestial> {
+        self.celestial.as_ref()
+    }
+
+    /// Convert `pixel` (in pixel coordinates starting from 0) to world
+    /// coordinates.
+    ///
+    /// Celestial coordinates are in degrees. They are NaN if `pixel` is out
+    /// of the bounds of the projection.
+    pub fn pix2world(&self, pixel: [f32; WCS_MAX_AXES]) -> [f32; WCS_MAX_AXES] {
+        let mut intermediate = [0.0; WCS_MAX_AXES];
+        for (i, x) in intermediate.iter_mut().enumerate() {
+            for (j, p) in pixel.iter().enumerate() {
+                *x += self.pc[i][j] * (*p as f64 + 1.0 - self.crpix[j]);
+            }
+            *x *= self.cdelt[i];
+        }
+
+        let mut world = [0.0; WCS_MAX_AXES];
+        for (i, w) in world.iter_mut().enumerate() {
+            *w = (self.crval[i] + intermediate[i]) as f32;
+        }
+        if let Some(cel) = &self.celestial {
+            let (lng, lat) = match cel.intermediate_to_celestial(
+                intermediate[cel.lng],
+                intermediate[cel.lat],
+                &self.crval,
+            ) {
+                Some((lng, lat)) => (lng as f32, lat as f32),
+                None => (::std::f32::NAN, ::std::f32::NAN),
+            };
+            world[cel.lng] = lng;
+            world[cel.lat] = lat;
+        }
+        world
+    }
+
+    /// Convert `world` coordinates to pixel coordinates starting from 0.
+    ///
+    /// Return NaN pixel coordinates if `world` cannot be projected or if the
+    /// linear transformation is not invertible.
+    pub fn world2pix(&self, world: [f32; WCS_MAX_AXES]) -> [f32; WCS_MAX_AXES] {
+        let mut intermediate = [0.0; WCS_MAX_AXES];
+        for (i, x) in intermediate.iter_mut().enumerate() {
+            *x = world[i] as f64 - self.crval[i];
+        }
+        if let Some(cel) = &self.celestial {
+            let (x, y) = cel
+                .celestial_to_intermediate(
+                    world[cel.lng] as f64,
+                    world[cel.lat] as f64,
+                    &self.crval,
+                )
+                .unwrap_or((::std::f64::NAN, ::std::f64::NAN));
+            intermediate[cel.lng] = x;
+            intermediate[cel.lat] = y;
+        }
+
+        let mut pixel = [::std::f32::NAN; WCS_MAX_AXES];
+        let linear = Matrix4::from_fn(|i, j| self.cdelt[i] * self.pc[i][j]);
+        if let Some(inverse) = linear.try_inverse() {
+            for (i, p) in pixel.iter_mut().enumerate() {
+                let mut offset = 0.0;
+                for (j, x) in intermediate.iter().enumerate() {
+                    offset += inverse[(i, j)] * x;
+                }
+                *p = (self.crpix[i] - 1.0 + offset) as f32;
+            }
+        }
+        pixel
+    }
+
+    /// Make the WCS of an array whose axes are extracted from the array
+    /// described by this WCS.
+    ///
+    /// Axis `k` of the new array corresponds to axis `axes[k].0` of the
+    /// original array, pixel `p` of the new axis being located at pixel
+    /// `axes[k].1 + axes[k].2 * p` of the original axis (pixel coordinates
+    /// starting from 0).
+    ///
+    /// Axes that are not kept are dropped. Their coupling with the kept axes
+    /// through the PC matrix is ignored. If only one of the celestial axes is
+    /// kept, it is handled as a linear axis.
+    pub fn slice(&self, axes: &[(usize, f32, f32)]) -> Self {
+        let mut wcs = WCS::default();
+        for (k, &(i, start, scale)) in axes.iter().enumerate().take(WCS_MAX_AXES) {
+            let (start, scale) = (start as f64, scale as f64);
+            wcs.crpix[k] = 1.0 + (self.crpix[i] - 1.0 - start) / scale;
+            wcs.crval[k] = self.crval[i];
+            wcs.cdelt[k] = self.cdelt[i] * scale;
+            for (l, &(j, _, scale_j)) in axes.iter().enumerate().take(WCS_MAX_AXES) {
+                wcs.pc[k][l] = self.pc[i][j] * scale_j as f64 / scale;
+            }
+        }
+        wcs.celestial = self.celestial.as_ref().and_then(|cel| {
+            let new_index = |old| {
+                axes.iter()
+                    .take(WCS_MAX_AXES)
+                    .position(|&(i, _, _)| i == old)
+            };
+            match (new_index(cel.lng), new_index(cel.lat)) {
+                (Some(lng), Some(lat)) => Some(Celestial { lng, lat, ..*cel }),
+                _ => None,
+            }
+        });
+        wcs
+    }
+
+    /// Make the WCS where the world coordinates along linear axis `axis`
+    /// (WCS axis index) are multiplied by `factor`, e.g. to express them in
+    /// another unit.
+    ///
+    /// Return `None` for celestial axes, whose coordinates are always in
+    /// degrees.
+    pub fn scale_axis(&self, axis: usize, factor: f64) -> Option<Self> {
+        if axis >= WCS_MAX_AXES {
+            return None;
+        }
+        if let Some(cel) = &self.celestial {
+            if axis == cel.lng || axis == cel.lat {
+                return None;
+            }
+        }
+        let mut wcs = self.clone();
+        wcs.crval[axis] *= factor;
+        wcs.cdelt[axis] *= factor;
+        Some(wcs)
+    }
+}
+
+impl Celestial {
+    /// Find celestial axes from `CTYPEi` keywords.
+    fn from_hdu(hdu: &Hdu, crval: &[f64; WCS_MAX_AXES]) -> Option<Self> {
+        let mut lng = None;
+        let mut lat = None;
+        for i in 0..WCS_MAX_AXES {
+            let ctype = match read_string(hdu, &format!("CTYPE{}", i + 1)) {
+                Some(ctype) => ctype,
+                None => continue,
+            };
+            let ctype = ctype.trim();
+            // Byte 4 being ASCII, slicing around it is safe
+            if ctype.len() != 8 || ctype.as_bytes()[4] != b'-' {
+                continue;
+            }
+            let (name, code) = (&ctype[..4], &ctype[5..]);
+            if name == "RA--" || name.ends_with("LON") || name.ends_with("LN") {
+                lng = Some((i, code));
+            } else if name == "DEC-" || name.ends_with("LAT") || name.ends_with("LT") {
+                lat = Some((i, code));
+            }
+        }
+
+        match (lng, lat) {
+            (Some((lng, lng_code)), Some((lat, lat_code))) if lng_code == lat_code => {
+                let projection = Projection::from_code(lng_code)?;
+                let (_, theta0) = projection.native_reference();
+                let lonpole = read_float(hdu, "LONPOLE").unwrap_or({
+                    if crval[lat] >= theta0 {
+                        0.0
+                    } else {
+                        180.0
+                    }
+                });
+                let latpole = read_float(hdu, "LATPOLE").unwrap_or(90.0);
+                let pv1 = read_float(hdu, &format!("PV{}_1", lat + 1)).unwrap_or(1.0);
+                Some(Celestial {
+                    lng,
+                    lat,
+                    projection,
+                    lonpole,
+                    latpole,
+                    pv1,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    /// Index of the longitude axis.
+    pub fn lng_axis(&self) -> usize {
+        self.lng
+    }
+
+    /// Index of the latitude axis.
+    pub fn lat_axis(&self) -> usize {
+        self.lat
+    }
+
+    pub fn projection(&self) -> Projection {
+        self.projection
+    }
+
+    /// Compute celestial coordinates (α<sub>p</sub>, δ<sub>p</sub>) of the
+    /// native pole, in degrees.
+    fn pole(&self, crval: &[f64; WCS_MAX_AXES]) -> (f64, f64) {
+        let (phi0, theta0) = self.projection.native_reference();
+        let (alpha0, delta0) = (crval[self.lng], crval[self.lat]);
+        let dphi = (self.lonpole - phi0) * D2R;
+        let (sin_theta0, cos_theta0) = (theta0 * D2R).sin_cos();
+
+        let a = sin_theta0.atan2(cos_theta0 * dphi.cos()) * R2D;
+        let norm = (1.0 - (cos_theta0 * dphi.sin()).powi(2)).sqrt();
+        let b = ((delta0 * D2R).sin() / norm).max(-1.0).min(1.0).acos() * R2D;
+        let delta_p = [a + b, a - b]
+            .iter()
+            .cloned()
+            .filter(|delta| delta.abs() <= 90.0 + 1e-9)
+            .min_by(|d1, d2| {
+                let (d1, d2) = ((d1 - self.latpole).abs(), (d2 - self.latpole).abs());
+                d1.partial_cmp(&d2).unwrap()
+            })
+            .unwrap_or(delta0)
+            .max(-90.0)
+            .min(90.0);
+
+        // The native reference point must be located at (α0, δ0)
+        let (alpha, _) = rotate(phi0, theta0, 0.0, delta_p, self.lonpole);
+        (alpha0 - alpha, delta_p)
+    }
+
+    fn intermediate_to_celestial(
+        &self,
+        x: f64,
+        y: f64,
+        crval: &[f64; WCS_MAX_AXES],
+    ) -> Option<(f64, f64)> {
+        let (phi, theta) = self.projection.deproject(x, y, self.pv1)?;
+        let (alpha_p, delta_p) = self.pole(crval);
+        let (alpha, delta) = rotate(phi, theta, alpha_p, delta_p, self.lonpole);
+        Some((normalize_angle(alpha, 0.0), delta))
+    }
+
+    fn celestial_to_intermediate(
+        &self,
+        alpha: f64,
+        delta: f64,
+        crval: &[f64; WCS_MAX_AXES],
+    ) -> Option<(f64, f64)> {
+        let (alpha_p, delta_p) = self.pole(crval);
+        // Rotating back to native coordinates is the same rotation where
+        // native and celestial poles are swapped.
+        let (phi, theta) = rotate(alpha, delta, self.lonpole, delta_p, alpha_p);
+        let phi = normalize_angle(phi, -180.0);
+        self.projection.project(phi, theta, self.pv1)
+    }
+}
+
+/// Rotate spherical coordinates (`lng`, `lat`) to another frame.
+///
+/// (`pole_lng`, `lat_p`) are the coordinates of the pole of the original
+/// frame in the new frame, and `lng_p` is the longitude of the pole of the
+/// new frame in the original frame. All angles are in degrees.
+///
+/// See equations (2) and (5) of FITS WCS Paper II.
+fn rotate(lng: f64, lat: f64, pole_lng: f64, lat_p: f64, lng_p: f64) -> (f64, f64) {
+    let dlng = (lng - lng_p) * D2R;
+    let (sin_lat, cos_lat) = (lat * D2R).sin_cos();
+    let (sin_lat_p, cos_lat_p) = (lat_p * D2R).sin_cos();
+    let x = sin_lat * cos_lat_p - cos_lat * sin_lat_p * dlng.cos();
+    let y = -cos_lat * dlng.sin();
+    let new_lng = pole_lng + y.atan2(x) * R2D;
+    let new_lat = (sin_lat * sin_lat_p + cos_lat * cos_lat_p * dlng.cos())
+        .max(-1.0)
+        .min(1.0)
+        .asin()
+        * R2D;
+    (new_lng, new_lat)
+}
+
+/// Normalize `angle` (in degrees) into `[min, min + 360)`.
+fn normalize_angle(angle: f64, min: f64) -> f64 {
+    let angle = (angle - min) % 360.0;
+    if angle < 0.0 {
+        angle + 360.0 + min
+    } else {
+        angle + min
+    }
+}
+
+impl Projection {
+    /// Get projection from the three-letter code found in `CTYPEi`.
+    pub fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "TAN" => Some(Projection::Tan),
+            "SIN" => Some(Projection::Sin),
+            "ARC" => Some(Projection::Arc),
+            "STG" => Some(Projection::Stg),
+            "ZEA" => Some(Projection::Zea),
+            "CAR" => Some(Projection::Car),
+            "MER" => Some(Projection::Mer),
+            "CEA" => Some(Projection::Cea),
+            "SFL" | "GLS" => Some(Projection::Sfl),
+            _ => None,
+        }
+    }
+
+    /// Get FITS code of projection.
+    pub fn code(self) -> &'static str {
+        match self {
+            Projection::Tan => "TAN",
+            Projection::Sin => "SIN",
+            Projection::Arc => "ARC",
+            Projection::Stg => "STG",
+            Projection::Zea => "ZEA",
+            Projection::Car => "CAR",
+            Projection::Mer => "MER",
+            Projection::Cea => "CEA",
+            Projection::Sfl => "SFL",
+        }
+    }
+
+    fn is_zenithal(self) -> bool {
+        matches!(
+            self,
+            Projection::Tan | Projection::Sin | Projection::Arc | Projection::Stg | Projection::Zea
+        )
+    }
+
+    /// Native coordinates (φ<sub>0</sub>, θ<sub>0</sub>) of the reference
+    /// point, in degrees.
+    fn native_reference(self) -> (f64, f64) {
+        if self.is_zenithal() {
+            (0.0, 90.0)
+        } else {
+            (0.0, 0.0)
+        }
+    }
+
+    /// Project native spherical coordinates (φ, θ) to intermediate world
+    /// coordinates (x, y). All values are in degrees.
+    fn project(self, phi: f64, theta: f64, pv1: f64) -> Option<(f64, f64)> {
+        if self.is_zenithal() {
+            let r = match self {
+                Projection::Tan if theta > 0.0 => R2D / (theta * D2R).tan(),
+                Projection::Sin if theta >= 0.0 => R2D * (theta * D2R).cos(),
+                Projection::Arc => 90.0 - theta,
+                Projection::Stg if theta > -90.0 => 2.0 * R2D * ((90.0 - theta) * D2R / 2.0).tan(),
+                Projection::Zea => 2.0 * R2D * ((90.0 - theta) * D2R / 2.0).sin(),
+                _ => return None,
+            };
+            let (sin_phi, cos_phi) = (phi * D2R).sin_cos();
+            Some((r * sin_phi, -r * cos_phi))
+        } else {
+            match self {
+                Projection::Car => Some((phi, theta)),
+                Projection::Mer if theta.abs() < 90.0 => {
+                    Some((phi, R2D * ((90.0 + theta) * D2R / 2.0).tan().ln()))
+                }
+                Projection::Cea => Some((phi, R2D * (theta * D2R).sin() / pv1)),
+                Projection::Sfl => Some((phi * (theta * D2R).cos(), theta)),
+                _ => None,
+            }
+        }
+    }
+
+    /// Deproject intermediate world coordinates (x, y) to native spherical
+    /// coordinates (φ, θ). All values are in degrees.
+    fn deproject(self, x: f64, y: f64, pv1: f64) -> Option<(f64, f64)> {
+        if self.is_zenithal() {
+            let r = x.hypot(y);
+            let phi = if r == 0.0 { 0.0 } else { x.atan2(-y) * R2D };
+            let theta = match self {
+                Projection::Tan => R2D.atan2(r) * R2D,
+                Projection::Sin if r <= R2D => (r * D2R).acos() * R2D,
+                Projection::Arc if r <= 180.0 => 90.0 - r,
+                Projection::Stg => 90.0 - 2.0 * (r * D2R / 2.0).atan() * R2D,
+                Projection::Zea if r <= 2.0 * R2D => 90.0 - 2.0 * (r * D2R / 2.0).asin() * R2D,
+                _ => return None,
+            };
+            Some((phi, theta))
+        } else {
+            let (phi, theta) = match self {
+                Projection::Car => (x, y),
+                Projection::Mer => (x, 2.0 * (y * D2R).exp().atan() * R2D - 90.0),
+                Projection::Cea => {
+                    let sin_theta = pv1 * y * D2R;
+                    if sin_theta.abs() > 1.0 {
+                        return None;
+                    }
+                    (x, sin_theta.asin() * R2D)
+                }
+                Projection::Sfl => {
+                    let cos_theta = (y * D2R).cos();
+                    if cos_theta.abs() < ::std::f64::EPSILON {
+                        (0.0, y)
+                    } else {
+                        (x / cos_theta, y)
+                    }
+                }
+                _ => return None,
+            };
+            if theta.abs() > 90.0 || phi.abs() > 180.0 {
+                None
+            } else {
+                Some((phi, theta))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::WCS;
+    use fitrs::{Hdu, HeaderValue};
+
+    #[test]
+    fn test_celestial_wcs() {
+        fn make_hdu(projection: &str) -> Hdu {
+            let mut hdu = Hdu::new(&[10, 10], vec![0.0f32; 100]);
+            let cards = [
+                ("CRPIX1", 5.0),
+                ("CRPIX2", 5.0),
+                ("CRVAL1", 30.0),
+                ("CRVAL2", 45.0),
+                ("CDELT1", -0.1),
+                ("CDELT2", 0.1),
+            ];
+            for &(key, value) in &cards {
+                hdu.insert(key, HeaderValue::RealFloatingNumber(value));
+            }
+            hdu.insert(
+                "CTYPE1",
+                HeaderValue::CharacterString(format!("RA---{}", projection)),
+            );
+            hdu.insert(
+                "CTYPE2",
+                HeaderValue::CharacterString(format!("DEC--{}", projection)),
+            );
+            hdu
+        }
+
+        for projection in &[
+            "TAN", "SIN", "ARC", "STG", "ZEA", "CAR", "MER", "CEA", "SFL",
+        ] {
+            let wcs = WCS::from_hdu(&make_hdu(projection));
+            assert!(wcs.celestial().is_some(), "{}", projection);
+
+            // Reference pixel
+            let world = wcs.pix2world([4.0, 4.0, 0.0, 0.0]);
+            assert!(
+                (world[0] - 30.0).abs() < 1e-4,
+                "{}: {:?}",
+                projection,
+                world
+            );
+            assert!(
+                (world[1] - 45.0).abs() < 1e-4,
+                "{}: {:?}",
+                projection,
+                world
+            );
+
+            // Moving along the latitude axis from the reference pixel
+            let world = wcs.pix2world([4.0, 14.0, 0.0, 0.0]);
+            assert!(
+                (world[0] - 30.0).abs() < 1e-3,
+                "{}: {:?}",
+                projection,
+                world
+            );
+            assert!(
+                (world[1] - 46.0).abs() < 2e-2,
+                "{}: {:?}",
+                projection,
+                world
+            );
+
+            for &pixel in &[[0.0, 0.0], [9.0, 0.0], [2.5, 7.5], [9.0, 9.0]] {
+                let world = wcs.pix2world([pixel[0], pixel[1], 0.0, 0.0]);
+                let back = wcs.world2pix(world);
+                assert!(
+                    (back[0] - pixel[0]).abs() < 1e-2,
+                    "{}: {:?}",
+                    projection,
+                    back
+                );
+                assert!(
+                    (back[1] - pixel[1]).abs() < 1e-2,
+                    "{}: {:?}",
+                    projection,
+                    back
+                );
+            }
+        }
+    }
+}
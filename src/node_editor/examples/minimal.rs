@@ -141,6 +141,9 @@ impl cake::ConvertibleVariants for IOValue {
     ];
 }
 
+/// Values are small, so the default size estimate is good enough
+impl cake::EstimateSize for IOValue {}
+
 /// Define an editor to edit each constant type
 #[derive(Default)]
 struct MyConstantEditor;
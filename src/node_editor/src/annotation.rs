@@ -0,0 +1,172 @@
+//! Annotations on the node canvas: comment frames and notes.
+use std::collections::{btree_map, BTreeMap};
+
+use crate::vec2::Vec2;
+
+/// Uniquely identify an [`Annotation`] of a node editor.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct AnnotationId(usize);
+
+impl AnnotationId {
+    pub fn id(self) -> usize {
+        self.0
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum AnnotationKind {
+    /// Titled frame grouping the nodes it contains. Moving the frame moves
+    /// its nodes.
+    Frame,
+    /// Free-floating note.
+    Note,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Annotation {
+    pub kind: AnnotationKind,
+    /// Title of a frame, or text of a note
+    pub text: String,
+    pub color: [f32; 3],
+    pub pos: Vec2,
+    pub size: Vec2,
+}
+
+impl Annotation {
+    pub const DEFAULT_FRAME_COLOR: [f32; 3] = [0.25, 0.40, 0.60];
+    pub const DEFAULT_NOTE_COLOR: [f32; 3] = [0.55, 0.50, 0.25];
+
+    pub fn frame(pos: Vec2, size: Vec2) -> Self {
+        Self {
+            kind: AnnotationKind::Frame,
+            text: "Comment".to_owned(),
+            color: Self::DEFAULT_FRAME_COLOR,
+            pos,
+            size,
+        }
+    }
+
+    pub fn note(pos: Vec2) -> Self {
+        Self {
+            kind: AnnotationKind::Note,
+            text: "# Note\n- Lines starting with '-' are bullets".to_owned(),
+            color: Self::DEFAULT_NOTE_COLOR,
+            pos,
+            size: Vec2(240.0, 100.0),
+        }
+    }
+
+    /// Check whether the rectangle at `pos` of size `size` is inside the
+    /// annotation.
+    pub fn contains(&self, pos: Vec2, size: Vec2) -> bool {
+        let max = self.pos + self.size;
+        let other_max = pos + size;
+        self.pos.0 <= pos.0 && self.pos.1 <= pos.1 && other_max.0 <= max.0 && other_max.1 <= max.1
+    }
+}
+
+/// All the annotations of a node editor.
+#[derive(Clone, Debug, Default)]
+pub struct Annotations(BTreeMap<AnnotationId, Annotation>);
+
+impl Annotations {
+    pub fn new() -> Self {
+        Annotations(BTreeMap::new())
+    }
+
+    pub fn get(&self, id: &AnnotationId) -> Option<&Annotation> {
+        self.0.get(id)
+    }
+
+    pub fn get_mut(&mut self, id: &AnnotationId) -> Option<&mut Annotation> {
+        self.0.get_mut(id)
+    }
+
+    pub fn iter(&self) -> btree_map::Iter<AnnotationId, Annotation> {
+        self.0.iter()
+    }
+
+    /// Return an identifier that is not used by any annotation.
+    pub fn new_id(&self) -> AnnotationId {
+        let max = self.0.keys().next_back().map_or(0, |id| id.0);
+        AnnotationId(max + 1)
+    }
+
+    /// Insert/Replace annotation with given identifier.
+    pub fn insert(&mut self, id: AnnotationId, annotation: Annotation) -> Option<Annotation> {
+        self.0.insert(id, annotation)
+    }
+
+    pub fn remove(&mut self, id: &AnnotationId) -> Option<Annotation> {
+        self.0.remove(id)
+    }
+}
+
+/// Style of a line of a note.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum NoteLine<'a> {
+    /// Line starting with '#'
+    Heading(&'a str),
+    /// Line starting with '-' or '*'
+    Bullet(&'a str),
+    /// Line surrounded with '*' or '_'
+    Emphasis(&'a str),
+    Text(&'a str),
+}
+
+/// Split the text of a note in lines with a minimal markdown-like syntax.
+pub fn parse_note(text: &str) -> Vec<NoteLine<'_>> {
+    text.lines()
+        .map(|line| {
+            let trimmed = line.trim();
+            if trimmed.starts_with('#') {
+                NoteLine::Heading(trimmed.trim_start_matches('#').trim())
+            } else if trimmed.starts_with("- ") || trimmed.starts_with("* ") {
+                NoteLine::Bullet(trimmed[2..].trim())
+            } else if trimmed.len() > 2
+                && ((trimmed.starts_with('*') && trimmed.ends_with('*'))
+                    || (trimmed.starts_with('_') && trimmed.ends_with('_')))
+            {
+                NoteLine::Emphasis(trimmed[1..trimmed.len() - 1].trim())
+            } else {
+                NoteLine::Text(line)
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_note, Annotation, NoteLine};
+    use crate::vec2::Vec2;
+
+    #[test]
+    fn test_parse_note() {
+        let text = "# Title\n- first\n* second\n*important*\n_also_\nplain *text*\n**";
+        assert_eq!(
+            parse_note(text),
+            vec![
+                NoteLine::Heading("Title"),
+                NoteLine::Bullet("first"),
+                NoteLine::Bullet("second"),
+                NoteLine::Emphasis("important"),
+                NoteLine::Emphasis("also"),
+                NoteLine::Text("plain *text*"),
+                NoteLine::Text("**"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_frame_contains() {
+        let frame = Annotation::frame(Vec2(10.0, 20.0), Vec2(100.0, 50.0));
+        assert!(frame.contains(Vec2(10.0, 20.0), Vec2(100.0, 50.0)));
+        assert!(frame.contains(Vec2(30.0, 30.0), Vec2(20.0, 20.0)));
+        // Partially outside
+        assert!(!frame.contains(Vec2(5.0, 30.0), Vec2(20.0, 20.0)));
+        assert!(!frame.contains(Vec2(100.0, 30.0), Vec2(20.0, 20.0)));
+        assert!(!frame.contains(Vec2(30.0, 60.0), Vec2(20.0, 20.0)));
+        // Completely outside
+        assert!(!frame.contains(Vec2(200.0, 200.0), Vec2(20.0, 20.0)));
+    }
+}
@@ -0,0 +1,67 @@
+//! Copy and paste of nodes through the clipboard.
+use crate::cake;
+use crate::export::{ExportError, ImportError};
+use crate::node_state::{NodeState, NodeStates};
+
+/// Content of the clipboard: the copied nodes with the links between them
+/// and the macros they use, and the state of each node.
+#[derive(Serialize)]
+struct SerialClipboard<'e, T> {
+    dst: cake::macros::SerdeDSTStandAlone<T>,
+    node_states: Vec<(cake::NodeId, &'e NodeState)>,
+}
+
+#[derive(Deserialize)]
+#[serde(bound(deserialize = "T: serde::Deserialize<'de>"))]
+struct DeserClipboard<T> {
+    dst: cake::macros::SerdeDSTStandAlone<T>,
+    node_states: Vec<(cake::NodeId, NodeState)>,
+}
+
+/// Nodes pasted from the clipboard.
+pub struct Pasted<T: 'static, E: 'static> {
+    pub dst: cake::DST<'static, T, E>,
+    pub node_states: Vec<(cake::NodeId, NodeState)>,
+}
+
+/// Serialize the given nodes of `dst` in .ron format, to be put on the
+/// clipboard.
+pub fn copy<T, E>(
+    dst: &cake::DST<'static, T, E>,
+    node_states: &NodeStates,
+    nodes: &[cake::NodeId],
+) -> Result<String, ExportError>
+where
+    T: Clone + cake::VariantName + serde::Serialize,
+{
+    let sub_graph = dst.sub_graph(nodes);
+    let serializable = SerialClipboard {
+        dst: cake::macros::SerdeDSTStandAlone::from(&sub_graph),
+        node_states: nodes
+            .iter()
+            .filter_map(|node_id| node_states.get(node_id).map(|state| (*node_id, state)))
+            .collect(),
+    };
+    Ok(ron::ser::to_string(&serializable)?)
+}
+
+/// Deserialize nodes copied with [`copy`]. Macros missing from `macros` are
+/// added to it.
+pub fn paste<T, E>(
+    text: &str,
+    macros: &mut cake::macros::MacroManager<'static, T, E>,
+) -> Result<Pasted<T, E>, ImportError>
+where
+    T: Clone
+        + cake::NamedAlgorithms<E>
+        + cake::VariantName
+        + cake::ConvertibleVariants
+        + for<'de> serde::Deserialize<'de>,
+{
+    let deserialized: DeserClipboard<T> = ron::de::from_str(text)?;
+    let dst = deserialized.dst.into_dst_in(macros)?;
+    Ok(Pasted {
+        dst,
+        node_states: deserialized.node_states,
+    })
+}
@@ -0,0 +1,81 @@
+//! Differences between the edited graph and a saved graph, highlighted in the
+//! node editor.
+use std::collections::BTreeSet;
+use std::fmt;
+use std::path::PathBuf;
+
+use crate::cake::{self, export, macros, InputSlot, NodeId, VariantName};
+
+pub struct DiffHighlight {
+    /// Path to the saved graph
+    pub path: PathBuf,
+    /// Nodes missing from the saved graph
+    pub added: BTreeSet<NodeId>,
+    /// Nodes changed since the saved graph, including nodes using a changed
+    /// macro
+    pub changed: BTreeSet<NodeId>,
+    /// Input slots connected differently in the saved graph
+    pub links: BTreeSet<InputSlot>,
+    /// Number of nodes removed since the saved graph
+    pub removed: usize,
+    /// Description of each difference
+    pub lines: Vec<String>,
+}
+
+impl DiffHighlight {
+    /// Compare `dst` to the `saved` graph read from `path`.
+    pub fn new<T, E>(
+        path: PathBuf,
+        saved: &macros::SerdeDSTStandAlone<T>,
+        dst: &cake::DST<'static, T, E>,
+    ) -> Self
+    where
+        T: Clone + PartialEq + fmt::Debug + VariantName,
+    {
+        let diff = saved.diff(&macros::SerdeDSTStandAlone::from(dst));
+        let mut added = BTreeSet::new();
+        let mut changed = BTreeSet::new();
+        let mut removed = 0;
+        for (t_idx, node_diff) in &diff.main.nodes {
+            let node_id = NodeId::Transform(*t_idx);
+            match node_diff {
+                export::NodeDiff::Added(_) => {
+                    added.insert(node_id);
+                }
+                export::NodeDiff::Removed(_) => removed += 1,
+                export::NodeDiff::Changed { .. } => {
+                    changed.insert(node_id);
+                }
+            }
+        }
+        for (output_id, output_diff) in &diff.main.outputs {
+            let node_id = NodeId::Output(*output_id);
+            match output_diff {
+                export::OutputDiff::Added(_) => {
+                    added.insert(node_id);
+                }
+                export::OutputDiff::Removed(_) => removed += 1,
+                export::OutputDiff::Renamed(_) => {
+                    changed.insert(node_id);
+                }
+            }
+        }
+        for (node_id, node) in dst.nodes_iter() {
+            if let cake::Node::Transform(t) = node {
+                if let cake::Algorithm::Macro { handle } = t.algorithm() {
+                    if diff.changed_macros.contains(&handle.id()) && !added.contains(&node_id) {
+                        changed.insert(node_id);
+                    }
+                }
+            }
+        }
+        Self {
+            path,
+            added,
+            changed,
+            links: diff.main.links.keys().cloned().collect(),
+            removed,
+            lines: diff.to_string().lines().map(str::to_owned).collect(),
+        }
+    }
+}
@@ -1,6 +1,8 @@
 use std::{fmt, path::PathBuf};
 
+use super::annotation::{Annotation, AnnotationId};
 use super::export::{ExportError, ImportError};
+use super::vec2::Vec2;
 use crate::cake::{macros, InputSlot, NodeId, Output, OutputId, Transform, TransformIdx};
 pub enum RenderEvent<T: 'static, E: 'static> {
     Connect(Output, InputSlot),
@@ -27,6 +29,20 @@ pub enum RenderEvent<T: 'static, E: 'static> {
     AddMacro(macros::MacroHandle<'static, T, E>),
     EditNode(NodeId),
     ChangeOutputName(NodeId, String),
+    /// Paste nodes serialized on the clipboard
+    Paste(String),
+    /// Move the given nodes into a new macro
+    GroupIntoMacro(Vec<TransformIdx>),
+    /// Replace a macro node by the content of its macro
+    ExpandMacro(TransformIdx),
+    AddAnnotation(AnnotationId, Annotation),
+    /// Annotation after the update, and nodes moved along with it by the
+    /// given offsets
+    UpdateAnnotation(AnnotationId, Annotation, Vec<(NodeId, Vec2)>),
+    RemoveAnnotation(AnnotationId),
+    /// Move nodes by the given offsets and replace the given frames, e.g. to
+    /// lay out the graph
+    MoveNodes(Vec<(NodeId, Vec2)>, Vec<(AnnotationId, Annotation)>),
     Undo,
     Redo,
 }
@@ -65,6 +81,38 @@ pub enum ProvenanceEvent<T: 'static, E: 'static> {
     AddMacro(macros::MacroHandle<'static, T, E>, TransformIdx),
     EditNode(NodeId),
     ChangeOutputName(NodeId, String, String),
+    /// Clipboard content and pasted nodes
+    Paste(String, Vec<NodeId>, Result<(), ImportError>),
+    /// Grouped nodes, DST before grouping and new macro node
+    GroupIntoMacro(
+        Vec<TransformIdx>,
+        cake::DST<'static, T, E>,
+        Result<TransformIdx, cake::DSTError>,
+    ),
+    /// Expanded macro node, DST before expanding and inserted nodes
+    ExpandMacro(
+        TransformIdx,
+        cake::DST<'static, T, E>,
+        Result<Vec<TransformIdx>, cake::DSTError>,
+    ),
+    AddAnnotation(AnnotationId, Annotation),
+    /// Annotation before and after the update, and nodes moved along with it
+    /// by the given offsets. There is no annotation before if it does not
+    /// exist.
+    UpdateAnnotation(
+        AnnotationId,
+        Option<Annotation>,
+        Annotation,
+        Vec<(NodeId, Vec2)>,
+    ),
+    RemoveAnnotation(AnnotationId, Option<Annotation>),
+    /// Offsets by which nodes were moved, and replaced frames before and
+    /// after the move
+    MoveNodes(
+        Vec<(NodeId, Vec2)>,
+        Vec<(AnnotationId, Annotation)>,
+        Vec<(AnnotationId, Annotation)>,
+    ),
 }
 
 impl<T, E> From<ProvenanceEvent<T, E>> for RenderEvent<T, E> {
@@ -95,6 +143,17 @@ impl<T, E> From<ProvenanceEvent<T, E>> for RenderEvent<T, E> {
             ProvenanceEvent::ChangeOutputName(n, _, after_name) => {
                 RenderEvent::ChangeOutputName(n, after_name)
             }
+            ProvenanceEvent::Paste(text, _, _) => RenderEvent::Paste(text),
+            ProvenanceEvent::GroupIntoMacro(nodes, _, _) => RenderEvent::GroupIntoMacro(nodes),
+            ProvenanceEvent::ExpandMacro(t_idx, _, _) => RenderEvent::ExpandMacro(t_idx),
+            ProvenanceEvent::AddAnnotation(id, annotation) => {
+                RenderEvent::AddAnnotation(id, annotation)
+            }
+            ProvenanceEvent::UpdateAnnotation(id, _, annotation, moves) => {
+                RenderEvent::UpdateAnnotation(id, annotation, moves)
+            }
+            ProvenanceEvent::RemoveAnnotation(id, _) => RenderEvent::RemoveAnnotation(id),
+            ProvenanceEvent::MoveNodes(moves, _, frames) => RenderEvent::MoveNodes(moves, frames),
         }
     }
 }
@@ -134,6 +193,15 @@ impl<T: Clone, E> RenderEvent<T, E> {
             AddMacro(handle) => AddMacro(handle.clone()),
             EditNode(node_id) => EditNode(*node_id),
             ChangeOutputName(node_id, name) => ChangeOutputName(*node_id, name.clone()),
+            Paste(text) => Paste(text.clone()),
+            GroupIntoMacro(nodes) => GroupIntoMacro(nodes.clone()),
+            ExpandMacro(t_idx) => ExpandMacro(*t_idx),
+            AddAnnotation(id, annotation) => AddAnnotation(*id, annotation.clone()),
+            UpdateAnnotation(id, annotation, moves) => {
+                UpdateAnnotation(*id, annotation.clone(), moves.clone())
+            }
+            RemoveAnnotation(id) => RemoveAnnotation(*id),
+            MoveNodes(moves, frames) => MoveNodes(moves.clone(), frames.clone()),
             Undo => Undo,
             Redo => Redo,
         }
@@ -167,6 +235,19 @@ impl<T, E> fmt::Debug for RenderEvent<T, E> {
             ChangeOutputName(node_id, name) => {
                 write!(f, "ChangeOutputName(id={:?}, name={})", node_id, name)
             }
+            Paste(_) => write!(f, "Paste(_)"),
+            GroupIntoMacro(nodes) => write!(f, "GroupIntoMacro({:?})", nodes),
+            ExpandMacro(t_idx) => write!(f, "ExpandMacro({:?})", t_idx),
+            AddAnnotation(id, annotation) => write!(f, "AddAnnotation({:?}, {:?})", id, annotation),
+            UpdateAnnotation(id, annotation, moves) => {
+                write!(
+                    f,
+                    "UpdateAnnotation({:?}, {:?}, {:?})",
+                    id, annotation, moves
+                )
+            }
+            RemoveAnnotation(id) => write!(f, "RemoveAnnotation({:?})", id),
+            MoveNodes(moves, frames) => write!(f, "MoveNodes({:?}, {:?})", moves, frames),
             Undo => write!(f, "Undo"),
             Redo => write!(f, "Redo"),
         }
@@ -249,6 +330,21 @@ impl<T: fmt::Debug, E: fmt::Debug> fmt::Debug for ProvenanceEvent<T, E> {
                     node_id, before_name, after_name
                 )
             }
+            Paste(_, node_ids, res) => write!(f, "Paste({:?}, {:?})", node_ids, res),
+            GroupIntoMacro(nodes, _, res) => write!(f, "GroupIntoMacro({:?}, {:?})", nodes, res),
+            ExpandMacro(t_idx, _, res) => write!(f, "ExpandMacro({:?}, {:?})", t_idx, res),
+            AddAnnotation(id, annotation) => write!(f, "AddAnnotation({:?}, {:?})", id, annotation),
+            UpdateAnnotation(id, before, after, moves) => write!(
+                f,
+                "UpdateAnnotation({:?}, {:?} -> {:?}, {:?})",
+                id, before, after, moves
+            ),
+            RemoveAnnotation(id, annotation) => {
+                write!(f, "RemoveAnnotation({:?}, {:?})", id, annotation)
+            }
+            MoveNodes(moves, before, after) => {
+                write!(f, "MoveNodes({:?}, {:?} -> {:?})", moves, before, after)
+            }
         }
     }
 }
@@ -276,6 +372,15 @@ pub trait ApplyRenderEvent<T, E> {
             AddMacro(handle) => self.add_macro(handle),
             EditNode(node_id) => self.edit_node(node_id),
             ChangeOutputName(node_id, name) => self.change_output_name(node_id, name),
+            Paste(text) => self.paste(text),
+            GroupIntoMacro(nodes) => self.group_into_macro(nodes),
+            ExpandMacro(t_idx) => self.expand_macro(t_idx),
+            AddAnnotation(id, annotation) => self.add_annotation(id, annotation),
+            UpdateAnnotation(id, annotation, moves) => {
+                self.update_annotation(id, annotation, moves)
+            }
+            RemoveAnnotation(id) => self.remove_annotation(id),
+            MoveNodes(moves, frames) => self.move_nodes(moves, frames),
             Undo | Redo => {}
         }
     }
@@ -307,4 +412,16 @@ pub trait ApplyRenderEvent<T, E> {
     fn add_macro(&mut self, handle: macros::MacroHandle<'static, T, E>);
     fn edit_node(&mut self, node: NodeId);
     fn change_output_name(&mut self, node_id: NodeId, name: String);
+    fn paste(&mut self, text: String);
+    fn group_into_macro(&mut self, nodes: Vec<TransformIdx>);
+    fn expand_macro(&mut self, t_idx: TransformIdx);
+    fn add_annotation(&mut self, id: AnnotationId, annotation: Annotation);
+    fn update_annotation(
+        &mut self,
+        id: AnnotationId,
+        annotation: Annotation,
+        moves: Vec<(NodeId, Vec2)>,
+    );
+    fn remove_annotation(&mut self, id: AnnotationId);
+    fn move_nodes(&mut self, moves: Vec<(NodeId, Vec2)>, frames: Vec<(AnnotationId, Annotation)>);
 }
@@ -44,6 +44,9 @@ pub enum ImportError {
     DSTError(cake::ImportError),
     DeserializationError(de::Error),
     IOError(Arc<io::Error>),
+    /// Pasted nodes use macros, which cannot be registered from inside the
+    /// editor of the macro with the given name.
+    MacroInMacro(String),
 }
 
 impl fmt::Display for ImportError {
@@ -52,6 +55,11 @@ impl fmt::Display for ImportError {
             ImportError::DSTError(ref e) => write!(f, "Error while building DST! {}", e),
             ImportError::DeserializationError(ref e) => write!(f, "Deserialization error! {}", e),
             ImportError::IOError(ref e) => write!(f, "I/O error! {}", e),
+            ImportError::MacroInMacro(ref name) => write!(
+                f,
+                "Cannot paste macro nodes into macro '{}'! Paste them in the main editor.",
+                name
+            ),
         }
     }
 }
@@ -0,0 +1,257 @@
+//! Persistent provenance history of a node editor.
+//!
+//! Each [`ProvenanceEvent`] that is kept for undo is recorded as a
+//! [`HistoryEntry`] with the time it happened. Entries are saved along with
+//! the editor, and can be replayed without any window to rebuild the graph.
+use std::error;
+use std::fmt;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::annotation::{Annotation, AnnotationId};
+use crate::cake::{self, macros, InputSlot, NodeId, Output, TransformIdx, VariantName};
+use crate::event::ProvenanceEvent;
+use crate::export::ImportError;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HistoryEntry<T> {
+    /// Seconds since the UNIX epoch
+    pub timestamp: u64,
+    pub event: HistoryEvent<T>,
+}
+
+/// Serializable record of a [`ProvenanceEvent`].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum HistoryEvent<T> {
+    Connect(Output, InputSlot),
+    Disconnect(Output, InputSlot),
+    /// Name of the added transform
+    AddTransform(String),
+    CreateOutput,
+    /// Variant name of the added constant
+    AddConstant(String),
+    SetConstant(TransformIdx, T),
+    WriteDefaultInput(TransformIdx, usize, T),
+    RemoveNode(NodeId),
+    ChangeOutputName(NodeId, String),
+    Paste(String),
+    AddAnnotation(AnnotationId, Annotation),
+    UpdateAnnotation(AnnotationId, Annotation),
+    RemoveAnnotation(AnnotationId),
+    /// Move the given nodes. Nothing to replay, as the layout is not
+    /// replayed.
+    MoveNodes(Vec<NodeId>),
+    /// Open a macro editor. Nothing to replay.
+    EditNode(NodeId),
+    /// Export to a file. Nothing to replay.
+    Export(PathBuf),
+    /// Open a saved graph that the previous entries rebuild. Nothing to
+    /// replay.
+    Open,
+    /// Description and graph right after an event that cannot be replayed
+    /// on its own, such as opening a file or creating a macro.
+    Snapshot(String, macros::SerdeDSTStandAlone<T>),
+}
+
+impl<T> HistoryEntry<T>
+where
+    T: Clone + VariantName,
+{
+    /// Record `event` at time `timestamp`. `dst` is the graph right after
+    /// the event.
+    pub fn new<E>(
+        timestamp: u64,
+        event: &ProvenanceEvent<T, E>,
+        dst: &cake::DST<'static, T, E>,
+    ) -> Self {
+        use self::HistoryEvent as H;
+        use crate::event::ProvenanceEvent as P;
+
+        let snapshot = |description: String| H::Snapshot(description, dst.into());
+        let event = match event {
+            P::Connect(o, i, _) => H::Connect(*o, *i),
+            P::Disconnect(o, i, _) => H::Disconnect(*o, *i),
+            P::AddTransform(t, _) => H::AddTransform(t.name().into_owned()),
+            P::AddOwnedTransform(..) => snapshot("Restore node".to_owned()),
+            P::CreateOutput(_) => H::CreateOutput,
+            P::AddConstant(name, _) => H::AddConstant((*name).to_owned()),
+            P::SetConstant(t_idx, _, val, _) => H::SetConstant(*t_idx, (**val).clone()),
+            P::WriteDefaultInput(t_idx, input_index, _, val, _) => {
+                H::WriteDefaultInput(*t_idx, *input_index, (**val).clone())
+            }
+            P::RemoveNode(node_id, ..) => H::RemoveNode(*node_id),
+            P::Import(Some(path), _, after_dst, _) => {
+                H::Snapshot(format!("Import {}", path.display()), after_dst.into())
+            }
+            P::Import(None, None, after_dst, _) => H::Snapshot("Open".to_owned(), after_dst.into()),
+            P::Import(None, Some(_), after_dst, _) => {
+                H::Snapshot("Restore".to_owned(), after_dst.into())
+            }
+            P::Export(path, _, _) => H::Export(path.clone()),
+            P::AddNewMacro(_) => snapshot("Add new macro".to_owned()),
+            P::AddMacro(handle, _) => snapshot(format!("Add macro '{}'", handle.name())),
+            P::EditNode(node_id) => H::EditNode(*node_id),
+            P::ChangeOutputName(node_id, _, name) => H::ChangeOutputName(*node_id, name.clone()),
+            P::Paste(text, _, _) => H::Paste(text.clone()),
+            // New macros get new identifiers and macros may have changed
+            // since, so keep the resulting graph
+            P::GroupIntoMacro(nodes, _, _) => {
+                snapshot(format!("Group {} nodes into macro", nodes.len()))
+            }
+            P::ExpandMacro(t_idx, _, _) => snapshot(format!("Expand macro #{}", t_idx.id())),
+            P::AddAnnotation(id, annotation) => H::AddAnnotation(*id, annotation.clone()),
+            P::UpdateAnnotation(id, _, annotation, _) => {
+                H::UpdateAnnotation(*id, annotation.clone())
+            }
+            P::RemoveAnnotation(id, _) => H::RemoveAnnotation(*id),
+            P::MoveNodes(moves, _, _) => {
+                H::MoveNodes(moves.iter().map(|(node_id, _)| *node_id).collect())
+            }
+        };
+        Self { timestamp, event }
+    }
+}
+
+impl<T: VariantName> fmt::Display for HistoryEvent<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::HistoryEvent::*;
+        match self {
+            Connect(o, i) => write!(f, "Connect {} to {}", o, i),
+            Disconnect(o, i) => write!(f, "Disconnect {} from {}", o, i),
+            AddTransform(name) => write!(f, "Add node '{}'", name),
+            CreateOutput => write!(f, "Add output node"),
+            AddConstant(name) => write!(f, "Add constant {}", name),
+            SetConstant(t_idx, val) => {
+                write!(f, "Set constant #{} ({})", t_idx.id(), val.variant_name())
+            }
+            WriteDefaultInput(t_idx, input_index, val) => write!(
+                f,
+                "Set input #{} of node #{} ({})",
+                input_index + 1,
+                t_idx.id(),
+                val.variant_name()
+            ),
+            RemoveNode(node_id) => write!(f, "Remove {}", DisplayNode(node_id)),
+            ChangeOutputName(node_id, name) => {
+                write!(f, "Rename {} to '{}'", DisplayNode(node_id), name)
+            }
+            Paste(_) => write!(f, "Paste nodes"),
+            AddAnnotation(id, _) => write!(f, "Add annotation #{}", id.id()),
+            UpdateAnnotation(id, _) => write!(f, "Update annotation #{}", id.id()),
+            RemoveAnnotation(id) => write!(f, "Remove annotation #{}", id.id()),
+            MoveNodes(node_ids) => write!(f, "Move {} nodes", node_ids.len()),
+            EditNode(node_id) => write!(f, "Edit {}", DisplayNode(node_id)),
+            Export(path) => write!(f, "Export to {}", path.display()),
+            Open => write!(f, "Open"),
+            Snapshot(description, _) => write!(f, "{}", description),
+        }
+    }
+}
+
+struct DisplayNode<'a>(&'a NodeId);
+
+impl<'a> fmt::Display for DisplayNode<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            NodeId::Transform(t_idx) => write!(f, "node #{}", t_idx.id()),
+            NodeId::Output(output_id) => write!(f, "output node #{}", output_id.id()),
+        }
+    }
+}
+
+/// Errors when replaying a history.
+#[derive(Debug, Clone)]
+pub enum ReplayError {
+    /// No transform has the given name
+    UnknownTransform(usize, String),
+    /// No constant has the given variant name
+    UnknownConstant(usize, String),
+    /// The graph of a snapshot could not be built
+    Snapshot(usize, ImportError),
+    /// The event at the given index did not have the same result when
+    /// replayed
+    Failed(usize, String),
+    /// The macros of the editor could not be copied to replay the history
+    Macros(ImportError),
+    /// Replaying the history gives a different graph
+    Mismatch,
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::ReplayError::*;
+        match self {
+            UnknownTransform(i, name) => write!(f, "Event #{}: Unknown transform '{}'", i, name),
+            UnknownConstant(i, name) => write!(f, "Event #{}: Unknown constant '{}'", i, name),
+            Snapshot(i, e) => write!(f, "Event #{}: {}", i, e),
+            Failed(i, e) => write!(f, "Event #{} failed when replayed: {}", i, e),
+            Macros(e) => write!(f, "Could not copy macros: {}", e),
+            Mismatch => write!(
+                f,
+                "Replaying the history does not give back the current graph!"
+            ),
+        }
+    }
+}
+
+impl error::Error for ReplayError {
+    fn description(&self) -> &'static str {
+        "ReplayError"
+    }
+}
+
+/// Current time as seconds since the UNIX epoch.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+/// Format `timestamp` in seconds since the UNIX epoch as a UTC date and
+/// time, e.g. "2019-03-14 09:26:53".
+pub fn format_timestamp(timestamp: u64) -> String {
+    const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+    let days = (timestamp / SECONDS_PER_DAY) as i64;
+    let seconds = timestamp % SECONDS_PER_DAY;
+    // Convert days to a date of the proleptic Gregorian calendar, using eras
+    // of 400 years starting on March 1st
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::format_timestamp;
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "1970-01-01 00:00:00");
+        assert_eq!(format_timestamp(1_552_555_613), "2019-03-14 09:26:53");
+        // Leap days, including on years divisible by 400 but not by 100
+        assert_eq!(format_timestamp(951_782_400), "2000-02-29 00:00:00");
+        assert_eq!(format_timestamp(4_107_542_399), "2100-02-28 23:59:59");
+    }
+}
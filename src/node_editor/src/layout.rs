@@ -1,19 +1,27 @@
 use crate::collections::HashSet;
 use std::error::Error;
-use std::{collections::BTreeMap, path::PathBuf};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::PathBuf,
+};
 
 use imgui::{
-    ChildWindow, CollapsingHeader, DrawListMut, ImString, Key, MenuItem, MouseButton, MouseCursor,
-    Selectable, StyleColor, StyleVar, Ui, WindowFocusedFlags,
+    ChannelsSplit, ChildWindow, CollapsingHeader, ColorEdit, DrawListMut, ImString, Key, MenuItem,
+    MouseButton, MouseCursor, ProgressBar, Selectable, StyleColor, StyleVar, Ui,
+    WindowFocusedFlags,
 };
 use serde::{Deserialize, Serialize};
 
 use crate::cake::{self, InputSlot, Transform, TransformIdx, VariantName, DST};
 
+use crate::annotation::{self, Annotation, AnnotationId, AnnotationKind, Annotations};
+use crate::clipboard;
 use crate::constant_editor::ConstantEditor;
+use crate::diff::DiffHighlight;
 use crate::event::RenderEvent;
 use crate::id_stack::GetId;
-use crate::node_state::NodeStates;
+use crate::node_state::{NodeState, NodeStates};
+use crate::palette::{self, CommandPalette};
 use crate::scrolling::Scrolling;
 use crate::vec2::Vec2;
 use imgui_file_explorer::UiFileExplorer;
@@ -31,16 +39,38 @@ pub struct NodeEditorLayout<T: 'static, E: 'static> {
     left_pane_size: Option<f32>,
     show_top_pane: bool,
     show_connection_names: bool,
+    /// Color nodes according to the time spent computing them
+    show_heat: bool,
+    /// Highlight nodes with problems found by `DST::validate`
+    show_diagnostics: bool,
     scrolling: Scrolling,
     show_grid: bool,
     import_opened: bool,
     export_opened: bool,
+    compare_opened: bool,
     pub import_path: Option<std::path::PathBuf>,
     pub export_path: Option<std::path::PathBuf>,
+    /// Saved graph picked to be compared to the edited graph
+    pub compare_path: Option<std::path::PathBuf>,
+    /// Differences with a saved graph, highlighted if any
+    pub diff: Option<DiffHighlight>,
     selected_dir: Option<std::path::PathBuf>,
     filename: String,
     pub is_macro: bool,
     adding_new_node_pos: Option<Vec2>,
+    palette: CommandPalette,
+    annotations: Annotations,
+    /// Annotation being moved or resized
+    annotation_drag: Option<AnnotationDrag>,
+    /// Annotation being edited and its identifier
+    editing_annotation: Option<(AnnotationId, Annotation)>,
+    /// Transform chosen in the command palette while linking, and the output
+    /// to connect to the given input of the node it is added as
+    pending_link: Option<(&'static Transform<'static, T, E>, cake::Output, usize)>,
+    /// Link to the node added from the command palette, made on next render
+    pending_connection: Option<(cake::Output, cake::Input)>,
+    /// Lay out the whole graph on next render
+    auto_layout_requested: bool,
     // Used at runtime to aggregate events
     events: Vec<RenderEvent<T, E>>,
 }
@@ -60,16 +90,28 @@ impl<T, E> Default for NodeEditorLayout<T, E> {
             left_pane_size: None,
             show_top_pane: true,
             show_connection_names: true,
+            show_heat: false,
+            show_diagnostics: true,
             scrolling: Default::default(),
             show_grid: true,
             import_opened: false,
             export_opened: false,
+            compare_opened: false,
             import_path: None,
             export_path: None,
+            compare_path: None,
+            diff: None,
             selected_dir: None,
             filename: String::with_capacity(256),
             is_macro: false,
             adding_new_node_pos: None,
+            palette: CommandPalette::default(),
+            annotations: Annotations::new(),
+            annotation_drag: None,
+            editing_annotation: None,
+            pending_link: None,
+            pending_connection: None,
+            auto_layout_requested: false,
             events: vec![],
         }
     }
@@ -80,9 +122,40 @@ pub enum LinkExtremity {
     Input(InputSlot),
 }
 
+struct AnnotationDrag {
+    id: AnnotationId,
+    /// Annotation before it was dragged
+    before: Annotation,
+    /// Resize the annotation instead of moving it
+    resize: bool,
+    /// Nodes inside the frame, moved along with it
+    nodes: Vec<cake::NodeId>,
+    /// Offset by which the annotation was moved so far
+    offset: Vec2,
+}
+
 const NODE_FRAME_COLOR: [f32; 3] = [0.39, 0.39, 0.39];
+const NODE_ERROR_COLOR: [f32; 3] = [0.90, 0.20, 0.20];
+const NODE_WARNING_COLOR: [f32; 3] = [0.90, 0.65, 0.20];
+const DIFF_ADDED_COLOR: [f32; 3] = [0.30, 0.80, 0.30];
+const DIFF_CHANGED_COLOR: [f32; 3] = [0.30, 0.60, 0.95];
 const NODE_WINDOW_PADDING: Vec2 = Vec2(5.0, 5.0);
 const CURRENT_FONT_WINDOW_SCALE: f32 = 1.0;
+/// Offset of pasted nodes relative to their copied position.
+const PASTE_OFFSET: Vec2 = Vec2(20.0, 20.0);
+const NODE_PROGRESS_BAR_WIDTH: f32 = 120.0 * CURRENT_FONT_WINDOW_SCALE;
+/// Space around the nodes of a comment frame. The top leaves room for the
+/// title.
+const FRAME_MARGIN: Vec2 = Vec2(20.0, 40.0);
+/// Horizontal space between two layers of the automatic layout
+const AUTO_LAYOUT_LAYER_GAP: f32 = 80.0;
+/// Vertical space between two nodes of the same layer
+const AUTO_LAYOUT_NODE_GAP: f32 = 30.0;
+/// Height taken by a link going through a layer
+const AUTO_LAYOUT_LINK_HEIGHT: f32 = 10.0;
+const AUTO_LAYOUT_DEFAULT_NODE_SIZE: Vec2 = Vec2(150.0, 80.0);
+/// Number of passes ordering the nodes of each layer to reduce crossings
+const AUTO_LAYOUT_SWEEPS: usize = 8;
 
 impl<T, E> NodeEditorLayout<T, E>
 where
@@ -102,6 +175,9 @@ where
         &mut self,
         ui: &Ui,
         dst: &DST<'static, T, E>,
+        node_progress: &BTreeMap<TransformIdx, cake::Progress>,
+        node_heat: &BTreeMap<TransformIdx, f32>,
+        diagnostics: &[cake::Diagnostic],
         addable_nodes: &[&'static Transform<T, E>],
         addable_macros: &cake::macros::MacroManager<'static, T, E>,
         constant_editor: &ED,
@@ -112,6 +188,11 @@ where
     {
         self.events = vec![];
 
+        // Link the node added from the command palette
+        if let Some((output, input)) = self.pending_connection.take() {
+            self.events
+                .push(RenderEvent::Connect(output, InputSlot::Transform(input)));
+        }
         for idx in dst.node_ids() {
             // Initialization of node states
             let win_pos: Vec2 = ui.cursor_screen_pos().into();
@@ -134,12 +215,20 @@ where
         self.render_graph_node(
             ui,
             dst,
+            node_progress,
+            node_heat,
+            diagnostics,
             addable_nodes,
             addable_macros,
             constant_editor,
             &mut scroll_target_node_pos_size,
             attaching,
         );
+        // Node sizes are known once the nodes were drawn
+        if self.auto_layout_requested {
+            self.auto_layout_requested = false;
+            self.auto_layout(dst, false);
+        }
 
         if ui.is_window_focused_with_flags(WindowFocusedFlags::CHILD_WINDOWS)
             && !ui.io().want_capture_keyboard
@@ -271,6 +360,9 @@ where
         &mut self,
         ui: &Ui,
         dst: &DST<'static, T, E>,
+        node_progress: &BTreeMap<TransformIdx, cake::Progress>,
+        node_heat: &BTreeMap<TransformIdx, f32>,
+        diagnostics: &[cake::Diagnostic],
         addable_nodes: &[&'static Transform<T, E>],
         addable_macros: &cake::macros::MacroManager<'static, T, E>,
         constant_editor: &ED,
@@ -292,7 +384,41 @@ where
                     &mut self.show_connection_names,
                 );
                 ui.same_line_with_spacing(0.0, 15.0);
+                ui.checkbox(format!("Show computation time."), &mut self.show_heat);
+                if ui.is_item_hovered() {
+                    ui.tooltip(|| {
+                        ui.text(format!(
+                            "Color nodes in red according to the time spent computing them."
+                        ));
+                    });
+                }
+                ui.same_line_with_spacing(0.0, 15.0);
+                ui.checkbox(format!("Show problems."), &mut self.show_diagnostics);
+                if ui.is_item_hovered() {
+                    ui.tooltip(|| {
+                        ui.text(format!(
+                            "Highlight nodes with errors (red) or warnings (orange)."
+                        ));
+                        ui.text(format!("Hover a node to see its problems."));
+                    });
+                }
+                ui.same_line_with_spacing(0.0, 15.0);
                 ui.text(format!("Scroll with Ctrl+LMB or Alt+LMB."));
+                if !self.is_macro {
+                    ui.same_line_with_pos(ui.window_size()[0] - 310.0);
+                    if ui.button(format!("Compare")) {
+                        self.compare_opened = true;
+                    }
+                    if ui.is_item_hovered() {
+                        ui.tooltip(|| {
+                            ui.text(format!(
+                                "Highlight differences with a *.ron file: \
+                                 added nodes in green, changed nodes and links in blue."
+                            ));
+                        });
+                    }
+                    self.render_compare_window(ui);
+                }
                 ui.same_line_with_pos(ui.window_size()[0] - 240.0);
                 if ui.button(format!("Import")) {
                     if !self.is_macro {
@@ -445,6 +571,13 @@ where
                 ui.text(format!(
                     "Press Delete or Backspace key to remove selected nodes."
                 ));
+                ui.text(format!(
+                    "Press Space to search for a node to add, also while dragging a link."
+                ));
+                ui.text(format!(
+                    "Double-click on the title of a comment frame or on a note to edit it."
+                ));
+                self.render_diff_summary(ui);
 
                 style_stack_itemspacing.pop();
                 style_stack_iteminnerspacing.pop();
@@ -460,6 +593,23 @@ where
                     self.events.push(RenderEvent::Undo);
                 } else if ui.io().key_ctrl && ui.is_key_pressed(imgui::Key::Y) {
                     self.events.push(RenderEvent::Redo);
+                } else if ui.io().key_ctrl && ui.is_key_pressed(imgui::Key::C) {
+                    self.copy_selected_nodes(ui, dst);
+                } else if ui.io().key_ctrl && ui.is_key_pressed(imgui::Key::X) {
+                    self.cut_selected_nodes(ui, dst);
+                } else if ui.io().key_ctrl && ui.is_key_pressed(imgui::Key::V) {
+                    if let Some(text) = ui.clipboard_text() {
+                        self.events.push(RenderEvent::Paste(text));
+                    }
+                } else if ui.is_key_pressed(imgui::Key::Space) {
+                    let from_output = match self.creating_link {
+                        Some(LinkExtremity::Output(output)) => dst
+                            .get_transform(output.t_idx)
+                            .map(|t| (output, t.nth_output_type(output.index()))),
+                        _ => None,
+                    };
+                    self.creating_link = None;
+                    self.palette.open(from_output);
                 }
             }
             ChildWindow::new("scrolling_region")
@@ -479,6 +629,9 @@ where
                     self.render_graph_canvas(
                         ui,
                         dst,
+                        node_progress,
+                        node_heat,
+                        diagnostics,
                         addable_nodes,
                         addable_macros,
                         constant_editor,
@@ -494,6 +647,9 @@ where
         &mut self,
         ui: &Ui,
         dst: &DST<'static, T, E>,
+        node_progress: &BTreeMap<TransformIdx, cake::Progress>,
+        node_heat: &BTreeMap<TransformIdx, f32>,
+        diagnostics: &[cake::Diagnostic],
         addable_nodes: &[&'static Transform<T, E>],
         addable_macros: &cake::macros::MacroManager<'static, T, E>,
         constant_editor: &ED,
@@ -557,6 +713,11 @@ where
                         ui.open_popup(format!("add-new-node"));
                     }
                 }
+                if self.palette.open_requested {
+                    self.palette.open_requested = false;
+                    self.adding_new_node_pos = Some(Vec2::from(ui.io().mouse_pos) - win_pos);
+                    ui.open_popup(format!("command-palette"));
+                }
                 // Scroll
                 if self.drag_node.is_none()
                     && self.creating_link.is_none()
@@ -577,6 +738,8 @@ where
             let link_line_width = LINK_LINE_WIDTH * CURRENT_FONT_WINDOW_SCALE;
             // NODE LINK CULLING?
 
+            self.render_annotations(ui, &draw_list, channels, offset);
+
             for idx in dst.node_ids() {
                 let node_pos = self
                     .node_states
@@ -591,9 +754,17 @@ where
                     .node_states
                     .get_state(&idx, |state| node_rect_min + state.size);
                 ui.set_cursor_screen_pos((node_rect_min + NODE_WINDOW_PADDING).into());
-                self.draw_node_inside(ui, dst, &draw_list, &idx, constant_editor);
+                self.draw_node_inside(ui, dst, node_progress, &draw_list, &idx, constant_editor);
 
                 let node = dst.get_node(&idx).unwrap();
+                let node_diagnostics: Vec<_> = if self.show_diagnostics {
+                    diagnostics
+                        .iter()
+                        .filter(|diagnostic| diagnostic.nodes().contains(&idx))
+                        .collect()
+                } else {
+                    vec![]
+                };
                 let node_states = &mut self.node_states;
                 let item_rect_size = Vec2::new(ui.item_rect_size());
                 node_states.set_state(&idx, |state| {
@@ -606,11 +777,28 @@ where
                     format!("node##nodeinvbtn"),
                     node_states.get_state(&idx, |state| state.size.into()),
                 );
+                if ui.is_item_hovered() && !node_diagnostics.is_empty() {
+                    ui.tooltip(|| {
+                        for diagnostic in &node_diagnostics {
+                            ui.text(format!("{}", diagnostic));
+                        }
+                    });
+                }
                 // TODO: Handle selection
 
                 const NODE_ROUNDING: f32 = 4.0;
                 const NODE_COLOR: [f32; 3] = [0.24, 0.24, 0.24];
-                let node_bg_color = NODE_COLOR;
+                const NODE_HOT_COLOR: [f32; 3] = [0.70, 0.12, 0.08];
+                let heat = match idx {
+                    cake::NodeId::Transform(t_idx) if self.show_heat => {
+                        node_heat.get(&t_idx).cloned().unwrap_or(0.0)
+                    }
+                    _ => 0.0,
+                };
+                let mut node_bg_color = NODE_COLOR;
+                for (c, hot) in node_bg_color.iter_mut().zip(&NODE_HOT_COLOR) {
+                    *c += (hot - *c) * heat;
+                }
                 draw_list
                     .add_rect(node_rect_min.into(), node_rect_max.into(), node_bg_color)
                     .rounding(NODE_ROUNDING)
@@ -672,13 +860,35 @@ where
                     };
                 });
                 // Display frame
+                let severity = node_diagnostics
+                    .iter()
+                    .map(|diagnostic| diagnostic.severity())
+                    .max();
+                // Differences with a saved graph take precedence over problems
+                let diff_color = self.diff.as_ref().and_then(|diff| {
+                    if diff.added.contains(&idx) {
+                        Some(DIFF_ADDED_COLOR)
+                    } else if diff.changed.contains(&idx) {
+                        Some(DIFF_CHANGED_COLOR)
+                    } else {
+                        None
+                    }
+                });
                 let line_thickness = if node_states.get_state(&idx, |s| s.selected) {
                     3.0
+                } else if severity.is_some() || diff_color.is_some() {
+                    2.0
                 } else {
                     1.0
                 } * CURRENT_FONT_WINDOW_SCALE;
+                let frame_color = match (diff_color, severity) {
+                    (Some(color), _) => color,
+                    (None, Some(cake::Severity::Error)) => NODE_ERROR_COLOR,
+                    (None, Some(cake::Severity::Warning)) => NODE_WARNING_COLOR,
+                    (None, None) => NODE_FRAME_COLOR,
+                };
                 draw_list
-                    .add_rect(node_rect_min.into(), node_rect_max.into(), NODE_FRAME_COLOR)
+                    .add_rect(node_rect_min.into(), node_rect_max.into(), frame_color)
                     .thickness(line_thickness)
                     .rounding(NODE_ROUNDING)
                     .build();
@@ -686,6 +896,8 @@ where
                 // Display connectors
                 const CONNECTOR_BORDER_THICKNESS: f32 = NODE_SLOT_RADIUS * 0.25;
                 const INPUT_SLOT_COLOR: [f32; 4] = [0.59, 0.59, 0.59, 0.59];
+                const INPUT_SLOT_ERROR_COLOR: [f32; 4] = [0.90, 0.20, 0.20, 0.90];
+                const INPUT_SLOT_WARNING_COLOR: [f32; 4] = [0.90, 0.65, 0.20, 0.90];
                 for (slot_idx, slot_name) in node.input_slot_names_iter().into_iter().enumerate() {
                     let connector_pos = Vec2::new(node_states.get_state(&idx, |state| {
                         state.get_input_slot_pos(
@@ -695,12 +907,24 @@ where
                         )
                     }));
                     let connector_screen_pos = offset + connector_pos;
+                    let slot = match idx {
+                        cake::NodeId::Transform(t_idx) => {
+                            InputSlot::Transform(cake::Input::new(t_idx, slot_idx))
+                        }
+                        cake::NodeId::Output(output_id) => InputSlot::Output(output_id),
+                    };
+                    let slot_severity = node_diagnostics
+                        .iter()
+                        .filter(|diagnostic| diagnostic.input_slot() == Some(slot))
+                        .map(|diagnostic| diagnostic.severity())
+                        .max();
+                    let slot_color = match slot_severity {
+                        Some(cake::Severity::Error) => INPUT_SLOT_ERROR_COLOR,
+                        Some(cake::Severity::Warning) => INPUT_SLOT_WARNING_COLOR,
+                        None => INPUT_SLOT_COLOR,
+                    };
                     draw_list
-                        .add_circle(
-                            connector_screen_pos.into(),
-                            NODE_SLOT_RADIUS,
-                            INPUT_SLOT_COLOR,
-                        )
+                        .add_circle(connector_screen_pos.into(), NODE_SLOT_RADIUS, slot_color)
                         .thickness(CONNECTOR_BORDER_THICKNESS)
                         .filled(true)
                         .build();
@@ -719,12 +943,7 @@ where
                             <= NODE_CLICK_BOX_RADIUS_SQUARED
                         {
                             self.drag_node = None;
-                            self.creating_link = Some(LinkExtremity::Input(match idx {
-                                cake::NodeId::Transform(t_idx) => {
-                                    InputSlot::Transform(cake::Input::new(t_idx, slot_idx))
-                                }
-                                cake::NodeId::Output(output_id) => InputSlot::Output(output_id),
-                            }));
+                            self.creating_link = Some(LinkExtremity::Input(slot));
                         }
                     }
                     if let Some(LinkExtremity::Output(link_output)) = self.creating_link {
@@ -733,15 +952,7 @@ where
                         if (mouse_pos - connector_screen_pos).squared_norm()
                             <= NODE_CLICK_BOX_RADIUS_SQUARED
                         {
-                            self.new_link = Some((
-                                link_output,
-                                match idx {
-                                    cake::NodeId::Transform(t_idx) => {
-                                        InputSlot::Transform(cake::Input::new(t_idx, slot_idx))
-                                    }
-                                    cake::NodeId::Output(output_id) => InputSlot::Output(output_id),
-                                },
-                            ));
+                            self.new_link = Some((link_output, slot));
                             self.creating_link = None;
                         }
                     }
@@ -938,6 +1149,10 @@ where
                 let cp2 = p2 + link_cp;
                 const LINK_COLOR: [f32; 3] = [0.78, 0.78, 0.39];
                 const DELETE_LINK_COLOR: [f32; 3] = [1.0, 0.0, 0.0];
+                let link_color = match &self.diff {
+                    Some(diff) if diff.links.contains(&input_slot) => DIFF_CHANGED_COLOR,
+                    _ => LINK_COLOR,
+                };
                 let (p1, cp1, cp2, p2) = (p1.into(), cp1.into(), cp2.into(), p2.into());
                 if is_deleting_link {
                     draw_list
@@ -949,7 +1164,7 @@ where
                     }
                 } else {
                     draw_list
-                        .add_bezier_curve(p1, cp1, cp2, p2, LINK_COLOR)
+                        .add_bezier_curve(p1, cp1, cp2, p2, link_color)
                         .thickness(link_line_width)
                         .build();
                 }
@@ -1053,6 +1268,81 @@ where
             ui.text("Add node");
             color_stack.pop();
             ui.separator();
+            let has_selection = !self.selected_node_ids().is_empty();
+            if MenuItem::new(format!("Copy"))
+                .shortcut(format!("Ctrl+C"))
+                .enabled(has_selection)
+                .build(ui)
+            {
+                self.copy_selected_nodes(ui, dst);
+            }
+            if MenuItem::new(format!("Cut"))
+                .shortcut(format!("Ctrl+X"))
+                .enabled(has_selection)
+                .build(ui)
+            {
+                self.cut_selected_nodes(ui, dst);
+            }
+            if MenuItem::new(format!("Paste"))
+                .shortcut(format!("Ctrl+V"))
+                .build(ui)
+            {
+                if let Some(text) = ui.clipboard_text() {
+                    self.events.push(RenderEvent::Paste(text));
+                }
+            }
+            if MenuItem::new(format!("Duplicate"))
+                .enabled(has_selection)
+                .build(ui)
+            {
+                match clipboard::copy(dst, &self.node_states, &self.selected_node_ids()) {
+                    Ok(text) => self.events.push(RenderEvent::Paste(text)),
+                    Err(e) => eprintln!("Could not duplicate nodes! {}", e),
+                }
+            }
+            let selected_transforms: Vec<_> = self
+                .selected_node_ids()
+                .into_iter()
+                .filter_map(|node_id| match node_id {
+                    cake::NodeId::Transform(t_idx) => Some(t_idx),
+                    cake::NodeId::Output(_) => None,
+                })
+                .collect();
+            let selected_macros: Vec<_> = selected_transforms
+                .iter()
+                .cloned()
+                .filter(
+                    |t_idx| match dst.get_transform(*t_idx).map(Transform::algorithm) {
+                        Some(cake::Algorithm::Macro { .. }) => true,
+                        _ => false,
+                    },
+                )
+                .collect();
+            if MenuItem::new(format!("Group into macro"))
+                .enabled(!selected_transforms.is_empty())
+                .build(ui)
+            {
+                self.events
+                    .push(RenderEvent::GroupIntoMacro(selected_transforms));
+            }
+            if MenuItem::new(format!("Expand macro"))
+                .enabled(!selected_macros.is_empty())
+                .build(ui)
+            {
+                for t_idx in selected_macros {
+                    self.events.push(RenderEvent::ExpandMacro(t_idx));
+                }
+            }
+            if MenuItem::new(format!("Auto-layout graph")).build(ui) {
+                self.push_auto_layout(dst, false);
+            }
+            if MenuItem::new(format!("Auto-layout selection"))
+                .enabled(has_selection)
+                .build(ui)
+            {
+                self.push_auto_layout(dst, true);
+            }
+            ui.separator();
             let mut addable_nodes_class = BTreeMap::<String, Vec<&&Transform<T, E>>>::new();
             for node in addable_nodes.iter() {
                 let kind = node.kind().into_owned();
@@ -1100,6 +1390,22 @@ where
                 self.events.push(RenderEvent::CreateOutput);
             }
             ui.separator();
+            let clicked_pos =
+                self.adding_new_node_pos.unwrap_or_default() + self.scrolling.get_current();
+            if MenuItem::new(format!("Comment frame")).build(ui) {
+                let annotation = self.frame_around_selection(clicked_pos);
+                self.events.push(RenderEvent::AddAnnotation(
+                    self.annotations.new_id(),
+                    annotation,
+                ));
+            }
+            if MenuItem::new(format!("Note")).build(ui) {
+                self.events.push(RenderEvent::AddAnnotation(
+                    self.annotations.new_id(),
+                    Annotation::note(clicked_pos),
+                ));
+            }
+            ui.separator();
             for constant_type in T::editable_variants() {
                 let item_name = ImString::new(format!("Input node: {}", constant_type));
                 if MenuItem::new(&item_name).build(ui) {
@@ -1107,12 +1413,432 @@ where
                 }
             }
         });
+        self.render_command_palette(ui, addable_nodes);
+    }
+
+    /// Draw comment frames and notes in the background of the canvas, and
+    /// handle moving, resizing and editing them.
+    fn render_annotations(
+        &mut self,
+        ui: &Ui,
+        draw_list: &DrawListMut,
+        channels: &ChannelsSplit,
+        offset: Vec2,
+    ) {
+        const FRAME_FILL_ALPHA: f32 = 0.25;
+        const NOTE_FILL_ALPHA: f32 = 0.85;
+        const TITLE_BAR_PADDING: f32 = 4.0;
+        const RESIZE_GRIP_SIZE: f32 = 12.0;
+        const HEADING_COLOR: [f32; 4] = [1.0, 0.9, 0.6, 1.0];
+
+        channels.set_current(0);
+        let title_bar_height = ui.text_line_height() + 2.0 * TITLE_BAR_PADDING;
+        for (id, annotation) in self.annotations.iter() {
+            let id_stack = ui.push_id(id.id() as i32);
+            let rect_min = offset + annotation.pos;
+            let rect_max = rect_min + annotation.size;
+            let [r, g, b] = annotation.color;
+
+            let hovered = match annotation.kind {
+                AnnotationKind::Frame => {
+                    draw_list
+                        .add_rect(
+                            rect_min.into(),
+                            rect_max.into(),
+                            [r, g, b, FRAME_FILL_ALPHA],
+                        )
+                        .filled(true)
+                        .build();
+                    let title_max = Vec2(rect_max.0, rect_min.1 + title_bar_height);
+                    draw_list
+                        .add_rect(rect_min.into(), title_max.into(), [r, g, b, 1.0])
+                        .filled(true)
+                        .build();
+                    draw_list
+                        .add_rect(rect_min.into(), rect_max.into(), [r, g, b, 1.0])
+                        .build();
+                    draw_list.with_clip_rect_intersect(rect_min.into(), title_max.into(), || {
+                        ui.set_cursor_screen_pos(
+                            (rect_min + Vec2(TITLE_BAR_PADDING, TITLE_BAR_PADDING)).into(),
+                        );
+                        ui.text(&annotation.text);
+                    });
+                    ui.set_cursor_screen_pos(rect_min.into());
+                    ui.invisible_button(
+                        format!("annotation-title"),
+                        [annotation.size.0.max(1.0), title_bar_height],
+                    );
+                    ui.is_item_hovered()
+                }
+                AnnotationKind::Note => {
+                    draw_list
+                        .add_rect(rect_min.into(), rect_max.into(), [r, g, b, NOTE_FILL_ALPHA])
+                        .filled(true)
+                        .rounding(4.0)
+                        .build();
+                    draw_list.with_clip_rect_intersect(rect_min.into(), rect_max.into(), || {
+                        ui.set_cursor_screen_pos(
+                            (rect_min + Vec2(TITLE_BAR_PADDING, TITLE_BAR_PADDING)).into(),
+                        );
+                        ui.group(|| {
+                            for line in annotation::parse_note(&annotation.text) {
+                                match line {
+                                    annotation::NoteLine::Heading(text) => {
+                                        ui.text_colored(HEADING_COLOR, text)
+                                    }
+                                    annotation::NoteLine::Bullet(text) => ui.bullet_text(text),
+                                    annotation::NoteLine::Emphasis(text) => ui.text_disabled(text),
+                                    annotation::NoteLine::Text(text) => ui.text(text),
+                                }
+                            }
+                        });
+                    });
+                    ui.set_cursor_screen_pos(rect_min.into());
+                    ui.invisible_button(
+                        format!("annotation-note"),
+                        [annotation.size.0.max(1.0), annotation.size.1.max(1.0)],
+                    );
+                    ui.is_item_hovered()
+                }
+            };
+            let double_clicked = hovered && ui.is_mouse_double_clicked(MouseButton::Left);
+
+            // Resize grip in the bottom-right corner
+            let grip_min = rect_max - Vec2(RESIZE_GRIP_SIZE, RESIZE_GRIP_SIZE);
+            draw_list
+                .add_triangle(
+                    [rect_max.0, grip_min.1],
+                    rect_max.into(),
+                    [grip_min.0, rect_max.1],
+                    [r, g, b, 1.0],
+                )
+                .filled(true)
+                .build();
+            ui.set_cursor_screen_pos(grip_min.into());
+            ui.invisible_button(
+                format!("annotation-resize"),
+                [RESIZE_GRIP_SIZE, RESIZE_GRIP_SIZE],
+            );
+            let grip_hovered = ui.is_item_hovered();
+            if grip_hovered {
+                ui.set_mouse_cursor(Some(MouseCursor::ResizeNWSE));
+            }
+
+            if self.annotation_drag.is_none()
+                && self.drag_node.is_none()
+                && self.creating_link.is_none()
+                && ui.is_mouse_clicked(MouseButton::Left)
+                && (hovered || grip_hovered)
+            {
+                let nodes = if annotation.kind == AnnotationKind::Frame && !grip_hovered {
+                    self.node_states
+                        .iter()
+                        .filter(|(_, state)| annotation.contains(state.pos, state.size))
+                        .map(|(node_id, _)| *node_id)
+                        .collect()
+                } else {
+                    vec![]
+                };
+                self.annotation_drag = Some(AnnotationDrag {
+                    id: *id,
+                    before: annotation.clone(),
+                    resize: grip_hovered,
+                    nodes,
+                    offset: Vec2(0.0, 0.0),
+                });
+            }
+            if double_clicked && !grip_hovered {
+                self.editing_annotation = Some((*id, annotation.clone()));
+                ui.open_popup(format!("edit-annotation"));
+            }
+            id_stack.pop();
+        }
+
+        self.update_annotation_drag(ui);
+        self.render_edit_annotation_popup(ui);
+    }
+
+    /// Move or resize the annotation being dragged. Record the change once
+    /// the mouse is released.
+    fn update_annotation_drag(&mut self, ui: &Ui) {
+        const MIN_ANNOTATION_SIZE: Vec2 = Vec2(60.0, 30.0);
+
+        let drag = if let Some(drag) = &mut self.annotation_drag {
+            drag
+        } else {
+            return;
+        };
+        if ui.is_mouse_dragging(MouseButton::Left) {
+            let delta: Vec2 = ui.io().mouse_delta.into();
+            if let Some(annotation) = self.annotations.get_mut(&drag.id) {
+                if drag.resize {
+                    let size = annotation.size + delta;
+                    annotation.size = Vec2(
+                        size.0.max(MIN_ANNOTATION_SIZE.0),
+                        size.1.max(MIN_ANNOTATION_SIZE.1),
+                    );
+                } else {
+                    annotation.pos = annotation.pos + delta;
+                    drag.offset = drag.offset + delta;
+                }
+            }
+            for node_id in &drag.nodes {
+                if self.node_states.get(node_id).is_some() {
+                    self.node_states.set_state(node_id, |state| {
+                        state.pos = state.pos + delta;
+                    });
+                }
+            }
+        } else if !ui.is_mouse_down(MouseButton::Left) {
+            let drag = self.annotation_drag.take().unwrap();
+            // Put the annotation and its nodes back as they were, so that
+            // the change is recorded by the update event and can be undone.
+            let back: Vec<_> = drag
+                .nodes
+                .iter()
+                .map(|node_id| (*node_id, drag.offset * -1.0))
+                .collect();
+            self.move_nodes(&back);
+            let moves = drag
+                .nodes
+                .iter()
+                .map(|node_id| (*node_id, drag.offset))
+                .collect();
+            if let Some(after) = self.annotations.insert(drag.id, drag.before.clone()) {
+                if after != drag.before {
+                    self.events
+                        .push(RenderEvent::UpdateAnnotation(drag.id, after, moves));
+                }
+            }
+        }
+    }
+
+    /// Draw the file explorer used to pick a saved graph to compare to.
+    fn render_compare_window(&mut self, ui: &Ui) {
+        if !self.compare_opened {
+            return;
+        }
+        let mut selected_path = None;
+        let mut cancelled = false;
+        let mouse_pos = ui.io().mouse_pos;
+        imgui::Window::new(&ImString::new(format!("Compare with .ron")))
+            .opened(&mut self.compare_opened)
+            .save_settings(false)
+            .position(mouse_pos, imgui::Condition::Appearing)
+            .size([400.0, 410.0], imgui::Condition::Appearing)
+            .build(ui, || {
+                ChildWindow::new("compare_fileexplorer")
+                    .size([0.0, 350.0])
+                    .horizontal_scrollbar(true)
+                    .build(ui, || {
+                        if let Ok((Some(path), _)) =
+                            ui.file_explorer(imgui_file_explorer::CURRENT_FOLDER, &["ron"])
+                        {
+                            selected_path = Some(path);
+                        }
+                    });
+                if ui.button(format!("Cancel")) {
+                    cancelled = true;
+                }
+            });
+        if cancelled {
+            self.compare_opened = false;
+        } else if let Some(path) = selected_path {
+            self.compare_path = Some(path);
+            self.compare_opened = false;
+        }
+    }
+
+    /// Summarize the differences with the compared graph, if any.
+    fn render_diff_summary(&mut self, ui: &Ui) {
+        const DIFF_TEXT_COLOR: [f32; 4] = [0.30, 0.60, 0.95, 1.0];
+        let mut stop = false;
+        if let Some(diff) = &self.diff {
+            let file_name = diff
+                .path
+                .file_name()
+                .map(|name| name.to_string_lossy())
+                .unwrap_or_default();
+            if diff.lines.is_empty() {
+                ui.text_colored(
+                    DIFF_TEXT_COLOR,
+                    format!("No difference with {}.", file_name),
+                );
+            } else {
+                ui.text_colored(
+                    DIFF_TEXT_COLOR,
+                    format!(
+                        "Compared with {}: {} added, {} changed and {} removed nodes, \
+                         {} changed links.",
+                        file_name,
+                        diff.added.len(),
+                        diff.changed.len(),
+                        diff.removed,
+                        diff.links.len()
+                    ),
+                );
+                if ui.is_item_hovered() {
+                    ui.tooltip(|| {
+                        for line in &diff.lines {
+                            ui.text(line);
+                        }
+                    });
+                }
+            }
+            ui.same_line_with_spacing(0.0, 15.0);
+            if ui.small_button(format!("Stop comparing")) {
+                stop = true;
+            }
+        }
+        if stop {
+            self.diff = None;
+        }
+    }
+
+    fn render_edit_annotation_popup(&mut self, ui: &Ui) {
+        let events = &mut self.events;
+        let editing_annotation = &mut self.editing_annotation;
+        ui.popup(format!("edit-annotation"), || {
+            if let Some((id, annotation)) = editing_annotation {
+                const HEADER_COLOR: [f32; 4] = [0.7, 0.7, 0.7, 1.0];
+                let color_stack = ui.push_style_color(StyleColor::Text, HEADER_COLOR);
+                match annotation.kind {
+                    AnnotationKind::Frame => ui.text("Edit comment frame"),
+                    AnnotationKind::Note => ui.text("Edit note"),
+                }
+                color_stack.pop();
+                ui.separator();
+                match annotation.kind {
+                    AnnotationKind::Frame => {
+                        ui.input_text(format!("Title"), &mut annotation.text)
+                            .build();
+                    }
+                    AnnotationKind::Note => {
+                        ui.input_text_multiline(
+                            format!("##note-text"),
+                            &mut annotation.text,
+                            [320.0, 160.0],
+                        )
+                        .build();
+                        ui.text_disabled(
+                            "'# ' for headings, '- ' for bullets, _text_ for emphasis",
+                        );
+                    }
+                }
+                ColorEdit::new(format!("Color"), &mut annotation.color).build(ui);
+                if ui.button(format!("Apply")) {
+                    events.push(RenderEvent::UpdateAnnotation(
+                        *id,
+                        annotation.clone(),
+                        vec![],
+                    ));
+                    ui.close_current_popup();
+                }
+                ui.same_line();
+                if ui.button(format!("Delete")) {
+                    events.push(RenderEvent::RemoveAnnotation(*id));
+                    ui.close_current_popup();
+                }
+            }
+        });
+    }
+
+    /// Comment frame around the selected nodes, or at `pos` if no node is
+    /// selected.
+    fn frame_around_selection(&self, pos: Vec2) -> Annotation {
+        const DEFAULT_FRAME_SIZE: Vec2 = Vec2(300.0, 200.0);
+
+        let selected = self.node_states.iter().filter(|(_, state)| state.selected);
+        let mut frame = Annotation::frame(pos, DEFAULT_FRAME_SIZE);
+        if let Some((min, max)) = bounding_box(selected.map(|(_, state)| state)) {
+            fit_frame(&mut frame, min, max);
+        }
+        frame
+    }
+
+    /// Popup to search for a node to add by name, description or type.
+    fn render_command_palette(&mut self, ui: &Ui, addable_nodes: &[&'static Transform<T, E>]) {
+        ui.popup(format!("command-palette"), || {
+            const HEADER_COLOR: [f32; 4] = [0.7, 0.7, 0.7, 1.0];
+            let color_stack = ui.push_style_color(StyleColor::Text, HEADER_COLOR);
+            if let Some((_, output_type)) = self.palette.from_output {
+                ui.text(format!("Add node taking {}", output_type.name()));
+            } else {
+                ui.text("Add node");
+            }
+            color_stack.pop();
+            ui.separator();
+
+            if ui.is_window_appearing() {
+                ui.set_keyboard_focus_here();
+            }
+            if ui
+                .input_text(format!("##palette-query"), &mut self.palette.query)
+                .build()
+            {
+                self.palette.selected = 0;
+            }
+            let results = self.palette.results(addable_nodes);
+            if ui.is_key_pressed(Key::DownArrow) && self.palette.selected + 1 < results.len() {
+                self.palette.selected += 1;
+            }
+            if ui.is_key_pressed(Key::UpArrow) {
+                self.palette.selected = self.palette.selected.saturating_sub(1);
+            }
+
+            let mut chosen = None;
+            if ui.is_key_pressed(Key::Enter) || ui.is_key_pressed(Key::KeyPadEnter) {
+                chosen = results.get(self.palette.selected).cloned();
+            }
+            for (i, t) in results.iter().enumerate() {
+                let id_stack = ui.push_id(i as i32);
+                let selected = i == self.palette.selected;
+                if Selectable::new(&ImString::new(t.name()))
+                    .selected(selected)
+                    .build(ui)
+                {
+                    chosen = Some(*t);
+                }
+                if ui.is_item_hovered() {
+                    ui.tooltip(|| ui.text(t.description()));
+                }
+                id_stack.pop();
+            }
+            if results.is_empty() {
+                ui.text("No matching node");
+            }
+
+            if let Some(t) = chosen {
+                self.events.push(RenderEvent::AddTransform(t));
+                if let Some((output, output_type)) = self.palette.from_output {
+                    self.pending_link = palette::accepting_input(t, output_type)
+                        .map(|input_index| (t, output, input_index));
+                }
+                ui.close_current_popup();
+            } else if ui.is_key_pressed(Key::Escape) {
+                ui.close_current_popup();
+            }
+        });
+    }
+
+    /// Put the selected nodes on the clipboard.
+    fn copy_selected_nodes(&self, ui: &Ui, dst: &DST<'static, T, E>) {
+        match clipboard::copy(dst, &self.node_states, &self.selected_node_ids()) {
+            Ok(text) => ui.set_clipboard_text(text),
+            Err(e) => eprintln!("Could not copy nodes! {}", e),
+        }
+    }
+
+    fn cut_selected_nodes(&mut self, ui: &Ui, dst: &DST<'static, T, E>) {
+        self.copy_selected_nodes(ui, dst);
+        self.delete_selected_nodes();
     }
 
     fn draw_node_inside<ED>(
         &mut self,
         ui: &Ui,
         dst: &DST<'static, T, E>,
+        node_progress: &BTreeMap<TransformIdx, cake::Progress>,
         draw_list: &DrawListMut,
         id: &cake::NodeId,
         constant_editor: &ED,
@@ -1223,6 +1949,14 @@ where
                         }
                     }
                 }
+                // Show progress below the inputs, so that they stay aligned
+                // with their slots
+                if let Some(progress) = node_progress.get(&t_idx) {
+                    ProgressBar::new(progress.fraction)
+                        .overlay_text(&progress.message)
+                        .size([NODE_PROGRESS_BAR_WIDTH, 0.0])
+                        .build(ui);
+                }
             }
             // TODO: Add copy-paste buttons
         });
@@ -1281,14 +2015,16 @@ impl<T, E> NodeEditorLayout<T, E>
 where
     T: VariantName,
 {
-    fn delete_selected_nodes(&mut self) {
-        let selected_node_ids: Vec<_> = self
-            .node_states
+    fn selected_node_ids(&self) -> Vec<cake::NodeId> {
+        self.node_states
             .iter()
             .filter(|(_, state)| state.selected)
             .map(|(id, _)| *id)
-            .collect();
-        for node_id in selected_node_ids {
+            .collect()
+    }
+
+    fn delete_selected_nodes(&mut self) {
+        for node_id in self.selected_node_ids() {
             self.events.push(RenderEvent::RemoveNode(node_id));
             self.node_states.remove_node(&node_id);
             if self.active_node == Some(node_id) {
@@ -1303,6 +2039,64 @@ impl<T, E> NodeEditorLayout<T, E> {
         &self.scrolling
     }
 
+    /// Insert the states of pasted nodes, slightly offset and selected.
+    /// `new_ids` maps the ids of the copied nodes to the ids of the pasted
+    /// nodes. Return the ids of the pasted nodes.
+    pub fn paste_node_states(
+        &mut self,
+        node_states: Vec<(cake::NodeId, NodeState)>,
+        new_ids: &BTreeMap<cake::NodeId, cake::NodeId>,
+    ) -> Vec<cake::NodeId> {
+        self.node_states.deselect_all();
+        for (node_id, mut state) in node_states {
+            if let Some(new_id) = new_ids.get(&node_id) {
+                state.pos = state.pos + PASTE_OFFSET;
+                state.selected = true;
+                self.node_states.insert(*new_id, state);
+            }
+        }
+        new_ids.values().cloned().collect()
+    }
+
+    /// Put the macro node `t_idx` replacing the grouped `nodes` at their mean
+    /// position, and select it. The states of the grouped nodes are kept so
+    /// that they are found back on undo.
+    pub fn group_node_states(&mut self, nodes: &[TransformIdx], t_idx: TransformIdx) {
+        let positions: Vec<_> = nodes
+            .iter()
+            .filter_map(|idx| self.node_states.get(&cake::NodeId::Transform(*idx)))
+            .map(|state| state.pos)
+            .collect();
+        let mut state = NodeState::default();
+        if !positions.is_empty() {
+            let sum = positions
+                .iter()
+                .fold(Vec2::default(), |sum, pos| sum + *pos);
+            state.pos = sum * (1.0 / positions.len() as f32);
+        }
+        state.selected = true;
+        self.node_states.deselect_all();
+        self.node_states
+            .insert(cake::NodeId::Transform(t_idx), state);
+    }
+
+    /// Put the nodes inserted when expanding the macro node `t_idx` around
+    /// its position.
+    pub fn expand_node_states(&mut self, t_idx: TransformIdx, new_nodes: &[TransformIdx]) {
+        let node_id = cake::NodeId::Transform(t_idx);
+        if let Some(pos) = self.node_states.get(&node_id).map(|state| state.pos) {
+            self.node_states
+                .set_state(&node_id, |state| state.selected = false);
+            for idx in new_nodes {
+                self.node_states
+                    .init_node(&cake::NodeId::Transform(*idx), pos);
+            }
+        }
+        if self.active_node == Some(node_id) {
+            self.active_node.take();
+        }
+    }
+
     pub fn node_states(&self) -> &NodeStates {
         &self.node_states
     }
@@ -1311,13 +2105,207 @@ impl<T, E> NodeEditorLayout<T, E> {
         &mut self.node_states
     }
 
+    /// Move each node by its offset. Nodes that do not exist are ignored.
+    pub fn move_nodes(&mut self, moves: &[(cake::NodeId, Vec2)]) {
+        for (node_id, offset) in moves {
+            if self.node_states.get(node_id).is_some() {
+                self.node_states.set_state(node_id, |state| {
+                    state.pos = state.pos + *offset;
+                });
+            }
+        }
+    }
+
+    /// Link node `t_idx` if transform `t` was added from the command palette
+    /// while linking.
+    pub fn transform_added(&mut self, t: &'static Transform<'static, T, E>, t_idx: TransformIdx) {
+        if let Some((pending, output, input_index)) = self.pending_link {
+            if std::ptr::eq(pending, t) {
+                self.pending_link = None;
+                self.pending_connection = Some((output, cake::Input::new(t_idx, input_index)));
+            }
+        }
+    }
+
+    pub fn annotations(&self) -> &Annotations {
+        &self.annotations
+    }
+
+    pub fn annotations_mut(&mut self) -> &mut Annotations {
+        &mut self.annotations
+    }
+
+    /// Insert/Replace the given annotations. Return the replaced ones.
+    pub fn replace_annotations(
+        &mut self,
+        annotations: &[(AnnotationId, Annotation)],
+    ) -> Vec<(AnnotationId, Annotation)> {
+        annotations
+            .iter()
+            .filter_map(|(id, annotation)| {
+                self.annotations
+                    .insert(*id, annotation.clone())
+                    .map(|before| (*id, before))
+            })
+            .collect()
+    }
+
+    /// Lay out nodes like [`auto_layout`](Self::auto_layout), but push the
+    /// moves as an event so that they can be undone.
+    fn push_auto_layout(&mut self, dst: &DST<'static, T, E>, only_selected: bool) {
+        let before_pos: BTreeMap<_, _> = self
+            .node_states
+            .iter()
+            .map(|(node_id, state)| (*node_id, state.pos))
+            .collect();
+        let before_annotations = self.annotations.clone();
+        self.auto_layout(dst, only_selected);
+
+        let moves: Vec<_> = self
+            .node_states
+            .iter()
+            .map(|(node_id, state)| {
+                let before = before_pos.get(node_id).cloned().unwrap_or_default();
+                (*node_id, state.pos - before)
+            })
+            .filter(|(_, offset)| *offset != Vec2::default())
+            .collect();
+        let frames: Vec<_> = self
+            .annotations
+            .iter()
+            .filter(|(id, annotation)| before_annotations.get(id) != Some(annotation))
+            .map(|(id, annotation)| (*id, annotation.clone()))
+            .collect();
+        // Put nodes and frames back, they are moved when the event is applied
+        for (node_id, offset) in &moves {
+            self.node_states.set_state(node_id, |state| {
+                state.pos = state.pos - *offset;
+            });
+        }
+        self.annotations = before_annotations;
+        if !moves.is_empty() || !frames.is_empty() {
+            self.events.push(RenderEvent::MoveNodes(moves, frames));
+        }
+    }
+
+    /// Place nodes in layers from left to right following the links of
+    /// `dst`, so that links cross as little as possible. Only selected nodes
+    /// are moved if `only_selected` is set. The top-left corner of the moved
+    /// nodes stays in place.
+    ///
+    /// Nodes inside a comment frame are kept next to each other, and the
+    /// frame is fitted around them afterwards.
+    pub fn auto_layout(&mut self, dst: &DST<'static, T, E>, only_selected: bool) {
+        let nodes: Vec<_> = dst
+            .node_ids()
+            .into_iter()
+            .filter(|node_id| {
+                !only_selected
+                    || self
+                        .node_states
+                        .get(node_id)
+                        .map_or(false, |state| state.selected)
+            })
+            .collect();
+        if nodes.is_empty() {
+            return;
+        }
+        let index_of: BTreeMap<_, _> = nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node_id)| (*node_id, i))
+            .collect();
+        let links: Vec<_> = dst
+            .links_iter()
+            .filter_map(|(output, input)| {
+                let to = match input {
+                    InputSlot::Transform(input) => cake::NodeId::Transform(input.t_idx),
+                    InputSlot::Output(output_id) => cake::NodeId::Output(output_id),
+                };
+                let from = index_of.get(&cake::NodeId::Transform(output.t_idx))?;
+                Some((*from, *index_of.get(&to)?))
+            })
+            .collect();
+
+        // Frames with the nodes they contain
+        let frames: Vec<(AnnotationId, Vec<cake::NodeId>)> = self
+            .annotations
+            .iter()
+            .filter(|(_, annotation)| annotation.kind == AnnotationKind::Frame)
+            .map(|(id, annotation)| {
+                let contained = self
+                    .node_states
+                    .iter()
+                    .filter(|(_, state)| annotation.contains(state.pos, state.size))
+                    .map(|(node_id, _)| *node_id)
+                    .collect();
+                (*id, contained)
+            })
+            .filter(|(_, contained)| {
+                contained
+                    .iter()
+                    .any(|node_id| index_of.contains_key(node_id))
+            })
+            .collect();
+
+        let mut sizes = Vec::with_capacity(nodes.len());
+        let mut initial_y = Vec::with_capacity(nodes.len());
+        let mut groups = Vec::with_capacity(nodes.len());
+        for node_id in &nodes {
+            let state = self.node_states.get(node_id).cloned().unwrap_or_default();
+            // Nodes that were never drawn do not know their size yet
+            sizes.push(if state.size == Vec2::default() {
+                AUTO_LAYOUT_DEFAULT_NODE_SIZE
+            } else {
+                state.size
+            });
+            initial_y.push(state.pos.1);
+            groups.push(
+                frames
+                    .iter()
+                    .position(|(_, contained)| contained.contains(node_id)),
+            );
+        }
+        let origin = bounding_box(
+            nodes
+                .iter()
+                .filter_map(|node_id| self.node_states.get(node_id)),
+        )
+        .map_or_else(|| self.scrolling.get_current(), |(min, _)| min);
+
+        let positions = layered_layout(&sizes, &initial_y, &groups, &links);
+        for ((node_id, pos), size) in nodes.iter().zip(positions).zip(sizes) {
+            let mut state = self.node_states.get(node_id).cloned().unwrap_or_default();
+            state.pos = origin + pos;
+            state.size = size;
+            self.node_states.insert(*node_id, state);
+        }
+        for (frame_id, contained) in frames {
+            let states = contained
+                .iter()
+                .filter_map(|node_id| self.node_states.get(node_id));
+            if let (Some((min, max)), Some(frame)) =
+                (bounding_box(states), self.annotations.get_mut(&frame_id))
+            {
+                fit_frame(frame, min, max);
+            }
+        }
+    }
+
     pub fn active_node_mut(&mut self) -> &mut Option<cake::NodeId> {
         &mut self.active_node
     }
 
-    pub fn import(&mut self, node_states: NodeStates, scrolling: Scrolling) {
-        // Set UI node states
+    pub fn import(
+        &mut self,
+        node_states: NodeStates,
+        scrolling: Scrolling,
+        annotations: Annotations,
+    ) {
+        // Set UI node states, or place nodes on next render if there are none
+        self.auto_layout_requested = node_states.iter().next().is_none();
         self.node_states = node_states;
+        self.annotations = annotations;
         // Set scrolling offset
         self.scrolling = scrolling;
 
@@ -1328,3 +2316,337 @@ impl<T, E> NodeEditorLayout<T, E> {
         self.new_link = None;
     }
 }
+
+/// Smallest rectangle containing all the given nodes, as its top-left and
+/// bottom-right corners.
+fn bounding_box<'a, I>(states: I) -> Option<(Vec2, Vec2)>
+where
+    I: IntoIterator<Item = &'a NodeState>,
+{
+    states.into_iter().fold(None, |bounds, state| {
+        let max = state.pos + state.size;
+        Some(match bounds {
+            None => (state.pos, max),
+            Some((min_, max_)) => (
+                Vec2(min_.0.min(state.pos.0), min_.1.min(state.pos.1)),
+                Vec2(max_.0.max(max.0), max_.1.max(max.1)),
+            ),
+        })
+    })
+}
+
+/// Fit `frame` around the rectangle from `min` to `max`.
+fn fit_frame(frame: &mut Annotation, min: Vec2, max: Vec2) {
+    frame.pos = min - FRAME_MARGIN;
+    frame.size = max - min + FRAME_MARGIN + Vec2(FRAME_MARGIN.0, FRAME_MARGIN.0);
+}
+
+/// Layered layout of a directed acyclic graph, from left to right.
+///
+/// Vertices are given by their `sizes`, their current vertical position and
+/// the comment frame they belong to, if any. `links` are pairs of indices of
+/// vertices. Return the position of each vertex relative to the top-left
+/// corner of the graph.
+///
+/// Each vertex is put one layer to the right of its rightmost parent. Links
+/// spanning several layers go through a dummy vertex in each layer. The
+/// vertices of each layer are then ordered by the mean position of their
+/// neighbours in the previous layer, going back and forth, keeping the
+/// ordering with the fewest crossings.
+fn layered_layout(
+    sizes: &[Vec2],
+    initial_y: &[f32],
+    groups: &[Option<usize>],
+    links: &[(usize, usize)],
+) -> Vec<Vec2> {
+    let count = sizes.len();
+    let links: BTreeSet<_> = links.iter().cloned().collect();
+
+    // Assign layers with the longest path from the sources
+    let mut layer = vec![0; count];
+    let mut in_degree = vec![0; count];
+    let mut children = vec![vec![]; count];
+    for &(from, to) in &links {
+        children[from].push(to);
+        in_degree[to] += 1;
+    }
+    let mut ready: Vec<_> = (0..count).filter(|v| in_degree[*v] == 0).collect();
+    let sources = ready.clone();
+    while let Some(v) = ready.pop() {
+        for &child in &children[v] {
+            layer[child] = layer[child].max(layer[v] + 1);
+            in_degree[child] -= 1;
+            if in_degree[child] == 0 {
+                ready.push(child);
+            }
+        }
+    }
+    // Move sources (e.g. constants) next to the vertices they feed
+    for v in sources {
+        if let Some(min_child_layer) = children[v].iter().map(|child| layer[*child]).min() {
+            layer[v] = min_child_layer - 1;
+        }
+    }
+
+    // Split links spanning several layers with dummy vertices
+    let mut vertex_layer = layer;
+    let mut vertex_size = sizes.to_vec();
+    let mut vertex_y = initial_y.to_vec();
+    let mut vertex_group = groups.to_vec();
+    let mut segments = vec![];
+    for &(from, to) in &links {
+        let mut previous = from;
+        let span = vertex_layer[to] - vertex_layer[from];
+        for step in 1..span {
+            let dummy = vertex_layer.len();
+            let t = step as f32 / span as f32;
+            vertex_layer.push(vertex_layer[from] + step);
+            vertex_size.push(Vec2(0.0, AUTO_LAYOUT_LINK_HEIGHT));
+            vertex_y.push(initial_y[from] * (1.0 - t) + initial_y[to] * t);
+            vertex_group.push(None);
+            segments.push((previous, dummy));
+            previous = dummy;
+        }
+        segments.push((previous, to));
+    }
+    let vertex_count = vertex_layer.len();
+    let mut parents = vec![vec![]; vertex_count];
+    let mut children = vec![vec![]; vertex_count];
+    for &(from, to) in &segments {
+        parents[to].push(from);
+        children[from].push(to);
+    }
+
+    // Order the vertices of each layer
+    let layer_count = vertex_layer.iter().max().map_or(0, |max| max + 1);
+    let mut order = vec![vec![]; layer_count];
+    for (v, l) in vertex_layer.iter().enumerate() {
+        order[*l].push(v);
+    }
+    for vertices in &mut order {
+        sort_grouped(vertices, &vertex_group, |v| vertex_y[v]);
+    }
+    let mut best_order = order.clone();
+    let mut best_crossings = count_crossings(&order, &segments, &vertex_layer);
+    for sweep in 0..AUTO_LAYOUT_SWEEPS {
+        if sweep % 2 == 0 {
+            for l in 1..layer_count {
+                order_by_barycenter(&mut order, l, l - 1, &parents, &vertex_group);
+            }
+        } else {
+            for l in (0..layer_count.saturating_sub(1)).rev() {
+                order_by_barycenter(&mut order, l, l + 1, &children, &vertex_group);
+            }
+        }
+        let crossings = count_crossings(&order, &segments, &vertex_layer);
+        if crossings < best_crossings {
+            best_crossings = crossings;
+            best_order = order.clone();
+        }
+    }
+
+    // Assign coordinates: layers are columns, and each vertex is put as close
+    // as possible to the mean height of its parents
+    let mut positions = vec![Vec2::default(); vertex_count];
+    let mut x = 0.0;
+    for vertices in &best_order {
+        let mut bottom = f32::NEG_INFINITY;
+        for &v in vertices {
+            let centers: Vec<_> = parents[v]
+                .iter()
+                .map(|parent| positions[*parent].1 + vertex_size[*parent].1 / 2.0)
+                .collect();
+            let mut y = if centers.is_empty() {
+                bottom
+            } else {
+                centers.iter().sum::<f32>() / centers.len() as f32 - vertex_size[v].1 / 2.0
+            };
+            if bottom.is_finite() {
+                y = y.max(bottom + AUTO_LAYOUT_NODE_GAP);
+            } else if !y.is_finite() {
+                y = 0.0;
+            }
+            positions[v] = Vec2(x, y);
+            bottom = y + vertex_size[v].1;
+        }
+        let width = vertices
+            .iter()
+            .map(|v| vertex_size[*v].0)
+            .fold(0.0, f32::max);
+        x += width + AUTO_LAYOUT_LAYER_GAP;
+    }
+
+    positions.truncate(count);
+    let top = positions
+        .iter()
+        .map(|pos| pos.1)
+        .fold(f32::INFINITY, f32::min);
+    positions
+        .into_iter()
+        .map(|pos| Vec2(pos.0, pos.1 - top))
+        .collect()
+}
+
+/// Sort the vertices of layer `l` by the mean position of their neighbours
+/// in layer `fixed`.
+fn order_by_barycenter(
+    order: &mut [Vec<usize>],
+    l: usize,
+    fixed: usize,
+    neighbours: &[Vec<usize>],
+    groups: &[Option<usize>],
+) {
+    let mut rank = BTreeMap::new();
+    for (i, v) in order[fixed].iter().enumerate() {
+        rank.insert(*v, i as f32);
+    }
+    for (i, v) in order[l].iter().enumerate() {
+        let ranks: Vec<_> = neighbours[*v]
+            .iter()
+            .filter_map(|neighbour| rank.get(neighbour).cloned())
+            .collect();
+        // Vertices without neighbours keep their place
+        let barycenter = if ranks.is_empty() {
+            i as f32
+        } else {
+            ranks.iter().sum::<f32>() / ranks.len() as f32
+        };
+        rank.insert(*v, barycenter);
+    }
+    sort_grouped(&mut order[l], groups, |v| rank[&v]);
+}
+
+/// Sort `vertices` by `key`, keeping the vertices of a same group together
+/// at the mean key of the group.
+fn sort_grouped<F>(vertices: &mut [usize], groups: &[Option<usize>], key: F)
+where
+    F: Fn(usize) -> f32,
+{
+    let mut group_keys: BTreeMap<usize, (f32, usize)> = BTreeMap::new();
+    for &v in vertices.iter() {
+        if let Some(group) = groups[v] {
+            let entry = group_keys.entry(group).or_insert((0.0, 0));
+            entry.0 += key(v);
+            entry.1 += 1;
+        }
+    }
+    let sort_key = |v: usize| {
+        let group_key = groups[v].map_or_else(
+            || key(v),
+            |group| {
+                let (sum, count) = group_keys[&group];
+                sum / count as f32
+            },
+        );
+        (group_key, groups[v], key(v))
+    };
+    vertices.sort_by(|v1, v2| {
+        sort_key(*v1)
+            .partial_cmp(&sort_key(*v2))
+            .unwrap_or(::std::cmp::Ordering::Equal)
+    });
+}
+
+/// Count the crossings between segments linking consecutive layers.
+fn count_crossings(order: &[Vec<usize>], segments: &[(usize, usize)], layer: &[usize]) -> usize {
+    let mut rank = vec![0; layer.len()];
+    for vertices in order {
+        for (i, v) in vertices.iter().enumerate() {
+            rank[*v] = i;
+        }
+    }
+    let mut crossings = 0;
+    for (i, &(from1, to1)) in segments.iter().enumerate() {
+        for &(from2, to2) in &segments[i + 1..] {
+            if layer[from1] == layer[from2]
+                && ((rank[from1] < rank[from2] && rank[to1] > rank[to2])
+                    || (rank[from1] > rank[from2] && rank[to1] < rank[to2]))
+            {
+                crossings += 1;
+            }
+        }
+    }
+    crossings
+}
+
+#[cfg(test)]
+mod test {
+    use super::{count_crossings, layered_layout, sort_grouped};
+    use crate::vec2::Vec2;
+
+    const SIZE: Vec2 = Vec2(100.0, 50.0);
+
+    #[test]
+    fn test_layered_layout_layers() {
+        // 0 -> 1 -> 2, and source 3 -> 2 is put next to 2
+        let positions = layered_layout(
+            &[SIZE; 4],
+            &[0.0, 0.0, 0.0, 100.0],
+            &[None; 4],
+            &[(0, 1), (1, 2), (3, 2)],
+        );
+        assert_eq!(positions[0].0, 0.0);
+        assert_eq!(positions[1].0, 180.0);
+        assert_eq!(positions[2].0, 360.0);
+        assert_eq!(positions[3].0, 180.0);
+    }
+
+    #[test]
+    fn test_layered_layout_long_link() {
+        // Link 0 -> 2 spans two layers, so a dummy vertex is put below 1 and
+        // 2 is placed between 1 and the dummy vertex
+        let positions = layered_layout(
+            &[SIZE; 3],
+            &[0.0, 0.0, 100.0],
+            &[None; 3],
+            &[(0, 1), (1, 2), (0, 2)],
+        );
+        assert_eq!(positions[0], Vec2(0.0, 0.0));
+        assert_eq!(positions[1], Vec2(180.0, 0.0));
+        assert_eq!(positions[2], Vec2(360.0, 30.0));
+    }
+
+    #[test]
+    fn test_layered_layout_removes_crossings() {
+        // Initial order crosses the links 0 -> 3 and 1 -> 2
+        let initial_y = [0.0, 100.0, 0.0, 100.0];
+        let links = [(0, 3), (1, 2)];
+        let layer = [0, 0, 1, 1];
+        assert_eq!(
+            count_crossings(&[vec![0, 1], vec![2, 3]], &links, &layer),
+            1
+        );
+
+        let positions = layered_layout(&[SIZE; 4], &initial_y, &[None; 4], &links);
+        assert!(positions[0].1 < positions[1].1);
+        assert!(positions[3].1 < positions[2].1);
+        assert_eq!(
+            count_crossings(&[vec![0, 1], vec![3, 2]], &links, &layer),
+            0
+        );
+    }
+
+    #[test]
+    fn test_layered_layout_keeps_frames_together() {
+        let groups = [Some(0), None, Some(0), None];
+        let positions = layered_layout(&[SIZE; 4], &[0.0, 10.0, 20.0, 30.0], &groups, &[]);
+        // Vertices are stacked in order 1, 0, 2, 3
+        assert_eq!(positions[1].1, 0.0);
+        assert_eq!(positions[0].1, 80.0);
+        assert_eq!(positions[2].1, 160.0);
+        assert_eq!(positions[3].1, 240.0);
+    }
+
+    #[test]
+    fn test_sort_grouped() {
+        let y = [0.0, 10.0, 20.0, 30.0];
+        let mut vertices = [3, 2, 1, 0];
+        sort_grouped(&mut vertices, &[None; 4], |v| y[v]);
+        assert_eq!(vertices, [0, 1, 2, 3]);
+
+        // Group of 0 and 2 is sorted at their mean key, 10
+        let groups = [Some(0), None, Some(0), None];
+        sort_grouped(&mut vertices, &groups, |v| y[v]);
+        assert_eq!(vertices, [1, 0, 2, 3]);
+    }
+}
@@ -11,16 +11,23 @@ extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 
+mod annotation;
+mod clipboard;
 mod constant_editor;
+mod diff;
 pub mod event;
 mod export;
+pub mod history;
 mod id_stack;
 mod layout;
 mod node_state;
+mod palette;
 mod scrolling;
+#[cfg(test)]
+mod test_support;
 mod vec2;
 
-use std::{collections, error, fmt, fs, io, path};
+use std::{collections, error, fmt, fs, io, mem, path, sync, time};
 
 use crate::cake::{Future, TransformIdx};
 use imgui::ImString;
@@ -35,6 +42,16 @@ pub struct NodeEditor<T: 'static, E: 'static> {
     pub dst: cake::DST<'static, T, E>,
     output_results: collections::BTreeMap<cake::OutputId, ComputationState<T, E>>,
     cache: cake::Cache<T, cake::compute::ComputeError<E>>,
+    /// Nodes whose results were displayed since the last frame.
+    /// Their cached results are pinned so they are never evicted.
+    displayed_nodes: collections::BTreeSet<TransformIdx>,
+    progress_sender: cake::progress::ProgressSender,
+    progress_receiver: cake::progress::ProgressReceiver,
+    /// Progress of the nodes being computed
+    node_progress: collections::BTreeMap<TransformIdx, cake::Progress>,
+    /// Number of running computations of each node. The same node may be
+    /// computed concurrently for several outputs.
+    node_computations: collections::BTreeMap<TransformIdx, usize>,
     pub macros: cake::macros::MacroManager<'static, T, E>,
     layout: NodeEditorLayout<T, E>,
     error_stack: Vec<Box<dyn error::Error>>,
@@ -43,6 +60,19 @@ pub struct NodeEditor<T: 'static, E: 'static> {
     import_macro: Option<cake::macros::MacroHandle<'static, T, E>>,
     pub valid_history: Vec<event::ProvenanceEvent<T, E>>,
     redo_stack: Vec<event::ProvenanceEvent<T, E>>,
+    /// Record of each event of `valid_history`
+    history: Vec<history::HistoryEntry<T>>,
+    /// Record of each event of `redo_stack`
+    redo_history: Vec<history::HistoryEntry<T>>,
+    /// History saved in previous sessions
+    loaded_history: Vec<history::HistoryEntry<T>>,
+    /// Saved graph compared to the edited graph, its path and the time of
+    /// the comparison
+    compared: Option<(
+        path::PathBuf,
+        cake::macros::SerdeDSTStandAlone<T>,
+        time::Instant,
+    )>,
 }
 
 struct InnerNodeEditor<T: 'static, E: 'static> {
@@ -80,12 +110,17 @@ impl<T, E> InnerNodeEditor<T, E> {
 struct ComputationState<T, E> {
     previous_result: Option<cake::compute::NodeResult<T, E>>,
     task: cake::Task<cake::compute::SuccessOut<T>, cake::compute::ErrorOut<E>>,
+    /// Token cancelling the running task
+    token: cake::CancellationToken,
+    /// Time at which the graph computing the output was last updated when the
+    /// running task started
+    updated_on: Option<time::Instant>,
     counter: u8,
 }
 
 impl<T, E> NodeEditor<T, E>
 where
-    T: Clone + cake::VariantName + cake::ConvertibleVariants + Send + Sync,
+    T: Clone + cake::VariantName + cake::ConvertibleVariants + cake::EstimateSize + Send + Sync,
     E: Send + Sync,
 {
     /// Compute output's result asynchonously.
@@ -93,30 +128,60 @@ where
         &mut self,
         id: cake::OutputId,
     ) -> Option<cake::compute::NodeResult<T, E>> {
+        let t_idx = self
+            .dst
+            .outputs_iter()
+            .find(|(out_id, _)| **out_id == id)
+            .and_then(|(_, (output, _))| output.map(|output| output.t_idx));
+        if let Some(t_idx) = t_idx {
+            self.displayed_nodes.insert(t_idx);
+        }
         let dst = &mut self.dst;
         let cache = &mut self.cache;
-        let state = self
-            .output_results
-            .entry(id)
-            .or_insert_with(|| ComputationState {
+        let progress = &self.progress_sender;
+        let updated_on = t_idx.map(|t_idx| dst.updated_on(t_idx));
+        let state = self.output_results.entry(id).or_insert_with(|| {
+            let token = cake::CancellationToken::new();
+            ComputationState {
                 previous_result: None,
-                task: dst.compute(id, cache),
+                task: start_computation(dst, id, cache, &token, progress),
+                token,
+                updated_on,
                 counter: 1,
-            });
+            }
+        });
 
         const WRAP: u8 = 5;
         if state.counter % WRAP == 0 {
-            match state.task.poll() {
+            let restart = match state.task.poll() {
                 Ok(cake::Async::Ready(t)) => {
                     state.previous_result = Some(Ok(t));
-                    state.task = dst.compute(id, cache);
+                    true
+                }
+                Ok(cake::Async::NotReady) => {
+                    if updated_on > state.updated_on {
+                        // The graph changed since the task started, its
+                        // result would be stale.
+                        state.token.cancel();
+                        true
+                    } else {
+                        false
+                    }
                 }
-                Ok(cake::Async::NotReady) => (),
                 Err(e) => {
-                    state.previous_result = Some(Err(e));
-                    state.task = dst.compute(id, cache);
+                    // A cancelled computation has no result of its own,
+                    // keep showing the previous one.
+                    if !e.is_cancelled() {
+                        state.previous_result = Some(Err(e));
+                    }
+                    true
                 }
             };
+            if restart {
+                state.token = cake::CancellationToken::new();
+                state.updated_on = updated_on;
+                state.task = start_computation(dst, id, cache, &state.token, progress);
+            }
             dst.update_defaults_from_cache(cache);
         }
         if state.counter == WRAP - 1 {
@@ -128,6 +193,22 @@ where
     }
 }
 
+/// Start the computation of output `id`, that can be cancelled with `token`.
+fn start_computation<T, E>(
+    dst: &cake::DST<'static, T, E>,
+    id: cake::OutputId,
+    cache: &mut cake::Cache<T, cake::compute::ComputeError<E>>,
+    token: &cake::CancellationToken,
+    progress: &cake::progress::ProgressSender,
+) -> cake::Task<cake::compute::SuccessOut<T>, cake::compute::ErrorOut<E>>
+where
+    T: Clone + cake::VariantName + cake::ConvertibleVariants + cake::EstimateSize + Send + Sync,
+    E: Send + Sync,
+{
+    let ctx = cake::ComputeContext::new(token.clone()).with_progress(progress.clone());
+    dst.compute_with(id, cache, ctx)
+}
+
 impl<T, E> NodeEditor<T, E>
 where
     T: Clone + cake::VariantName,
@@ -200,6 +281,8 @@ where
         + cake::VariantName
         + cake::DefaultFor
         + cake::ConvertibleVariants
+        + PartialEq
+        + fmt::Debug
         + serde::Serialize
         + for<'de> serde::Deserialize<'de>,
     E: 'static + error::Error,
@@ -214,193 +297,548 @@ where
     ) where
         ED: ConstantEditor<T>,
     {
+        // Pin the results displayed during the previous frame
+        self.cache.set_pinned(mem::take(&mut self.displayed_nodes));
+        self.update_progress();
+        let node_heat = self.node_heat();
+        let diagnostics = self.validate();
+
         let events = self.layout.render(
             ui,
             &self.dst,
+            &self.node_progress,
+            &node_heat,
+            &diagnostics,
             addable_nodes,
             &self.macros,
             constant_editor,
             attaching,
         );
+        if self.layout.diff.is_none() {
+            // Stopped comparing
+            self.compared = None;
+        }
+        if let Some(path) = self.layout.compare_path.take() {
+            if let Err(e) = self.compare_with_file(path) {
+                eprintln!("Error on compare! {}", e);
+                self.error_stack.push(Box::new(e));
+            }
+        }
+        let graph_changed = !events.is_empty();
         for event in events {
-            use event::ProvenanceEvent;
-            use event::RenderEvent;
-
-            let push_event = RenderEvent::new(&event);
-            self.apply_event(event);
-            let pushed = self.valid_history.pop().unwrap();
-            if self.valid_history.is_empty() {
-                match push_event {
-                    RenderEvent::Undo => {
-                        self.valid_history.push(pushed);
-                        println!("Cannot Undo");
-                        continue;
-                    }
-                    _ => {}
-                }
+            self.handle_event(event);
+            self.record_history();
+        }
+        if let Some((_, _, compared_on)) = &self.compared {
+            let compared_on = *compared_on;
+            if graph_changed
+                || self
+                    .macros
+                    .macros()
+                    .any(|handle| handle.updated_on() > compared_on)
+            {
+                self.update_diff();
             }
-            if self.redo_stack.is_empty() {
-                match push_event {
-                    RenderEvent::Redo => {
-                        self.valid_history.push(pushed);
-                        println!("Cannot Redo");
-                        continue;
-                    }
-                    _ => {}
+        }
+    }
+
+    /// Highlight the differences between the edited graph and the graph
+    /// saved in the .ron file at `file_path`.
+    pub fn compare_with_file<P: AsRef<path::Path>>(
+        &mut self,
+        file_path: P,
+    ) -> Result<(), export::ImportError> {
+        let f = fs::File::open(&file_path)?;
+        let saved = Self::dst_from_export_buf(f)?;
+        self.compared = Some((file_path.as_ref().to_owned(), saved, time::Instant::now()));
+        self.update_diff();
+        Ok(())
+    }
+
+    fn update_diff(&mut self) {
+        if let Some((path, saved, compared_on)) = &mut self.compared {
+            self.layout.diff = Some(diff::DiffHighlight::new(path.clone(), saved, &self.dst));
+            *compared_on = time::Instant::now();
+        }
+    }
+
+    /// Apply `event`, and keep it in the provenance history for undo. Undo
+    /// and redo events move events between the history and the redo stack.
+    fn handle_event(&mut self, event: event::RenderEvent<T, E>) {
+        use event::ProvenanceEvent;
+        use event::RenderEvent;
+
+        let push_event = RenderEvent::new(&event);
+        self.apply_event(event);
+        let pushed = self.valid_history.pop().unwrap();
+        if self.valid_history.is_empty() {
+            match push_event {
+                RenderEvent::Undo => {
+                    self.valid_history.push(pushed);
+                    println!("Cannot Undo");
+                    return;
                 }
+                _ => {}
             }
-            match (push_event, &pushed) {
-                (RenderEvent::Connect(_, _), ProvenanceEvent::Connect(_, _, Ok(())))
-                | (RenderEvent::Disconnect(_, _), ProvenanceEvent::Disconnect(_, _, Ok(())))
-                | (RenderEvent::AddTransform(_), ProvenanceEvent::AddTransform(_, _))
-                | (RenderEvent::CreateOutput, ProvenanceEvent::CreateOutput(_))
-                | (RenderEvent::AddConstant(_), ProvenanceEvent::AddConstant(_, _))
-                | (RenderEvent::SetConstant(_, _), ProvenanceEvent::SetConstant(_, _, _, Ok(())))
-                | (
-                    RenderEvent::WriteDefaultInput { .. },
-                    ProvenanceEvent::WriteDefaultInput(_, _, _, _, Ok(())),
-                )
-                | (RenderEvent::RemoveNode(_), ProvenanceEvent::RemoveNode(_, _, _, _, _, _))
-                | (RenderEvent::Import, ProvenanceEvent::Import(_, _, _, Ok(())))
-                | (RenderEvent::Export, ProvenanceEvent::Export(_, _, Ok(())))
-                | (RenderEvent::AddNewMacro, ProvenanceEvent::AddNewMacro(_))
-                | (RenderEvent::AddMacro(_), ProvenanceEvent::AddMacro(_, _))
-                | (RenderEvent::EditNode(_), ProvenanceEvent::EditNode(_))
-                | (
-                    RenderEvent::ChangeOutputName(_, _),
-                    ProvenanceEvent::ChangeOutputName(_, _, _),
-                ) => {
-                    self.redo_stack.clear();
+        }
+        if self.redo_stack.is_empty() {
+            match push_event {
+                RenderEvent::Redo => {
                     self.valid_history.push(pushed);
+                    println!("Cannot Redo");
+                    return;
                 }
-                //Undo by looking at the previous operation, the opposite event is executed.
-                (RenderEvent::Undo, ProvenanceEvent::Connect(o, i, Ok(()))) => {
-                    let alternative_event = RenderEvent::<T, E>::Disconnect(*o, *i);
-                    self.apply_event(alternative_event);
-                    self.valid_history.pop();
-                    self.redo_stack.push(pushed);
-                }
-                (RenderEvent::Undo, ProvenanceEvent::Disconnect(o, i, Ok(()))) => {
-                    let alternative_event = RenderEvent::<T, E>::Connect(*o, *i);
-                    self.apply_event(alternative_event);
-                    self.valid_history.pop();
-                    self.redo_stack.push(pushed);
-                }
-                (RenderEvent::Undo, ProvenanceEvent::AddTransform(_, t_idx))
-                | (RenderEvent::Undo, ProvenanceEvent::AddConstant(_, t_idx)) => {
-                    let node = cake::NodeId::Transform(*t_idx);
-                    let alternative_event = RenderEvent::<T, E>::RemoveNode(node);
-                    self.apply_event(alternative_event);
-                    self.valid_history.pop();
-                    self.redo_stack.push(pushed);
-                }
-                (RenderEvent::Undo, ProvenanceEvent::CreateOutput(output_id)) => {
-                    let node = cake::NodeId::Output(*output_id);
-                    let alternative_event = RenderEvent::<T, E>::RemoveNode(node);
-                    self.apply_event(alternative_event);
-                    self.valid_history.pop();
-                    self.redo_stack.push(pushed);
-                }
-                (RenderEvent::Undo, ProvenanceEvent::SetConstant(t_idx, before, _, Ok(()))) => {
-                    let alternative_event =
-                        RenderEvent::<T, E>::SetConstant(*t_idx, Box::new(before.clone().unwrap()));
-                    self.apply_event(alternative_event);
-                    self.valid_history.pop();
-                    self.redo_stack.push(pushed);
+                _ => {}
+            }
+        }
+        match (push_event, &pushed) {
+            (RenderEvent::Connect(_, _), ProvenanceEvent::Connect(_, _, Ok(())))
+            | (RenderEvent::Disconnect(_, _), ProvenanceEvent::Disconnect(_, _, Ok(())))
+            | (RenderEvent::AddTransform(_), ProvenanceEvent::AddTransform(_, _))
+            | (RenderEvent::CreateOutput, ProvenanceEvent::CreateOutput(_))
+            | (RenderEvent::AddConstant(_), ProvenanceEvent::AddConstant(_, _))
+            | (RenderEvent::SetConstant(_, _), ProvenanceEvent::SetConstant(_, _, _, Ok(())))
+            | (
+                RenderEvent::WriteDefaultInput { .. },
+                ProvenanceEvent::WriteDefaultInput(_, _, _, _, Ok(())),
+            )
+            | (RenderEvent::RemoveNode(_), ProvenanceEvent::RemoveNode(_, _, _, _, _, _))
+            | (RenderEvent::Import, ProvenanceEvent::Import(_, _, _, Ok(())))
+            | (RenderEvent::Export, ProvenanceEvent::Export(_, _, Ok(())))
+            | (RenderEvent::AddNewMacro, ProvenanceEvent::AddNewMacro(_))
+            | (RenderEvent::AddMacro(_), ProvenanceEvent::AddMacro(_, _))
+            | (RenderEvent::EditNode(_), ProvenanceEvent::EditNode(_))
+            | (RenderEvent::ChangeOutputName(_, _), ProvenanceEvent::ChangeOutputName(_, _, _))
+            | (RenderEvent::Paste(_), ProvenanceEvent::Paste(_, _, Ok(())))
+            | (RenderEvent::GroupIntoMacro(_), ProvenanceEvent::GroupIntoMacro(_, _, Ok(_)))
+            | (RenderEvent::ExpandMacro(_), ProvenanceEvent::ExpandMacro(_, _, Ok(_)))
+            | (RenderEvent::AddAnnotation(_, _), ProvenanceEvent::AddAnnotation(_, _))
+            | (
+                RenderEvent::UpdateAnnotation(_, _, _),
+                ProvenanceEvent::UpdateAnnotation(_, Some(_), _, _),
+            )
+            | (RenderEvent::RemoveAnnotation(_), ProvenanceEvent::RemoveAnnotation(_, Some(_)))
+            | (RenderEvent::MoveNodes(_, _), ProvenanceEvent::MoveNodes(_, _, _)) => {
+                self.redo_stack.clear();
+                self.valid_history.push(pushed);
+            }
+            //Undo by looking at the previous operation, the opposite event is executed.
+            (RenderEvent::Undo, ProvenanceEvent::Connect(o, i, Ok(()))) => {
+                let alternative_event = RenderEvent::<T, E>::Disconnect(*o, *i);
+                self.apply_event(alternative_event);
+                self.valid_history.pop();
+                self.redo_stack.push(pushed);
+            }
+            (RenderEvent::Undo, ProvenanceEvent::Disconnect(o, i, Ok(()))) => {
+                let alternative_event = RenderEvent::<T, E>::Connect(*o, *i);
+                self.apply_event(alternative_event);
+                self.valid_history.pop();
+                self.redo_stack.push(pushed);
+            }
+            (RenderEvent::Undo, ProvenanceEvent::AddTransform(_, t_idx))
+            | (RenderEvent::Undo, ProvenanceEvent::AddConstant(_, t_idx)) => {
+                let node = cake::NodeId::Transform(*t_idx);
+                let alternative_event = RenderEvent::<T, E>::RemoveNode(node);
+                self.apply_event(alternative_event);
+                self.valid_history.pop();
+                self.redo_stack.push(pushed);
+            }
+            (RenderEvent::Undo, ProvenanceEvent::CreateOutput(output_id)) => {
+                let node = cake::NodeId::Output(*output_id);
+                let alternative_event = RenderEvent::<T, E>::RemoveNode(node);
+                self.apply_event(alternative_event);
+                self.valid_history.pop();
+                self.redo_stack.push(pushed);
+            }
+            (RenderEvent::Undo, ProvenanceEvent::SetConstant(t_idx, before, _, Ok(()))) => {
+                let alternative_event =
+                    RenderEvent::<T, E>::SetConstant(*t_idx, Box::new(before.clone().unwrap()));
+                self.apply_event(alternative_event);
+                self.valid_history.pop();
+                self.redo_stack.push(pushed);
+            }
+            (
+                RenderEvent::Undo,
+                ProvenanceEvent::WriteDefaultInput(t_idx, input_index, before, _, Ok(())),
+            ) => {
+                let alternative_event = RenderEvent::<T, E>::WriteDefaultInput {
+                    t_idx: *t_idx,
+                    input_index: *input_index,
+                    val: Box::new(before.clone().unwrap()),
+                };
+                self.apply_event(alternative_event);
+                self.valid_history.pop();
+                self.redo_stack.push(pushed);
+            }
+            (
+                RenderEvent::Undo,
+                ProvenanceEvent::RemoveNode(cake::NodeId::Transform(_), t, _, d_i, i_c, o_c),
+            ) => {
+                self.apply_event(RenderEvent::<T, E>::AddOwnedTransform(
+                    t.clone(),
+                    d_i.clone(),
+                    i_c.clone(),
+                    o_c.clone(),
+                ));
+                self.valid_history.pop();
+                self.redo_stack.push(pushed);
+            }
+            (
+                RenderEvent::Undo,
+                ProvenanceEvent::RemoveNode(cake::NodeId::Output(output_id), _, name, _, i_c, _),
+            ) => {
+                if i_c.len() == 1 {
+                    if let Some(output) = i_c.get(0).unwrap() {
+                        self.dst.attach_output_with_id_name(
+                            *output,
+                            *output_id,
+                            name.clone().unwrap(),
+                        );
+                    } else {
+                        self.dst
+                            .create_output_with_id_name(*output_id, name.clone().unwrap());
+                    }
                 }
-                (
-                    RenderEvent::Undo,
-                    ProvenanceEvent::WriteDefaultInput(t_idx, input_index, before, _, Ok(())),
-                ) => {
-                    let alternative_event = RenderEvent::<T, E>::WriteDefaultInput {
-                        t_idx: *t_idx,
-                        input_index: *input_index,
-                        val: Box::new(before.clone().unwrap()),
-                    };
-                    self.apply_event(alternative_event);
+                self.redo_stack.push(pushed);
+            }
+            (RenderEvent::Undo, ProvenanceEvent::Import(_, Some(before_dst), _, Ok(()))) => {
+                self.dst = before_dst.clone();
+                self.redo_stack.push(pushed);
+            }
+            (RenderEvent::Undo, ProvenanceEvent::Export(_, _, _)) => {
+                println!("Export undo skipped.");
+                self.redo_stack.push(pushed);
+            }
+            (RenderEvent::Undo, ProvenanceEvent::AddNewMacro(t_idx)) => {
+                let node = cake::NodeId::Transform(*t_idx);
+                let alternative_event = RenderEvent::<T, E>::RemoveNode(node);
+                self.apply_event(alternative_event);
+                self.valid_history.pop();
+                self.redo_stack.push(pushed);
+            }
+            (RenderEvent::Undo, ProvenanceEvent::AddMacro(_, t_idx)) => {
+                let node = cake::NodeId::Transform(*t_idx);
+                let alternative_event = RenderEvent::<T, E>::RemoveNode(node);
+                self.apply_event(alternative_event);
+                self.valid_history.pop();
+                self.redo_stack.push(pushed);
+            }
+            (RenderEvent::Undo, ProvenanceEvent::EditNode(_)) => {
+                println!("EditNode skipped.");
+                self.redo_stack.push(pushed);
+            }
+            (RenderEvent::Undo, ProvenanceEvent::ChangeOutputName(node_id, before_name, _)) => {
+                let alternative_event =
+                    RenderEvent::<T, E>::ChangeOutputName(*node_id, before_name.clone());
+                self.apply_event(alternative_event);
+                self.valid_history.pop();
+                self.redo_stack.push(pushed);
+            }
+            (RenderEvent::Undo, ProvenanceEvent::Paste(_, node_ids, Ok(()))) => {
+                for node_id in node_ids {
+                    self.apply_event(RenderEvent::<T, E>::RemoveNode(*node_id));
                     self.valid_history.pop();
-                    self.redo_stack.push(pushed);
                 }
-                (
-                    RenderEvent::Undo,
-                    ProvenanceEvent::RemoveNode(cake::NodeId::Transform(_), t, _, d_i, i_c, o_c),
-                ) => {
-                    self.apply_event(RenderEvent::<T, E>::AddOwnedTransform(
-                        t.clone(),
-                        d_i.clone(),
-                        i_c.clone(),
-                        o_c.clone(),
-                    ));
-                    self.valid_history.pop();
-                    self.redo_stack.push(pushed);
+                self.redo_stack.push(pushed);
+            }
+            (RenderEvent::Undo, ProvenanceEvent::GroupIntoMacro(_, before_dst, Ok(_)))
+            | (RenderEvent::Undo, ProvenanceEvent::ExpandMacro(_, before_dst, Ok(_))) => {
+                self.dst = before_dst.clone();
+                self.layout.node_states_mut().deselect_all();
+                self.redo_stack.push(pushed);
+            }
+            (RenderEvent::Undo, ProvenanceEvent::AddAnnotation(id, _)) => {
+                self.apply_event(RenderEvent::<T, E>::RemoveAnnotation(*id));
+                self.valid_history.pop();
+                self.redo_stack.push(pushed);
+            }
+            (RenderEvent::Undo, ProvenanceEvent::UpdateAnnotation(id, Some(before), _, moves)) => {
+                let alternative_event = RenderEvent::<T, E>::UpdateAnnotation(
+                    *id,
+                    before.clone(),
+                    reverse_moves(moves),
+                );
+                self.apply_event(alternative_event);
+                self.valid_history.pop();
+                self.redo_stack.push(pushed);
+            }
+            (RenderEvent::Undo, ProvenanceEvent::RemoveAnnotation(id, Some(annotation))) => {
+                let alternative_event = RenderEvent::<T, E>::AddAnnotation(*id, annotation.clone());
+                self.apply_event(alternative_event);
+                self.valid_history.pop();
+                self.redo_stack.push(pushed);
+            }
+            (RenderEvent::Undo, ProvenanceEvent::MoveNodes(moves, before, _)) => {
+                let alternative_event =
+                    RenderEvent::<T, E>::MoveNodes(reverse_moves(moves), before.clone());
+                self.apply_event(alternative_event);
+                self.valid_history.pop();
+                self.redo_stack.push(pushed);
+            }
+            (RenderEvent::Redo, _) => {
+                if let Some(redo_event) = self.redo_stack.pop() {
+                    self.valid_history.push(pushed);
+                    let redo_event = RenderEvent::<T, E>::from(redo_event);
+                    self.apply_event(redo_event);
                 }
-                (
-                    RenderEvent::Undo,
-                    ProvenanceEvent::RemoveNode(
-                        cake::NodeId::Output(output_id),
-                        _,
-                        name,
-                        _,
-                        i_c,
-                        _,
-                    ),
-                ) => {
-                    if i_c.len() == 1 {
-                        if let Some(output) = i_c.get(0).unwrap() {
-                            self.dst.attach_output_with_id_name(
-                                *output,
-                                *output_id,
-                                name.clone().unwrap(),
-                            );
-                        } else {
-                            self.dst
-                                .create_output_with_id_name(*output_id, name.clone().unwrap());
-                        }
+            }
+            _ => {}
+        }
+    }
+
+    /// Record the events added to the provenance history since last call.
+    /// Undone events are moved to the redo history.
+    fn record_history(&mut self) {
+        while self.history.len() > self.valid_history.len() {
+            let entry = self.history.pop().unwrap();
+            self.redo_history.push(entry);
+        }
+        self.redo_history.truncate(self.redo_stack.len());
+        let timestamp = history::now();
+        for event in &self.valid_history[self.history.len()..] {
+            let entry = match event {
+                // Do not save the whole graph again on each open if the
+                // saved history already gives it back
+                event::ProvenanceEvent::Import(None, None, dst, Ok(()))
+                    if self.history.is_empty() && self.loaded_history_rebuilds(dst) =>
+                {
+                    history::HistoryEntry {
+                        timestamp,
+                        event: history::HistoryEvent::Open,
                     }
-                    self.redo_stack.push(pushed);
                 }
-                (RenderEvent::Undo, ProvenanceEvent::Import(_, Some(before_dst), _, Ok(()))) => {
-                    self.dst = before_dst.clone();
-                    self.redo_stack.push(pushed);
-                }
-                (RenderEvent::Undo, ProvenanceEvent::Export(_, _, _)) => {
-                    println!("Export undo skipped.");
-                    self.redo_stack.push(pushed);
-                }
-                (RenderEvent::Undo, ProvenanceEvent::AddNewMacro(t_idx)) => {
-                    let node = cake::NodeId::Transform(*t_idx);
-                    let alternative_event = RenderEvent::<T, E>::RemoveNode(node);
-                    self.apply_event(alternative_event);
-                    self.valid_history.pop();
-                    self.redo_stack.push(pushed);
+                _ => history::HistoryEntry::new(timestamp, event, &self.dst),
+            };
+            self.history.push(entry);
+        }
+    }
+
+    /// Check whether replaying the history saved in previous sessions gives
+    /// back `dst`.
+    fn loaded_history_rebuilds(&self, dst: &cake::DST<'static, T, E>) -> bool {
+        !self.loaded_history.is_empty()
+            && self
+                .replay(&self.loaded_history)
+                .map_or(false, |replayed| same_graph(&replayed, dst))
+    }
+
+    /// Draw the provenance history, from the events saved in previous
+    /// sessions to the undone events that can be redone. Click on an event to
+    /// go back to the state right after it. Doing anything from a past state
+    /// starts a new branch of the history.
+    pub fn render_history(&mut self, ui: &imgui::Ui) {
+        enum Target {
+            Loaded(usize),
+            Undo(usize),
+            Redo(usize),
+        }
+
+        let mut target = None;
+        if !self.loaded_history.is_empty() {
+            ui.text_disabled("Previous sessions (replayed when clicked)");
+            for (i, entry) in self.loaded_history.iter().enumerate() {
+                if history_selectable(ui, i, entry, false) {
+                    target = Some(Target::Loaded(i));
                 }
-                (RenderEvent::Undo, ProvenanceEvent::AddMacro(_, t_idx)) => {
-                    let node = cake::NodeId::Transform(*t_idx);
-                    let alternative_event = RenderEvent::<T, E>::RemoveNode(node);
-                    self.apply_event(alternative_event);
-                    self.valid_history.pop();
-                    self.redo_stack.push(pushed);
+            }
+            ui.separator();
+        }
+        let offset = self.loaded_history.len();
+        let current = self.history.len().saturating_sub(1);
+        for (i, entry) in self.history.iter().enumerate() {
+            if history_selectable(ui, offset + i, entry, i == current) {
+                target = Some(Target::Undo(current - i));
+            }
+        }
+        let offset = offset + self.history.len();
+        {
+            let style = ui.push_style_var(imgui::StyleVar::Alpha(0.5));
+            for (i, entry) in self.redo_history.iter().rev().enumerate() {
+                if history_selectable(ui, offset + i, entry, false) {
+                    target = Some(Target::Redo(i + 1));
                 }
-                (RenderEvent::Undo, ProvenanceEvent::EditNode(_)) => {
-                    println!("EditNode skipped.");
-                    self.redo_stack.push(pushed);
+            }
+            style.pop();
+        }
+        if ui.button(format!("Check replay")) {
+            match self.replay_history() {
+                Ok(dst) if same_graph(&dst, &self.dst) => self.success_stack.push(ImString::new(
+                    "Replaying the history gives back the current graph.",
+                )),
+                Ok(_) => self
+                    .error_stack
+                    .push(Box::new(history::ReplayError::Mismatch)),
+                Err(e) => self.error_stack.push(Box::new(e)),
+            }
+        }
+
+        match target {
+            Some(Target::Loaded(i)) => self.restore_history(i),
+            Some(Target::Undo(count)) => {
+                for _ in 0..count {
+                    self.handle_event(event::RenderEvent::Undo);
+                    self.record_history();
                 }
-                (RenderEvent::Undo, ProvenanceEvent::ChangeOutputName(node_id, before_name, _)) => {
-                    let alternative_event =
-                        RenderEvent::<T, E>::ChangeOutputName(*node_id, before_name.clone());
-                    self.apply_event(alternative_event);
-                    self.valid_history.pop();
-                    self.redo_stack.push(pushed);
+            }
+            Some(Target::Redo(count)) => {
+                for _ in 0..count {
+                    self.handle_event(event::RenderEvent::Redo);
+                    self.record_history();
                 }
-                (RenderEvent::Redo, _) => {
-                    if let Some(redo_event) = self.redo_stack.pop() {
-                        self.valid_history.push(pushed);
-                        let redo_event = RenderEvent::<T, E>::from(redo_event);
-                        self.apply_event(redo_event);
-                    }
+            }
+            None => {}
+        }
+    }
+
+    /// Go back to the state right after the `i`-th event saved in a previous
+    /// session, by replaying the saved history up to it. This can be undone.
+    fn restore_history(&mut self, i: usize) {
+        let replayed = match self.replay(&self.loaded_history[..=i]) {
+            Ok(dst) => dst,
+            Err(e) => {
+                self.error_stack.push(Box::new(e));
+                return;
+            }
+        };
+        // Use the macros of the editor instead of the copies used to replay
+        let serializable = cake::macros::SerdeDSTStandAlone::from(&replayed);
+        match serializable.into_dst_in(&mut self.macros) {
+            Ok(dst) => {
+                let before_dst = mem::replace(&mut self.dst, dst);
+                self.valid_history.push(event::ProvenanceEvent::Import(
+                    None,
+                    Some(before_dst),
+                    self.dst.clone(),
+                    Ok(()),
+                ));
+                self.redo_stack.clear();
+                self.layout.node_states_mut().deselect_all();
+                self.record_history();
+                if let Some(history::HistoryEntry {
+                    event: history::HistoryEvent::Snapshot(description, _),
+                    ..
+                }) = self.history.last_mut()
+                {
+                    *description = format!("Restore state #{}", i);
                 }
-                _ => {}
             }
+            Err(e) => self
+                .error_stack
+                .push(Box::new(export::ImportError::from(e))),
+        }
+    }
+
+    /// Rebuild the graph from an empty editor by replaying the whole
+    /// history, including the history saved in previous sessions.
+    ///
+    /// Macros are replayed with their current content, as changes to macros
+    /// are part of the history of their own editor.
+    pub fn replay_history(&self) -> Result<cake::DST<'static, T, E>, history::ReplayError> {
+        let entries: Vec<_> = self
+            .loaded_history
+            .iter()
+            .chain(&self.history)
+            .cloned()
+            .collect();
+        self.replay(&entries)
+    }
+
+    /// Replace the graph by the one rebuilt by [`NodeEditor::replay_history`].
+    /// Return whether the rebuilt graph is the same as the replaced one.
+    ///
+    /// The graph is kept as it is if the rebuilt graph is different, so that
+    /// a history that does not match the saved graph cannot replace it.
+    pub fn rebuild_from_history(&mut self) -> Result<bool, history::ReplayError> {
+        let replayed = self.replay_history()?;
+        if !same_graph(&replayed, &self.dst) {
+            return Ok(false);
+        }
+        let serializable = cake::macros::SerdeDSTStandAlone::from(&replayed);
+        self.dst = serializable
+            .into_dst_in(&mut self.macros)
+            .map_err(|e| history::ReplayError::Macros(e.into()))?;
+        Ok(true)
+    }
+
+    fn replay(
+        &self,
+        entries: &[history::HistoryEntry<T>],
+    ) -> Result<cake::DST<'static, T, E>, history::ReplayError> {
+        let mut editor = Self::default();
+        for handle in self.macros.macros() {
+            cake::macros::SerdeMacroStandAlone::from(handle)
+                .into_macro()
+                .and_then(|macr| editor.macros.add_macro(macr))
+                .map_err(|e| history::ReplayError::Macros(e.into()))?;
+        }
+        for (i, entry) in entries.iter().enumerate() {
+            editor.replay_event(i, &entry.event)?;
+        }
+        Ok(editor.dst)
+    }
+
+    /// Apply the recorded event at `index` in the history.
+    fn replay_event(
+        &mut self,
+        index: usize,
+        event: &history::HistoryEvent<T>,
+    ) -> Result<(), history::ReplayError> {
+        use event::ProvenanceEvent as P;
+        use event::RenderEvent as R;
+        use history::HistoryEvent as H;
+        use history::ReplayError;
+
+        let event = match event {
+            H::Connect(output, input_slot) => R::Connect(*output, *input_slot),
+            H::Disconnect(output, input_slot) => R::Disconnect(*output, *input_slot),
+            H::AddTransform(name) => R::AddTransform(
+                T::get_transform(name)
+                    .ok_or_else(|| ReplayError::UnknownTransform(index, name.clone()))?,
+            ),
+            H::CreateOutput => R::CreateOutput,
+            H::AddConstant(name) => R::AddConstant(
+                T::editable_variants()
+                    .iter()
+                    .find(|variant| **variant == name.as_str())
+                    .cloned()
+                    .ok_or_else(|| ReplayError::UnknownConstant(index, name.clone()))?,
+            ),
+            H::SetConstant(t_idx, val) => R::SetConstant(*t_idx, Box::new(val.clone())),
+            H::WriteDefaultInput(t_idx, input_index, val) => R::WriteDefaultInput {
+                t_idx: *t_idx,
+                input_index: *input_index,
+                val: Box::new(val.clone()),
+            },
+            H::RemoveNode(node_id) => R::RemoveNode(*node_id),
+            H::ChangeOutputName(node_id, name) => R::ChangeOutputName(*node_id, name.clone()),
+            H::Paste(text) => R::Paste(text.clone()),
+            H::AddAnnotation(id, annotation) => R::AddAnnotation(*id, annotation.clone()),
+            // Nodes are not moved, as the layout is not replayed
+            H::UpdateAnnotation(id, annotation) => {
+                R::UpdateAnnotation(*id, annotation.clone(), vec![])
+            }
+            H::RemoveAnnotation(id) => R::RemoveAnnotation(*id),
+            H::MoveNodes(_) | H::EditNode(_) | H::Export(_) | H::Open => return Ok(()),
+            H::Snapshot(_, dst) => {
+                self.dst = dst
+                    .clone()
+                    .into_dst_in(&mut self.macros)
+                    .map_err(|e| ReplayError::Snapshot(index, e.into()))?;
+                return Ok(());
+            }
+        };
+        self.apply_event(event);
+        match self.valid_history.last() {
+            Some(P::Connect(_, _, Err(e))) | Some(P::Disconnect(_, _, Err(e))) => {
+                Err(ReplayError::Failed(index, e.to_string()))
+            }
+            Some(P::SetConstant(_, _, _, Err(())))
+            | Some(P::WriteDefaultInput(_, _, _, _, Err(()))) => Err(ReplayError::Failed(
+                index,
+                "Value could not be set".to_owned(),
+            )),
+            Some(P::Paste(_, _, Err(e))) => Err(ReplayError::Failed(index, e.to_string())),
+            _ => Ok(()),
         }
     }
 
@@ -409,6 +847,11 @@ where
         self.render_success_popup(ui);
     }
 
+    /// Check the graph for problems, without computing anything.
+    pub fn validate(&self) -> Vec<cake::Diagnostic> {
+        self.dst.validate_with_macros(&self.macros)
+    }
+
     /// Get all the outputs defined in the node editor.
     pub fn outputs(&self) -> Vec<(cake::OutputId, String)> {
         self.dst
@@ -463,6 +906,8 @@ where
         let mut macros_to_edit = vec![];
         let macros = &mut self.macros;
         let import_macro = &mut self.import_macro;
+        let node_progress = &self.node_progress;
+        let node_heat = collections::BTreeMap::new();
         let mut import_macro_focus = false;
         for (i, node_edit) in self.nodes_edit.iter_mut().enumerate() {
             let mut opened = node_edit.opened;
@@ -482,9 +927,23 @@ where
                     let events = {
                         let lock = node_edit.handle.read();
                         let dst = lock.dst();
+                        // Unattached inputs and outputs of a macro are the
+                        // inputs of the macro
+                        let diagnostics: Vec<_> = dst
+                            .validate_with_macros(macros)
+                            .into_iter()
+                            .filter(|diagnostic| match diagnostic {
+                                cake::Diagnostic::MissingInput { .. }
+                                | cake::Diagnostic::DanglingOutput(_) => false,
+                                _ => true,
+                            })
+                            .collect();
                         node_edit.layout.render(
                             ui,
                             dst,
+                            node_progress,
+                            &node_heat,
+                            &diagnostics,
                             addable_nodes,
                             macros,
                             constant_editor,
@@ -492,7 +951,21 @@ where
                         )
                     };
                     for event in events {
-                        if let event::RenderEvent::AddNewMacro = event {
+                        if let event::RenderEvent::GroupIntoMacro(nodes) = event {
+                            // Macros are read to name the new macro, so the
+                            // edited one cannot be locked while grouping
+                            let mut dst = node_edit.handle.read().dst().clone();
+                            let macro_id = Some(node_edit.handle.id());
+                            match dst.group_into_macro(&nodes, macros, macro_id) {
+                                Ok(t_idx) => {
+                                    *node_edit.handle.write().dst_mut() = dst;
+                                    node_edit.layout.group_node_states(&nodes, t_idx);
+                                }
+                                Err(e) => node_edit
+                                    .error_stack
+                                    .push(InnerEditorError::IncorrectNodeConnection(e)),
+                            }
+                        } else if let event::RenderEvent::AddNewMacro = event {
                             let new_macr = macros.create_macro().clone();
                             let macro_id = new_macr.id();
                             node_edit.handle.write().dst_mut().add_owned_transform(
@@ -572,6 +1045,27 @@ where
                                 | (
                                     RenderEvent::ChangeOutputName(_, _),
                                     ProvenanceEvent::ChangeOutputName(_, _, _),
+                                )
+                                | (RenderEvent::Paste(_), ProvenanceEvent::Paste(_, _, Ok(())))
+                                | (
+                                    RenderEvent::ExpandMacro(_),
+                                    ProvenanceEvent::ExpandMacro(_, _, Ok(_)),
+                                )
+                                | (
+                                    RenderEvent::AddAnnotation(_, _),
+                                    ProvenanceEvent::AddAnnotation(_, _),
+                                )
+                                | (
+                                    RenderEvent::UpdateAnnotation(_, _, _),
+                                    ProvenanceEvent::UpdateAnnotation(_, Some(_), _, _),
+                                )
+                                | (
+                                    RenderEvent::RemoveAnnotation(_),
+                                    ProvenanceEvent::RemoveAnnotation(_, Some(_)),
+                                )
+                                | (
+                                    RenderEvent::MoveNodes(_, _),
+                                    ProvenanceEvent::MoveNodes(_, _, _),
                                 ) => {
                                     node_edit.redo_stack.clear();
                                     node_edit.valid_history.push(pushed);
@@ -731,6 +1225,66 @@ where
                                     node_edit.valid_history.pop();
                                     node_edit.redo_stack.push(pushed);
                                 }
+                                (
+                                    RenderEvent::Undo,
+                                    ProvenanceEvent::Paste(_, node_ids, Ok(())),
+                                ) => {
+                                    for node_id in node_ids {
+                                        node_edit
+                                            .apply_event(RenderEvent::<T, E>::RemoveNode(*node_id));
+                                        node_edit.valid_history.pop();
+                                    }
+                                    node_edit.redo_stack.push(pushed);
+                                }
+                                (
+                                    RenderEvent::Undo,
+                                    ProvenanceEvent::ExpandMacro(_, before_dst, Ok(_)),
+                                ) => {
+                                    *node_edit.handle.write().dst_mut() = before_dst.clone();
+                                    node_edit.layout.node_states_mut().deselect_all();
+                                    node_edit.redo_stack.push(pushed);
+                                }
+                                (RenderEvent::Undo, ProvenanceEvent::AddAnnotation(id, _)) => {
+                                    node_edit
+                                        .apply_event(RenderEvent::<T, E>::RemoveAnnotation(*id));
+                                    node_edit.valid_history.pop();
+                                    node_edit.redo_stack.push(pushed);
+                                }
+                                (
+                                    RenderEvent::Undo,
+                                    ProvenanceEvent::UpdateAnnotation(id, Some(before), _, moves),
+                                ) => {
+                                    let alternative_event = RenderEvent::<T, E>::UpdateAnnotation(
+                                        *id,
+                                        before.clone(),
+                                        reverse_moves(moves),
+                                    );
+                                    node_edit.apply_event(alternative_event);
+                                    node_edit.valid_history.pop();
+                                    node_edit.redo_stack.push(pushed);
+                                }
+                                (
+                                    RenderEvent::Undo,
+                                    ProvenanceEvent::RemoveAnnotation(id, Some(annotation)),
+                                ) => {
+                                    let alternative_event =
+                                        RenderEvent::<T, E>::AddAnnotation(*id, annotation.clone());
+                                    node_edit.apply_event(alternative_event);
+                                    node_edit.valid_history.pop();
+                                    node_edit.redo_stack.push(pushed);
+                                }
+                                (
+                                    RenderEvent::Undo,
+                                    ProvenanceEvent::MoveNodes(moves, before, _),
+                                ) => {
+                                    let alternative_event = RenderEvent::<T, E>::MoveNodes(
+                                        reverse_moves(moves),
+                                        before.clone(),
+                                    );
+                                    node_edit.apply_event(alternative_event);
+                                    node_edit.valid_history.pop();
+                                    node_edit.redo_stack.push(pushed);
+                                }
                                 (RenderEvent::Redo, _) => {
                                     if let Some(redo_event) = node_edit.redo_stack.pop() {
                                         node_edit.valid_history.push(pushed);
@@ -953,6 +1507,7 @@ where
     }
     fn add_transform(&mut self, t: &'static cake::Transform<'static, T, E>) {
         let t_idx = self.dst.add_transform(t, None);
+        self.layout.transform_added(t, t_idx);
         self.valid_history
             .push(event::ProvenanceEvent::AddTransform(t, t_idx));
     }
@@ -1180,6 +1735,107 @@ where
             }
         }
     }
+    fn paste(&mut self, text: String) {
+        match clipboard::paste(&text, &mut self.macros) {
+            Ok(pasted) => {
+                let new_ids = self.dst.merge(pasted.dst, None);
+                let node_ids = self.layout.paste_node_states(pasted.node_states, &new_ids);
+                self.valid_history
+                    .push(event::ProvenanceEvent::Paste(text, node_ids, Ok(())));
+            }
+            Err(e) => {
+                eprintln!("Error on paste! {}", e);
+                self.valid_history.push(event::ProvenanceEvent::Paste(
+                    text,
+                    vec![],
+                    Err(e.clone()),
+                ));
+                self.error_stack.push(Box::new(e));
+            }
+        }
+    }
+    fn group_into_macro(&mut self, nodes: Vec<TransformIdx>) {
+        let before_dst = self.dst.clone();
+        match self.dst.group_into_macro(&nodes, &mut self.macros, None) {
+            Ok(t_idx) => {
+                self.layout.group_node_states(&nodes, t_idx);
+                self.valid_history
+                    .push(event::ProvenanceEvent::GroupIntoMacro(
+                        nodes,
+                        before_dst,
+                        Ok(t_idx),
+                    ));
+            }
+            Err(e) => {
+                eprintln!("Error on grouping nodes! {}", e);
+                self.valid_history
+                    .push(event::ProvenanceEvent::GroupIntoMacro(
+                        nodes,
+                        before_dst,
+                        Err(e.clone()),
+                    ));
+                self.error_stack.push(Box::new(e));
+            }
+        }
+    }
+    fn expand_macro(&mut self, t_idx: TransformIdx) {
+        let before_dst = self.dst.clone();
+        match self.dst.expand_macro(t_idx, None) {
+            Ok(new_nodes) => {
+                self.layout.expand_node_states(t_idx, &new_nodes);
+                self.valid_history.push(event::ProvenanceEvent::ExpandMacro(
+                    t_idx,
+                    before_dst,
+                    Ok(new_nodes),
+                ));
+            }
+            Err(e) => {
+                eprintln!("Error on expanding macro! {}", e);
+                self.valid_history.push(event::ProvenanceEvent::ExpandMacro(
+                    t_idx,
+                    before_dst,
+                    Err(e.clone()),
+                ));
+                self.error_stack.push(Box::new(e));
+            }
+        }
+    }
+    fn add_annotation(&mut self, id: annotation::AnnotationId, annotation: annotation::Annotation) {
+        self.layout.annotations_mut().insert(id, annotation.clone());
+        self.valid_history
+            .push(event::ProvenanceEvent::AddAnnotation(id, annotation));
+    }
+    fn update_annotation(
+        &mut self,
+        id: annotation::AnnotationId,
+        annotation: annotation::Annotation,
+        moves: Vec<(cake::NodeId, vec2::Vec2)>,
+    ) {
+        let before = self.layout.annotations().get(&id).cloned();
+        if before.is_some() {
+            self.layout.annotations_mut().insert(id, annotation.clone());
+            self.layout.move_nodes(&moves);
+        }
+        self.valid_history
+            .push(event::ProvenanceEvent::UpdateAnnotation(
+                id, before, annotation, moves,
+            ));
+    }
+    fn remove_annotation(&mut self, id: annotation::AnnotationId) {
+        let annotation = self.layout.annotations_mut().remove(&id);
+        self.valid_history
+            .push(event::ProvenanceEvent::RemoveAnnotation(id, annotation));
+    }
+    fn move_nodes(
+        &mut self,
+        moves: Vec<(cake::NodeId, vec2::Vec2)>,
+        frames: Vec<(annotation::AnnotationId, annotation::Annotation)>,
+    ) {
+        self.layout.move_nodes(&moves);
+        let before = self.layout.replace_annotations(&frames);
+        self.valid_history
+            .push(event::ProvenanceEvent::MoveNodes(moves, before, frames));
+    }
     fn change_output_name(&mut self, node_id: cake::NodeId, name: String) {
         if let cake::NodeId::Output(output_id) = node_id {
             let node = self.dst.get_node(&node_id);
@@ -1198,7 +1854,13 @@ where
 
 impl<T, E> ApplyRenderEvent<T, E> for InnerNodeEditor<T, E>
 where
-    T: Clone + cake::ConvertibleVariants + cake::DefaultFor + serde::Serialize,
+    T: Clone
+        + cake::ConvertibleVariants
+        + cake::DefaultFor
+        + cake::NamedAlgorithms<E>
+        + cake::VariantName
+        + serde::Serialize
+        + for<'de> serde::Deserialize<'de>,
 {
     fn connect(
         &mut self,
@@ -1267,6 +1929,7 @@ where
             .write()
             .dst_mut()
             .add_transform(t, Some(handle_id));
+        self.layout.transform_added(t, t_idx);
         self.valid_history
             .push(event::ProvenanceEvent::AddTransform(t, t_idx));
     }
@@ -1476,6 +2139,101 @@ where
             }
         }
     }
+    fn paste(&mut self, text: String) {
+        // Macros cannot be registered from inside a macro editor
+        let mut macros = cake::macros::MacroManager::new();
+        let pasted = clipboard::paste(&text, &mut macros).and_then(|pasted| {
+            if macros.macros().next().is_some() {
+                Err(export::ImportError::MacroInMacro(self.handle.name()))
+            } else {
+                Ok(pasted)
+            }
+        });
+        match pasted {
+            Ok(pasted) => {
+                let new_ids = self
+                    .handle
+                    .write()
+                    .dst_mut()
+                    .merge(pasted.dst, Some(self.handle.id()));
+                let node_ids = self.layout.paste_node_states(pasted.node_states, &new_ids);
+                self.valid_history
+                    .push(event::ProvenanceEvent::Paste(text, node_ids, Ok(())));
+            }
+            Err(e) => {
+                eprintln!("Error on paste! {}", e);
+                self.valid_history.push(event::ProvenanceEvent::Paste(
+                    text,
+                    vec![],
+                    Err(e.clone()),
+                ));
+                self.error_stack.push(InnerEditorError::PasteError(e));
+            }
+        }
+    }
+    fn group_into_macro(&mut self, _: Vec<TransformIdx>) {
+        unreachable!("Macro can only be created in NodeEditor's context!");
+    }
+    fn expand_macro(&mut self, t_idx: TransformIdx) {
+        let macro_id = Some(self.handle.id());
+        let mut lock = self.handle.write();
+        let before_dst = lock.dst().clone();
+        match lock.dst_mut().expand_macro(t_idx, macro_id) {
+            Ok(new_nodes) => {
+                self.layout.expand_node_states(t_idx, &new_nodes);
+                self.valid_history.push(event::ProvenanceEvent::ExpandMacro(
+                    t_idx,
+                    before_dst,
+                    Ok(new_nodes),
+                ));
+            }
+            Err(e) => {
+                self.valid_history.push(event::ProvenanceEvent::ExpandMacro(
+                    t_idx,
+                    before_dst,
+                    Err(e.clone()),
+                ));
+                self.error_stack
+                    .push(InnerEditorError::IncorrectNodeConnection(e));
+            }
+        }
+    }
+    fn add_annotation(&mut self, id: annotation::AnnotationId, annotation: annotation::Annotation) {
+        self.layout.annotations_mut().insert(id, annotation.clone());
+        self.valid_history
+            .push(event::ProvenanceEvent::AddAnnotation(id, annotation));
+    }
+    fn update_annotation(
+        &mut self,
+        id: annotation::AnnotationId,
+        annotation: annotation::Annotation,
+        moves: Vec<(cake::NodeId, vec2::Vec2)>,
+    ) {
+        let before = self.layout.annotations().get(&id).cloned();
+        if before.is_some() {
+            self.layout.annotations_mut().insert(id, annotation.clone());
+            self.layout.move_nodes(&moves);
+        }
+        self.valid_history
+            .push(event::ProvenanceEvent::UpdateAnnotation(
+                id, before, annotation, moves,
+            ));
+    }
+    fn remove_annotation(&mut self, id: annotation::AnnotationId) {
+        let annotation = self.layout.annotations_mut().remove(&id);
+        self.valid_history
+            .push(event::ProvenanceEvent::RemoveAnnotation(id, annotation));
+    }
+    fn move_nodes(
+        &mut self,
+        moves: Vec<(cake::NodeId, vec2::Vec2)>,
+        frames: Vec<(annotation::AnnotationId, annotation::Annotation)>,
+    ) {
+        self.layout.move_nodes(&moves);
+        let before = self.layout.replace_annotations(&frames);
+        self.valid_history
+            .push(event::ProvenanceEvent::MoveNodes(moves, before, frames));
+    }
 }
 
 #[derive(Debug)]
@@ -1483,6 +2241,7 @@ enum InnerEditorError {
     IncorrectNodeConnection(cake::DSTError),
     SelfDefiningMacro { name: String },
     ExportError(export::ExportError),
+    PasteError(export::ImportError),
 }
 
 impl fmt::Display for InnerEditorError {
@@ -1492,6 +2251,7 @@ impl fmt::Display for InnerEditorError {
             IncorrectNodeConnection(e) => write!(f, "{}", e),
             SelfDefiningMacro { name } => write!(f, "Cannot re-use macro '{}' in itself!", name),
             ExportError(e) => write!(f, "Error on export macro! {}", e),
+            PasteError(e) => write!(f, "Error on paste! {}", e),
         }
     }
 }
@@ -1517,6 +2277,21 @@ where
         editor.import_from_buf(r)?;
         Ok(editor)
     }
+
+    /// Make a node editor with the given graph. Nodes are laid out
+    /// automatically.
+    pub fn from_standalone_dst(
+        dst: cake::macros::SerdeDSTStandAlone<T>,
+    ) -> Result<Self, export::ImportError> {
+        let mut editor = Self::default();
+        editor.dst = dst.into_dst_in(&mut editor.macros)?;
+        editor.layout.import(
+            node_state::NodeStates::new(),
+            Default::default(),
+            annotation::Annotations::new(),
+        );
+        Ok(editor)
+    }
 }
 
 #[derive(Serialize)]
@@ -1524,6 +2299,8 @@ struct SerialEditor<'e, T: 'e> {
     dst: cake::macros::SerdeDSTStandAlone<T>,
     node_states: Vec<(&'e cake::NodeId, &'e node_state::NodeState)>,
     scrolling: vec2::Vec2,
+    annotations: Vec<(&'e annotation::AnnotationId, &'e annotation::Annotation)>,
+    history: Vec<history::HistoryEntry<T>>,
 
     nodes_edit: Vec<SerialInnerEditor>,
 }
@@ -1537,6 +2314,13 @@ where
             dst: cake::macros::SerdeDSTStandAlone::from(&editor.dst),
             node_states: editor.layout.node_states().iter().collect(),
             scrolling: editor.layout.scrolling().get_current(),
+            annotations: editor.layout.annotations().iter().collect(),
+            history: editor
+                .loaded_history
+                .iter()
+                .chain(&editor.history)
+                .cloned()
+                .collect(),
             nodes_edit: editor
                 .nodes_edit
                 .iter()
@@ -1550,8 +2334,16 @@ where
 #[serde(bound(deserialize = "T: serde::Deserialize<'de>"))]
 struct DeserEditor<T> {
     dst: cake::macros::SerdeDSTStandAlone<T>,
+    /// Nodes are laid out automatically if missing
+    #[serde(default)]
     node_states: Vec<(cake::NodeId, node_state::NodeState)>,
     scrolling: vec2::Vec2,
+    /// Missing in files exported before annotations were introduced
+    #[serde(default)]
+    annotations: Vec<(annotation::AnnotationId, annotation::Annotation)>,
+    /// Missing in files exported before the history was saved
+    #[serde(default)]
+    history: Vec<history::HistoryEntry<T>>,
 
     nodes_edit: Vec<SerialInnerEditor>,
 }
@@ -1572,6 +2364,15 @@ where
         self.import_from_buf(f)
     }
 
+    /// Deserialize a buffer in .ron format and only get the graph, along
+    /// with the macros it uses.
+    pub fn dst_from_export_buf<R: io::Read>(
+        r: R,
+    ) -> Result<cake::macros::SerdeDSTStandAlone<T>, export::ImportError> {
+        let deserialized: DeserEditor<T> = ron::de::from_reader(r)?;
+        Ok(deserialized.dst)
+    }
+
     fn import_from_buf<R: io::Read>(&mut self, r: R) -> Result<(), export::ImportError> {
         let deserialized: DeserEditor<T> = ron::de::from_reader(r)?;
         let (dst, macros) = deserialized.dst.into_dst()?;
@@ -1586,11 +2387,17 @@ where
             node_states
         };
         let scrolling = scrolling::Scrolling::new(deserialized.scrolling);
-        self.layout.import(node_states, scrolling);
+        let annotations = annotations_from_vec(deserialized.annotations);
+        self.layout.import(node_states, scrolling, annotations);
+        self.loaded_history = deserialized.history;
 
         // Reset cache
         self.output_results = collections::BTreeMap::new();
+        let budget = self.cache.budget();
+        let disk_cache = self.cache.disk_cache().cloned();
         self.cache = cake::Cache::new();
+        self.cache.set_budget(budget);
+        self.cache.set_disk_cache(disk_cache);
 
         // Load macro editing windows
         let mut nodes_edit = Vec::with_capacity(deserialized.nodes_edit.len());
@@ -1617,7 +2424,7 @@ where
     }
 
     /// Serialize node editor to .ron file.
-    fn export_to_file<P: AsRef<path::Path>>(
+    pub fn export_to_file<P: AsRef<path::Path>>(
         &self,
         file_path: P,
     ) -> Result<(), export::ExportError> {
@@ -1626,12 +2433,117 @@ where
     }
 }
 
+impl<T, E> NodeEditor<T, E> {
+    /// Get statistics on the usage of the result cache.
+    pub fn cache_stats(&self) -> cake::CacheStats {
+        self.cache.stats()
+    }
+
+    /// Progress of the nodes being computed.
+    pub fn node_progress(&self) -> &collections::BTreeMap<TransformIdx, cake::Progress> {
+        &self.node_progress
+    }
+
+    /// Update the progress of the nodes with the events received from the
+    /// running computations.
+    fn update_progress(&mut self) {
+        for event in self.progress_receiver.try_iter() {
+            match event {
+                cake::ProgressEvent::Started(t_idx) => {
+                    *self.node_computations.entry(t_idx).or_insert(0) += 1;
+                    self.node_progress.entry(t_idx).or_default();
+                }
+                cake::ProgressEvent::Progress(t_idx, progress) => {
+                    if let Some(node_progress) = self.node_progress.get_mut(&t_idx) {
+                        *node_progress = progress;
+                    }
+                }
+                cake::ProgressEvent::Finished(t_idx) => {
+                    // Keep the progress until all the computations of the
+                    // node finished
+                    if let Some(count) = self.node_computations.get_mut(&t_idx) {
+                        *count -= 1;
+                        if *count == 0 {
+                            self.node_computations.remove(&t_idx);
+                            self.node_progress.remove(&t_idx);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Profiles of the computations of the nodes, sorted by transform index.
+    pub fn node_profiles(&self) -> Vec<(TransformIdx, cake::NodeProfile)> {
+        self.dst.profile(&self.cache)
+    }
+
+    /// Forget the profiles of the computations of the nodes.
+    pub fn reset_node_profiles(&self) {
+        self.cache.reset_profiles()
+    }
+
+    /// Heat of each node, between 0 and 1, proportional to the time spent
+    /// computing it the last time it was computed.
+    fn node_heat(&self) -> collections::BTreeMap<TransformIdx, f32> {
+        let profiles = self.node_profiles();
+        let max_time = profiles
+            .iter()
+            .map(|(_, profile)| profile.last_time)
+            .max()
+            .unwrap_or_default();
+        if max_time.as_nanos() == 0 {
+            return collections::BTreeMap::new();
+        }
+        profiles
+            .into_iter()
+            .map(|(t_idx, profile)| {
+                let heat = profile.last_time.as_secs_f32() / max_time.as_secs_f32();
+                (t_idx, heat)
+            })
+            .collect()
+    }
+
+    /// Reset hit, miss and eviction counters of the result cache.
+    pub fn reset_cache_stats(&self) {
+        self.cache.reset_stats()
+    }
+
+    /// Get the memory budget of the result cache in bytes, if any.
+    pub fn cache_budget(&self) -> Option<usize> {
+        self.cache.budget()
+    }
+
+    /// Set the memory budget of the result cache in bytes.
+    /// Results are evicted immediately if the cache is over budget.
+    pub fn set_cache_budget(&self, budget: Option<usize>) {
+        self.cache.set_budget(budget)
+    }
+
+    /// Get the disk cache in which expensive results are persisted, if any.
+    pub fn disk_cache(&self) -> Option<&sync::Arc<cake::DiskCache<T>>> {
+        self.cache.disk_cache()
+    }
+
+    /// Persist expensive results in a disk cache, so that they are not
+    /// computed again in later sessions.
+    pub fn set_disk_cache(&mut self, disk_cache: Option<sync::Arc<cake::DiskCache<T>>>) {
+        self.cache.set_disk_cache(disk_cache)
+    }
+}
+
 impl<T, E> Default for NodeEditor<T, E> {
     fn default() -> Self {
+        let (progress_sender, progress_receiver) = cake::progress::channel();
         NodeEditor {
             dst: Default::default(),
             output_results: collections::BTreeMap::new(),
             cache: cake::Cache::new(),
+            displayed_nodes: collections::BTreeSet::new(),
+            progress_sender,
+            progress_receiver,
+            node_progress: collections::BTreeMap::new(),
+            node_computations: collections::BTreeMap::new(),
             macros: cake::macros::MacroManager::new(),
             layout: Default::default(),
             error_stack: vec![],
@@ -1640,6 +2552,10 @@ impl<T, E> Default for NodeEditor<T, E> {
             import_macro: None,
             valid_history: vec![],
             redo_stack: vec![],
+            history: vec![],
+            redo_history: vec![],
+            loaded_history: vec![],
+            compared: None,
         }
     }
 }
@@ -1665,11 +2581,63 @@ where
     }
 }
 
+/// Selectable line showing the `index`-th entry of the history.
+fn history_selectable<T: cake::VariantName>(
+    ui: &imgui::Ui,
+    index: usize,
+    entry: &history::HistoryEntry<T>,
+    selected: bool,
+) -> bool {
+    let label = ImString::new(format!(
+        "#{:<4} {}  {}##history-{}",
+        index,
+        history::format_timestamp(entry.timestamp),
+        entry.event,
+        index
+    ));
+    imgui::Selectable::new(&label).selected(selected).build(ui)
+}
+
+/// Check whether both graphs are made of the same nodes and links.
+fn same_graph<T, E>(dst1: &cake::DST<'static, T, E>, dst2: &cake::DST<'static, T, E>) -> bool
+where
+    T: Clone + cake::VariantName + serde::Serialize,
+{
+    let serialize = |dst: &cake::DST<'static, T, E>| {
+        ron::ser::to_string(&cake::macros::SerdeDSTStandAlone::from(dst)).ok()
+    };
+    match (serialize(dst1), serialize(dst2)) {
+        (Some(serialized1), Some(serialized2)) => serialized1 == serialized2,
+        _ => false,
+    }
+}
+
+/// Offsets moving nodes back to where they were before `moves`.
+fn reverse_moves(moves: &[(cake::NodeId, vec2::Vec2)]) -> Vec<(cake::NodeId, vec2::Vec2)> {
+    moves
+        .iter()
+        .map(|(node_id, offset)| (*node_id, *offset * -1.0))
+        .collect()
+}
+
+fn annotations_from_vec(
+    vec: Vec<(annotation::AnnotationId, annotation::Annotation)>,
+) -> annotation::Annotations {
+    let mut annotations = annotation::Annotations::new();
+    for (id, annotation) in vec {
+        annotations.insert(id, annotation);
+    }
+    annotations
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 struct SerialInnerEditor {
     macro_id: cake::uuid::Uuid,
+    #[serde(default)]
     node_states: Vec<(cake::NodeId, node_state::NodeState)>,
     scrolling: vec2::Vec2,
+    #[serde(default)]
+    annotations: Vec<(annotation::AnnotationId, annotation::Annotation)>,
 }
 
 impl SerialInnerEditor {
@@ -1683,6 +2651,12 @@ impl SerialInnerEditor {
                 .map(|(id, state)| (*id, state.clone()))
                 .collect(),
             scrolling: editor.layout.scrolling().get_current(),
+            annotations: editor
+                .layout
+                .annotations()
+                .iter()
+                .map(|(id, annotation)| (*id, annotation.clone()))
+                .collect(),
         }
     }
 
@@ -1700,7 +2674,8 @@ impl SerialInnerEditor {
                 node_states
             };
             let scrolling = scrolling::Scrolling::new(self.scrolling);
-            layout.import(node_states, scrolling);
+            let annotations = annotations_from_vec(self.annotations);
+            layout.import(node_states, scrolling, annotations);
             Ok(InnerNodeEditor {
                 handle: handle.clone(),
                 layout,
@@ -1746,3 +2721,146 @@ impl<T> SerialInnerEditorStandAlone<T> {
         self.editor.into_inner_node_editor(&manager)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{event, same_graph, NodeEditor};
+    use crate::cake;
+    use crate::test_support::{IOErr, IOValue, PLUS1};
+
+    type Editor = NodeEditor<IOValue, IOErr>;
+
+    /// Handle `event` as when rendering the editor, and return what was
+    /// recorded.
+    fn apply(
+        editor: &mut Editor,
+        event: event::RenderEvent<IOValue, IOErr>,
+    ) -> event::ProvenanceEvent<IOValue, IOErr> {
+        editor.handle_event(event);
+        editor.record_history();
+        editor.valid_history.last().cloned().unwrap()
+    }
+
+    /// Make an editor computing constant + 1 into an output, export it and
+    /// import it back.
+    fn edited_editor() -> Editor {
+        use crate::event::{ProvenanceEvent as P, RenderEvent as R};
+
+        let mut editor = Editor::default();
+        editor
+            .valid_history
+            .push(P::Import(None, None, editor.dst.clone(), Ok(())));
+        let constant = match apply(&mut editor, R::AddConstant("Integer")) {
+            P::AddConstant(_, t_idx) => t_idx,
+            _ => panic!("Constant not added"),
+        };
+        apply(
+            &mut editor,
+            R::SetConstant(constant, Box::new(IOValue::Integer(2))),
+        );
+        let plus1 = match apply(&mut editor, R::AddTransform(&*PLUS1)) {
+            P::AddTransform(_, t_idx) => t_idx,
+            _ => panic!("Transform not added"),
+        };
+        apply(
+            &mut editor,
+            R::Connect(
+                cake::Output::new(constant, 0),
+                cake::InputSlot::Transform(cake::Input::new(plus1, 0)),
+            ),
+        );
+        let output_id = match apply(&mut editor, R::CreateOutput) {
+            P::CreateOutput(output_id) => output_id,
+            _ => panic!("Output not created"),
+        };
+        apply(
+            &mut editor,
+            R::Connect(
+                cake::Output::new(plus1, 0),
+                cake::InputSlot::Output(output_id),
+            ),
+        );
+
+        let mut buf = vec![];
+        editor.export_to_buf(&mut buf).unwrap();
+        let imported = Editor::from_export_buf(&buf[..]).unwrap();
+        assert!(same_graph(&imported.dst, &editor.dst));
+        imported
+    }
+
+    #[test]
+    fn test_replay_exported_history() {
+        let mut editor = edited_editor();
+        assert_eq!(editor.loaded_history.len(), 7);
+        let replayed = editor.replay_history().unwrap();
+        assert!(same_graph(&replayed, &editor.dst));
+        assert!(editor.rebuild_from_history().unwrap());
+    }
+
+    #[test]
+    fn test_reopen_does_not_save_graph_again() {
+        use crate::event::ProvenanceEvent as P;
+        use crate::history::HistoryEvent as H;
+
+        let mut editor = edited_editor();
+        for i in 0..2 {
+            editor
+                .valid_history
+                .push(P::Import(None, None, editor.dst.clone(), Ok(())));
+            editor.record_history();
+            assert!(matches!(editor.history[0].event, H::Open));
+
+            let mut buf = vec![];
+            editor.export_to_buf(&mut buf).unwrap();
+            editor = Editor::from_export_buf(&buf[..]).unwrap();
+            assert_eq!(editor.loaded_history.len(), 8 + i);
+        }
+        let replayed = editor.replay_history().unwrap();
+        assert!(same_graph(&replayed, &editor.dst));
+    }
+
+    #[test]
+    fn test_rebuild_from_history_keeps_graph_on_mismatch() {
+        let mut editor = edited_editor();
+        let output_id = *editor.dst.outputs_iter().next().unwrap().0;
+        editor.dst.remove_node(&cake::NodeId::Output(output_id));
+        let edited = editor.dst.clone();
+
+        assert!(!editor.rebuild_from_history().unwrap());
+        assert!(same_graph(&editor.dst, &edited));
+    }
+
+    #[test]
+    fn test_progress_of_concurrent_computations() {
+        let mut editor = Editor::default();
+        let t_idx = cake::DST::<IOValue, IOErr>::new().add_transform(&*PLUS1, None);
+        let progress = cake::Progress {
+            fraction: 0.5,
+            message: "Half".to_owned(),
+        };
+        editor
+            .progress_sender
+            .send(cake::ProgressEvent::Started(t_idx));
+        editor
+            .progress_sender
+            .send(cake::ProgressEvent::Started(t_idx));
+        editor
+            .progress_sender
+            .send(cake::ProgressEvent::Progress(t_idx, progress.clone()));
+        editor
+            .progress_sender
+            .send(cake::ProgressEvent::Finished(t_idx));
+        editor.update_progress();
+        // The other computation of the node is still running
+        assert_eq!(editor.node_progress().get(&t_idx), Some(&progress));
+
+        editor
+            .progress_sender
+            .send(cake::ProgressEvent::Finished(t_idx));
+        editor
+            .progress_sender
+            .send(cake::ProgressEvent::Progress(t_idx, progress));
+        editor.update_progress();
+        assert!(editor.node_progress().is_empty());
+    }
+}
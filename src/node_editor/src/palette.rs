@@ -0,0 +1,181 @@
+//! Fuzzy-search palette to add nodes.
+use crate::cake::{self, Transform, TypeId};
+
+/// Maximum number of transforms listed in the palette.
+pub const MAX_RESULTS: usize = 20;
+
+/// State of the command palette.
+#[derive(Default)]
+pub struct CommandPalette {
+    /// The palette is opened on next frame
+    pub open_requested: bool,
+    pub query: String,
+    /// Index of the highlighted transform in the result list
+    pub selected: usize,
+    /// Output being linked when the palette was opened, and its type
+    pub from_output: Option<(cake::Output, TypeId)>,
+}
+
+impl CommandPalette {
+    /// Request the palette to open, linking the chosen node to `from_output`
+    /// if any.
+    pub fn open(&mut self, from_output: Option<(cake::Output, TypeId)>) {
+        self.open_requested = true;
+        self.query.clear();
+        self.selected = 0;
+        self.from_output = from_output;
+    }
+
+    /// Transforms matching the query, best match first. If the palette was
+    /// opened from an output, only transforms with an input accepting the
+    /// type of the output are kept.
+    pub fn results<T, E>(
+        &self,
+        transforms: &[&'static Transform<'static, T, E>],
+    ) -> Vec<&'static Transform<'static, T, E>>
+    where
+        T: cake::ConvertibleVariants,
+    {
+        let mut scored: Vec<_> = transforms
+            .iter()
+            .filter(|t| match self.from_output {
+                Some((_, output_type)) => accepting_input::<T, E>(t, output_type).is_some(),
+                None => true,
+            })
+            .filter_map(|t| score_transform(&self.query, t).map(|score| (score, *t)))
+            .collect();
+        // Stable sort keeps the order of transforms with the same score
+        scored.sort_by(|(score1, _), (score2, _)| score2.cmp(score1));
+        scored
+            .into_iter()
+            .take(MAX_RESULTS)
+            .map(|(_, t)| t)
+            .collect()
+    }
+}
+
+/// Index of the first input of `t` that accepts values of type `output_type`.
+pub fn accepting_input<T, E>(t: &Transform<'_, T, E>, output_type: TypeId) -> Option<usize>
+where
+    T: cake::ConvertibleVariants,
+{
+    t.input_types()
+        .iter()
+        .position(|input_type| T::convertible(output_type.name(), input_type.name()))
+}
+
+/// Score how well `query` matches transform `t`, looking at its name, its
+/// description and the types of its inputs and outputs. Matches on the name
+/// weigh the most. Return [`None`] if nothing matches.
+fn score_transform<T, E>(query: &str, t: &Transform<'_, T, E>) -> Option<u32> {
+    let name_score = fuzzy_score(query, &t.name()).map(|score| 3 * score);
+    let description_score = fuzzy_score(query, &t.description());
+    let type_score = t
+        .input_types()
+        .into_iter()
+        .chain(t.outputs())
+        .filter_map(|type_id| fuzzy_score(query, type_id.name()))
+        .max()
+        .map(|score| 2 * score);
+    name_score
+        .into_iter()
+        .chain(description_score)
+        .chain(type_score)
+        .max()
+}
+
+/// Score how well `query` matches `text`.
+///
+/// All the characters of `query` must appear in `text` in the same order,
+/// regardless of case. Whitespaces in `query` are ignored. Characters found
+/// right after the previous one or at the start of a word score higher.
+/// Return [`None`] if `text` does not match.
+pub fn fuzzy_score(query: &str, text: &str) -> Option<u32> {
+    const MATCH_SCORE: u32 = 1;
+    const CONSECUTIVE_BONUS: u32 = 5;
+    const WORD_START_BONUS: u32 = 3;
+
+    let text: Vec<char> = text.chars().flat_map(char::to_lowercase).collect();
+    let mut score = 0;
+    let mut start = 0;
+    let mut previous = None;
+    for query_char in query
+        .chars()
+        .flat_map(char::to_lowercase)
+        .filter(|c| !c.is_whitespace())
+    {
+        let found = start + text[start..].iter().position(|c| *c == query_char)?;
+        score += MATCH_SCORE;
+        if found > 0 && previous == Some(found - 1) {
+            score += CONSECUTIVE_BONUS;
+        }
+        if found == 0 || !text[found - 1].is_alphanumeric() {
+            score += WORD_START_BONUS;
+        }
+        previous = Some(found);
+        start = found + 1;
+    }
+    Some(score)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{fuzzy_score, CommandPalette};
+    use crate::cake::{self, Transform, TypeId};
+    use crate::test_support::{IOErr, IOValue, DIVIDE_BY_10, PLUS1};
+
+    fn transforms() -> Vec<&'static Transform<'static, IOValue, IOErr>> {
+        vec![&*PLUS1, &*DIVIDE_BY_10]
+    }
+
+    fn result_names(palette: &CommandPalette) -> Vec<String> {
+        palette
+            .results(&transforms())
+            .iter()
+            .map(|t| t.name().into_owned())
+            .collect()
+    }
+
+    #[test]
+    fn test_fuzzy_score() {
+        assert_eq!(fuzzy_score("", "plus1"), Some(0));
+        assert_eq!(fuzzy_score("xyz", "plus1"), None);
+        // Characters must appear in order
+        assert_eq!(fuzzy_score("lp", "plus1"), None);
+        // Case and whitespaces are ignored
+        assert_eq!(
+            fuzzy_score("F T", "fits_to_table"),
+            fuzzy_score("ft", "fits_to_table")
+        );
+        // Consecutive characters and word starts score higher
+        assert!(fuzzy_score("fit", "fits").unwrap() > fuzzy_score("fit", "f_i_t").unwrap());
+        assert!(fuzzy_score("t", "to").unwrap() > fuzzy_score("t", "at").unwrap());
+    }
+
+    #[test]
+    fn test_results() {
+        let mut palette = CommandPalette::default();
+        assert_eq!(result_names(&palette), vec!["plus1", "divide_by_10"]);
+
+        palette.query = "divide".to_owned();
+        assert_eq!(result_names(&palette), vec!["divide_by_10"]);
+
+        // Matches on the name weigh more than matches on the description
+        palette.query = "d".to_owned();
+        assert_eq!(result_names(&palette), vec!["divide_by_10", "plus1"]);
+    }
+
+    #[test]
+    fn test_results_from_output() {
+        let mut dst = cake::DST::new();
+        let output = cake::Output::new(dst.add_transform(&*PLUS1, None), 0);
+        let mut palette = CommandPalette::default();
+
+        palette.open(Some((output, TypeId("Float"))));
+        assert_eq!(result_names(&palette), vec!["divide_by_10"]);
+
+        // Integers can be converted to floats
+        palette.open(Some((output, TypeId("Integer"))));
+        assert_eq!(result_names(&palette), vec!["plus1", "divide_by_10"]);
+    }
+}
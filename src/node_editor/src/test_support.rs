@@ -0,0 +1,79 @@
+//! Values and transforms used to test the node editor.
+use std::{error, fmt};
+
+use crate::cake::{self, cake_fn, cake_some_first_value, cake_transform, VariantName};
+use variant_name_derive::VariantName;
+
+#[derive(Clone, Debug, PartialEq, VariantName, Serialize, Deserialize)]
+pub enum IOValue {
+    Integer(i64),
+    Float(f64),
+}
+
+#[derive(Clone, Debug)]
+pub struct IOErr;
+
+impl fmt::Display for IOErr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "IOErr")
+    }
+}
+
+impl error::Error for IOErr {}
+
+lazy_static::lazy_static! {
+    pub static ref PLUS1: cake::Transform<'static, IOValue, IOErr> = cake_transform!(
+        "Add 1", "Calculate", 1, 0, 0,
+        plus1<IOValue, IOErr>(i: Integer = 0) -> Integer {
+            vec![Ok(IOValue::Integer(i + 1))]
+        }
+    );
+    pub static ref DIVIDE_BY_10: cake::Transform<'static, IOValue, IOErr> = cake_transform!(
+        "Divide by 10", "Calculate", 1, 0, 0,
+        divide_by_10<IOValue, IOErr>(f: Float) -> Float {
+            vec![Ok(IOValue::Float(f / 10.0))]
+        }
+    );
+}
+
+impl cake::NamedAlgorithms<IOErr> for IOValue {
+    fn get_transform(s: &str) -> Option<&'static cake::Transform<'static, IOValue, IOErr>> {
+        [&*PLUS1, &*DIVIDE_BY_10]
+            .iter()
+            .find(|t| t.name() == s)
+            .cloned()
+    }
+}
+
+impl cake::DefaultFor for IOValue {
+    fn default_for(variant_name: &str) -> Self {
+        match variant_name {
+            "Integer" => IOValue::Integer(0),
+            "Float" => IOValue::Float(0.0),
+            _ => panic!("Unknown variant name provided: {}.", variant_name),
+        }
+    }
+}
+
+impl cake::EditableVariants for IOValue {
+    fn editable_variants() -> &'static [&'static str] {
+        &["Integer", "Float"]
+    }
+}
+
+fn integer_to_float(from: &IOValue) -> IOValue {
+    if let IOValue::Integer(int) = from {
+        IOValue::Float(*int as f64)
+    } else {
+        panic!("Unexpected input!")
+    }
+}
+
+impl cake::ConvertibleVariants for IOValue {
+    const CONVERTION_TABLE: &'static [cake::ConvertibleVariant<Self>] =
+        &[cake::ConvertibleVariant {
+            from: "Integer",
+            into: "Float",
+            f: integer_to_float,
+        }];
+}
@@ -1,22 +1,28 @@
 use std::collections::HashMap;
 use std::error;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use glium;
-use imgui::{Condition, ImString, MenuItem, MouseButton, Ui, Window};
+use imgui::{Condition, ImString, MenuItem, MouseButton, Selectable, Ui, Window};
 
 use crate::aflak_plot::{imshow::Textures, interactions::InteractionId};
-use crate::cake::{NodeId, OutputId, Transform, TransformIdx};
-use crate::primitives::{IOErr, IOValue};
-use node_editor::NodeEditor;
+use crate::cake::{NodeId, NodeProfile, OutputId, Transform, TransformIdx};
+use crate::primitives::{IOErr, IOValue, SuccessOut};
+use node_editor::{history, NodeEditor};
 
 use crate::constant_editor::MyConstantEditor;
 use crate::file_dialog::{FileDialog, FileDialogEvent};
 use crate::implot::Context;
 use crate::layout::{Layout, LayoutEngine};
-use crate::output_window::OutputWindow;
+use crate::output_window::{self, OutputWindow};
 pub type AflakNodeEditor = NodeEditor<IOValue, IOErr>;
 
+/// Prefix of the name of the files where computed images are saved as FITS
+/// extensions
+const EXTENSIONS_FILE_PREFIX: &str = "outputs";
+/// Memory budget in megabytes of the cache when limiting is first enabled
+const DEFAULT_CACHE_BUDGET_MB: usize = 1024;
+
 pub struct Aflak {
     node_editor: AflakNodeEditor,
     layout_engine: LayoutEngine,
@@ -27,8 +33,19 @@ pub struct Aflak {
     recent_files: Vec<PathBuf>,
     pub show_metrics: bool,
     pub show_bind_manager: bool,
+    pub show_cache: bool,
+    pub show_profiling: bool,
+    pub show_history: bool,
+    /// Column by which the profiling table is sorted, and whether the
+    /// order is descending
+    profiling_sort: (ProfilingColumn, bool),
     copying: Option<(InteractionId, TransformIdx)>,
     attaching: Option<(OutputId, TransformIdx, usize)>,
+    /// Outputs listed when saving images as FITS extensions, and whether
+    /// they are selected
+    extension_outputs: Vec<(OutputId, String, bool)>,
+    /// Path of the file where images were last saved as FITS extensions
+    saved_extensions: Option<PathBuf>,
 }
 
 impl Aflak {
@@ -43,13 +60,21 @@ impl Aflak {
             recent_files: vec![],
             show_metrics: false,
             show_bind_manager: false,
+            show_cache: false,
+            show_profiling: false,
+            show_history: false,
+            profiling_sort: (ProfilingColumn::LastTime, true),
             copying: None,
             attaching: None,
+            extension_outputs: vec![],
+            saved_extensions: None,
         }
     }
 
     pub fn main_menu_bar(&mut self, ui: &Ui) {
         let mut new_editor = false;
+        let mut save_extensions = false;
+        let mut extensions_saved_success_popup = false;
 
         if let Some(menu_bar) = ui.begin_main_menu_bar() {
             if let Some(menu) = ui.begin_menu_with_enabled(format!("File"), true) {
@@ -70,6 +95,15 @@ impl Aflak {
                     menu.end();
                 }
                 ui.separator();
+                if MenuItem::new(format!("Save Images as FITS Extensions")).build(ui) {
+                    save_extensions = true;
+                }
+                if ui.is_item_hovered() {
+                    ui.tooltip_text(format!(
+                        "Save the images computed by the selected outputs in a single FITS file, one extension per output"
+                    ));
+                }
+                ui.separator();
                 if MenuItem::new(format!("Quit"))
                     .shortcut(format!("Alt+F4"))
                     .build(ui)
@@ -85,11 +119,79 @@ impl Aflak {
                 if MenuItem::new(format!("Bind manager")).build(ui) {
                     self.show_bind_manager = !self.show_bind_manager;
                 }
+                if MenuItem::new(format!("Cache")).build(ui) {
+                    self.show_cache = !self.show_cache;
+                }
+                if MenuItem::new(format!("Profiling")).build(ui) {
+                    self.show_profiling = !self.show_profiling;
+                }
+                if MenuItem::new(format!("History")).build(ui) {
+                    self.show_history = !self.show_history;
+                }
                 menu.end();
             }
             menu_bar.end();
         }
 
+        if save_extensions {
+            self.extension_outputs = self
+                .node_editor
+                .outputs()
+                .into_iter()
+                .map(|(output_id, name)| (output_id, name, true))
+                .collect();
+            ui.open_popup(format!("Save Images as FITS Extensions"));
+        }
+        let mut save = false;
+        ui.popup_modal(format!("Save Images as FITS Extensions"))
+            .always_auto_resize(true)
+            .build(ui, || {
+                ui.text("Outputs to save. Outputs that are not images are skipped.");
+                ui.separator();
+                for (output_id, name, selected) in &mut self.extension_outputs {
+                    let label = if name.is_empty() {
+                        format!("Output #{}", output_id.id())
+                    } else {
+                        format!("Output #{} ({})", output_id.id(), name)
+                    };
+                    ui.checkbox(label, selected);
+                }
+                ui.separator();
+                if ui.button(format!("Save")) {
+                    save = true;
+                    ui.close_current_popup();
+                }
+                ui.same_line();
+                if ui.button(format!("Cancel")) {
+                    ui.close_current_popup();
+                }
+            });
+        if save {
+            match self.save_images_as_extensions() {
+                Ok(path) => {
+                    self.saved_extensions = Some(path);
+                    extensions_saved_success_popup = true;
+                }
+                Err(e) => self.error_alerts.push(Box::new(e)),
+            }
+        }
+
+        if extensions_saved_success_popup {
+            ui.open_popup(format!("Images saved!"));
+        }
+        let saved_extensions = &self.saved_extensions;
+        ui.popup_modal(format!("Images saved!")).build(ui, || {
+            if let Some(path) = saved_extensions {
+                ui.text(format!(
+                    "Images saved with success to '{}'.",
+                    path.display()
+                ));
+            }
+            if ui.button(format!("Close")) {
+                ui.close_current_popup();
+            }
+        });
+
         if new_editor {
             ui.open_popup(format!("Start new node program"));
         }
@@ -99,7 +201,7 @@ impl Aflak {
                 ui.text("The current node program will be lost. Proceed?");
                 ui.separator();
                 if ui.button(format!("OK")) {
-                    self.node_editor = NodeEditor::default();
+                    self.replace_node_editor(NodeEditor::default());
                     ui.close_current_popup();
                 }
                 ui.same_line();
@@ -109,6 +211,29 @@ impl Aflak {
             });
     }
 
+    /// Save the images computed by the selected outputs in a new FITS file
+    /// in the current directory, and return its path. Outputs still being
+    /// computed are skipped.
+    fn save_images_as_extensions(&mut self) -> Result<PathBuf, output_window::ExportError> {
+        let mut values = vec![];
+        for (output_id, name, selected) in &self.extension_outputs {
+            if !selected {
+                continue;
+            }
+            if let Some(Ok(result)) = self.node_editor.compute_output(*output_id) {
+                values.push((*output_id, name, SuccessOut::take(result)));
+            }
+        }
+        let path = unique_extensions_path(Path::new("."), history::now());
+        output_window::save_images_as_extensions(
+            values
+                .iter()
+                .map(|(output_id, name, value)| (*output_id, name.as_str(), &**value)),
+            &path,
+        )?;
+        Ok(path)
+    }
+
     pub fn node_editor(&mut self, ui: &Ui, addable_nodes: &[&'static Transform<IOValue, IOErr>]) {
         let display_size = ui.io().display_size;
         let Layout { position, size } = self.layout_engine.default_editor_layout(display_size);
@@ -189,7 +314,7 @@ impl Aflak {
             match dialog.build(ui) {
                 Some(FileDialogEvent::Selection(result)) => {
                     match result.to_node_editor() {
-                        Ok(node_editor) => self.node_editor = node_editor,
+                        Ok(node_editor) => self.replace_node_editor(node_editor),
                         Err(e) => self.error_alerts.push(Box::new(e)),
                     }
                     if !self.recent_files.contains(&result.path) {
@@ -203,6 +328,178 @@ impl Aflak {
         }
     }
 
+    /// Replace the current node editor, keeping the memory budget and the
+    /// disk cache of its cache.
+    fn replace_node_editor(&mut self, mut node_editor: AflakNodeEditor) {
+        node_editor.set_cache_budget(self.node_editor.cache_budget());
+        node_editor.set_disk_cache(self.node_editor.disk_cache().cloned());
+        self.node_editor = node_editor;
+    }
+
+    pub fn cache_window(&mut self, ui: &Ui) {
+        const MB: f64 = 1024.0 * 1024.0;
+        let node_editor = &self.node_editor;
+        let mut disk_cache_error = None;
+        Window::new(format!("Cache"))
+            .opened(&mut self.show_cache)
+            .always_auto_resize(true)
+            .build(ui, || {
+                let stats = node_editor.cache_stats();
+                ui.text(format!("Entries: {}", stats.entries));
+                match stats.budget {
+                    Some(budget) => ui.text(format!(
+                        "Memory: {:.1} / {:.1} MB",
+                        stats.bytes as f64 / MB,
+                        budget as f64 / MB
+                    )),
+                    None => ui.text(format!("Memory: {:.1} MB", stats.bytes as f64 / MB)),
+                }
+                ui.text(format!("Hits: {}", stats.hits));
+                ui.text(format!("Shared with concurrent requests: {}", stats.shared));
+                ui.text(format!("Misses: {}", stats.misses));
+                if node_editor.disk_cache().is_some() {
+                    ui.text(format!("Loaded from disk: {}", stats.disk_hits));
+                }
+                ui.text(format!("Evictions: {}", stats.evictions));
+                if ui.button(format!("Reset statistics")) {
+                    node_editor.reset_cache_stats();
+                }
+                ui.separator();
+
+                let mut bounded = stats.budget.is_some();
+                if ui.checkbox(format!("Limit memory"), &mut bounded) {
+                    node_editor.set_cache_budget(if bounded {
+                        Some(DEFAULT_CACHE_BUDGET_MB * 1024 * 1024)
+                    } else {
+                        None
+                    });
+                }
+                if let Some(budget) = stats.budget {
+                    let mut budget_mb = (budget / (1024 * 1024)) as i32;
+                    if ui
+                        .input_int(format!("Budget (MB)"), &mut budget_mb)
+                        .enter_returns_true(true)
+                        .build()
+                    {
+                        let budget_mb = budget_mb.max(0) as usize;
+                        node_editor.set_cache_budget(Some(budget_mb * 1024 * 1024));
+                    }
+                }
+
+                if let Some(disk_cache) = node_editor.disk_cache() {
+                    ui.separator();
+                    ui.text(format!("Disk cache: {}", disk_cache.dir().display()));
+                    ui.text(format!("Stored results: {}", disk_cache.len()));
+                    match disk_cache.max_bytes() {
+                        Some(max_bytes) => ui.text(format!(
+                            "Disk usage: {:.1} / {:.1} MB",
+                            disk_cache.size() as f64 / MB,
+                            max_bytes as f64 / MB
+                        )),
+                        None => ui.text(format!(
+                            "Disk usage: {:.1} MB",
+                            disk_cache.size() as f64 / MB
+                        )),
+                    }
+                    if ui.button(format!("Clear disk cache")) {
+                        if let Err(e) = disk_cache.clear() {
+                            disk_cache_error = Some(e);
+                        }
+                    }
+                }
+            });
+        if let Some(e) = disk_cache_error {
+            self.error_alerts.push(Box::new(e));
+        }
+    }
+
+    pub fn history_window(&mut self, ui: &Ui) {
+        let node_editor = &mut self.node_editor;
+        Window::new(format!("History"))
+            .opened(&mut self.show_history)
+            .size([480.0, 400.0], Condition::FirstUseEver)
+            .build(ui, || node_editor.render_history(ui));
+    }
+
+    pub fn profiling_window(&mut self, ui: &Ui) {
+        let node_editor = &self.node_editor;
+        let (sort_column, descending) = &mut self.profiling_sort;
+        Window::new(format!("Profiling"))
+            .opened(&mut self.show_profiling)
+            .size([640.0, 400.0], Condition::FirstUseEver)
+            .build(ui, || {
+                let mut rows: Vec<_> = node_editor
+                    .node_profiles()
+                    .into_iter()
+                    .map(|(t_idx, profile)| {
+                        let name = node_editor
+                            .dst
+                            .get_transform(t_idx)
+                            .map(|t| t.name().to_string())
+                            .unwrap_or_default();
+                        (t_idx, name, profile)
+                    })
+                    .collect();
+                rows.sort_by(|row1, row2| {
+                    let ordering = sort_column.compare(row1, row2);
+                    if *descending {
+                        ordering.reverse()
+                    } else {
+                        ordering
+                    }
+                });
+
+                let total: f64 = rows
+                    .iter()
+                    .map(|(_, _, profile)| profile.last_time.as_secs_f64())
+                    .sum();
+                ui.text(format!(
+                    "{} nodes, {:.1} ms spent in the last computation of each node",
+                    rows.len(),
+                    total * 1000.0
+                ));
+                ui.same_line();
+                if ui.button(format!("Reset")) {
+                    node_editor.reset_node_profiles();
+                }
+                ui.separator();
+
+                ui.columns(
+                    ProfilingColumn::ALL.len() as i32,
+                    format!("profiling_columns"),
+                    true,
+                );
+                for column in ProfilingColumn::ALL.iter() {
+                    let header = if column == sort_column {
+                        format!(
+                            "{} {}",
+                            column.header(),
+                            if *descending { "v" } else { "^" }
+                        )
+                    } else {
+                        column.header().to_owned()
+                    };
+                    if Selectable::new(&header).build(ui) {
+                        if column == sort_column {
+                            *descending = !*descending;
+                        } else {
+                            *sort_column = *column;
+                            *descending = true;
+                        }
+                    }
+                    ui.next_column();
+                }
+                ui.separator();
+                for row in &rows {
+                    for column in ProfilingColumn::ALL.iter() {
+                        ui.text(column.format(row));
+                        ui.next_column();
+                    }
+                }
+                ui.columns(1, format!("profiling_columns_end"), false);
+            });
+    }
+
     pub fn bind_manager(&mut self, ui: &Ui) {
         Window::new(format!("Bind Manager")).build(ui, || {
             ui.text("Bindings:");
@@ -278,3 +575,96 @@ impl Aflak {
         });
     }
 }
+
+/// Path of a file in `dir` named after `timestamp` in seconds since the UNIX
+/// epoch, e.g. "outputs-20190314-092653.fits". A counter is appended if such a
+/// file already exists, so that no file is overwritten.
+fn unique_extensions_path(dir: &Path, timestamp: u64) -> PathBuf {
+    let time = history::format_timestamp(timestamp)
+        .replace(|c| c == '-' || c == ':', "")
+        .replace(' ', "-");
+    let mut path = dir.join(format!("{}-{}.fits", EXTENSIONS_FILE_PREFIX, time));
+    let mut count = 1;
+    while path.exists() {
+        path = dir.join(format!(
+            "{}-{}-{}.fits",
+            EXTENSIONS_FILE_PREFIX, time, count
+        ));
+        count += 1;
+    }
+    path
+}
+
+/// Column of the profiling table.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum ProfilingColumn {
+    Node,
+    Name,
+    LastTime,
+    MeanTime,
+    TotalTime,
+    Hits,
+    Misses,
+    Size,
+}
+
+type ProfilingRow = (TransformIdx, String, NodeProfile);
+
+impl ProfilingColumn {
+    const ALL: [ProfilingColumn; 8] = [
+        ProfilingColumn::Node,
+        ProfilingColumn::Name,
+        ProfilingColumn::LastTime,
+        ProfilingColumn::MeanTime,
+        ProfilingColumn::TotalTime,
+        ProfilingColumn::Hits,
+        ProfilingColumn::Misses,
+        ProfilingColumn::Size,
+    ];
+
+    fn header(self) -> &'static str {
+        match self {
+            ProfilingColumn::Node => "Node",
+            ProfilingColumn::Name => "Name",
+            ProfilingColumn::LastTime => "Last (ms)",
+            ProfilingColumn::MeanTime => "Mean (ms)",
+            ProfilingColumn::TotalTime => "Total (ms)",
+            ProfilingColumn::Hits => "Hits",
+            ProfilingColumn::Misses => "Misses",
+            ProfilingColumn::Size => "Size (KB)",
+        }
+    }
+
+    fn format(self, (t_idx, name, profile): &ProfilingRow) -> String {
+        match self {
+            ProfilingColumn::Node => format!("#{}", t_idx.id()),
+            ProfilingColumn::Name => name.clone(),
+            ProfilingColumn::LastTime => format!("{:.2}", profile.last_time.as_secs_f64() * 1e3),
+            ProfilingColumn::MeanTime => {
+                format!("{:.2}", profile.mean_time().as_secs_f64() * 1e3)
+            }
+            ProfilingColumn::TotalTime => {
+                format!("{:.2}", profile.total_time.as_secs_f64() * 1e3)
+            }
+            ProfilingColumn::Hits => format!("{}", profile.hits),
+            ProfilingColumn::Misses => format!("{}", profile.misses),
+            ProfilingColumn::Size => format!("{:.1}", profile.bytes as f64 / 1024.0),
+        }
+    }
+
+    fn compare(self, row1: &ProfilingRow, row2: &ProfilingRow) -> std::cmp::Ordering {
+        let (t_idx1, name1, profile1) = row1;
+        let (t_idx2, name2, profile2) = row2;
+        match self {
+            ProfilingColumn::Node => t_idx1.cmp(t_idx2),
+            ProfilingColumn::Name => name1.cmp(name2),
+            ProfilingColumn::LastTime => profile1.last_time.cmp(&profile2.last_time),
+            ProfilingColumn::MeanTime => profile1.mean_time().cmp(&profile2.mean_time()),
+            ProfilingColumn::TotalTime => profile1.total_time.cmp(&profile2.total_time),
+            ProfilingColumn::Hits => profile1.hits.cmp(&profile2.hits),
+            ProfilingColumn::Misses => profile1.misses.cmp(&profile2.misses),
+            ProfilingColumn::Size => profile1.bytes.cmp(&profile2.bytes),
+        }
+        .then(t_idx1.cmp(t_idx2))
+    }
+}
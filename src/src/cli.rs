@@ -1,4 +1,4 @@
-use clap::{App, Arg};
+use clap::{App, Arg, SubCommand};
 
 pub const TEMPLATES: &[&str] = &[
     "waveform",
@@ -33,4 +33,88 @@ pub fn build_cli() -> App<'static, 'static> {
                 .conflicts_with("template")
                 .help("Import editor from .ron file"),
         )
+        .arg(
+            Arg::with_name("replay")
+                .long("replay")
+                .requires("ron")
+                .help("Rebuild the graph by replaying the history saved in the .ron file"),
+        )
+        .arg(
+            Arg::with_name("headless")
+                .long("headless")
+                .help("Compute all outputs without opening any window, then exit"),
+        )
+        .arg(
+            Arg::with_name("output-dir")
+                .short("o")
+                .long("output-dir")
+                .value_name("DIR")
+                .default_value(".")
+                .help("Directory where outputs are saved in headless mode"),
+        )
+        .arg(
+            Arg::with_name("set")
+                .long("set")
+                .value_name("NODE_ID=VALUE")
+                .multiple(true)
+                .number_of_values(1)
+                .requires("headless")
+                .help(
+                    "Override the value of the constant node with the given ID \
+                     (e.g. --set 1=cube.fits). Paths are separated by commas",
+                ),
+        )
+        .arg(
+            Arg::with_name("cache-size")
+                .long("cache-size")
+                .conflicts_with("headless")
+                .value_name("MB")
+                .help("Limit the memory used to cache intermediate results (in megabytes)"),
+        )
+        .arg(
+            Arg::with_name("disk-cache")
+                .long("disk-cache")
+                .conflicts_with("headless")
+                .value_name("DIR")
+                .help(
+                    "Persist expensive results in this directory, \
+                     so that they are reloaded instead of computed in later sessions",
+                ),
+        )
+        .arg(
+            Arg::with_name("disk-cache-size")
+                .long("disk-cache-size")
+                .conflicts_with("headless")
+                .value_name("MB")
+                .requires("disk-cache")
+                .help("Limit the size of the disk cache (in megabytes)"),
+        )
+        .subcommand(
+            SubCommand::with_name("diff")
+                .about("Print the differences between two .ron files, or merge them")
+                .arg(
+                    Arg::with_name("ours")
+                        .value_name("OURS")
+                        .required(true)
+                        .help("Older .ron file, or our version of the pipeline when merging"),
+                )
+                .arg(
+                    Arg::with_name("theirs")
+                        .value_name("THEIRS")
+                        .required(true)
+                        .help("Newer .ron file, or their version of the pipeline when merging"),
+                )
+                .arg(Arg::with_name("base").long("base").value_name("BASE").help(
+                    "Common ancestor of OURS and THEIRS. \
+                             Attempt a three-way merge of both versions",
+                ))
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("RON FILE")
+                        .requires("base")
+                        .help("Save the merged pipeline to this .ron file"),
+                ),
+        )
 }
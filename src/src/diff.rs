@@ -0,0 +1,131 @@
+//! Compare two pipelines saved as .ron files from the command line, and
+//! attempt to merge them.
+use std::fs;
+
+use crate::cake::macros::SerdeDSTStandAlone;
+use crate::primitives::IOValue;
+
+use crate::aflak::AflakNodeEditor;
+
+/// Run the `diff` subcommand.
+///
+/// Without a base, print the differences from OURS to THEIRS. With a base,
+/// print the changes made on each side, then merge them and report every
+/// conflict. On conflict, our side is kept.
+///
+/// Return the exit code of the process: non-zero if any file could not be
+/// read or written, or if the merge has conflicts.
+pub fn run(matches: &clap::ArgMatches) -> i32 {
+    let ours = matches.value_of("ours").unwrap();
+    let theirs = matches.value_of("theirs").unwrap();
+    let (ours, theirs) = match (read_dst(ours), read_dst(theirs)) {
+        (Some(ours), Some(theirs)) => (ours, theirs),
+        _ => return 1,
+    };
+
+    let base = if let Some(base) = matches.value_of("base") {
+        match read_dst(base) {
+            Some(base) => base,
+            None => return 1,
+        }
+    } else {
+        print_diff(&ours, &theirs);
+        return 0;
+    };
+
+    println!("Changes in OURS:");
+    print_diff(&base, &ours);
+    println!("Changes in THEIRS:");
+    print_diff(&base, &theirs);
+
+    let merge = SerdeDSTStandAlone::merge(&base, &ours, &theirs);
+    for (from, to) in &merge.renumbered_nodes {
+        println!("Node #{} of THEIRS is renumbered #{}.", from.id(), to.id());
+    }
+    for (from, to) in &merge.renumbered_outputs {
+        println!(
+            "Output node #{} of THEIRS is renumbered #{}.",
+            from.id(),
+            to.id()
+        );
+    }
+    for (id, renumbered_nodes) in &merge.macro_renumbered_nodes {
+        for (from, to) in renumbered_nodes {
+            println!(
+                "Node #{} of THEIRS in macro {} is renumbered #{}.",
+                from.id(),
+                id,
+                to.id()
+            );
+        }
+    }
+    for (id, renumbered_outputs) in &merge.macro_renumbered_outputs {
+        for (from, to) in renumbered_outputs {
+            println!(
+                "Output node #{} of THEIRS in macro {} is renumbered #{}.",
+                from.id(),
+                id,
+                to.id()
+            );
+        }
+    }
+    let mut conflict_count = merge.conflicts.len();
+    for conflict in &merge.conflicts {
+        eprintln!("Conflict: {}", conflict);
+    }
+    for (id, conflicts) in &merge.macro_conflicts {
+        conflict_count += conflicts.len();
+        for conflict in conflicts {
+            eprintln!("Conflict in macro {}: {}", id, conflict);
+        }
+    }
+    if conflict_count == 0 {
+        println!("Merged without conflict.");
+    } else {
+        eprintln!(
+            "{} conflict(s) found. OURS is kept for each of them.",
+            conflict_count
+        );
+    }
+
+    if let Some(output) = matches.value_of("output") {
+        let saved = AflakNodeEditor::from_standalone_dst(merge.dst)
+            .map_err(|e| e.to_string())
+            .and_then(|editor| editor.export_to_file(output).map_err(|e| e.to_string()));
+        match saved {
+            Ok(()) => println!("Merged pipeline saved to '{}'.", output),
+            Err(e) => {
+                eprintln!("Could not save merged pipeline to '{}': {}", output, e);
+                return 1;
+            }
+        }
+    }
+
+    if conflict_count == 0 {
+        0
+    } else {
+        1
+    }
+}
+
+fn read_dst(path: &str) -> Option<SerdeDSTStandAlone<IOValue>> {
+    let dst = fs::File::open(path)
+        .map_err(|e| e.to_string())
+        .and_then(|f| AflakNodeEditor::dst_from_export_buf(f).map_err(|e| e.to_string()));
+    match dst {
+        Ok(dst) => Some(dst),
+        Err(e) => {
+            eprintln!("Error on opening file '{}': {}", path, e);
+            None
+        }
+    }
+}
+
+fn print_diff(before: &SerdeDSTStandAlone<IOValue>, after: &SerdeDSTStandAlone<IOValue>) {
+    let diff = before.diff(after);
+    if diff.is_empty() {
+        println!("No difference.");
+    } else {
+        print!("{}", diff);
+    }
+}
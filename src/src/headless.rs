@@ -0,0 +1,195 @@
+//! Run a pipeline from the command line, without opening any window.
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cake::{NodeId, TransformIdx, VariantName};
+use crate::primitives::{IOValue, PATHS};
+
+use crate::aflak::AflakNodeEditor;
+use crate::output_window;
+
+/// Compute every attached output of `editor` and save each result in
+/// `output_dir`.
+///
+/// `overrides` are strings formatted as `NODE_ID=VALUE`. Each of them replaces
+/// the value of a constant node of the editor before anything is computed.
+///
+/// Return the exit code of the process: non-zero if any override is invalid or
+/// if any output could not be computed or saved.
+pub fn run<'a, I>(mut editor: AflakNodeEditor, overrides: I, output_dir: &Path) -> i32
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    for arg in overrides {
+        if let Err(e) = override_constant(&mut editor, arg) {
+            eprintln!("Invalid override '{}': {}", arg, e);
+            return 2;
+        }
+    }
+
+    if let Err(e) = fs::create_dir_all(output_dir) {
+        eprintln!(
+            "Could not create output directory '{}': {}",
+            output_dir.display(),
+            e
+        );
+        return 1;
+    }
+
+    let outputs = editor.outputs();
+    if outputs.is_empty() {
+        eprintln!("No output node is attached. Nothing to compute.");
+    }
+
+    let mut cache = HashMap::new();
+    let mut failed = false;
+    for (output_id, name) in outputs {
+        match editor.dst.compute_sync(output_id, &mut cache) {
+            Ok(value) => match output_window::save_output(output_id, &value, output_dir) {
+                Ok(Some(path)) => println!(
+                    "Output #{} '{}' saved to '{}'.",
+                    output_id.id(),
+                    name,
+                    path.display()
+                ),
+                Ok(None) => println!(
+                    "Output #{} '{}' of type {} cannot be saved. Skipped.",
+                    output_id.id(),
+                    name,
+                    value.variant_name()
+                ),
+                Err(e) => {
+                    eprintln!(
+                        "Error on saving output #{} '{}': {}",
+                        output_id.id(),
+                        name,
+                        e
+                    );
+                    failed = true;
+                }
+            },
+            Err(e) => {
+                eprintln!(
+                    "Error on computing output #{} '{}': {}",
+                    output_id.id(),
+                    name,
+                    e
+                );
+                failed = true;
+            }
+        }
+    }
+
+    if failed {
+        1
+    } else {
+        0
+    }
+}
+
+/// Parse `arg` formatted as `NODE_ID=VALUE` and set the constant node
+/// `NODE_ID` of the main graph to `VALUE`.
+fn override_constant(editor: &mut AflakNodeEditor, arg: &str) -> Result<(), OverrideError> {
+    let mut split = arg.splitn(2, '=');
+    let id = split.next().unwrap_or_default();
+    let value = split.next().ok_or(OverrideError::Syntax)?;
+    let id: usize = id.trim().parse().map_err(|_| OverrideError::Syntax)?;
+
+    let t_idx = find_transform(editor, id).ok_or(OverrideError::NodeNotFound(id))?;
+    let current = editor
+        .constant_node_value(t_idx)
+        .ok_or(OverrideError::NotAConstant(id))?;
+    let new_value = parse_constant(current, value)?;
+    editor.update_constant_node(t_idx, new_value);
+    Ok(())
+}
+
+fn find_transform(editor: &AflakNodeEditor, id: usize) -> Option<TransformIdx> {
+    editor
+        .dst
+        .nodes_iter()
+        .find_map(|(node_id, _)| match node_id {
+            NodeId::Transform(t_idx) if t_idx.macro_id().is_none() && t_idx.id() == id => {
+                Some(t_idx)
+            }
+            _ => None,
+        })
+}
+
+/// Parse `value` into a value of the same type as `current`.
+fn parse_constant(current: &IOValue, value: &str) -> Result<IOValue, OverrideError> {
+    let invalid = || OverrideError::InvalidValue {
+        expected: current.variant_name(),
+        value: value.to_owned(),
+    };
+    match current {
+        IOValue::Integer(_) => value
+            .trim()
+            .parse()
+            .map(IOValue::Integer)
+            .map_err(|_| invalid()),
+        IOValue::Float(_) => value
+            .trim()
+            .parse()
+            .map(IOValue::Float)
+            .map_err(|_| invalid()),
+        IOValue::Bool(_) => value
+            .trim()
+            .parse()
+            .map(IOValue::Bool)
+            .map_err(|_| invalid()),
+        IOValue::Str(_) => Ok(IOValue::Str(value.to_owned())),
+        IOValue::Paths(_) => Ok(IOValue::Paths(PATHS::FileList(
+            value
+                .split(',')
+                .filter(|path| !path.trim().is_empty())
+                .map(|path| PathBuf::from(path.trim()))
+                .collect(),
+        ))),
+        _ => Err(OverrideError::UnsupportedType(current.variant_name())),
+    }
+}
+
+#[derive(Debug)]
+enum OverrideError {
+    Syntax,
+    NodeNotFound(usize),
+    NotAConstant(usize),
+    InvalidValue {
+        expected: &'static str,
+        value: String,
+    },
+    UnsupportedType(&'static str),
+}
+
+impl fmt::Display for OverrideError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::OverrideError::*;
+        match self {
+            Syntax => write!(f, "Expected NODE_ID=VALUE"),
+            NodeNotFound(id) => write!(f, "Node #{} not found", id),
+            NotAConstant(id) => write!(f, "Node #{} is not a constant node", id),
+            InvalidValue { expected, value } => {
+                write!(f, "Could not parse '{}' as {}", value, expected)
+            }
+            UnsupportedType(name) => {
+                write!(
+                    f,
+                    "Constant of type {} cannot be set from command line",
+                    name
+                )
+            }
+        }
+    }
+}
+
+impl error::Error for OverrideError {
+    /// description is deprecated. See https://github.com/rust-lang/rust/issues/44842
+    /// Implement for compilation to succeed on older compilers.
+    fn description(&self) -> &str {
+        "OverrideError"
+    }
+}
@@ -18,7 +18,9 @@ extern crate node_editor;
 mod aflak;
 mod cli;
 mod constant_editor;
+mod diff;
 mod file_dialog;
+mod headless;
 mod layout;
 mod output_window;
 mod templates;
@@ -28,7 +30,10 @@ use std::fs;
 use std::io::{self, Read};
 use std::path::PathBuf;
 use std::process;
+use std::sync::Arc;
 
+use crate::cake::DiskCache;
+use crate::primitives::IOValueCodec;
 use node_editor::NodeEditor;
 
 use crate::aflak::Aflak;
@@ -40,6 +45,10 @@ const CLEAR_COLOR: [f32; 4] = [0.05, 0.05, 0.05, 1.0];
 fn main() {
     let matches = cli::build_cli().version(version()).get_matches();
 
+    if let Some(matches) = matches.subcommand_matches("diff") {
+        process::exit(diff::run(matches));
+    }
+
     let import_data = match open_buffer(&matches) {
         Ok(buf) => buf,
         Err(e) => {
@@ -52,7 +61,7 @@ fn main() {
         }
     };
 
-    let node_editor = match NodeEditor::from_export_buf(import_data) {
+    let mut node_editor = match NodeEditor::from_export_buf(import_data) {
         Ok(mut editor) => {
             editor
                 .valid_history
@@ -65,11 +74,79 @@ fn main() {
             editor
         }
         Err(e) => {
+            if matches.is_present("headless") {
+                eprintln!("Import failed! {}", e);
+                process::exit(1)
+            }
             eprintln!("Import failed! Initialize empty node editor.\n{}", e);
             NodeEditor::default()
         }
     };
 
+    if matches.is_present("replay") {
+        match node_editor.rebuild_from_history() {
+            Ok(true) => println!("Replayed history gives back the saved graph."),
+            Ok(false) => {
+                eprintln!("Replayed history differs from the saved graph! Keep the saved graph.");
+                if matches.is_present("headless") {
+                    process::exit(1)
+                }
+            }
+            Err(e) => {
+                eprintln!("Could not replay history! {}", e);
+                process::exit(1)
+            }
+        }
+    }
+
+    if let Some(cache_size) = matches.value_of("cache-size") {
+        match cache_size.parse::<usize>() {
+            Ok(mb) => match mb.checked_mul(1024 * 1024) {
+                Some(bytes) => node_editor.set_cache_budget(Some(bytes)),
+                None => {
+                    eprintln!("Cache size of {} MB is too large", mb);
+                    process::exit(1)
+                }
+            },
+            Err(e) => {
+                eprintln!("Invalid cache size '{}': {}", cache_size, e);
+                process::exit(1)
+            }
+        }
+    }
+
+    if let Some(dir) = matches.value_of("disk-cache") {
+        let mut disk_cache = match DiskCache::open(dir, Box::new(IOValueCodec)) {
+            Ok(disk_cache) => disk_cache,
+            Err(e) => {
+                eprintln!("Could not open disk cache '{}': {}", dir, e);
+                process::exit(1)
+            }
+        };
+        if let Some(disk_cache_size) = matches.value_of("disk-cache-size") {
+            match disk_cache_size.parse::<u64>() {
+                Ok(mb) => match mb.checked_mul(1024 * 1024) {
+                    Some(bytes) => disk_cache.set_max_bytes(Some(bytes)),
+                    None => {
+                        eprintln!("Disk cache size of {} MB is too large", mb);
+                        process::exit(1)
+                    }
+                },
+                Err(e) => {
+                    eprintln!("Invalid disk cache size '{}': {}", disk_cache_size, e);
+                    process::exit(1)
+                }
+            }
+        }
+        node_editor.set_disk_cache(Some(Arc::new(disk_cache)));
+    }
+
+    if matches.is_present("headless") {
+        let overrides = matches.values_of("set").into_iter().flatten();
+        let output_dir = path_clean_up(matches.value_of("output-dir"), ".");
+        process::exit(headless::run(node_editor, overrides, &output_dir));
+    }
+
     let mut aflak = Aflak::init(node_editor);
 
     let config = support::AppConfig {
@@ -94,6 +171,15 @@ fn main() {
         if aflak.show_bind_manager {
             aflak.bind_manager(ui);
         }
+        if aflak.show_cache {
+            aflak.cache_window(ui);
+        }
+        if aflak.show_profiling {
+            aflak.profiling_window(ui);
+        }
+        if aflak.show_history {
+            aflak.history_window(ui);
+        }
         !aflak.quit
     })
 }
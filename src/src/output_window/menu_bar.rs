@@ -3,6 +3,7 @@ use std::fmt;
 use std::fs;
 use std::io::{self, Write};
 use std::path::Path;
+use std::sync::Arc;
 use std::time::Instant;
 
 use glium;
@@ -11,17 +12,13 @@ use imgui::{MenuItem, TextureId, Ui, Window};
 use owning_ref::ArcRef;
 
 use crate::aflak_plot::{
-    imshow::{Textures, UiImage2d},
+    imshow::{Graticule, Textures, UiImage2d},
     plot::UiImage1d,
     scatter_lineplot::UiScatter,
     AxisTransform, InteractionId, InteractionIterMut, ValueIter,
 };
 use crate::cake::{OutputId, TransformIdx};
-use crate::primitives::{
-    self,
-    fitrs::{Fits, Hdu},
-    IOValue, PATHS, ROI,
-};
+use crate::primitives::{self, fitrs::Fits, FitsExtensions, IOValue, Table, PATHS, ROI, WCS};
 
 use implot::Context;
 
@@ -224,6 +221,63 @@ fn update_editor_from_state(
     }
 }
 
+/// Get pixel coordinates of the center of the image made by the two last
+/// axes of `array`.
+fn image_center(array: &primitives::ndarray::ArrayD<f32>) -> (f32, f32) {
+    let shape = array.shape();
+    let ndim = shape.len();
+    (
+        (shape[ndim - 1] as f32 - 1.0) / 2.0,
+        (shape[ndim - 2] as f32 - 1.0) / 2.0,
+    )
+}
+
+/// Make the transformation from pixel to world coordinates of the first two
+/// axes of `wcs`, for images whose axes are not independent.
+fn pix2world_2d(wcs: &WCS) -> Box<dyn Fn(f32, f32) -> (f32, f32)> {
+    let wcs = wcs.clone();
+    Box::new(move |x, y| {
+        let world = wcs.pix2world([x, y, 0.0, 0.0]);
+        (world[0], world[1])
+    })
+}
+
+/// Make the graticule of a 2D image whose axes are celestial coordinates.
+fn graticule(image: &primitives::WcsArray) -> Option<Graticule> {
+    let wcs = image.wcs()?;
+    let celestial = wcs.celestial()?;
+    let (lng, lat) = (celestial.lng_axis(), celestial.lat_axis());
+    if lng > 1 || lat > 1 {
+        return None;
+    }
+    let hours = image
+        .axes()
+        .map_or(false, |axes| axes[lng].name().starts_with("RA--"));
+
+    let pix2world = {
+        let wcs = wcs.clone();
+        move |x, y| {
+            let world = wcs.pix2world([x, y, 0.0, 0.0]);
+            (world[lng], world[lat])
+        }
+    };
+    let world2pix = {
+        let wcs = wcs.clone();
+        move |lng_value, lat_value| {
+            let mut world = [0.0; 4];
+            world[lng] = lng_value;
+            world[lat] = lat_value;
+            let pixel = wcs.world2pix(world);
+            (pixel[0], pixel[1])
+        }
+    };
+    Some(Graticule::new(
+        Box::new(pix2world),
+        Box::new(world2pix),
+        hours,
+    ))
+}
+
 /// Used to compute the ID of a texture
 fn hash_outputid(id: OutputId) -> usize {
     use std::collections::hash_map::DefaultHasher;
@@ -423,6 +477,120 @@ impl MenuBar for ROI {
     const EXTENSION: &'static str = "txt";
 }
 
+/// Maximum number of rows of a table shown in the output window
+const MAX_TABLE_ROWS: usize = 1000;
+
+impl MenuBar for Table {
+    fn visualize<F>(&self, mut ctx: OutputWindowCtx<'_, '_, '_, '_, '_, '_, '_, F>)
+    where
+        F: glium::backend::Facade,
+    {
+        if let Some(attaching) = ctx.attaching {
+            if attaching.0 == ctx.output {
+                attach_failued(&mut ctx, &"Table");
+            }
+        }
+        let ui = ctx.ui;
+        let columns = self.columns();
+        if columns.is_empty() {
+            ui.text("Table has no column.");
+            return;
+        }
+        let row_count = self.row_count();
+        let shown_rows = row_count.min(MAX_TABLE_ROWS);
+        ui.text(format!(
+            "{} columns, {} rows (showing {})",
+            columns.len(),
+            row_count,
+            shown_rows
+        ));
+        ui.separator();
+
+        ui.columns(columns.len() as i32, format!("table_columns"), true);
+        for column in columns {
+            let unit = column.unit().repr();
+            if unit.is_empty() {
+                ui.text(column.name());
+            } else {
+                ui.text(format!("{} [{}]", column.name(), unit));
+            }
+            ui.next_column();
+        }
+        ui.separator();
+        for row in 0..shown_rows {
+            for column in columns {
+                ui.text(column.format_value(row));
+                ui.next_column();
+            }
+        }
+        ui.columns(1, format!("table_columns_end"), false);
+    }
+
+    /// Save table as comma-separated values. The first line contains the
+    /// column names, followed by their units in brackets.
+    fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), ExportError> {
+        let mut buf = String::new();
+        let header: Vec<_> = self
+            .columns()
+            .iter()
+            .map(|column| {
+                let unit = column.unit().repr();
+                if unit.is_empty() {
+                    csv_field(column.name())
+                } else {
+                    csv_field(&format!("{} [{}]", column.name(), unit))
+                }
+            })
+            .collect();
+        buf.push_str(&header.join(","));
+        buf.push('\n');
+        for row in 0..self.row_count() {
+            let values: Vec<_> = self
+                .columns()
+                .iter()
+                .map(|column| csv_field(&column.format_value(row)))
+                .collect();
+            buf.push_str(&values.join(","));
+            buf.push('\n');
+        }
+        write_to_file_as_bytes(path, buf.as_bytes())?;
+        Ok(())
+    }
+
+    const EXTENSION: &'static str = "csv";
+}
+
+impl MenuBar for FitsExtensions {
+    fn visualize<F>(&self, mut ctx: OutputWindowCtx<'_, '_, '_, '_, '_, '_, '_, F>)
+    where
+        F: glium::backend::Facade,
+    {
+        if let Some(attaching) = ctx.attaching {
+            if attaching.0 == ctx.output {
+                attach_failued(&mut ctx, &"FitsExtensions");
+            }
+        }
+        let ui = ctx.ui;
+        ui.text(format!("{} extensions", self.len()));
+        ui.separator();
+        for (name, image) in self.iter() {
+            ui.text(format!(
+                "{}: {:?} [{}]",
+                name,
+                image.scalar().shape(),
+                image.array().unit().repr()
+            ));
+        }
+    }
+
+    fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), ExportError> {
+        FitsExtensions::save(self, path)?;
+        Ok(())
+    }
+
+    const EXTENSION: &'static str = "fits";
+}
+
 impl MenuBar for primitives::WcsArray {
     fn file_submenu(&self, ui: &Ui, window: &mut OutputWindow) {
         match &self.tag() {
@@ -435,6 +603,15 @@ impl MenuBar for primitives::WcsArray {
                     if !has_wcs_data && ui.is_item_hovered() {
                         ui.tooltip_text("Data has no WCS metadata attached.");
                     }
+                    if self.scalar().ndim() == 2 {
+                        let has_variance = self.variance().is_some();
+                        MenuItem::new(format!("Show Uncertainty"))
+                            .enabled(has_variance)
+                            .build_with_ref(ui, &mut window.show_uncertainty);
+                        if !has_variance && ui.is_item_hovered() {
+                            ui.tooltip_text("Data has no uncertainty attached.");
+                        }
+                    }
                 }
                 _ => {}
             },
@@ -470,6 +647,13 @@ impl MenuBar for primitives::WcsArray {
                     if let Some(menu) = ui.begin_menu_with_enabled(format!("Others"), true) {
                         MenuItem::new(format!("Approx Line"))
                             .build_with_ref(ui, &mut window.image2d_state.show_approx_line);
+                        let has_celestial_axes = graticule(self).is_some();
+                        MenuItem::new(format!("Celestial Grid"))
+                            .enabled(has_celestial_axes)
+                            .build_with_ref(ui, &mut window.image2d_state.show_graticule);
+                        if !has_celestial_axes && ui.is_item_hovered() {
+                            ui.tooltip_text("Image axes are not celestial coordinates.");
+                        }
                         MenuItem::new(format!("Axes Option"))
                             .build_with_ref(ui, &mut window.image2d_state.show_axis_option);
                         menu.end();
@@ -608,6 +792,21 @@ impl MenuBar for primitives::WcsArray {
                         &ctx.window.editable_values,
                         ctx.node_editor,
                     );
+                    let new_incoming_image = match ctx.window.image1d_created_on {
+                        Some(image_created_on) => ctx.created_on > image_created_on,
+                        None => true,
+                    };
+                    if new_incoming_image {
+                        ctx.window.image1d_created_on = Some(ctx.created_on);
+                        state.set_uncertainty(
+                            self.uncertainty()
+                                .map(|uncertainty| uncertainty.scalar().iter().cloned().collect()),
+                        );
+                        state.set_mask(
+                            self.mask()
+                                .map(|mask| mask.iter().map(|&flags| flags != 0).collect()),
+                        );
+                    }
                     let unit = self.array().unit().repr();
                     let transform = if ctx.window.show_pixels {
                         None
@@ -667,14 +866,15 @@ impl MenuBar for primitives::WcsArray {
                             (Some(axes), Some(wcs)) => {
                                 let axis0 = &axes[0];
                                 let axis1 = &axes[1];
+                                let (x_center, y_center) = image_center(self.scalar());
                                 (
                                     Some({
                                         AxisTransform::new(axis0.name(), axis0.unit(), move |t| {
-                                            wcs.pix2world([t, 0.0, 0.0, 0.0])[0]
+                                            wcs.pix2world([t, y_center, 0.0, 0.0])[0]
                                         })
                                     }),
                                     Some(AxisTransform::new(axis1.name(), axis1.unit(), {
-                                        move |t| wcs.pix2world([0.0, t, 0.0, 0.0])[1]
+                                        move |t| wcs.pix2world([x_center, t, 0.0, 0.0])[1]
                                     })),
                                 )
                             }
@@ -682,13 +882,21 @@ impl MenuBar for primitives::WcsArray {
                         }
                     };
                     let unit = self.array().unit().repr();
+                    let show_uncertainty = ctx.window.show_uncertainty && self.variance().is_some();
                     let new_incoming_image = match state.image_created_on() {
                         Some(image_created_on) => ctx.created_on > image_created_on,
                         None => true,
-                    };
+                    } || show_uncertainty != ctx.window.shown_uncertainty;
                     if new_incoming_image {
+                        ctx.window.shown_uncertainty = show_uncertainty;
                         state.zoom_init();
-                        let value_ref: ArcRef<_> = ctx.value.clone().into();
+                        state.set_mask(self.mask().map(|mask| mask.mapv(|flags| flags != 0)));
+                        let value_ref: ArcRef<_> = match self.uncertainty() {
+                            Some(uncertainty) if show_uncertainty => {
+                                ArcRef::new(Arc::new(IOValue::Image(uncertainty)))
+                            }
+                            _ => ctx.value.clone().into(),
+                        };
                         let image_ref = value_ref.map(|value| {
                             if let IOValue::Image(image) = value {
                                 image.scalar()
@@ -706,6 +914,12 @@ impl MenuBar for primitives::WcsArray {
                             ui.text(format!("Error on creating image! {}", e));
                         }
                     }
+                    state.set_pix2world(if ctx.window.show_pixels {
+                        None
+                    } else {
+                        self.wcs().map(pix2world_2d)
+                    });
+                    state.set_graticule(graticule(self));
                     if let Err(e) = ui.image2d(
                         ctx.gl_ctx,
                         ctx.textures,
@@ -818,16 +1032,17 @@ impl MenuBar for primitives::WcsArray {
                                 (Some(axes), Some(wcs)) => {
                                     let axis0 = &axes[0];
                                     let axis1 = &axes[1];
+                                    let (x_center, y_center) = image_center(self.scalar());
                                     (
                                         Some({
                                             AxisTransform::new(
                                                 axis0.name(),
                                                 axis0.unit(),
-                                                move |t| wcs.pix2world([t, 0.0, 0.0, 0.0])[0],
+                                                move |t| wcs.pix2world([t, y_center, 0.0, 0.0])[0],
                                             )
                                         }),
                                         Some(AxisTransform::new(axis1.name(), axis1.unit(), {
-                                            move |t| wcs.pix2world([0.0, t, 0.0, 0.0])[1]
+                                            move |t| wcs.pix2world([x_center, t, 0.0, 0.0])[1]
                                         })),
                                     )
                                 }
@@ -859,6 +1074,11 @@ impl MenuBar for primitives::WcsArray {
                                 ui.text(format!("Error on creating image! {}", e));
                             }
                         }
+                        state.set_pix2world(if ctx.window.show_pixels {
+                            None
+                        } else {
+                            self.wcs().map(pix2world_2d)
+                        });
                         if let Err(e) = ui.color_image(
                             ctx.gl_ctx,
                             ctx.textures,
@@ -901,20 +1121,13 @@ impl MenuBar for primitives::WcsArray {
     }
 
     fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), ExportError> {
-        let arr = self.scalar();
-        // 0-dim ndarrays contain a single scalar value, however they should be
-        // treated as a 1-dimensional data array of length 1 when embedded as
-        // FITS file.
-        let shape = if arr.ndim() == 0 { &[1] } else { arr.shape() };
-        Fits::create(
-            path,
-            Hdu::new(
-                shape,
-                arr.as_slice()
-                    .expect("Could not get slice out of array")
-                    .to_owned(),
-            ),
-        )?;
+        let mut fits = Fits::create(path, self.to_hdu())?;
+        if let Some(variance) = self.variance_to_hdu() {
+            fits.push(variance)?;
+        }
+        if let Some(mask) = self.mask_to_hdu() {
+            fits.push(mask)?;
+        }
         Ok(())
     }
 
@@ -934,6 +1147,15 @@ fn write_to_file_as_bytes<P: AsRef<Path>>(path: P, buf: &[u8]) -> io::Result<()>
     file.write_all(buf)
 }
 
+/// Quote `field` if it contains characters that are special in CSV.
+fn csv_field(field: &str) -> String {
+    if field.contains(|c: char| c == ',' || c == '"' || c == '\n') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
 fn attach_failued<F>(ctx: &mut OutputWindowCtx<'_, '_, '_, '_, '_, '_, '_, F>, viztype: &str)
 where
     F: glium::backend::Facade,
@@ -962,12 +1184,14 @@ where
 #[derive(Debug)]
 pub enum ExportError {
     IOError(io::Error),
+    NoImage,
 }
 
 impl fmt::Display for ExportError {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         match self {
             ExportError::IOError(e) => write!(fmt, "{}", e),
+            ExportError::NoImage => write!(fmt, "No image has been computed. Nothing to save."),
         }
     }
 }
@@ -1,5 +1,7 @@
 use std::collections::HashMap;
 use std::error;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
 
 mod menu_bar;
 mod visualizable;
@@ -13,9 +15,10 @@ use crate::aflak_plot::{
     plot, scatter_lineplot, InteractionId,
 };
 use crate::cake::{OutputId, TransformIdx};
-use crate::primitives::{ndarray, IOValue, SuccessOut};
+use crate::primitives::{ndarray, FitsExtensions, IOValue, SuccessOut};
 use implot::Context;
 
+pub use self::menu_bar::ExportError;
 use self::menu_bar::MenuBar;
 use self::visualizable::{Initializing, Unimplemented, Visualizable};
 use crate::aflak::AflakNodeEditor;
@@ -27,6 +30,12 @@ pub struct OutputWindow {
     scatter_lineplot_state: scatter_lineplot::State,
     pub editable_values: EditableValues,
     show_pixels: bool,
+    show_uncertainty: bool,
+    /// Whether the texture of the 2D image currently shows the uncertainty
+    shown_uncertainty: bool,
+    /// Time at which the result whose uncertainty and mask are set in the
+    /// 1D plot was computed
+    image1d_created_on: Option<Instant>,
 }
 
 type EditableValues = HashMap<InteractionId, TransformIdx>;
@@ -85,6 +94,8 @@ impl OutputWindow {
                     IOValue::Bool(b) => b.draw(ctx, window),
                     IOValue::Image(ref image) => image.draw(ctx, window),
                     IOValue::Roi(ref roi) => roi.draw(ctx, window),
+                    IOValue::Table(ref table) => table.draw(ctx, window),
+                    IOValue::FitsExtensions(ref extensions) => extensions.draw(ctx, window),
                     IOValue::Fits(ref fits) => {
                         fits.draw(ui, window);
                         vec![]
@@ -98,3 +109,70 @@ impl OutputWindow {
         }
     }
 }
+
+/// Save `value` computed for `output` into directory `dir`, using the same
+/// file name and format as the "Save" item of the output window's menu bar.
+///
+/// Return the path of the written file, or `None` if `value` cannot be saved.
+pub fn save_output<P: AsRef<Path>>(
+    output: OutputId,
+    value: &IOValue,
+    dir: P,
+) -> Result<Option<PathBuf>, ExportError> {
+    fn save<V: MenuBar>(
+        value: &V,
+        output: OutputId,
+        dir: &Path,
+    ) -> Result<Option<PathBuf>, ExportError> {
+        let path = dir.join(value.file_name(output));
+        value.save(&path)?;
+        Ok(Some(path))
+    }
+
+    let dir = dir.as_ref();
+    match value {
+        IOValue::Str(ref string) => save(string, output, dir),
+        IOValue::Paths(ref files) => save(files, output, dir),
+        IOValue::Integer(integer) => save(integer, output, dir),
+        IOValue::Float(float) => save(float, output, dir),
+        IOValue::Float2(floats) => save(floats, output, dir),
+        IOValue::Float3(floats) => save(floats, output, dir),
+        IOValue::Float3x3(floats) => save(floats, output, dir),
+        IOValue::Bool(b) => save(b, output, dir),
+        IOValue::Image(ref image) => save(image, output, dir),
+        IOValue::Roi(ref roi) => save(roi, output, dir),
+        IOValue::Table(ref table) => save(table, output, dir),
+        IOValue::FitsExtensions(ref extensions) => save(extensions, output, dir),
+        _ => Ok(None),
+    }
+}
+
+/// Save all image `values` into a single FITS file at `path`.
+///
+/// Each image is written in its own extension, named after the output that
+/// computed it. Values that are not images are skipped.
+///
+/// Return the number of saved images.
+pub fn save_images_as_extensions<'a, I, P>(values: I, path: P) -> Result<usize, ExportError>
+where
+    I: IntoIterator<Item = (OutputId, &'a str, &'a IOValue)>,
+    P: AsRef<Path>,
+{
+    let mut extensions = FitsExtensions::new();
+    for (output, name, value) in values {
+        if let IOValue::Image(image) = value {
+            let name = name.trim();
+            let name = if name.is_empty() || extensions.contains(name) {
+                format!("OUTPUT{}", output.id())
+            } else {
+                name.to_owned()
+            };
+            extensions.push(name, image.clone());
+        }
+    }
+    if extensions.is_empty() {
+        return Err(ExportError::NoImage);
+    }
+    extensions.save(path)?;
+    Ok(extensions.len())
+}